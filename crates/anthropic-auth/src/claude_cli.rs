@@ -0,0 +1,120 @@
+//! Claude CLI credential import
+//!
+//! A logged-in Claude Code install keeps its OAuth tokens in
+//! `~/.claude/.credentials.json` (on macOS, the same JSON in the keychain
+//! under service `Claude Code-credentials`):
+//!
+//! ```json
+//! {
+//!   "claudeAiOauth": {
+//!     "accessToken": "sk-ant-oat01-...",
+//!     "refreshToken": "sk-ant-ort01-...",
+//!     "expiresAt": 1735500000000,
+//!     "scopes": ["user:inference", "user:profile"],
+//!     "subscriptionType": "max"
+//!   }
+//! }
+//! ```
+//!
+//! `expiresAt` is already unix milliseconds, like `Credential::expires`.
+//! Fields other than the three tokens are ignored. The bare `claudeAiOauth`
+//! object is accepted too.
+
+use serde::Deserialize;
+
+use crate::credentials::Credential;
+use crate::error::{Error, Result};
+
+/// Path of the Claude CLI credential file, relative to the home directory.
+pub const CLAUDE_CLI_CREDENTIALS_PATH: &str = ".claude/.credentials.json";
+
+/// The `claudeAiOauth` object.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ClaudeAiOauth {
+    access_token: String,
+    refresh_token: String,
+    expires_at: u64,
+}
+
+/// The credential file, or just its `claudeAiOauth` object.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ClaudeCliFile {
+    #[serde(rename_all = "camelCase")]
+    Wrapped {
+        claude_ai_oauth: ClaudeAiOauth,
+    },
+    Bare(ClaudeAiOauth),
+}
+
+/// Convert Claude CLI credentials JSON into a gateway `Credential`.
+///
+/// Fails with `Error::CredentialParse` if the JSON has no `claudeAiOauth`
+/// tokens (e.g. a file from an API-key login) or the refresh token is empty.
+pub fn parse_claude_cli_credentials(json: &[u8]) -> Result<Credential> {
+    let file: ClaudeCliFile = serde_json::from_slice(json).map_err(|_| {
+        Error::CredentialParse(
+            "expected Claude CLI credentials with claudeAiOauth accessToken, refreshToken, \
+             and expiresAt"
+                .into(),
+        )
+    })?;
+    let (ClaudeCliFile::Wrapped {
+        claude_ai_oauth: oauth,
+    }
+    | ClaudeCliFile::Bare(oauth)) = file;
+    if oauth.refresh_token.is_empty() {
+        return Err(Error::CredentialParse(
+            "claudeAiOauth has an empty refreshToken".into(),
+        ));
+    }
+    Ok(Credential {
+        credential_type: "oauth".into(),
+        refresh: oauth.refresh_token,
+        access: oauth.access_token,
+        expires: oauth.expires_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_credential_file() {
+        let json = br#"{
+            "claudeAiOauth": {
+                "accessToken": "at_cli",
+                "refreshToken": "rt_cli",
+                "expiresAt": 1735500000000,
+                "scopes": ["user:inference", "user:profile"],
+                "subscriptionType": "max"
+            }
+        }"#;
+        let credential = parse_claude_cli_credentials(json).unwrap();
+        assert_eq!(credential.credential_type, "oauth");
+        assert_eq!(credential.access, "at_cli");
+        assert_eq!(credential.refresh, "rt_cli");
+        assert_eq!(credential.expires, 1735500000000);
+    }
+
+    #[test]
+    fn parses_bare_oauth_object() {
+        let json = br#"{"accessToken": "at", "refreshToken": "rt", "expiresAt": 1}"#;
+        assert_eq!(parse_claude_cli_credentials(json).unwrap().refresh, "rt");
+    }
+
+    #[test]
+    fn rejects_files_without_oauth_tokens() {
+        for json in [
+            &br#"{"mcpOAuth": {}}"#[..],
+            br#"{"claudeAiOauth": {"accessToken": "at"}}"#,
+            br#"{"claudeAiOauth": {"accessToken": "at", "refreshToken": "", "expiresAt": 1}}"#,
+            b"not json",
+        ] {
+            let err = parse_claude_cli_credentials(json).unwrap_err();
+            assert!(matches!(err, Error::CredentialParse(_)), "{err}");
+        }
+    }
+}
@@ -1,20 +1,40 @@
 //! Credential storage for OAuth tokens
 //!
-//! Manages a JSON file mapping account IDs to OAuth credentials. All writes
-//! use atomic temp-file + rename to prevent corruption on crash. A tokio Mutex
-//! serializes concurrent writes from request-time refresh and background refresh.
+//! Manages a JSON document mapping account IDs to OAuth credentials, kept in
+//! a `CredentialBackend` (a local file by default; see `storage`). Every
+//! change rewrites the whole document, and a tokio Mutex serializes
+//! concurrent writes from request-time refresh and background refresh.
+//! The in-memory credentials sit behind their own lock, held only to copy or
+//! update them, so reads never wait on the backend or a token request.
 //!
-//! The credential file is the single source of truth for token data. The pool
+//! The backend is the single source of truth for token data. The pool
 //! reads credentials from this store at selection time.
+//!
+//! Refresh tokens are single-use, so processes sharing a backend must not
+//! refresh the same account twice. `refresh` re-reads the account under the
+//! backend's cross-process lock and skips the token request if another
+//! process already rotated it.
+//!
+//! Changes made outside this process (an operator editing the file, a
+//! sidecar, another replica) are picked up by `reload`, which the pool calls
+//! periodically.
+//!
+//! With an `Encryption` key the document is envelope-encrypted (see
+//! `encryption`). A plaintext document found at load time is rewritten
+//! encrypted, so enabling encryption migrates existing deployments in place.
 
-use std::collections::HashMap;
-use std::path::{Path, PathBuf};
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
+use crate::encryption::{EncryptedFile, Encryption};
 use crate::error::{Error, Result};
+use crate::storage::{CredentialBackend, FileBackend, REFRESH_TIMEOUT};
+use crate::token::TokenResponse;
 
 /// A single account's OAuth credentials.
 ///
@@ -34,13 +54,57 @@ pub struct Credential {
     pub expires: u64,
 }
 
-/// Thread-safe credential file manager.
+/// Result of `CredentialStore::refresh`.
+#[derive(Debug, Clone)]
+pub struct RefreshOutcome {
+    /// The account's current credential
+    pub credential: Credential,
+    /// `false` if another process had already rotated the token, so no
+    /// refresh request was made
+    pub refreshed: bool,
+}
+
+/// Accounts that appeared or disappeared in a `CredentialStore::reload`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Reloaded {
+    /// Accounts stored by another writer since the last read, sorted
+    pub added: Vec<String>,
+    /// Accounts deleted by another writer since the last read, sorted
+    pub removed: Vec<String>,
+}
+
+impl Reloaded {
+    /// Whether the account list is unchanged.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// In-memory view of the backend.
+struct State {
+    credentials: HashMap<String, Credential>,
+    /// Backend version `credentials` was read at or last written as
+    version: Option<String>,
+    /// Accounts whose refreshed tokens failed to persist. Their in-memory
+    /// credentials win over re-reads until a write succeeds: the old refresh
+    /// token is already spent.
+    unsaved: HashSet<String>,
+}
+
+/// Thread-safe credential manager.
 ///
-/// The Mutex serializes all writes. Reads acquire the lock briefly to clone
-/// the in-memory state, so request-time reads don't block on background writes.
+/// `writer` serializes all writes within the process; the backend lock
+/// serializes them across processes sharing the backend. Every change
+/// re-reads the backend under both, applies itself to the latest document,
+/// and writes it back compare-and-swap. `state` is only held to clone or
+/// update the in-memory credentials, never across backend I/O or a token
+/// request, so request-time reads don't block on background writes.
 pub struct CredentialStore {
-    path: PathBuf,
-    state: Mutex<HashMap<String, Credential>>,
+    backend: Box<dyn CredentialBackend>,
+    writer: Mutex<()>,
+    state: Mutex<State>,
+    /// Key for the document at rest (`None`: plaintext)
+    encryption: Option<Encryption>,
 }
 
 impl CredentialStore {
@@ -50,74 +114,176 @@ impl CredentialStore {
     /// accounts). The pool will report `unhealthy` until accounts are added
     /// via the admin API.
     pub async fn load(path: PathBuf) -> Result<Self> {
-        let state = if path.exists() {
-            let contents = tokio::fs::read_to_string(&path)
-                .await
-                .map_err(|e| Error::Io(format!("reading credential file: {e}")))?;
-            let credentials: HashMap<String, Credential> = serde_json::from_str(&contents)
-                .map_err(|e| Error::CredentialParse(format!("parsing credential file: {e}")))?;
-            info!(path = %path.display(), accounts = credentials.len(), "loaded credentials");
-            credentials
-        } else {
-            info!(path = %path.display(), "credential file not found, starting with empty store");
-            let store = HashMap::new();
-            // Create the empty file so future loads don't need the cold-start path
-            write_atomic(&path, &store).await?;
-            store
+        Self::load_with_encryption(path, None).await
+    }
+
+    /// Load credentials from a file, reading and writing it encrypted under
+    /// `encryption` when one is given.
+    pub async fn load_with_encryption(
+        path: PathBuf,
+        encryption: Option<Encryption>,
+    ) -> Result<Self> {
+        Self::open(Box::new(FileBackend::new(path)), encryption).await
+    }
+
+    /// Load credentials from `backend`, encrypted under `encryption` when one
+    /// is given. An empty backend is initialized with `{}`.
+    ///
+    /// A plaintext document is accepted and immediately rewritten encrypted.
+    /// An encrypted document without a key, or with the wrong one, is an
+    /// error.
+    pub async fn open(
+        backend: Box<dyn CredentialBackend>,
+        encryption: Option<Encryption>,
+    ) -> Result<Self> {
+        let store = Self {
+            backend,
+            writer: Mutex::new(()),
+            state: Mutex::new(State {
+                credentials: HashMap::new(),
+                version: None,
+                unsaved: HashSet::new(),
+            }),
+            encryption,
         };
+        let location = store.location();
+        store
+            .with_lock(async {
+                match store.backend.read().await? {
+                    Some(stored) => {
+                        let (credentials, was_encrypted) =
+                            decode(stored.data, store.encryption.as_ref()).await?;
+                        info!(
+                            location,
+                            accounts = credentials.len(),
+                            encrypted = was_encrypted,
+                            "loaded credentials"
+                        );
+                        {
+                            let mut state = store.state.lock().await;
+                            state.credentials = credentials;
+                            state.version = Some(stored.version);
+                        }
+                        if store.encryption.is_some() && !was_encrypted {
+                            info!(location, "encrypting plaintext credentials");
+                            store.persist().await?;
+                        }
+                    }
+                    None => {
+                        info!(location, "no stored credentials, starting with empty store");
+                        // Store the empty document so future loads don't need the cold-start path
+                        store.persist().await?;
+                    }
+                }
+                Ok(())
+            })
+            .await?;
+        Ok(store)
+    }
 
-        Ok(Self {
-            path,
-            state: Mutex::new(state),
-        })
+    /// Rewrite the credentials in `backend` from key `from` to key `to`
+    /// (`None` on either side means plaintext). Returns the number of
+    /// accounts rewritten.
+    ///
+    /// Nothing else may write the backend meanwhile: a running proxy still
+    /// holding `from` would write it back under the old key.
+    pub async fn rotate_key(
+        backend: &dyn CredentialBackend,
+        from: Option<&Encryption>,
+        to: Option<&Encryption>,
+    ) -> Result<usize> {
+        backend.lock().await?;
+        let result = async {
+            let stored = backend.read().await?.ok_or_else(|| {
+                Error::NotFound(format!("no credentials stored in {}", backend.location()))
+            })?;
+            let (credentials, _) = decode(stored.data, from).await?;
+            let accounts = credentials.len();
+            backend
+                .write(&encode(credentials, to).await?, Some(&stored.version))
+                .await?;
+            Ok(accounts)
+        }
+        .await;
+        if let Err(e) = backend.unlock().await {
+            warn!(error = %e, "failed to release credential lock");
+        }
+        let accounts = result?;
+        info!(
+            location = backend.location(),
+            accounts,
+            encrypted = to.is_some(),
+            "rewrote credentials under new key"
+        );
+        Ok(accounts)
     }
 
-    /// Persist the current in-memory state to disk.
+    /// Persist the current in-memory state to the backend.
     ///
-    /// Uses atomic write (temp file + rename) to prevent corruption.
-    /// File permissions are set to 0600 (owner read/write only).
+    /// Fails with `Error::Conflict` if another process wrote since this
+    /// store last read.
     pub async fn save(&self) -> Result<()> {
-        let state = self.state.lock().await;
-        write_atomic(&self.path, &state).await
+        self.with_lock(self.persist()).await
+    }
+
+    /// Where the credentials are stored, for logs.
+    pub fn location(&self) -> String {
+        self.backend.location()
     }
 
     /// Get a clone of a specific credential.
     pub async fn get(&self, account_id: &str) -> Option<Credential> {
         let state = self.state.lock().await;
-        state.get(account_id).cloned()
+        state.credentials.get(account_id).cloned()
     }
 
     /// List all account IDs.
     pub async fn account_ids(&self) -> Vec<String> {
         let state = self.state.lock().await;
-        state.keys().cloned().collect()
+        state.credentials.keys().cloned().collect()
     }
 
-    /// Add or replace a credential and persist to disk.
+    /// Add or replace a credential and persist it.
     pub async fn add(&self, account_id: String, credential: Credential) -> Result<()> {
-        let mut state = self.state.lock().await;
-        state.insert(account_id.clone(), credential);
-        debug!(account_id, "added credential");
-        write_atomic(&self.path, &state).await
+        self.with_lock(async {
+            self.sync().await?;
+            self.state
+                .lock()
+                .await
+                .credentials
+                .insert(account_id.clone(), credential);
+            debug!(account_id, "added credential");
+            self.persist().await
+        })
+        .await
     }
 
-    /// Remove a credential and persist to disk.
+    /// Remove a credential and persist the change.
     ///
     /// Returns the removed credential if it existed.
     pub async fn remove(&self, account_id: &str) -> Result<Option<Credential>> {
-        let mut state = self.state.lock().await;
-        let removed = state.remove(account_id);
-        if removed.is_some() {
-            debug!(account_id, "removed credential");
-            write_atomic(&self.path, &state).await?;
-        }
-        Ok(removed)
+        self.with_lock(async {
+            self.sync().await?;
+            let removed = {
+                let mut state = self.state.lock().await;
+                state.unsaved.remove(account_id);
+                state.credentials.remove(account_id)
+            };
+            if removed.is_some() {
+                debug!(account_id, "removed credential");
+                self.persist().await?;
+            }
+            Ok(removed)
+        })
+        .await
     }
 
     /// Update tokens for an existing account after a refresh.
     ///
     /// Updates the access token, refresh token, and expiration in-memory
-    /// and persists to disk. Returns an error if the account doesn't exist.
+    /// and persists them. Returns an error if the account doesn't exist.
+    /// Prefer `refresh`, which also keeps processes sharing the backend
+    /// from refreshing the same token twice.
     pub async fn update_token(
         &self,
         account_id: &str,
@@ -125,67 +291,272 @@ impl CredentialStore {
         refresh: String,
         expires: u64,
     ) -> Result<()> {
-        let mut state = self.state.lock().await;
-        let credential = state.get_mut(account_id).ok_or_else(|| {
-            Error::NotFound(format!("account {account_id} not in credential store"))
-        })?;
-        credential.access = access;
-        credential.refresh = refresh;
-        credential.expires = expires;
-        debug!(account_id, "updated token");
-        write_atomic(&self.path, &state).await
+        self.with_lock(async {
+            self.sync().await?;
+            set_token(
+                &mut *self.state.lock().await,
+                account_id,
+                access,
+                refresh,
+                expires,
+            )?;
+            self.persist_token(account_id).await
+        })
+        .await
+    }
+
+    /// Refresh an account's tokens with `refresh`, unless another process
+    /// sharing the backend already did.
+    ///
+    /// `stale_refresh` is the refresh token the caller saw. Under the backend
+    /// lock the store re-reads the account; if its refresh token has changed
+    /// meanwhile, the token was already rotated and the stored credential is
+    /// returned without calling `refresh`. Otherwise `refresh` is called with
+    /// the stored refresh token and the result is written back before the
+    /// lock is released, so two replicas never spend the same refresh token.
+    ///
+    /// `refresh` is abandoned with `Error::Http` after `REFRESH_TIMEOUT`, so
+    /// the backend lease can't expire while it runs.
+    ///
+    /// If the new tokens can't be persisted they are still returned and kept
+    /// in memory (the old refresh token is spent), and the next successful
+    /// write stores them.
+    pub async fn refresh<F, Fut>(
+        &self,
+        account_id: &str,
+        stale_refresh: &str,
+        refresh: F,
+    ) -> Result<RefreshOutcome>
+    where
+        F: FnOnce(String) -> Fut,
+        Fut: Future<Output = Result<TokenResponse>>,
+    {
+        self.with_lock(async {
+            self.sync().await?;
+            let current = self.get(account_id).await.ok_or_else(|| {
+                Error::NotFound(format!("account {account_id} not in credential store"))
+            })?;
+            if current.refresh != stale_refresh {
+                info!(account_id, "token already refreshed by another process");
+                return Ok(RefreshOutcome {
+                    credential: current,
+                    refreshed: false,
+                });
+            }
+
+            let token = tokio::time::timeout(REFRESH_TIMEOUT, refresh(current.refresh))
+                .await
+                .map_err(|_| {
+                    Error::Http(format!(
+                        "token refresh for {account_id} timed out after {REFRESH_TIMEOUT:?}"
+                    ))
+                })??;
+            let expires = now_millis() + token.expires_in * 1000;
+            let credential = {
+                let mut state = self.state.lock().await;
+                set_token(
+                    &mut state,
+                    account_id,
+                    token.access_token,
+                    token.refresh_token,
+                    expires,
+                )?;
+                state.credentials[account_id].clone()
+            };
+            if let Err(e) = self.persist_token(account_id).await {
+                warn!(account_id, error = %e, "failed to persist refreshed token");
+            }
+            Ok(RefreshOutcome {
+                credential,
+                refreshed: true,
+            })
+        })
+        .await
+    }
+
+    /// Pick up changes written to the backend by anyone else.
+    ///
+    /// Cheap when nothing changed: the stored version matches and the
+    /// document is not parsed. Otherwise the in-memory credentials are
+    /// replaced with the stored ones, except refreshed tokens that have not
+    /// been persisted yet. A document that fails to parse (say, an editor
+    /// caught mid-write) is an error and leaves the store unchanged.
+    pub async fn reload(&self) -> Result<Reloaded> {
+        // Writers sync under `writer` too; keep the before/after diff theirs-free
+        let _writer = self.writer.lock().await;
+        let before: HashSet<String> = self.account_ids().await.into_iter().collect();
+        self.sync().await?;
+        let state = self.state.lock().await;
+        let mut added: Vec<String> = state
+            .credentials
+            .keys()
+            .filter(|id| !before.contains(*id))
+            .cloned()
+            .collect();
+        let mut removed: Vec<String> = before
+            .into_iter()
+            .filter(|id| !state.credentials.contains_key(id))
+            .collect();
+        added.sort();
+        removed.sort();
+        if !added.is_empty() || !removed.is_empty() {
+            info!(
+                location = self.location(),
+                added = added.len(),
+                removed = removed.len(),
+                "credentials changed externally"
+            );
+        }
+        Ok(Reloaded { added, removed })
     }
 
     /// Number of stored credentials.
     pub async fn len(&self) -> usize {
         let state = self.state.lock().await;
-        state.len()
+        state.credentials.len()
     }
 
     /// Whether the store is empty.
     pub async fn is_empty(&self) -> bool {
         self.len().await == 0
     }
-}
 
-/// Write credentials to a file atomically.
-///
-/// Writes to a temporary file in the same directory, then renames it over
-/// the target. This prevents corruption if the process crashes mid-write.
-/// Sets file permissions to 0600 (owner read/write only) since the file
-/// contains OAuth tokens.
-async fn write_atomic(path: &Path, data: &HashMap<String, Credential>) -> Result<()> {
-    let json = serde_json::to_string_pretty(data)
-        .map_err(|e| Error::CredentialParse(format!("serializing credentials: {e}")))?;
-
-    let dir = path
-        .parent()
-        .ok_or_else(|| Error::Io("credential path has no parent directory".into()))?;
-
-    let tmp_path = dir.join(format!(".credentials.tmp.{}", std::process::id()));
+    /// Run `op` holding the process's writer lock and the backend lock,
+    /// releasing them whatever `op` returns.
+    async fn with_lock<T>(&self, op: impl Future<Output = Result<T>>) -> Result<T> {
+        let _writer = self.writer.lock().await;
+        self.backend.lock().await?;
+        let result = op.await;
+        if let Err(e) = self.backend.unlock().await {
+            warn!(location = self.location(), error = %e, "failed to release credential lock");
+        }
+        result
+    }
 
-    tokio::fs::write(&tmp_path, json.as_bytes())
-        .await
-        .map_err(|e| Error::Io(format!("writing temp credential file: {e}")))?;
+    /// Catch up with writes from other processes: replace the in-memory
+    /// credentials with the stored ones, except unsaved refreshed tokens.
+    /// Callers hold `writer`, so the version can't change underneath.
+    async fn sync(&self) -> Result<()> {
+        let Some(stored) = self.backend.read().await? else {
+            return Ok(());
+        };
+        if self.state.lock().await.version.as_deref() == Some(stored.version.as_str()) {
+            return Ok(());
+        }
+        let (mut credentials, _) = decode(stored.data, self.encryption.as_ref()).await?;
+        let mut state = self.state.lock().await;
+        for id in &state.unsaved {
+            if let Some(credential) = state.credentials.get(id) {
+                credentials.insert(id.clone(), credential.clone());
+            }
+        }
+        debug!(accounts = credentials.len(), "re-read credentials");
+        state.credentials = credentials;
+        state.version = Some(stored.version);
+        Ok(())
+    }
 
-    // Set 0600 permissions (unix only)
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        let perms = std::fs::Permissions::from_mode(0o600);
-        tokio::fs::set_permissions(&tmp_path, perms)
-            .await
-            .map_err(|e| Error::Io(format!("setting credential file permissions: {e}")))?;
+    /// Write the in-memory credentials, expecting the backend to be at the
+    /// version they were read at.
+    async fn persist(&self) -> Result<()> {
+        let (credentials, expected) = {
+            let state = self.state.lock().await;
+            (state.credentials.clone(), state.version.clone())
+        };
+        let document = encode(credentials, self.encryption.as_ref()).await?;
+        let version = self.backend.write(&document, expected.as_deref()).await?;
+        let mut state = self.state.lock().await;
+        state.version = Some(version);
+        state.unsaved.clear();
+        Ok(())
     }
 
-    tokio::fs::rename(&tmp_path, path)
-        .await
-        .map_err(|e| Error::Io(format!("renaming temp credential file: {e}")))?;
+    /// `persist` after a token change, remembering the account as unsaved
+    /// if the write fails.
+    async fn persist_token(&self, account_id: &str) -> Result<()> {
+        let result = self.persist().await;
+        if result.is_err() {
+            self.state
+                .lock()
+                .await
+                .unsaved
+                .insert(account_id.to_string());
+        }
+        result
+    }
+}
 
-    debug!(path = %path.display(), "persisted credentials");
+fn set_token(
+    state: &mut State,
+    account_id: &str,
+    access: String,
+    refresh: String,
+    expires: u64,
+) -> Result<()> {
+    let credential = state
+        .credentials
+        .get_mut(account_id)
+        .ok_or_else(|| Error::NotFound(format!("account {account_id} not in credential store")))?;
+    credential.access = access;
+    credential.refresh = refresh;
+    credential.expires = expires;
+    debug!(account_id, "updated token");
     Ok(())
 }
 
+fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Parse a stored document, decrypting it if it is encrypted. Also reports
+/// whether it was.
+///
+/// Decryption runs on the blocking thread pool: deriving a passphrase key
+/// takes long enough to stall the runtime.
+async fn decode(
+    contents: Vec<u8>,
+    encryption: Option<&Encryption>,
+) -> Result<(HashMap<String, Credential>, bool)> {
+    let (plaintext, encrypted) = match serde_json::from_slice::<EncryptedFile>(&contents) {
+        Ok(file) => {
+            let encryption = encryption.cloned().ok_or_else(|| {
+                Error::Encryption("credentials are encrypted but no key is configured".into())
+            })?;
+            let plaintext = tokio::task::spawn_blocking(move || encryption.open(&file.encrypted))
+                .await
+                .map_err(|e| Error::Encryption(format!("decryption task failed: {e}")))??;
+            (plaintext, true)
+        }
+        Err(_) => (contents, false),
+    };
+    let credentials = serde_json::from_slice(&plaintext)
+        .map_err(|e| Error::CredentialParse(format!("parsing credentials: {e}")))?;
+    Ok((credentials, encrypted))
+}
+
+/// Serialize credentials, sealing them with `encryption` if given (on the
+/// blocking thread pool, like `decode`).
+async fn encode(
+    data: HashMap<String, Credential>,
+    encryption: Option<&Encryption>,
+) -> Result<Vec<u8>> {
+    let json = serde_json::to_string_pretty(&data)
+        .map_err(|e| Error::CredentialParse(format!("serializing credentials: {e}")))?;
+    let Some(encryption) = encryption.cloned() else {
+        return Ok(json.into_bytes());
+    };
+    let file = EncryptedFile {
+        encrypted: tokio::task::spawn_blocking(move || encryption.seal(json.as_bytes()))
+            .await
+            .map_err(|e| Error::Encryption(format!("encryption task failed: {e}")))??,
+    };
+    serde_json::to_vec_pretty(&file)
+        .map_err(|e| Error::CredentialParse(format!("serializing envelope: {e}")))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -333,6 +704,307 @@ mod tests {
         assert_eq!(ids, vec!["a-acct", "b-acct"]);
     }
 
+    #[tokio::test]
+    async fn encrypted_store_migrates_plaintext_and_rotates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("credentials.json");
+        let old_key = Encryption::from_key([7; 32]);
+        let new_key = Encryption::from_passphrase("rotated").unwrap();
+
+        // Plaintext file from before encryption was enabled
+        CredentialStore::load(path.clone())
+            .await
+            .unwrap()
+            .add("acct-1".into(), test_credential("1"))
+            .await
+            .unwrap();
+
+        let store = CredentialStore::load_with_encryption(path.clone(), Some(old_key.clone()))
+            .await
+            .unwrap();
+        assert_eq!(store.get("acct-1").await.unwrap().refresh, "rt_1");
+        let contents = tokio::fs::read_to_string(&path).await.unwrap();
+        assert!(contents.contains("\"encrypted\""));
+        assert!(
+            !contents.contains("rt_1"),
+            "tokens must not be stored in the clear"
+        );
+
+        // Without the key the file can't be read
+        let err = CredentialStore::load(path.clone()).await.err().unwrap();
+        assert!(matches!(err, Error::Encryption(_)), "{err}");
+
+        let rotated =
+            CredentialStore::rotate_key(&FileBackend::new(&path), Some(&old_key), Some(&new_key))
+                .await
+                .unwrap();
+        assert_eq!(rotated, 1);
+        assert!(
+            CredentialStore::load_with_encryption(path.clone(), Some(old_key))
+                .await
+                .is_err()
+        );
+        let store = CredentialStore::load_with_encryption(path, Some(new_key))
+            .await
+            .unwrap();
+        assert_eq!(store.get("acct-1").await.unwrap().access, "at_1");
+    }
+
+    #[tokio::test]
+    async fn store_runs_on_any_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("credentials.db");
+        let key = Encryption::from_key([3; 32]);
+
+        let backend = crate::SqliteBackend::open(&path).unwrap();
+        let store = CredentialStore::open(Box::new(backend), Some(key.clone()))
+            .await
+            .unwrap();
+        assert!(store.location().starts_with("sqlite:"));
+        store
+            .add("acct-1".into(), test_credential("1"))
+            .await
+            .unwrap();
+
+        let backend = crate::SqliteBackend::open(&path).unwrap();
+        let stored = backend.read().await.unwrap().unwrap();
+        assert!(!String::from_utf8_lossy(&stored.data).contains("rt_1"));
+        let store = CredentialStore::open(Box::new(backend), Some(key))
+            .await
+            .unwrap();
+        assert_eq!(store.get("acct-1").await.unwrap().refresh, "rt_1");
+    }
+
+    fn token(suffix: &str) -> TokenResponse {
+        TokenResponse {
+            access_token: format!("at_{suffix}"),
+            refresh_token: format!("rt_{suffix}"),
+            expires_in: 3600,
+        }
+    }
+
+    #[tokio::test]
+    async fn processes_sharing_a_file_refresh_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("credentials.json");
+        let first = std::sync::Arc::new(CredentialStore::load(path.clone()).await.unwrap());
+        first
+            .add("acct-1".into(), test_credential("1"))
+            .await
+            .unwrap();
+        // A second replica, loaded with the same (soon stale) refresh token
+        let second = std::sync::Arc::new(CredentialStore::load(path).await.unwrap());
+
+        let calls = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let refresh = |store: std::sync::Arc<CredentialStore>| {
+            let calls = calls.clone();
+            tokio::spawn(async move {
+                store
+                    .refresh("acct-1", "rt_1", |stale| async move {
+                        assert_eq!(stale, "rt_1");
+                        calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                        Ok(token("2"))
+                    })
+                    .await
+                    .unwrap()
+            })
+        };
+        let (a, b) = tokio::join!(refresh(first.clone()), refresh(second.clone()));
+        let (a, b) = (a.unwrap(), b.unwrap());
+
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert!(a.refreshed != b.refreshed);
+        assert_eq!(a.credential.refresh, "rt_2");
+        assert_eq!(b.credential.refresh, "rt_2");
+        assert_eq!(second.get("acct-1").await.unwrap().access, "at_2");
+        assert_eq!(first.get("acct-1").await.unwrap().access, "at_2");
+    }
+
+    #[tokio::test]
+    async fn reads_do_not_wait_for_a_refresh_in_progress() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = std::sync::Arc::new(
+            CredentialStore::load(dir.path().join("credentials.json"))
+                .await
+                .unwrap(),
+        );
+        store
+            .add("acct-1".into(), test_credential("1"))
+            .await
+            .unwrap();
+
+        let (started_tx, started_rx) = tokio::sync::oneshot::channel();
+        let (finish_tx, finish_rx) = tokio::sync::oneshot::channel::<()>();
+        let refreshing = store.clone();
+        let refresh = tokio::spawn(async move {
+            refreshing
+                .refresh("acct-1", "rt_1", |_| async move {
+                    started_tx.send(()).unwrap();
+                    finish_rx.await.unwrap();
+                    Ok(token("2"))
+                })
+                .await
+                .unwrap()
+        });
+        started_rx.await.unwrap();
+
+        // The token request is outstanding; reads still answer immediately
+        let read = tokio::time::timeout(std::time::Duration::from_secs(1), store.get("acct-1"));
+        assert_eq!(read.await.unwrap().unwrap().access, "at_1");
+
+        finish_tx.send(()).unwrap();
+        assert!(refresh.await.unwrap().refreshed);
+        assert_eq!(store.get("acct-1").await.unwrap().access, "at_2");
+    }
+
+    #[tokio::test]
+    async fn slow_refresh_is_abandoned_before_its_lease_expires() {
+        use crate::storage::LEASE_DURATION;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("credentials.db");
+        let open = || async {
+            std::sync::Arc::new(
+                CredentialStore::open(Box::new(crate::SqliteBackend::open(&path).unwrap()), None)
+                    .await
+                    .unwrap(),
+            )
+        };
+        let first = open().await;
+        first
+            .add("acct-1".into(), test_credential("1"))
+            .await
+            .unwrap();
+        let second = open().await;
+
+        // Set while the first replica's token request is outstanding
+        let in_flight = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        struct Clear(std::sync::Arc<std::sync::atomic::AtomicBool>);
+        impl Drop for Clear {
+            fn drop(&mut self) {
+                self.0.store(false, std::sync::atomic::Ordering::SeqCst);
+            }
+        }
+
+        let (started_tx, started_rx) = tokio::sync::oneshot::channel();
+        let slow = {
+            let (store, in_flight) = (first.clone(), in_flight.clone());
+            tokio::spawn(async move {
+                store
+                    .refresh("acct-1", "rt_1", |_| async move {
+                        in_flight.store(true, std::sync::atomic::Ordering::SeqCst);
+                        let _clear = Clear(in_flight);
+                        started_tx.send(()).unwrap();
+                        tokio::time::sleep(LEASE_DURATION).await;
+                        Ok(token("slow"))
+                    })
+                    .await
+            })
+        };
+        started_rx.await.unwrap();
+
+        // The second replica waits for the lock, and only gets it once the
+        // slow request has been abandoned
+        let started = std::time::Instant::now();
+        let outcome = second
+            .refresh("acct-1", "rt_1", |stale| {
+                let in_flight = in_flight.clone();
+                async move {
+                    assert_eq!(stale, "rt_1");
+                    assert!(!in_flight.load(std::sync::atomic::Ordering::SeqCst));
+                    Ok(token("2"))
+                }
+            })
+            .await
+            .unwrap();
+        assert!(started.elapsed() < LEASE_DURATION);
+        assert!(outcome.refreshed);
+
+        let err = slow.await.unwrap().unwrap_err();
+        assert!(err.to_string().contains("timed out"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn writes_merge_changes_from_other_processes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("credentials.json");
+        let first = CredentialStore::load(path.clone()).await.unwrap();
+        let second = CredentialStore::load(path.clone()).await.unwrap();
+
+        first
+            .add("acct-1".into(), test_credential("1"))
+            .await
+            .unwrap();
+        second
+            .add("acct-2".into(), test_credential("2"))
+            .await
+            .unwrap();
+        assert_eq!(second.len().await, 2);
+
+        let reloaded = CredentialStore::load(path).await.unwrap();
+        let mut ids = reloaded.account_ids().await;
+        ids.sort();
+        assert_eq!(ids, vec!["acct-1", "acct-2"]);
+
+        // Without re-reading, a blind save would overwrite acct-2
+        let err = first.save().await.unwrap_err();
+        assert!(matches!(err, Error::Conflict(_)), "{err}");
+    }
+
+    #[tokio::test]
+    async fn reload_picks_up_external_edits() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("credentials.json");
+        let store = CredentialStore::load(path.clone()).await.unwrap();
+        store
+            .add("acct-1".into(), test_credential("1"))
+            .await
+            .unwrap();
+        // The store's own writes are not reported
+        assert!(store.reload().await.unwrap().is_empty());
+
+        // An operator replaces acct-1 with acct-2 by hand
+        let edited = HashMap::from([("acct-2".to_string(), test_credential("2"))]);
+        std::fs::write(&path, serde_json::to_vec(&edited).unwrap()).unwrap();
+        let changes = store.reload().await.unwrap();
+        assert_eq!(changes.added, vec!["acct-2"]);
+        assert_eq!(changes.removed, vec!["acct-1"]);
+        assert_eq!(store.get("acct-2").await.unwrap().access, "at_2");
+        assert!(store.reload().await.unwrap().is_empty());
+
+        // A half-written file is rejected and the store keeps its accounts
+        std::fs::write(&path, b"{\"acct-3\": {").unwrap();
+        assert!(store.reload().await.is_err());
+        assert_eq!(store.account_ids().await, vec!["acct-2"]);
+    }
+
+    #[tokio::test]
+    async fn failed_refresh_leaves_the_credential() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = CredentialStore::load(dir.path().join("credentials.json"))
+            .await
+            .unwrap();
+        store
+            .add("acct-1".into(), test_credential("1"))
+            .await
+            .unwrap();
+
+        let err = store
+            .refresh("acct-1", "rt_1", |_| async {
+                Err(Error::InvalidCredentials("revoked".into()))
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidCredentials(_)), "{err}");
+        assert_eq!(store.get("acct-1").await.unwrap().refresh, "rt_1");
+        // The lock was released
+        store
+            .add("acct-2".into(), test_credential("2"))
+            .await
+            .unwrap();
+    }
+
     #[tokio::test]
     async fn concurrent_writes_dont_corrupt() {
         let dir = tempfile::tempdir().unwrap();
@@ -0,0 +1,329 @@
+//! Envelope encryption for the credential file
+//!
+//! Each write generates a fresh random data key, encrypts the credential JSON
+//! with it (AES-256-GCM), and stores the data key wrapped by the key
+//! encryption key. The key encryption key is either a 32-byte key (from an
+//! env var or a mounted file) or derived from a passphrase with
+//! PBKDF2-HMAC-SHA256 and a salt stored in the file. Deriving a passphrase
+//! key is deliberately slow, so the derived key is cached and its salt reused
+//! for every write in the process; the per-write data key keeps ciphertexts
+//! distinct. Rotating the key rewrites the file under the new key; nothing
+//! else in the file format changes.
+//!
+//! An encrypted file is a JSON object whose only field, `encrypted`, holds
+//! the envelope. Anything else is read as a plaintext credential map, which
+//! is how existing files are migrated.
+
+use std::sync::{Arc, Mutex};
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use rand::RngExt;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::error::{Error, Result};
+
+/// Envelope format version written by this library.
+const VERSION: u32 = 1;
+
+/// PBKDF2 rounds for passphrase-derived keys (OWASP 2023 guidance for
+/// HMAC-SHA256). The count is stored in each envelope, so it can change
+/// without breaking existing files; unit tests use fewer to stay fast.
+const PBKDF2_ROUNDS: u32 = if cfg!(test) { 1_000 } else { 600_000 };
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const SALT_LEN: usize = 16;
+
+/// Where the key encryption key comes from.
+#[derive(Clone)]
+enum KeySource {
+    Key([u8; KEY_LEN]),
+    Passphrase {
+        passphrase: String,
+        /// Last derived key, shared by clones
+        derived: Arc<Mutex<Option<DerivedKey>>>,
+    },
+}
+
+/// A passphrase-derived key and the parameters it was derived with.
+#[derive(Clone)]
+struct DerivedKey {
+    salt: Vec<u8>,
+    rounds: u32,
+    kek: [u8; KEY_LEN],
+}
+
+/// Key material for encrypting and decrypting credential files.
+#[derive(Clone)]
+pub struct Encryption {
+    source: KeySource,
+}
+
+impl std::fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self.source {
+            KeySource::Key(_) => "key",
+            KeySource::Passphrase { .. } => "passphrase",
+        };
+        f.debug_struct("Encryption")
+            .field("source", &kind)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Wrapper marking an encrypted credential file.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct EncryptedFile {
+    pub(crate) encrypted: Envelope,
+}
+
+/// Encrypted credential file contents.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Envelope {
+    version: u32,
+    /// `"key"` or `"pbkdf2-sha256"`
+    kdf: String,
+    /// PBKDF2 salt (base64, passphrase keys only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    salt: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    iterations: Option<u32>,
+    /// Data key encrypted under the key encryption key (base64 nonce || ciphertext)
+    wrapped_key: String,
+    /// Credential JSON encrypted under the data key (base64 nonce || ciphertext)
+    ciphertext: String,
+}
+
+impl Encryption {
+    /// Use a raw 32-byte key.
+    pub fn from_key(key: [u8; KEY_LEN]) -> Self {
+        Self {
+            source: KeySource::Key(key),
+        }
+    }
+
+    /// Use a base64-encoded 32-byte key, as stored in an env var or key file
+    /// (surrounding whitespace is ignored).
+    pub fn from_base64_key(encoded: &str) -> Result<Self> {
+        let bytes = STANDARD
+            .decode(encoded.trim())
+            .map_err(|e| Error::Encryption(format!("key is not valid base64: {e}")))?;
+        let key: [u8; KEY_LEN] = bytes.try_into().map_err(|bytes: Vec<u8>| {
+            Error::Encryption(format!("key must be {KEY_LEN} bytes, got {}", bytes.len()))
+        })?;
+        Ok(Self::from_key(key))
+    }
+
+    /// Derive the key from a passphrase (PBKDF2-HMAC-SHA256).
+    ///
+    /// Derivation takes a noticeable fraction of a second; callers on an
+    /// async runtime should seal and open on the blocking thread pool.
+    pub fn from_passphrase(passphrase: impl Into<String>) -> Result<Self> {
+        let passphrase = passphrase.into();
+        if passphrase.is_empty() {
+            return Err(Error::Encryption("passphrase must not be empty".into()));
+        }
+        Ok(Self {
+            source: KeySource::Passphrase {
+                passphrase,
+                derived: Arc::default(),
+            },
+        })
+    }
+
+    /// Generate a random key, base64-encoded for an env var or key file.
+    pub fn generate_key() -> String {
+        STANDARD.encode(random::<KEY_LEN>())
+    }
+
+    /// Encrypt `plaintext` into a new envelope.
+    pub(crate) fn seal(&self, plaintext: &[u8]) -> Result<Envelope> {
+        let (kdf, salt, iterations, kek) = match self.source {
+            KeySource::Key(key) => ("key", None, None, key),
+            KeySource::Passphrase {
+                ref passphrase,
+                ref derived,
+            } => {
+                let key = derived_key(derived, PBKDF2_ROUNDS, None, passphrase);
+                (
+                    "pbkdf2-sha256",
+                    Some(STANDARD.encode(&key.salt)),
+                    Some(key.rounds),
+                    key.kek,
+                )
+            }
+        };
+        let data_key = random::<KEY_LEN>();
+        Ok(Envelope {
+            version: VERSION,
+            kdf: kdf.to_string(),
+            salt,
+            iterations,
+            wrapped_key: encrypt(&kek, &data_key)?,
+            ciphertext: encrypt(&data_key, plaintext)?,
+        })
+    }
+
+    /// Decrypt an envelope. Fails if this key didn't seal it.
+    pub(crate) fn open(&self, envelope: &Envelope) -> Result<Vec<u8>> {
+        if envelope.version != VERSION {
+            return Err(Error::Encryption(format!(
+                "unsupported envelope version {}",
+                envelope.version
+            )));
+        }
+        let kek = match (&self.source, envelope.kdf.as_str()) {
+            (KeySource::Key(key), "key") => *key,
+            (
+                KeySource::Passphrase {
+                    passphrase,
+                    derived,
+                },
+                "pbkdf2-sha256",
+            ) => {
+                let salt = envelope
+                    .salt
+                    .as_deref()
+                    .ok_or_else(|| Error::Encryption("envelope has no salt".into()))
+                    .and_then(|s| {
+                        STANDARD
+                            .decode(s)
+                            .map_err(|e| Error::Encryption(format!("invalid salt: {e}")))
+                    })?;
+                let rounds = envelope.iterations.unwrap_or(PBKDF2_ROUNDS);
+                derived_key(derived, rounds, Some(salt), passphrase).kek
+            }
+            (_, kdf) => {
+                return Err(Error::Encryption(format!(
+                    "file was encrypted with kdf '{kdf}', which doesn't match the configured key"
+                )));
+            }
+        };
+        let data_key: [u8; KEY_LEN] = decrypt(&kek, &envelope.wrapped_key)?
+            .try_into()
+            .map_err(|_| Error::Encryption("wrapped data key has the wrong length".into()))?;
+        decrypt(&data_key, &envelope.ciphertext)
+    }
+}
+
+fn random<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    rand::rng().fill(&mut bytes);
+    bytes
+}
+
+/// The cached key if it matches `rounds` and `salt`, otherwise a freshly
+/// derived one (under a new random salt if `salt` is `None`), which replaces
+/// the cache.
+fn derived_key(
+    cache: &Mutex<Option<DerivedKey>>,
+    rounds: u32,
+    salt: Option<Vec<u8>>,
+    passphrase: &str,
+) -> DerivedKey {
+    let mut cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(key) = cache.as_ref()
+        && key.rounds == rounds
+        && salt.as_ref().is_none_or(|salt| *salt == key.salt)
+    {
+        return key.clone();
+    }
+    let salt = salt.unwrap_or_else(|| random::<SALT_LEN>().to_vec());
+    let key = DerivedKey {
+        kek: derive(passphrase, &salt, rounds),
+        salt,
+        rounds,
+    };
+    *cache = Some(key.clone());
+    key
+}
+
+fn derive(passphrase: &str, salt: &[u8], rounds: u32) -> [u8; KEY_LEN] {
+    let mut key = [0u8; KEY_LEN];
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, rounds, &mut key);
+    key
+}
+
+/// AES-256-GCM encrypt with a random nonce; returns base64(nonce || ciphertext).
+fn encrypt(key: &[u8; KEY_LEN], plaintext: &[u8]) -> Result<String> {
+    let cipher = Aes256Gcm::new(key.into());
+    let nonce = random::<NONCE_LEN>();
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), plaintext)
+        .map_err(|_| Error::Encryption("encryption failed".into()))?;
+    let mut out = nonce.to_vec();
+    out.extend_from_slice(&ciphertext);
+    Ok(STANDARD.encode(out))
+}
+
+fn decrypt(key: &[u8; KEY_LEN], encoded: &str) -> Result<Vec<u8>> {
+    let bytes = STANDARD
+        .decode(encoded)
+        .map_err(|e| Error::Encryption(format!("invalid ciphertext encoding: {e}")))?;
+    if bytes.len() < NONCE_LEN {
+        return Err(Error::Encryption("ciphertext is truncated".into()));
+    }
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+    Aes256Gcm::new(key.into())
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| Error::Encryption("decryption failed (wrong key or corrupted file)".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_roundtrip() {
+        let encryption = Encryption::from_base64_key(&Encryption::generate_key()).unwrap();
+        let envelope = encryption.seal(b"{\"a\":1}").unwrap();
+        assert_eq!(envelope.kdf, "key");
+        assert_eq!(encryption.open(&envelope).unwrap(), b"{\"a\":1}");
+    }
+
+    #[test]
+    fn passphrase_roundtrip_reuses_derived_key() {
+        let encryption = Encryption::from_passphrase("correct horse").unwrap();
+        let first = encryption.seal(b"secret").unwrap();
+        let second = encryption.seal(b"secret").unwrap();
+        assert_eq!(first.salt, second.salt);
+        assert_ne!(first.wrapped_key, second.wrapped_key);
+        assert_ne!(first.ciphertext, second.ciphertext);
+        assert_eq!(encryption.open(&second).unwrap(), b"secret");
+
+        // Another process derives its own salt; opening its file adopts it
+        let other = Encryption::from_passphrase("correct horse").unwrap();
+        let theirs = other.seal(b"theirs").unwrap();
+        assert_ne!(theirs.salt, first.salt);
+        assert_eq!(encryption.open(&theirs).unwrap(), b"theirs");
+        assert_eq!(encryption.seal(b"secret").unwrap().salt, theirs.salt);
+        assert_eq!(encryption.open(&first).unwrap(), b"secret");
+    }
+
+    #[test]
+    fn wrong_key_fails() {
+        let envelope = Encryption::from_key([1; KEY_LEN]).seal(b"secret").unwrap();
+        let err = Encryption::from_key([2; KEY_LEN])
+            .open(&envelope)
+            .unwrap_err();
+        assert!(err.to_string().contains("wrong key"), "{err}");
+        let err = Encryption::from_passphrase("p")
+            .unwrap()
+            .open(&envelope)
+            .unwrap_err();
+        assert!(err.to_string().contains("kdf 'key'"), "{err}");
+    }
+
+    #[test]
+    fn rejects_malformed_keys() {
+        assert!(Encryption::from_base64_key("not base64!").is_err());
+        assert!(Encryption::from_base64_key(&STANDARD.encode([0u8; 16])).is_err());
+        assert!(Encryption::from_passphrase("").is_err());
+        let debug = format!("{:?}", Encryption::from_passphrase("hunter2").unwrap());
+        assert!(!debug.contains("hunter2"));
+    }
+}
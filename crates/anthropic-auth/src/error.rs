@@ -20,6 +20,12 @@ pub enum Error {
 
     #[error("not found: {0}")]
     NotFound(String),
+
+    #[error("credential encryption error: {0}")]
+    Encryption(String),
+
+    #[error("credential storage conflict: {0}")]
+    Conflict(String),
 }
 
 /// Result alias for auth operations.
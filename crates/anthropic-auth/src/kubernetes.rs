@@ -0,0 +1,725 @@
+//! Kubernetes Secret credential backend
+//!
+//! Stores the credential document under one key of a Secret, read and
+//! written through the API server:
+//!
+//! - read: `GET /api/v1/namespaces/{ns}/secrets/{name}`; a missing Secret or
+//!   key reads as empty. The Secret's `resourceVersion` is the version.
+//! - write: JSON merge `PATCH` of `data.{key}` carrying the expected
+//!   `resourceVersion`, leaving other keys alone; the API server answers 409
+//!   if the Secret changed. A missing Secret is created with `POST`.
+//! - lock: a `coordination.k8s.io/v1` Lease named `{name}-lock`, taken when
+//!   free or expired (`renewTime` + `leaseDurationSeconds` in the past) and
+//!   deleted on unlock
+//!
+//! In a pod, `in_cluster` uses the service account's token, CA, and
+//! namespace (the account needs `get`, `create`, and `patch` on Secrets and
+//! `get`, `create`, `update`, and `delete` on Leases). `new` takes any base
+//! URL without authentication, so `kubectl proxy` or a stand-in serving
+//! those requests works in development and tests.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::error::{Error, Result};
+use crate::storage::{
+    BackendFuture, CredentialBackend, LEASE_DURATION, Versioned, acquire, holder_identity,
+};
+
+/// Service account files mounted into every pod.
+const SERVICE_ACCOUNT_DIR: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+
+/// Upper bound for one API server request, so a hung API server fails the
+/// operation instead of stalling it.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Secret key holding the document unless configured otherwise.
+pub const DEFAULT_SECRET_KEY: &str = "credentials.json";
+
+/// Secret fields the backend reads.
+#[derive(Deserialize)]
+struct Secret {
+    metadata: ObjectMeta,
+    #[serde(default)]
+    data: std::collections::HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ObjectMeta {
+    #[serde(default)]
+    resource_version: String,
+}
+
+/// Credentials in a Kubernetes Secret.
+pub struct KubernetesSecretBackend {
+    client: reqwest::Client,
+    /// API server base URL (e.g. `https://kubernetes.default.svc`)
+    api_url: String,
+    namespace: String,
+    secret: String,
+    key: String,
+    /// Bearer token file, re-read per request (projected tokens rotate)
+    token_file: Option<PathBuf>,
+    /// This instance's identity in the lock Lease
+    holder: String,
+}
+
+impl KubernetesSecretBackend {
+    /// Use the API server at `api_url` without authentication.
+    pub fn new(
+        api_url: impl Into<String>,
+        namespace: impl Into<String>,
+        secret: impl Into<String>,
+    ) -> Result<Self> {
+        Ok(Self {
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .map_err(|e| Error::Http(format!("building Kubernetes client: {e}")))?,
+            api_url: api_url.into().trim_end_matches('/').to_string(),
+            namespace: namespace.into(),
+            secret: secret.into(),
+            key: DEFAULT_SECRET_KEY.to_string(),
+            token_file: None,
+            holder: holder_identity(),
+        })
+    }
+
+    /// Use the API server and service account of the pod this runs in.
+    /// `namespace` defaults to the pod's own.
+    pub fn in_cluster(namespace: Option<String>, secret: impl Into<String>) -> Result<Self> {
+        let host = std::env::var("KUBERNETES_SERVICE_HOST").map_err(|_| {
+            Error::Io("KUBERNETES_SERVICE_HOST is not set (not running in a pod?)".into())
+        })?;
+        let port = std::env::var("KUBERNETES_SERVICE_PORT").unwrap_or_else(|_| "443".into());
+        let host = if host.contains(':') {
+            format!("[{host}]")
+        } else {
+            host
+        };
+        let dir = Path::new(SERVICE_ACCOUNT_DIR);
+        let namespace = match namespace {
+            Some(namespace) => namespace,
+            None => read_trimmed(&dir.join("namespace"))?,
+        };
+        Self::new(format!("https://{host}:{port}"), namespace, secret)?
+            .with_token_file(dir.join("token"))
+            .with_ca_file(&dir.join("ca.crt"))
+    }
+
+    /// Store the document under `key` instead of `credentials.json`.
+    pub fn with_key(mut self, key: impl Into<String>) -> Self {
+        self.key = key.into();
+        self
+    }
+
+    /// Authenticate with the bearer token in `path`.
+    pub fn with_token_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.token_file = Some(path.into());
+        self
+    }
+
+    /// Trust the PEM CA bundle in `path` for the API server's certificate.
+    pub fn with_ca_file(mut self, path: &Path) -> Result<Self> {
+        let pem = std::fs::read(path)
+            .map_err(|e| Error::Io(format!("reading CA file {}: {e}", path.display())))?;
+        let certificate = reqwest::Certificate::from_pem(&pem)
+            .map_err(|e| Error::Io(format!("parsing CA file {}: {e}", path.display())))?;
+        self.client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .add_root_certificate(certificate)
+            .build()
+            .map_err(|e| Error::Http(format!("building Kubernetes client: {e}")))?;
+        Ok(self)
+    }
+
+    fn secrets_url(&self) -> String {
+        format!(
+            "{}/api/v1/namespaces/{}/secrets",
+            self.api_url, self.namespace
+        )
+    }
+
+    fn secret_url(&self) -> String {
+        format!("{}/{}", self.secrets_url(), self.secret)
+    }
+
+    /// The Lease serializing writers: `<secret>-lock`.
+    fn lease_name(&self) -> String {
+        format!("{}-lock", self.secret)
+    }
+
+    fn leases_url(&self) -> String {
+        format!(
+            "{}/apis/coordination.k8s.io/v1/namespaces/{}/leases",
+            self.api_url, self.namespace
+        )
+    }
+
+    fn lease_url(&self) -> String {
+        format!("{}/{}", self.leases_url(), self.lease_name())
+    }
+
+    /// Attach the service account token, if configured.
+    fn authorize(&self, request: reqwest::RequestBuilder) -> Result<reqwest::RequestBuilder> {
+        match self.token_file {
+            Some(ref path) => Ok(request.bearer_auth(read_trimmed(path)?)),
+            None => Ok(request),
+        }
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        self.authorize(request)?
+            .send()
+            .await
+            .map_err(|e| Error::Http(format!("Kubernetes API request failed: {e}")))
+    }
+}
+
+impl CredentialBackend for KubernetesSecretBackend {
+    fn location(&self) -> String {
+        format!("secret/{}/{}", self.namespace, self.secret)
+    }
+
+    fn read(&self) -> BackendFuture<'_, Option<Versioned>> {
+        Box::pin(async move {
+            let Some(secret) = self.get_secret().await? else {
+                return Ok(None);
+            };
+            secret
+                .data
+                .get(&self.key)
+                .map(|encoded| {
+                    let data = STANDARD.decode(encoded).map_err(|e| {
+                        Error::CredentialParse(format!(
+                            "Secret key {} is not base64: {e}",
+                            self.key
+                        ))
+                    })?;
+                    Ok(Versioned {
+                        data,
+                        version: secret.metadata.resource_version.clone(),
+                    })
+                })
+                .transpose()
+        })
+    }
+
+    fn write<'a>(&'a self, data: &'a [u8], expected: Option<&'a str>) -> BackendFuture<'a, String> {
+        Box::pin(async move {
+            let encoded = STANDARD.encode(data);
+            let version = match expected {
+                Some(version) => self.patch_secret(&encoded, version).await?,
+                None => self.create_secret(&encoded).await?,
+            };
+            debug!(secret = %self.location(), version, "persisted credentials");
+            Ok(version)
+        })
+    }
+
+    fn lock(&self) -> BackendFuture<'_, ()> {
+        Box::pin(async move {
+            let location = self.location();
+            acquire(&location, || self.try_take_lease()).await
+        })
+    }
+
+    fn unlock(&self) -> BackendFuture<'_, ()> {
+        Box::pin(async move {
+            let response = self.send(self.client.get(self.lease_url())).await?;
+            if response.status() == StatusCode::NOT_FOUND {
+                return Ok(());
+            }
+            let lease: serde_json::Value = check(response, "reading lock Lease for")
+                .await?
+                .json()
+                .await
+                .map_err(|e| Error::CredentialParse(format!("parsing Lease: {e}")))?;
+            if lease["spec"]["holderIdentity"].as_str() != Some(self.holder.as_str()) {
+                return Ok(());
+            }
+            // Only delete the Lease as we last saw it; a newer holder keeps it
+            let options = serde_json::json!({
+                "apiVersion": "v1",
+                "kind": "DeleteOptions",
+                "preconditions": { "resourceVersion": lease["metadata"]["resourceVersion"] },
+            });
+            let response = self
+                .send(self.client.delete(self.lease_url()).json(&options))
+                .await?;
+            match response.status() {
+                StatusCode::NOT_FOUND | StatusCode::CONFLICT => Ok(()),
+                _ => check(response, "releasing lock Lease for")
+                    .await
+                    .map(|_| ()),
+            }
+        })
+    }
+}
+
+impl KubernetesSecretBackend {
+    async fn get_secret(&self) -> Result<Option<Secret>> {
+        let response = self.send(self.client.get(self.secret_url())).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        check(response, "reading")
+            .await?
+            .json()
+            .await
+            .map(Some)
+            .map_err(|e| Error::CredentialParse(format!("parsing Secret: {e}")))
+    }
+
+    /// Set `data.{key}` if the Secret is still at `version`. Returns the
+    /// new version.
+    async fn patch_secret(&self, encoded: &str, version: &str) -> Result<String> {
+        let patch = serde_json::json!({
+            "metadata": { "resourceVersion": version },
+            "data": { &self.key: encoded },
+        });
+        let response = self
+            .send(
+                self.client
+                    .patch(self.secret_url())
+                    .header(
+                        reqwest::header::CONTENT_TYPE,
+                        "application/merge-patch+json",
+                    )
+                    .body(patch.to_string()),
+            )
+            .await?;
+        if matches!(
+            response.status(),
+            StatusCode::CONFLICT | StatusCode::NOT_FOUND
+        ) {
+            return Err(Error::Conflict(format!(
+                "{} changed since it was read",
+                self.location()
+            )));
+        }
+        resource_version(check(response, "writing").await?).await
+    }
+
+    /// Store the first document: create the Secret, or add the key to an
+    /// existing Secret that lacks it. Returns the new version.
+    async fn create_secret(&self, encoded: &str) -> Result<String> {
+        let secret = serde_json::json!({
+            "apiVersion": "v1",
+            "kind": "Secret",
+            "metadata": { "name": &self.secret },
+            "type": "Opaque",
+            "data": { &self.key: encoded },
+        });
+        let response = self
+            .send(self.client.post(self.secrets_url()).json(&secret))
+            .await?;
+        if response.status() != StatusCode::CONFLICT {
+            return resource_version(check(response, "creating").await?).await;
+        }
+        match self.get_secret().await? {
+            Some(existing) if !existing.data.contains_key(&self.key) => {
+                self.patch_secret(encoded, &existing.metadata.resource_version)
+                    .await
+            }
+            _ => Err(Error::Conflict(format!(
+                "{} was written by another process",
+                self.location()
+            ))),
+        }
+    }
+
+    /// One attempt at the lock Lease: create it, or take it over if it is
+    /// free, expired, or already ours. `false` while someone else holds it.
+    async fn try_take_lease(&self) -> Result<bool> {
+        let now = SystemTime::now();
+        let spec = serde_json::json!({
+            "holderIdentity": &self.holder,
+            "leaseDurationSeconds": LEASE_DURATION.as_secs(),
+            "acquireTime": humantime::format_rfc3339_micros(now).to_string(),
+            "renewTime": humantime::format_rfc3339_micros(now).to_string(),
+        });
+
+        let response = self.send(self.client.get(self.lease_url())).await?;
+        let response = if response.status() == StatusCode::NOT_FOUND {
+            let lease = serde_json::json!({
+                "apiVersion": "coordination.k8s.io/v1",
+                "kind": "Lease",
+                "metadata": { "name": self.lease_name() },
+                "spec": spec,
+            });
+            self.send(self.client.post(self.leases_url()).json(&lease))
+                .await?
+        } else {
+            let mut lease: serde_json::Value = check(response, "reading lock Lease for")
+                .await?
+                .json()
+                .await
+                .map_err(|e| Error::CredentialParse(format!("parsing Lease: {e}")))?;
+            if !lease_available(&lease["spec"], &self.holder, now) {
+                return Ok(false);
+            }
+            // The PUT carries the resourceVersion we read, so only one
+            // contender can take an expired lease
+            lease["spec"] = spec;
+            self.send(self.client.put(self.lease_url()).json(&lease))
+                .await?
+        };
+        if response.status() == StatusCode::CONFLICT {
+            return Ok(false);
+        }
+        check(response, "taking lock Lease for").await?;
+        Ok(true)
+    }
+}
+
+/// Whether a Lease `spec` is unheld, held by `holder`, or expired at `now`.
+fn lease_available(spec: &serde_json::Value, holder: &str, now: SystemTime) -> bool {
+    let current = spec["holderIdentity"].as_str().unwrap_or_default();
+    if current.is_empty() || current == holder {
+        return true;
+    }
+    let duration = Duration::from_secs(spec["leaseDurationSeconds"].as_u64().unwrap_or(0));
+    match spec["renewTime"]
+        .as_str()
+        .and_then(|t| humantime::parse_rfc3339_weak(t).ok())
+    {
+        // A renewal too far ahead to represent never expires
+        Some(renewed) => renewed.checked_add(duration).is_some_and(|end| end < now),
+        None => true,
+    }
+}
+
+/// `metadata.resourceVersion` of the object in a write response.
+async fn resource_version(response: reqwest::Response) -> Result<String> {
+    let object: serde_json::Value = response
+        .json()
+        .await
+        .map_err(|e| Error::CredentialParse(format!("parsing Secret: {e}")))?;
+    object["metadata"]["resourceVersion"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| Error::CredentialParse("Secret has no resourceVersion".into()))
+}
+
+/// Fail on a non-success status, quoting the API server's message.
+async fn check(response: reqwest::Response, action: &str) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(Error::Http(format!(
+        "{action} credential Secret: HTTP {status}: {body}"
+    )))
+}
+
+fn read_trimmed(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path)
+        .map(|s| s.trim().to_string())
+        .map_err(|e| Error::Io(format!("reading {}: {e}", path.display())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use axum::Router;
+    use axum::extract::{Path as UrlPath, State};
+    use axum::http::{HeaderMap, StatusCode as Status};
+    use axum::routing::{get, post};
+
+    /// Stored objects by `(kind, name)` and the last resourceVersion issued.
+    #[derive(Default)]
+    struct Objects {
+        objects: HashMap<(String, String), serde_json::Value>,
+        version: u64,
+    }
+
+    impl Objects {
+        fn store(&mut self, kind: &str, name: &str, mut object: serde_json::Value) -> String {
+            self.version += 1;
+            object["metadata"]["resourceVersion"] = self.version.to_string().into();
+            let body = object.to_string();
+            self.objects.insert((kind.into(), name.into()), object);
+            body
+        }
+    }
+
+    type Server = Arc<Mutex<Objects>>;
+
+    /// API-server stand-in for Secrets and Leases, with resourceVersion
+    /// preconditions. Requires the bearer token `test-token`.
+    async fn start_api_server() -> (String, Server) {
+        fn authorized(headers: &HeaderMap) -> bool {
+            headers
+                .get("authorization")
+                .is_some_and(|v| v == "Bearer test-token")
+        }
+
+        fn kind(collection: &str) -> &'static str {
+            if collection == "leases" {
+                "Lease"
+            } else {
+                "Secret"
+            }
+        }
+
+        /// Whether `object` carries a resourceVersion other than `current`'s.
+        fn stale(object: &serde_json::Value, current: &serde_json::Value) -> bool {
+            object["metadata"]["resourceVersion"]
+                .as_str()
+                .is_some_and(|v| current["metadata"]["resourceVersion"] != v)
+        }
+
+        async fn read(
+            State(server): State<Server>,
+            UrlPath((collection, name)): UrlPath<(String, String)>,
+            headers: HeaderMap,
+        ) -> (Status, String) {
+            if !authorized(&headers) {
+                return (Status::UNAUTHORIZED, String::new());
+            }
+            let server = server.lock().unwrap();
+            match server.objects.get(&(kind(&collection).into(), name)) {
+                Some(object) => (Status::OK, object.to_string()),
+                None => (Status::NOT_FOUND, "{}".into()),
+            }
+        }
+
+        async fn create(
+            State(server): State<Server>,
+            UrlPath(collection): UrlPath<String>,
+            headers: HeaderMap,
+            body: String,
+        ) -> (Status, String) {
+            if !authorized(&headers) {
+                return (Status::UNAUTHORIZED, String::new());
+            }
+            let object: serde_json::Value = serde_json::from_str(&body).unwrap();
+            let name = object["metadata"]["name"].as_str().unwrap().to_string();
+            let kind = kind(&collection);
+            let mut server = server.lock().unwrap();
+            if server.objects.contains_key(&(kind.into(), name.clone())) {
+                return (Status::CONFLICT, "{}".into());
+            }
+            (Status::CREATED, server.store(kind, &name, object))
+        }
+
+        async fn patch(
+            State(server): State<Server>,
+            UrlPath((_, name)): UrlPath<(String, String)>,
+            headers: HeaderMap,
+            body: String,
+        ) -> (Status, String) {
+            if !authorized(&headers) {
+                return (Status::UNAUTHORIZED, String::new());
+            }
+            assert_eq!(headers["content-type"], "application/merge-patch+json");
+            let patch: serde_json::Value = serde_json::from_str(&body).unwrap();
+            let mut server = server.lock().unwrap();
+            let Some(mut secret) = server
+                .objects
+                .get(&("Secret".into(), name.clone()))
+                .cloned()
+            else {
+                return (Status::NOT_FOUND, "{}".into());
+            };
+            if stale(&patch, &secret) {
+                return (Status::CONFLICT, "{}".into());
+            }
+            for (key, value) in patch["data"].as_object().unwrap() {
+                secret["data"][key] = value.clone();
+            }
+            (Status::OK, server.store("Secret", &name, secret))
+        }
+
+        async fn replace(
+            State(server): State<Server>,
+            UrlPath((collection, name)): UrlPath<(String, String)>,
+            headers: HeaderMap,
+            body: String,
+        ) -> (Status, String) {
+            if !authorized(&headers) {
+                return (Status::UNAUTHORIZED, String::new());
+            }
+            let object: serde_json::Value = serde_json::from_str(&body).unwrap();
+            let kind = kind(&collection);
+            let mut server = server.lock().unwrap();
+            let Some(current) = server.objects.get(&(kind.into(), name.clone())) else {
+                return (Status::NOT_FOUND, "{}".into());
+            };
+            if stale(&object, current) {
+                return (Status::CONFLICT, "{}".into());
+            }
+            (Status::OK, server.store(kind, &name, object))
+        }
+
+        async fn delete(
+            State(server): State<Server>,
+            UrlPath((collection, name)): UrlPath<(String, String)>,
+            headers: HeaderMap,
+            body: String,
+        ) -> Status {
+            if !authorized(&headers) {
+                return Status::UNAUTHORIZED;
+            }
+            let options: serde_json::Value = serde_json::from_str(&body).unwrap_or_default();
+            let key = (kind(&collection).to_string(), name);
+            let mut server = server.lock().unwrap();
+            let Some(current) = server.objects.get(&key) else {
+                return Status::NOT_FOUND;
+            };
+            let precondition = &options["preconditions"]["resourceVersion"];
+            if !precondition.is_null() && current["metadata"]["resourceVersion"] != *precondition {
+                return Status::CONFLICT;
+            }
+            server.objects.remove(&key);
+            Status::OK
+        }
+
+        let server = Server::default();
+        let app = Router::new()
+            .route(
+                "/api/v1/namespaces/proxy/{collection}/{name}",
+                get(read).patch(patch),
+            )
+            .route("/api/v1/namespaces/proxy/{collection}", post(create))
+            .route(
+                "/apis/coordination.k8s.io/v1/namespaces/proxy/{collection}/{name}",
+                get(read).put(replace).delete(delete),
+            )
+            .route(
+                "/apis/coordination.k8s.io/v1/namespaces/proxy/{collection}",
+                post(create),
+            )
+            .with_state(server.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{addr}"), server)
+    }
+
+    fn backend(url: &str, dir: &tempfile::TempDir) -> KubernetesSecretBackend {
+        let token = dir.path().join("token");
+        std::fs::write(&token, "test-token\n").unwrap();
+        KubernetesSecretBackend::new(url, "proxy", "oauth-credentials")
+            .unwrap()
+            .with_token_file(token)
+    }
+
+    #[tokio::test]
+    async fn creates_then_patches_the_secret() {
+        let (url, server) = start_api_server().await;
+        let dir = tempfile::tempdir().unwrap();
+        let backend = backend(&url, &dir);
+
+        assert!(backend.read().await.unwrap().is_none());
+        let first = backend.write(b"{\"a\":1}", None).await.unwrap();
+        let stored = backend.read().await.unwrap().unwrap();
+        assert_eq!(stored.data, b"{\"a\":1}");
+        assert_eq!(stored.version, first);
+
+        // Other keys in the Secret survive a write
+        let key = ("Secret".to_string(), "oauth-credentials".to_string());
+        {
+            let mut server = server.lock().unwrap();
+            let mut secret = server.objects[&key].clone();
+            secret["data"]["other"] = "eA==".into();
+            server.store("Secret", "oauth-credentials", secret);
+        }
+        let err = backend.write(b"{}", Some(&first)).await.unwrap_err();
+        assert!(matches!(err, Error::Conflict(_)), "{err}");
+        let current = backend.read().await.unwrap().unwrap().version;
+        backend.write(b"{\"b\":2}", Some(&current)).await.unwrap();
+        assert_eq!(backend.read().await.unwrap().unwrap().data, b"{\"b\":2}");
+        assert_eq!(
+            server.lock().unwrap().objects[&key]["data"]["other"],
+            "eA=="
+        );
+        assert_eq!(backend.location(), "secret/proxy/oauth-credentials");
+    }
+
+    #[tokio::test]
+    async fn first_write_adds_key_to_existing_secret() {
+        let (url, server) = start_api_server().await;
+        let dir = tempfile::tempdir().unwrap();
+        let backend = backend(&url, &dir);
+        server.lock().unwrap().store(
+            "Secret",
+            "oauth-credentials",
+            serde_json::json!({ "metadata": { "name": "oauth-credentials" }, "data": {} }),
+        );
+
+        assert!(backend.read().await.unwrap().is_none());
+        backend.write(b"{}", None).await.unwrap();
+        assert_eq!(backend.read().await.unwrap().unwrap().data, b"{}");
+        let err = backend.write(b"{}", None).await.unwrap_err();
+        assert!(matches!(err, Error::Conflict(_)), "{err}");
+    }
+
+    #[tokio::test]
+    async fn lease_serializes_writers() {
+        let (url, server) = start_api_server().await;
+        let dir = tempfile::tempdir().unwrap();
+        let first = backend(&url, &dir);
+        let second = backend(&url, &dir);
+
+        first.lock().await.unwrap();
+        let waiter = tokio::spawn(async move {
+            second.lock().await.unwrap();
+            second
+        });
+        tokio::time::sleep(crate::storage::LOCK_POLL * 3).await;
+        assert!(!waiter.is_finished());
+
+        first.unlock().await.unwrap();
+        let second = waiter.await.unwrap();
+        let lease_key = ("Lease".to_string(), "oauth-credentials-lock".to_string());
+        assert_eq!(
+            server.lock().unwrap().objects[&lease_key]["spec"]["holderIdentity"],
+            second.holder.as_str()
+        );
+        second.unlock().await.unwrap();
+        assert!(!server.lock().unwrap().objects.contains_key(&lease_key));
+    }
+
+    #[test]
+    fn expired_lease_is_available() {
+        let now = SystemTime::now();
+        let renewed = |ago: u64| {
+            serde_json::json!({
+                "holderIdentity": "other-pod",
+                "leaseDurationSeconds": 30,
+                "renewTime": humantime::format_rfc3339_micros(now - Duration::from_secs(ago)).to_string(),
+            })
+        };
+        assert!(!lease_available(&renewed(5), "me", now));
+        assert!(lease_available(&renewed(5), "other-pod", now));
+        assert!(lease_available(&renewed(60), "me", now));
+        assert!(lease_available(&serde_json::json!({}), "me", now));
+        let forever = serde_json::json!({
+            "holderIdentity": "other-pod",
+            "leaseDurationSeconds": u64::MAX,
+            "renewTime": humantime::format_rfc3339_micros(now).to_string(),
+        });
+        assert!(!lease_available(&forever, "me", now));
+    }
+
+    #[tokio::test]
+    async fn api_errors_are_reported() {
+        let (url, _) = start_api_server().await;
+        let backend = KubernetesSecretBackend::new(url, "proxy", "oauth-credentials").unwrap();
+        let err = backend.write(b"{}", None).await.unwrap_err();
+        assert!(err.to_string().contains("401"), "{err}");
+    }
+}
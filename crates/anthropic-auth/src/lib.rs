@@ -1,9 +1,9 @@
 //! Anthropic OAuth authentication library
 //!
 //! Provides PKCE flow generation, token exchange/refresh, and credential
-//! file storage for the Anthropic OAuth gateway. This crate is a standalone
-//! library with no dependency on the proxy binary — it can be tested and
-//! used independently.
+//! storage (a file, SQLite, or a Kubernetes Secret) for the Anthropic OAuth
+//! gateway. This crate is a standalone library with no dependency on the
+//! proxy binary — it can be tested and used independently.
 //!
 //! Credential flow:
 //! 1. Admin calls `pkce::generate_verifier()` + `pkce::compute_challenge()`
@@ -12,15 +12,28 @@
 //! 4. Credential stored via `credentials::CredentialStore::add()`
 //! 5. Background task calls `token::refresh_token()` proactively
 //! 6. Updated tokens saved via `credentials::CredentialStore::update_token()`
+//!
+//! Accounts already logged in with the Claude CLI can skip steps 1-3:
+//! `claude_cli::parse_claude_cli_credentials()` converts its credential file.
 
+pub mod claude_cli;
 pub mod constants;
 pub mod credentials;
+pub mod encryption;
 pub mod error;
+pub mod kubernetes;
 pub mod pkce;
+pub mod sqlite;
+pub mod storage;
 pub mod token;
 
+pub use claude_cli::parse_claude_cli_credentials;
 pub use constants::*;
-pub use credentials::{Credential, CredentialStore};
+pub use credentials::{Credential, CredentialStore, RefreshOutcome, Reloaded};
+pub use encryption::Encryption;
 pub use error::{Error, Result};
+pub use kubernetes::KubernetesSecretBackend;
 pub use pkce::{build_authorization_url, compute_challenge, generate_verifier};
+pub use sqlite::SqliteBackend;
+pub use storage::{CredentialBackend, FileBackend, Versioned};
 pub use token::{TokenResponse, exchange_code, refresh_token};
@@ -0,0 +1,304 @@
+//! SQLite credential backend
+//!
+//! Stores the credential document in a single-row table, and the write lock
+//! as a lease row:
+//!
+//! ```sql
+//! CREATE TABLE credentials (
+//!     id         INTEGER PRIMARY KEY CHECK (id = 1),
+//!     document   BLOB NOT NULL,
+//!     updated_at INTEGER NOT NULL,  -- unix milliseconds
+//!     version    INTEGER NOT NULL   -- bumped by every write
+//! );
+//! CREATE TABLE locks (
+//!     name       TEXT PRIMARY KEY,
+//!     holder     TEXT NOT NULL,
+//!     expires_at INTEGER NOT NULL   -- unix milliseconds
+//! );
+//! ```
+//!
+//! The database runs in WAL mode with a busy timeout, so other processes
+//! (other replicas, backups, an operator's `sqlite3` shell) can read it
+//! while the proxy writes. Queries run on the blocking thread pool.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rusqlite::{Connection, OptionalExtension};
+use tracing::debug;
+
+use crate::error::{Error, Result};
+use crate::storage::{
+    BackendFuture, CredentialBackend, LEASE_DURATION, Versioned, acquire, holder_identity,
+};
+
+/// How long a write waits for another connection's lock.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Name of the credential write lock in the `locks` table.
+const LOCK_NAME: &str = "credentials";
+
+/// Credentials in a SQLite database.
+pub struct SqliteBackend {
+    path: PathBuf,
+    connection: Arc<Mutex<Connection>>,
+    /// This instance's name in the `locks` table
+    holder: String,
+}
+
+impl SqliteBackend {
+    /// Open (or create) the database at `path` and its tables.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let connection = Connection::open(&path).map_err(|e| sqlite_error("opening", e))?;
+        connection
+            .busy_timeout(BUSY_TIMEOUT)
+            .map_err(|e| sqlite_error("configuring", e))?;
+        connection
+            .pragma_update(None, "journal_mode", "WAL")
+            .map_err(|e| sqlite_error("configuring", e))?;
+        connection
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS credentials (
+                    id         INTEGER PRIMARY KEY CHECK (id = 1),
+                    document   BLOB NOT NULL,
+                    updated_at INTEGER NOT NULL,
+                    version    INTEGER NOT NULL
+                );
+                CREATE TABLE IF NOT EXISTS locks (
+                    name       TEXT PRIMARY KEY,
+                    holder     TEXT NOT NULL,
+                    expires_at INTEGER NOT NULL
+                )",
+            )
+            .map_err(|e| sqlite_error("creating tables in", e))?;
+        restrict_permissions(&path)?;
+        Ok(Self {
+            path,
+            connection: Arc::new(Mutex::new(connection)),
+            holder: holder_identity(),
+        })
+    }
+
+    /// Path of the database file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Run `f` with the connection on the blocking thread pool.
+    async fn with_connection<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> Result<T> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || {
+            let connection = connection
+                .lock()
+                .map_err(|_| Error::Io("sqlite connection lock poisoned".into()))?;
+            f(&connection)
+        })
+        .await
+        .map_err(|e| Error::Io(format!("sqlite task failed: {e}")))?
+    }
+}
+
+impl CredentialBackend for SqliteBackend {
+    fn location(&self) -> String {
+        format!("sqlite:{}", self.path.display())
+    }
+
+    fn read(&self) -> BackendFuture<'_, Option<Versioned>> {
+        Box::pin(self.with_connection(|connection| {
+            connection
+                .query_row(
+                    "SELECT document, version FROM credentials WHERE id = 1",
+                    [],
+                    |row| {
+                        Ok(Versioned {
+                            data: row.get(0)?,
+                            version: row.get::<_, i64>(1)?.to_string(),
+                        })
+                    },
+                )
+                .optional()
+                .map_err(|e| sqlite_error("reading", e))
+        }))
+    }
+
+    fn write<'a>(&'a self, data: &'a [u8], expected: Option<&'a str>) -> BackendFuture<'a, String> {
+        let data = data.to_vec();
+        let expected = expected.map(|v| v.parse::<i64>());
+        Box::pin(async move {
+            let version = self
+                .with_connection(move |connection| {
+                    let (changed, version) = match expected {
+                        None => {
+                            let changed = connection
+                                .execute(
+                                    "INSERT INTO credentials (id, document, updated_at, version)
+                                     VALUES (1, ?1, ?2, 1)
+                                     ON CONFLICT (id) DO NOTHING",
+                                    rusqlite::params![data, now_ms()],
+                                )
+                                .map_err(|e| sqlite_error("writing", e))?;
+                            (changed, 1)
+                        }
+                        Some(Ok(expected)) => {
+                            let changed = connection
+                                .execute(
+                                    "UPDATE credentials
+                                     SET document = ?1, updated_at = ?2, version = version + 1
+                                     WHERE id = 1 AND version = ?3",
+                                    rusqlite::params![data, now_ms(), expected],
+                                )
+                                .map_err(|e| sqlite_error("writing", e))?;
+                            (changed, expected + 1)
+                        }
+                        Some(Err(_)) => (0, 0),
+                    };
+                    if changed == 0 {
+                        return Err(Error::Conflict(
+                            "credential database changed since it was read".into(),
+                        ));
+                    }
+                    Ok(version.to_string())
+                })
+                .await?;
+            debug!(path = %self.path.display(), version, "persisted credentials");
+            Ok(version)
+        })
+    }
+
+    fn lock(&self) -> BackendFuture<'_, ()> {
+        Box::pin(async move {
+            let location = self.location();
+            acquire(&location, || {
+                let holder = self.holder.clone();
+                self.with_connection(move |connection| {
+                    let now = now_ms();
+                    // Take the lease if it is free, expired, or already ours
+                    let changed = connection
+                        .execute(
+                            "INSERT INTO locks (name, holder, expires_at) VALUES (?1, ?2, ?3)
+                             ON CONFLICT (name) DO UPDATE
+                             SET holder = excluded.holder, expires_at = excluded.expires_at
+                             WHERE locks.expires_at < ?4 OR locks.holder = excluded.holder",
+                            rusqlite::params![
+                                LOCK_NAME,
+                                holder,
+                                now + LEASE_DURATION.as_millis() as i64,
+                                now
+                            ],
+                        )
+                        .map_err(|e| sqlite_error("locking", e))?;
+                    Ok(changed == 1)
+                })
+            })
+            .await
+        })
+    }
+
+    fn unlock(&self) -> BackendFuture<'_, ()> {
+        let holder = self.holder.clone();
+        Box::pin(self.with_connection(move |connection| {
+            connection
+                .execute(
+                    "DELETE FROM locks WHERE name = ?1 AND holder = ?2",
+                    rusqlite::params![LOCK_NAME, holder],
+                )
+                .map_err(|e| sqlite_error("unlocking", e))?;
+            Ok(())
+        }))
+    }
+}
+
+fn sqlite_error(action: &str, e: rusqlite::Error) -> Error {
+    Error::Io(format!("{action} credential database: {e}"))
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+/// The database holds OAuth tokens: owner read/write only (unix only).
+fn restrict_permissions(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+            .map_err(|e| Error::Io(format!("setting credential database permissions: {e}")))?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn write_is_compare_and_swap() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = SqliteBackend::open(dir.path().join("credentials.db")).unwrap();
+        assert!(backend.read().await.unwrap().is_none());
+
+        let first = backend.write(b"{\"a\":1}", None).await.unwrap();
+        let second = backend.write(b"{\"b\":2}", Some(&first)).await.unwrap();
+        let err = backend.write(b"{}", Some(&first)).await.unwrap_err();
+        assert!(matches!(err, Error::Conflict(_)), "{err}");
+        let err = backend.write(b"{}", None).await.unwrap_err();
+        assert!(matches!(err, Error::Conflict(_)), "{err}");
+
+        // A second connection (another process) sees the same row
+        let reopened = SqliteBackend::open(backend.path()).unwrap();
+        let stored = reopened.read().await.unwrap().unwrap();
+        assert_eq!(stored.data, b"{\"b\":2}");
+        assert_eq!(stored.version, second);
+    }
+
+    #[tokio::test]
+    async fn lease_excludes_other_holders_until_released() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("credentials.db");
+        let first = SqliteBackend::open(&path).unwrap();
+        let second = SqliteBackend::open(&path).unwrap();
+
+        first.lock().await.unwrap();
+        let waiter = tokio::spawn(async move {
+            second.lock().await.unwrap();
+            second
+        });
+        tokio::time::sleep(crate::storage::LOCK_POLL * 3).await;
+        assert!(!waiter.is_finished());
+
+        first.unlock().await.unwrap();
+        let second = waiter.await.unwrap();
+        second.unlock().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn expired_lease_can_be_taken_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("credentials.db");
+        let crashed = SqliteBackend::open(&path).unwrap();
+        crashed.lock().await.unwrap();
+        crashed
+            .with_connection(|connection| {
+                connection
+                    .execute("UPDATE locks SET expires_at = 0", [])
+                    .map_err(|e| sqlite_error("expiring", e))?;
+                Ok(())
+            })
+            .await
+            .unwrap();
+
+        let next = SqliteBackend::open(&path).unwrap();
+        next.lock().await.unwrap();
+    }
+}
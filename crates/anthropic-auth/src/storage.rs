@@ -0,0 +1,300 @@
+//! Credential storage backends
+//!
+//! `CredentialStore` keeps credentials in memory and persists them through a
+//! `CredentialBackend`. A backend stores one opaque document: the credential
+//! JSON, or its encryption envelope. Every write replaces the whole
+//! document, so encryption, plaintext migration, and key rotation work the
+//! same way on every backend.
+//!
+//! Backends:
+//! - `FileBackend`: a JSON file, written atomically (the default)
+//! - `SqliteBackend` (`sqlite`): a row in a SQLite database
+//! - `KubernetesSecretBackend` (`kubernetes`): a key in a Kubernetes Secret,
+//!   so credentials outlive the pod and its volume
+//!
+//! Several processes may share one backend. Each backend provides a
+//! cross-process lock (`flock` on a lock file, a lease row, a Kubernetes
+//! Lease), and writes are compare-and-swap against the version the writer
+//! last read, so a holder whose lease lapsed mid-write fails with
+//! `Error::Conflict` instead of overwriting a newer document.
+
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+use rand::RngExt;
+use sha2::{Digest, Sha256};
+use tracing::debug;
+
+use crate::error::{Error, Result};
+
+/// How long `lock` waits for another holder before giving up.
+pub(crate) const LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often `lock` retries while another process holds the lock.
+pub(crate) const LOCK_POLL: Duration = Duration::from_millis(100);
+
+/// How long a lease outlives a holder that stopped without releasing it.
+/// Longer than a token refresh, the slowest operation done under the lock.
+pub(crate) const LEASE_DURATION: Duration = Duration::from_secs(30);
+
+/// Upper bound on the token request `CredentialStore::refresh` makes under
+/// the lock. Leases aren't renewed, so this stays well under
+/// `LEASE_DURATION`, leaving time to write the new tokens back before
+/// another process could take the lease.
+pub(crate) const REFRESH_TIMEOUT: Duration = if cfg!(test) {
+    Duration::from_millis(200)
+} else {
+    Duration::from_secs(10)
+};
+
+/// Future returned by `CredentialBackend` methods.
+pub type BackendFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+/// A stored document and the version it was read at.
+#[derive(Debug, Clone)]
+pub struct Versioned {
+    pub data: Vec<u8>,
+    /// Opaque; changes whenever the document is written
+    pub version: String,
+}
+
+/// Durable storage for the credential document.
+pub trait CredentialBackend: Send + Sync {
+    /// Where the credentials live, for logs (a path or `secret/<ns>/<name>`).
+    fn location(&self) -> String;
+
+    /// Read the stored document. `None` if nothing has been stored yet.
+    fn read(&self) -> BackendFuture<'_, Option<Versioned>>;
+
+    /// Replace the stored document if it is still at version `expected`
+    /// (`None`: if nothing is stored). Returns the new version, or
+    /// `Error::Conflict` if another writer got there first.
+    fn write<'a>(&'a self, data: &'a [u8], expected: Option<&'a str>) -> BackendFuture<'a, String>;
+
+    /// Take the cross-process write lock, waiting up to `LOCK_TIMEOUT`.
+    /// Each backend instance holds it at most once; callers serialize.
+    fn lock(&self) -> BackendFuture<'_, ()>;
+
+    /// Release the lock taken by `lock`.
+    fn unlock(&self) -> BackendFuture<'_, ()>;
+}
+
+/// Name identifying this process as a lock holder: the host (pod) name
+/// plus a random suffix, unique per backend instance.
+pub(crate) fn holder_identity() -> String {
+    let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "oauth-proxy".into());
+    format!("{host}-{:08x}", rand::rng().random::<u32>())
+}
+
+/// Retry `attempt` every `LOCK_POLL` until it reports the lock taken.
+pub(crate) async fn acquire<F, Fut>(location: &str, mut attempt: F) -> Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<bool>>,
+{
+    let deadline = Instant::now() + LOCK_TIMEOUT;
+    loop {
+        if attempt().await? {
+            debug!(location, "took credential lock");
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(Error::Conflict(format!(
+                "timed out waiting for the lock on {location}"
+            )));
+        }
+        tokio::time::sleep(LOCK_POLL).await;
+    }
+}
+
+/// Credentials in a local JSON file.
+///
+/// The lock is an exclusive `flock` on `<file>.lock` beside it; the version
+/// is the SHA-256 of the contents.
+pub struct FileBackend {
+    path: PathBuf,
+    lock_file: std::sync::Mutex<Option<std::fs::File>>,
+}
+
+impl FileBackend {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock_file: std::sync::Mutex::new(None),
+        }
+    }
+
+    /// Path of the credential file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn lock_path(&self) -> PathBuf {
+        let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+        name.push(".lock");
+        self.path.with_file_name(name)
+    }
+
+    async fn read_file(&self) -> Result<Option<Versioned>> {
+        match tokio::fs::read(&self.path).await {
+            Ok(data) => Ok(Some(Versioned {
+                version: content_version(&data),
+                data,
+            })),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Error::Io(format!("reading credential file: {e}"))),
+        }
+    }
+}
+
+impl CredentialBackend for FileBackend {
+    fn location(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn read(&self) -> BackendFuture<'_, Option<Versioned>> {
+        Box::pin(self.read_file())
+    }
+
+    /// The check and the rename are only atomic against other writers that
+    /// hold the lock, which `CredentialStore` always does.
+    fn write<'a>(&'a self, data: &'a [u8], expected: Option<&'a str>) -> BackendFuture<'a, String> {
+        Box::pin(async move {
+            let current = self.read_file().await?;
+            if current.as_ref().map(|c| c.version.as_str()) != expected {
+                return Err(Error::Conflict(format!(
+                    "{} changed since it was read",
+                    self.path.display()
+                )));
+            }
+            write_atomic(&self.path, data).await?;
+            Ok(content_version(data))
+        })
+    }
+
+    fn lock(&self) -> BackendFuture<'_, ()> {
+        Box::pin(async move {
+            let path = self.lock_path();
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&path)
+                .map_err(|e| Error::Io(format!("opening {}: {e}", path.display())))?;
+            let location = self.location();
+            acquire(&location, || {
+                let result = match file.try_lock() {
+                    Ok(()) => Ok(true),
+                    Err(std::fs::TryLockError::WouldBlock) => Ok(false),
+                    Err(std::fs::TryLockError::Error(e)) => {
+                        Err(Error::Io(format!("locking {}: {e}", path.display())))
+                    }
+                };
+                std::future::ready(result)
+            })
+            .await?;
+            *self.lock_file.lock().unwrap() = Some(file);
+            Ok(())
+        })
+    }
+
+    fn unlock(&self) -> BackendFuture<'_, ()> {
+        Box::pin(async move {
+            // Closing the file releases the flock
+            drop(self.lock_file.lock().unwrap().take());
+            Ok(())
+        })
+    }
+}
+
+/// SHA-256 of the file contents, hex-encoded.
+fn content_version(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Write a file atomically.
+///
+/// Writes to a temporary file in the same directory, then renames it over
+/// the target. This prevents corruption if the process crashes mid-write.
+/// Sets file permissions to 0600 (owner read/write only) since the file
+/// contains OAuth tokens.
+async fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let dir = path
+        .parent()
+        .ok_or_else(|| Error::Io("credential path has no parent directory".into()))?;
+
+    let tmp_path = dir.join(format!(".credentials.tmp.{}", std::process::id()));
+
+    tokio::fs::write(&tmp_path, data)
+        .await
+        .map_err(|e| Error::Io(format!("writing temp credential file: {e}")))?;
+
+    // Set 0600 permissions (unix only)
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let perms = std::fs::Permissions::from_mode(0o600);
+        tokio::fs::set_permissions(&tmp_path, perms)
+            .await
+            .map_err(|e| Error::Io(format!("setting credential file permissions: {e}")))?;
+    }
+
+    tokio::fs::rename(&tmp_path, path)
+        .await
+        .map_err(|e| Error::Io(format!("renaming temp credential file: {e}")))?;
+
+    debug!(path = %path.display(), "persisted credentials");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn file_write_is_compare_and_swap() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = FileBackend::new(dir.path().join("credentials.json"));
+        assert!(backend.read().await.unwrap().is_none());
+
+        let first = backend.write(b"{}", None).await.unwrap();
+        let err = backend.write(b"{\"a\":1}", None).await.unwrap_err();
+        assert!(matches!(err, Error::Conflict(_)), "{err}");
+
+        let second = backend.write(b"{\"a\":1}", Some(&first)).await.unwrap();
+        assert_ne!(first, second);
+        let err = backend.write(b"{}", Some(&first)).await.unwrap_err();
+        assert!(matches!(err, Error::Conflict(_)), "{err}");
+        assert_eq!(backend.read().await.unwrap().unwrap().version, second);
+    }
+
+    #[tokio::test]
+    async fn file_lock_excludes_other_processes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("credentials.json");
+        let first = FileBackend::new(&path);
+        let second = FileBackend::new(&path);
+
+        first.lock().await.unwrap();
+        // A separate open of the lock file stands in for another process
+        let other = std::fs::File::open(first.lock_path()).unwrap();
+        assert!(matches!(
+            other.try_lock(),
+            Err(std::fs::TryLockError::WouldBlock)
+        ));
+
+        let waiter = tokio::spawn(async move {
+            second.lock().await.unwrap();
+            second.unlock().await.unwrap();
+        });
+        tokio::time::sleep(LOCK_POLL * 2).await;
+        assert!(!waiter.is_finished());
+        first.unlock().await.unwrap();
+        waiter.await.unwrap();
+    }
+}
@@ -0,0 +1,315 @@
+//! Pool of plain Anthropic API keys
+//!
+//! Rotates requests across `x-api-key` credentials with the same status
+//! machine as the OAuth pool: a key that is rate limited cools down until the
+//! reset time its rate-limit headers report (or a fixed duration), and a key
+//! the upstream rejects is disabled. API keys have no token lifecycle, so
+//! there is no refresh, probing, or persistence: keys come from config and
+//! start Available on every run.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+
+use provider::ErrorClassification;
+use tokio::sync::RwLock;
+use tracing::{debug, info, warn};
+
+use crate::error::{Error, Result};
+use crate::pool::{AccountStatus, SelectedAccount};
+use crate::ratelimit::RateLimitSnapshot;
+
+/// An API key and the ID it is reported under in logs, metrics, and health.
+#[derive(Clone)]
+pub struct ApiKey {
+    pub id: String,
+    pub key: String,
+}
+
+impl std::fmt::Debug for ApiKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ApiKey")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Round-robin pool of API keys with cooldown and disable tracking.
+pub struct ApiKeyPool {
+    keys: Vec<ApiKey>,
+    statuses: RwLock<HashMap<String, AccountStatus>>,
+    rate_limits: RwLock<HashMap<String, RateLimitSnapshot>>,
+    next_index: AtomicUsize,
+    cooldown_duration: Duration,
+}
+
+impl ApiKeyPool {
+    /// Create a pool over `keys`. All keys start Available.
+    ///
+    /// `cooldown_duration` applies to rate-limited keys whose responses
+    /// didn't report a reset time.
+    pub fn new(keys: Vec<ApiKey>, cooldown_duration: Duration) -> Self {
+        let statuses = keys
+            .iter()
+            .map(|k| (k.id.clone(), AccountStatus::Available))
+            .collect();
+        info!(keys = keys.len(), "API key pool initialized");
+        Self {
+            keys,
+            statuses: RwLock::new(statuses),
+            rate_limits: RwLock::new(HashMap::new()),
+            next_index: AtomicUsize::new(0),
+            cooldown_duration,
+        }
+    }
+
+    /// Number of keys in the pool.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Whether the pool has no keys.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Select the next available key in round-robin order.
+    ///
+    /// Expired cooldowns are transitioned to Available automatically. The
+    /// key is returned as `access_token`. Returns `PoolExhausted` with pool
+    /// counts if every key is cooling down or disabled.
+    pub async fn select(&self) -> Result<SelectedAccount> {
+        let n = self.keys.len();
+        let start = self.next_index.fetch_add(1, Ordering::Relaxed);
+        let now = SystemTime::now();
+        for offset in 0..n {
+            let key = &self.keys[(start + offset) % n];
+            let status = self.statuses.read().await.get(&key.id).cloned();
+            let available = match status {
+                Some(AccountStatus::Available) => true,
+                Some(AccountStatus::CoolingDown { until }) if now >= until => {
+                    info!(key_id = %key.id, "cooldown expired, API key available again");
+                    self.statuses
+                        .write()
+                        .await
+                        .insert(key.id.clone(), AccountStatus::Available);
+                    true
+                }
+                _ => false,
+            };
+            if available {
+                return Ok(SelectedAccount {
+                    id: key.id.clone(),
+                    access_token: key.key.clone(),
+                });
+            }
+        }
+        Err(Error::PoolExhausted(self.exhausted_message().await))
+    }
+
+    /// Report an error classification for a key, triggering state transitions.
+    ///
+    /// - QuotaExceeded → CoolingDown until the reset time from the key's
+    ///   latest rate-limit headers, or for cooldown_duration if none is known
+    /// - Permanent → Disabled
+    /// - Transient → no change
+    pub async fn report_error(&self, key_id: &str, classification: ErrorClassification) {
+        let status = match classification {
+            ErrorClassification::QuotaExceeded => {
+                let now = SystemTime::now();
+                let cooldown = self
+                    .rate_limits
+                    .read()
+                    .await
+                    .get(key_id)
+                    .and_then(|snapshot| snapshot.reset_at(now))
+                    .and_then(|reset| reset.duration_since(now).ok())
+                    .unwrap_or(self.cooldown_duration);
+                info!(
+                    key_id,
+                    cooldown_secs = cooldown.as_secs(),
+                    "API key entering cooldown (rate limited)"
+                );
+                AccountStatus::CoolingDown {
+                    until: now + cooldown,
+                }
+            }
+            ErrorClassification::Permanent => {
+                warn!(key_id, "API key disabled (rejected by upstream)");
+                AccountStatus::Disabled {
+                    reason: "upstream rejected API key".into(),
+                }
+            }
+            ErrorClassification::Transient => {
+                debug!(key_id, "transient error, no pool action");
+                return;
+            }
+        };
+        if let Some(current) = self.statuses.write().await.get_mut(key_id) {
+            *current = status;
+        }
+    }
+
+    /// Record the rate-limit state reported on an upstream response.
+    pub async fn record_rate_limits(&self, key_id: &str, snapshot: RateLimitSnapshot) {
+        self.rate_limits
+            .write()
+            .await
+            .insert(key_id.to_string(), snapshot);
+    }
+
+    /// Current status of a key, or `None` if the pool doesn't know it.
+    pub async fn status(&self, key_id: &str) -> Option<AccountStatus> {
+        self.statuses.read().await.get(key_id).cloned()
+    }
+
+    /// Pool health summary for the health endpoint, in the same shape as
+    /// `Pool::health` (keys are listed under `accounts`, never their values).
+    pub async fn health(&self) -> serde_json::Value {
+        let statuses = self.statuses.read().await;
+        let now = SystemTime::now();
+        let (mut available, mut cooling, mut disabled) = (0usize, 0usize, 0usize);
+        let accounts: Vec<serde_json::Value> = self
+            .keys
+            .iter()
+            .map(|key| match statuses.get(&key.id) {
+                Some(AccountStatus::CoolingDown { until }) if now < *until => {
+                    cooling += 1;
+                    serde_json::json!({
+                        "id": key.id,
+                        "status": "cooling_down",
+                        "cooldown_remaining_secs": until.duration_since(now).unwrap_or_default().as_secs()
+                    })
+                }
+                Some(AccountStatus::Disabled { reason }) => {
+                    disabled += 1;
+                    serde_json::json!({"id": key.id, "status": "disabled", "reason": reason})
+                }
+                _ => {
+                    available += 1;
+                    serde_json::json!({"id": key.id, "status": "available"})
+                }
+            })
+            .collect();
+
+        let total = self.keys.len();
+        let status = if available == total && total > 0 {
+            "healthy"
+        } else if available > 0 {
+            "degraded"
+        } else {
+            "unhealthy"
+        };
+        serde_json::json!({
+            "status": status,
+            "accounts_total": total,
+            "accounts_available": available,
+            "accounts_cooling_down": cooling,
+            "accounts_disabled": disabled,
+            "accounts": accounts
+        })
+    }
+
+    /// Build the exhausted error message JSON.
+    async fn exhausted_message(&self) -> String {
+        let health = self.health().await;
+        serde_json::json!({
+            "error": {
+                "type": "pool_exhausted",
+                "message": "All API keys exhausted",
+                "pool": {
+                    "accounts_total": health["accounts_total"],
+                    "accounts_available": health["accounts_available"],
+                    "accounts_cooling_down": health["accounts_cooling_down"],
+                    "accounts_disabled": health["accounts_disabled"]
+                }
+            }
+        })
+        .to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(ids: &[&str]) -> ApiKeyPool {
+        ApiKeyPool::new(
+            ids.iter()
+                .map(|id| ApiKey {
+                    id: id.to_string(),
+                    key: format!("sk-ant-{id}"),
+                })
+                .collect(),
+            Duration::from_secs(60),
+        )
+    }
+
+    #[tokio::test]
+    async fn select_rotates_keys() {
+        let pool = pool(&["a", "b"]);
+        let first = pool.select().await.unwrap();
+        let second = pool.select().await.unwrap();
+        assert_ne!(first.id, second.id);
+        assert_eq!(first.access_token, format!("sk-ant-{}", first.id));
+    }
+
+    #[tokio::test]
+    async fn rate_limited_key_cools_down() {
+        let pool = pool(&["a", "b"]);
+        pool.report_error("a", ErrorClassification::QuotaExceeded)
+            .await;
+        for _ in 0..3 {
+            assert_eq!(pool.select().await.unwrap().id, "b");
+        }
+        let health = pool.health().await;
+        assert_eq!(health["status"], "degraded");
+        assert_eq!(health["accounts"][0]["status"], "cooling_down");
+        assert!(
+            health["accounts"][0]["cooldown_remaining_secs"]
+                .as_u64()
+                .unwrap()
+                <= 60
+        );
+    }
+
+    #[tokio::test]
+    async fn cooldown_follows_rate_limit_headers() {
+        let pool = pool(&["a"]);
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("retry-after", "5".parse().unwrap());
+        let snapshot = RateLimitSnapshot::from_headers(&headers, SystemTime::now()).unwrap();
+        pool.record_rate_limits("a", snapshot).await;
+        pool.report_error("a", ErrorClassification::QuotaExceeded)
+            .await;
+        let health = pool.health().await;
+        assert!(
+            health["accounts"][0]["cooldown_remaining_secs"]
+                .as_u64()
+                .unwrap()
+                <= 5
+        );
+    }
+
+    #[tokio::test]
+    async fn rejected_key_is_disabled_until_pool_exhausted() {
+        let pool = pool(&["a"]);
+        pool.report_error("a", ErrorClassification::Permanent).await;
+        assert!(matches!(
+            pool.status("a").await,
+            Some(AccountStatus::Disabled { .. })
+        ));
+        let err = pool.select().await.unwrap_err();
+        assert!(err.to_string().contains("\"accounts_disabled\":1"), "{err}");
+        assert_eq!(pool.health().await["status"], "unhealthy");
+    }
+
+    #[test]
+    fn debug_hides_key() {
+        let key = ApiKey {
+            id: "a".into(),
+            key: "sk-ant-secret".into(),
+        };
+        assert!(!format!("{key:?}").contains("secret"));
+    }
+}
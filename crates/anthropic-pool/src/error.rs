@@ -14,6 +14,9 @@ pub enum Error {
 
     #[error("token refresh failed: {0}")]
     RefreshFailed(String),
+
+    #[error("status persistence error: {0}")]
+    Persistence(String),
 }
 
 /// Result alias for pool operations.
@@ -1,24 +1,45 @@
 //! Subscription pool for Anthropic OAuth accounts
 //!
-//! Manages multiple Claude Max subscription accounts with round-robin selection,
+//! Manages multiple Claude Max subscription accounts with configurable selection,
 //! quota detection, cooldown state machine, and proactive token refresh. The pool
 //! reads credentials from `CredentialStore` (single source of truth) and maintains
 //! per-account status independently.
 //!
 //! Account lifecycle:
 //! 1. Admin adds account via admin API → credential stored, status `Available`
-//! 2. Pool selects account round-robin → check/refresh token, return access token
-//! 3. Upstream returns 429 with quota message → `CoolingDown` for cooldown duration
+//! 2. Pool selects account per strategy (sticky per affinity key) → check/refresh
+//!    token, return access token; accounts near a rate-limit window's end are only
+//!    used when no other is available
+//! 3. Upstream returns 429 with quota message → `CoolingDown` until the reset time
+//!    reported by rate-limit headers (fixed cooldown duration if none is reported)
 //! 4. Upstream returns 401/403 → `Disabled` permanently
 //! 5. Cooldown expires → automatic transition back to `Available`
 //! 6. Background task refreshes tokens proactively before expiration
+//! 7. Background task probes `Disabled` accounts and re-enables those that work again
+//! 8. Background task reloads the credential store and adds or removes accounts
+//!    written there by anyone else (an operator, a sidecar, another replica)
+//!
+//! `ApiKeyPool` applies the same cooldown and disable transitions to plain
+//! `x-api-key` credentials, which have no tokens to refresh.
 
+pub mod api_keys;
 pub mod error;
+pub mod persistence;
 pub mod pool;
+pub mod probe;
 pub mod quota;
+pub mod ratelimit;
 pub mod refresh;
+pub mod reload;
+pub mod strategy;
 
+pub use api_keys::{ApiKey, ApiKeyPool};
 pub use error::{Error, Result};
-pub use pool::{AccountStatus, Pool, SelectedAccount};
+pub use persistence::StatusStore;
+pub use pool::{AccountStatus, DEFAULT_AFFINITY_TTL, DEFAULT_QUOTA_RESERVE, Pool, SelectedAccount};
+pub use probe::{ProbeConfig, spawn_probe_task};
 pub use quota::{classify_429, classify_status};
+pub use ratelimit::{RateLimitSnapshot, RateLimitWindow};
 pub use refresh::spawn_refresh_task;
+pub use reload::spawn_reload_task;
+pub use strategy::SelectionStrategy;
@@ -0,0 +1,257 @@
+//! Account status persistence
+//!
+//! Cooldowns and disables are written to a JSON file next to the credential
+//! file so a restart doesn't send traffic straight back to accounts that were
+//! exhausted or rejected. Cooldown deadlines are stored as unix milliseconds
+//! (wall clock), the same unit the credential file uses for token expiry.
+//!
+//! File format:
+//! ```json
+//! {
+//!   "claude-max-1": { "status": "cooling_down", "until": 1700000000000 },
+//!   "claude-max-2": { "status": "disabled", "reason": "refresh token rejected" }
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, RwLock};
+use tracing::{debug, info, warn};
+
+use crate::error::{Error, Result};
+use crate::pool::AccountStatus;
+
+/// File name of the status file, placed in the credential file's directory.
+const STATUS_FILE_NAME: &str = "pool-state.json";
+
+/// On-disk form of an account status.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum PersistedStatus {
+    Available,
+    CoolingDown {
+        /// Unix timestamp in milliseconds
+        until: u64,
+    },
+    Disabled {
+        #[serde(default)]
+        reason: String,
+    },
+}
+
+impl From<&AccountStatus> for PersistedStatus {
+    fn from(status: &AccountStatus) -> Self {
+        match status {
+            AccountStatus::Available => PersistedStatus::Available,
+            AccountStatus::CoolingDown { until } => PersistedStatus::CoolingDown {
+                until: until
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis() as u64,
+            },
+            AccountStatus::Disabled { reason } => PersistedStatus::Disabled {
+                reason: reason.clone(),
+            },
+        }
+    }
+}
+
+impl From<PersistedStatus> for AccountStatus {
+    fn from(status: PersistedStatus) -> Self {
+        match status {
+            PersistedStatus::Available => AccountStatus::Available,
+            PersistedStatus::CoolingDown { until } => AccountStatus::CoolingDown {
+                until: UNIX_EPOCH + Duration::from_millis(until),
+            },
+            PersistedStatus::Disabled { reason } => AccountStatus::Disabled { reason },
+        }
+    }
+}
+
+/// Status file manager.
+///
+/// Holds the statuses read at startup until the pool takes them, and
+/// serializes writes so concurrent status changes can't interleave.
+pub struct StatusStore {
+    path: PathBuf,
+    restored: HashMap<String, AccountStatus>,
+    write_lock: Mutex<()>,
+}
+
+impl StatusStore {
+    /// Status file path for a given credential file (same directory).
+    pub fn path_for(credential_file: &Path) -> PathBuf {
+        credential_file
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .join(STATUS_FILE_NAME)
+    }
+
+    /// Load persisted statuses from `path`.
+    ///
+    /// A missing file means a first start. An unreadable or malformed file is
+    /// logged and ignored: losing cooldown state is better than refusing to start.
+    pub async fn load(path: PathBuf) -> Self {
+        let restored = match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => {
+                match serde_json::from_str::<HashMap<String, PersistedStatus>>(&contents) {
+                    Ok(persisted) => {
+                        info!(path = %path.display(), accounts = persisted.len(), "loaded pool status file");
+                        persisted
+                            .into_iter()
+                            .map(|(id, status)| (id, AccountStatus::from(status)))
+                            .collect()
+                    }
+                    Err(e) => {
+                        warn!(path = %path.display(), error = %e, "ignoring malformed pool status file");
+                        HashMap::new()
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                debug!(path = %path.display(), "no pool status file, starting fresh");
+                HashMap::new()
+            }
+            Err(e) => {
+                warn!(path = %path.display(), error = %e, "failed to read pool status file");
+                HashMap::new()
+            }
+        };
+
+        Self {
+            path,
+            restored,
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    /// Take the statuses read at load time. Cooldowns that ended while the
+    /// process was down come back as `Available`.
+    pub(crate) fn take_restored(&mut self) -> HashMap<String, AccountStatus> {
+        let now = SystemTime::now();
+        std::mem::take(&mut self.restored)
+            .into_iter()
+            .map(|(id, status)| match status {
+                AccountStatus::CoolingDown { until } if until <= now => {
+                    (id, AccountStatus::Available)
+                }
+                other => (id, other),
+            })
+            .collect()
+    }
+
+    /// Write all statuses atomically (temp file + rename).
+    ///
+    /// The snapshot is taken after acquiring the write lock, so the last
+    /// write always reflects the latest state even when saves race.
+    pub(crate) async fn save(
+        &self,
+        statuses: &RwLock<HashMap<String, AccountStatus>>,
+    ) -> Result<()> {
+        let _guard = self.write_lock.lock().await;
+
+        let persisted: HashMap<String, PersistedStatus> = statuses
+            .read()
+            .await
+            .iter()
+            .map(|(id, status)| (id.clone(), PersistedStatus::from(status)))
+            .collect();
+        let json = serde_json::to_string_pretty(&persisted)
+            .map_err(|e| Error::Persistence(format!("serializing statuses: {e}")))?;
+
+        let dir = self
+            .path
+            .parent()
+            .ok_or_else(|| Error::Persistence("status path has no parent directory".into()))?;
+        let tmp_path = dir.join(format!(".pool-state.tmp.{}", std::process::id()));
+
+        tokio::fs::write(&tmp_path, json.as_bytes())
+            .await
+            .map_err(|e| Error::Persistence(format!("writing temp status file: {e}")))?;
+        tokio::fs::rename(&tmp_path, &self.path)
+            .await
+            .map_err(|e| Error::Persistence(format!("renaming temp status file: {e}")))?;
+
+        debug!(path = %self.path.display(), "persisted pool statuses");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_is_next_to_credential_file() {
+        assert_eq!(
+            StatusStore::path_for(Path::new("/data/credentials.json")),
+            PathBuf::from("/data/pool-state.json")
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_file_restores_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = StatusStore::load(dir.path().join("pool-state.json")).await;
+        assert!(store.take_restored().is_empty());
+    }
+
+    #[tokio::test]
+    async fn malformed_file_is_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pool-state.json");
+        tokio::fs::write(&path, "not json").await.unwrap();
+        let mut store = StatusStore::load(path).await;
+        assert!(store.take_restored().is_empty());
+    }
+
+    #[tokio::test]
+    async fn round_trip_preserves_deadline_and_reason() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pool-state.json");
+        let until = UNIX_EPOCH + Duration::from_millis(4_102_444_800_000);
+
+        let store = StatusStore::load(path.clone()).await;
+        let statuses = HashMap::from([
+            ("a".to_string(), AccountStatus::CoolingDown { until }),
+            (
+                "b".to_string(),
+                AccountStatus::Disabled {
+                    reason: "refresh token rejected".into(),
+                },
+            ),
+            ("c".to_string(), AccountStatus::Available),
+        ]);
+        store.save(&RwLock::new(statuses)).await.unwrap();
+
+        let mut reloaded = StatusStore::load(path).await;
+        let restored = reloaded.take_restored();
+        assert!(matches!(
+            restored["a"],
+            AccountStatus::CoolingDown { until: u } if u == until
+        ));
+        assert!(matches!(
+            &restored["b"],
+            AccountStatus::Disabled { reason } if reason == "refresh token rejected"
+        ));
+        assert!(matches!(restored["c"], AccountStatus::Available));
+    }
+
+    #[tokio::test]
+    async fn expired_cooldown_restores_as_available() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pool-state.json");
+        tokio::fs::write(&path, r#"{"a": {"status": "cooling_down", "until": 1000}}"#)
+            .await
+            .unwrap();
+
+        let mut store = StatusStore::load(path).await;
+        assert!(matches!(
+            store.take_restored()["a"],
+            AccountStatus::Available
+        ));
+    }
+}
@@ -1,15 +1,27 @@
-//! Pool state machine and round-robin account selection
+//! Pool state machine and account selection
 //!
 //! The pool holds per-account status (Available, CoolingDown, Disabled) and selects
-//! accounts round-robin. The credential store is the single source of truth for
+//! accounts in the order given by its `SelectionStrategy` (round-robin by
+//! default). The credential store is the single source of truth for
 //! token data; the pool reads credentials at selection time.
 //!
+//! Accounts whose latest rate-limit headers show a window at or below the quota
+//! reserve are deprioritized: they are only selected when no account with
+//! headroom is available, so requests shift away before the account hits 429.
+//!
+//! Callers may pass an affinity key (e.g. one per conversation). The pool
+//! remembers which account served each key and tries it first on later
+//! selections, so consecutive turns hit the same account's prompt cache. If that
+//! account is unavailable the key is remapped to whichever account is selected.
+//!
 //! Cooldown transitions happen automatically: when a CoolingDown account is checked
 //! and its cooldown has expired, it transitions back to Available without explicit action.
+//! Cooldown deadlines are wall-clock times so they survive a restart when the
+//! pool is given a `StatusStore`; every status change is then written to disk.
 
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
 use anthropic_auth::CredentialStore;
 use provider::ErrorClassification;
@@ -17,6 +29,60 @@ use tokio::sync::RwLock;
 use tracing::{debug, info, warn};
 
 use crate::error::{Error, Result};
+use crate::persistence::StatusStore;
+use crate::ratelimit::RateLimitSnapshot;
+use crate::strategy::{AccountUsage, SelectionInput, SelectionStrategy};
+
+/// Default fraction of a rate-limit window held in reserve before an account
+/// is deprioritized.
+pub const DEFAULT_QUOTA_RESERVE: f64 = 0.05;
+
+/// Default lifetime of an idle affinity mapping. Covers the 1-hour extended
+/// prompt cache TTL; the default 5-minute cache is covered with room to spare.
+pub const DEFAULT_AFFINITY_TTL: Duration = Duration::from_secs(3600);
+
+/// Minimum time between sweeps of expired affinity mappings.
+const AFFINITY_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Affinity mappings kept at most; past this the least recently used quarter
+/// is evicted even if unexpired.
+const AFFINITY_MAX_ENTRIES: usize = if cfg!(test) { 100 } else { 100_000 };
+
+/// Account bound to an affinity key and when the key was last used.
+struct AffinityEntry {
+    account_id: String,
+    last_used: Instant,
+}
+
+/// Affinity mappings and when expired ones were last swept out.
+struct AffinityMap {
+    entries: HashMap<String, AffinityEntry>,
+    swept: Instant,
+}
+
+impl AffinityMap {
+    fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            swept: Instant::now(),
+        }
+    }
+
+    /// Make room before an insert: drop expired mappings once per sweep
+    /// interval, and the least recently used quarter when the map is full.
+    fn prune(&mut self, now: Instant, ttl: Duration) {
+        if now.saturating_duration_since(self.swept) >= AFFINITY_SWEEP_INTERVAL {
+            self.entries
+                .retain(|_, entry| now.saturating_duration_since(entry.last_used) < ttl);
+            self.swept = now;
+        }
+        if self.entries.len() >= AFFINITY_MAX_ENTRIES {
+            let mut used: Vec<Instant> = self.entries.values().map(|e| e.last_used).collect();
+            let (_, &mut cutoff, _) = used.select_nth_unstable(AFFINITY_MAX_ENTRIES / 4);
+            self.entries.retain(|_, entry| entry.last_used > cutoff);
+        }
+    }
+}
 
 /// Runtime status of a pool account.
 ///
@@ -29,8 +95,8 @@ use crate::error::{Error, Result};
 #[derive(Debug, Clone)]
 pub enum AccountStatus {
     Available,
-    CoolingDown { until: Instant },
-    Disabled,
+    CoolingDown { until: SystemTime },
+    Disabled { reason: String },
 }
 
 impl AccountStatus {
@@ -39,7 +105,7 @@ impl AccountStatus {
         match self {
             AccountStatus::Available => "available",
             AccountStatus::CoolingDown { .. } => "cooling_down",
-            AccountStatus::Disabled => "disabled",
+            AccountStatus::Disabled { .. } => "disabled",
         }
     }
 }
@@ -54,13 +120,24 @@ pub struct SelectedAccount {
 /// Subscription pool managing multiple OAuth accounts.
 ///
 /// Uses an `AtomicUsize` for the round-robin index and `RwLock` for the account
-/// list and status map. The credential store is shared via `Arc` and provides
-/// the token data.
+/// list, status map, and latest rate-limit snapshots. Per-account usage (in-flight
+/// count, last selection) sits behind a std `Mutex` so it can be released from
+/// `Drop` without an async context; affinity mappings use the same kind of lock.
+/// The credential store is shared via `Arc` and provides the token data.
 pub struct Pool {
     account_ids: RwLock<Vec<String>>,
+    /// Length of `account_ids`, readable without the lock
+    account_count: AtomicUsize,
     statuses: RwLock<HashMap<String, AccountStatus>>,
+    rate_limits: RwLock<HashMap<String, RateLimitSnapshot>>,
+    usage: std::sync::Mutex<HashMap<String, AccountUsage>>,
+    affinity: std::sync::Mutex<AffinityMap>,
+    affinity_ttl: Duration,
     next_index: AtomicUsize,
     cooldown_duration: Duration,
+    quota_reserve: f64,
+    strategy: SelectionStrategy,
+    status_store: Option<StatusStore>,
     credential_store: std::sync::Arc<CredentialStore>,
     http_client: reqwest::Client,
 }
@@ -82,24 +159,89 @@ impl Pool {
             .collect();
         info!(accounts = account_ids.len(), "pool initialized");
         Self {
+            account_count: AtomicUsize::new(account_ids.len()),
             account_ids: RwLock::new(account_ids),
             statuses: RwLock::new(statuses),
+            rate_limits: RwLock::new(HashMap::new()),
+            usage: std::sync::Mutex::new(HashMap::new()),
+            affinity: std::sync::Mutex::new(AffinityMap::new()),
+            affinity_ttl: DEFAULT_AFFINITY_TTL,
             next_index: AtomicUsize::new(0),
             cooldown_duration,
+            quota_reserve: DEFAULT_QUOTA_RESERVE,
+            strategy: SelectionStrategy::default(),
+            status_store: None,
             credential_store,
             http_client,
         }
     }
 
-    /// Select the next available account via round-robin.
+    /// Set the fraction of each rate-limit window kept in reserve.
     ///
-    /// Scans all accounts starting from `next_index`. Expired cooldowns are
-    /// transitioned to Available automatically. If a selected account's token
-    /// expires within 60 seconds, attempts an inline refresh; on failure, the
-    /// account is disabled and the scan continues.
+    /// An account is deprioritized once any open window's remaining budget
+    /// drops to `ratio × limit` or below. `0.0` only deprioritizes windows
+    /// that are fully used up.
+    pub fn with_quota_reserve(mut self, ratio: f64) -> Self {
+        self.quota_reserve = ratio;
+        self
+    }
+
+    /// Set the account selection strategy (default: round-robin).
+    pub fn with_strategy(mut self, strategy: SelectionStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Restore statuses saved by a previous run and persist future changes.
+    ///
+    /// Only accounts in the pool's list are restored; accounts the file
+    /// doesn't mention stay Available.
+    pub fn with_status_store(mut self, mut store: StatusStore) -> Self {
+        let statuses = self.statuses.get_mut();
+        for (id, status) in store.take_restored() {
+            if let Some(current) = statuses.get_mut(&id) {
+                if !matches!(status, AccountStatus::Available) {
+                    info!(account_id = %id, status = status.label(), "restored account status");
+                }
+                *current = status;
+            }
+        }
+        self.status_store = Some(store);
+        self
+    }
+
+    /// Set how long an affinity key keeps its account after its last use.
+    /// `Duration::ZERO` disables affinity.
+    pub fn with_affinity_ttl(mut self, ttl: Duration) -> Self {
+        self.affinity_ttl = ttl;
+        self
+    }
+
+    /// Select the next available account.
+    ///
+    /// Scans all accounts in the order produced by the selection strategy,
+    /// trying accounts with quota headroom before those at or below the
+    /// reserve. Expired cooldowns are transitioned to Available automatically.
+    /// If a selected account's token expires within 60 seconds, attempts an
+    /// inline refresh; on failure, the account is disabled and the scan continues.
+    ///
+    /// The selected account counts as in flight until `release` is called.
     ///
     /// Returns `PoolExhausted` with pool counts if no account is available.
     pub async fn select(&self) -> Result<SelectedAccount> {
+        self.select_with_affinity(None).await
+    }
+
+    /// Select an account, preferring the one last used for `affinity_key`.
+    ///
+    /// The mapped account is tried first as long as it has quota headroom;
+    /// otherwise selection proceeds as in `select` and the key is remapped to
+    /// the account chosen.
+    pub async fn select_with_affinity(
+        &self,
+        affinity_key: Option<&str>,
+    ) -> Result<SelectedAccount> {
+        let affinity_key = affinity_key.filter(|_| !self.affinity_ttl.is_zero());
         let ids = self.account_ids.read().await;
         let n = ids.len();
         if n == 0 {
@@ -110,33 +252,62 @@ impl Pool {
 
         let start = self.next_index.fetch_add(1, Ordering::Relaxed) % n;
 
-        for offset in 0..n {
-            let idx = (start + offset) % n;
-            let id = &ids[idx];
+        // Split the strategy's order into accounts with headroom and accounts
+        // whose latest rate-limit snapshot is at or below the reserve.
+        let (mut preferred, low_quota): (Vec<&String>, Vec<&String>) = {
+            let rate_limits = self.rate_limits.read().await;
+            let usage = self.usage.lock().unwrap().clone();
+            let now = SystemTime::now();
+            let order = self.strategy.order(&SelectionInput {
+                ids: &ids,
+                start,
+                usage: &usage,
+                rate_limits: &rate_limits,
+                now,
+            });
+            order.into_iter().map(|idx| &ids[idx]).partition(|id| {
+                !rate_limits
+                    .get(*id)
+                    .is_some_and(|snapshot| snapshot.is_low(now, self.quota_reserve))
+            })
+        };
+        let preferred_count = preferred.len();
 
+        if let Some(sticky) = affinity_key.and_then(|key| self.affinity_account(key))
+            && let Some(pos) = preferred.iter().position(|id| **id == sticky)
+        {
+            let id = preferred.remove(pos);
+            preferred.insert(0, id);
+        }
+
+        for (position, id) in preferred.into_iter().chain(low_quota).enumerate() {
             // Check and possibly transition status
-            let available = {
-                let mut statuses = self.statuses.write().await;
-                let status = statuses.get(id);
-                match status {
-                    Some(AccountStatus::Available) => true,
-                    Some(AccountStatus::CoolingDown { until }) => {
-                        if Instant::now() >= *until {
-                            info!(account_id = id, "cooldown expired, account available again");
-                            statuses.insert(id.clone(), AccountStatus::Available);
-                            true
-                        } else {
-                            false
-                        }
+            let status = self.statuses.read().await.get(id).cloned();
+            let available = match status {
+                Some(AccountStatus::Available) => true,
+                Some(AccountStatus::CoolingDown { until }) => {
+                    if SystemTime::now() >= until {
+                        info!(account_id = id, "cooldown expired, account available again");
+                        self.set_status(id, AccountStatus::Available).await;
+                        true
+                    } else {
+                        false
                     }
-                    Some(AccountStatus::Disabled) | None => false,
                 }
+                Some(AccountStatus::Disabled { .. }) | None => false,
             };
 
             if !available {
                 continue;
             }
 
+            if position >= preferred_count {
+                debug!(
+                    account_id = id,
+                    "no account with quota headroom available, using low-quota account"
+                );
+            }
+
             // Get credential from store
             let credential = match self.credential_store.get(id).await {
                 Some(c) => c,
@@ -145,10 +316,13 @@ impl Pool {
                         account_id = id,
                         "account in pool but not in credential store, disabling"
                     );
-                    self.statuses
-                        .write()
-                        .await
-                        .insert(id.clone(), AccountStatus::Disabled);
+                    self.set_status(
+                        id,
+                        AccountStatus::Disabled {
+                            reason: "missing from credential store".into(),
+                        },
+                    )
+                    .await;
                     continue;
                 }
             };
@@ -165,38 +339,44 @@ impl Pool {
                     account_id = id,
                     "token expiring soon, attempting inline refresh"
                 );
-                match anthropic_auth::refresh_token(&self.http_client, &credential.refresh).await {
-                    Ok(token_response) => {
-                        let new_expires = now_millis + (token_response.expires_in * 1000);
-                        if let Err(e) = self
-                            .credential_store
-                            .update_token(
-                                id,
-                                token_response.access_token.clone(),
-                                token_response.refresh_token,
-                                new_expires,
-                            )
-                            .await
-                        {
-                            warn!(account_id = id, error = %e, "failed to persist refreshed token");
+                let client = self.http_client.clone();
+                let refreshed = self
+                    .credential_store
+                    .refresh(id, &credential.refresh, |refresh| async move {
+                        anthropic_auth::refresh_token(&client, &refresh).await
+                    })
+                    .await;
+                match refreshed {
+                    Ok(outcome) => {
+                        if outcome.refreshed {
+                            info!(account_id = id, "inline token refresh succeeded");
                         }
-                        info!(account_id = id, "inline token refresh succeeded");
+                        self.mark_selected(id, affinity_key);
                         return Ok(SelectedAccount {
                             id: id.clone(),
-                            access_token: token_response.access_token,
+                            access_token: outcome.credential.access,
                         });
                     }
+                    // The token is fine; another replica holds the store
+                    Err(anthropic_auth::Error::Conflict(e)) => {
+                        warn!(account_id = id, error = %e, "credential store busy, skipping account");
+                        continue;
+                    }
                     Err(e) => {
                         warn!(account_id = id, error = %e, "inline refresh failed, disabling account");
-                        self.statuses
-                            .write()
-                            .await
-                            .insert(id.clone(), AccountStatus::Disabled);
+                        self.set_status(
+                            id,
+                            AccountStatus::Disabled {
+                                reason: format!("inline token refresh failed: {e}"),
+                            },
+                        )
+                        .await;
                         continue;
                     }
                 }
             }
 
+            self.mark_selected(id, affinity_key);
             return Ok(SelectedAccount {
                 id: id.clone(),
                 access_token: credential.access,
@@ -213,24 +393,51 @@ impl Pool {
 
     /// Report an error classification for an account, triggering state transitions.
     ///
-    /// - QuotaExceeded → CoolingDown for cooldown_duration
+    /// - QuotaExceeded → CoolingDown until the reset time from the account's
+    ///   latest rate-limit headers, or for cooldown_duration if none is known
     /// - Permanent → Disabled
     /// - Transient → no change
     pub async fn report_error(&self, account_id: &str, classification: ErrorClassification) {
-        let mut statuses = self.statuses.write().await;
         match classification {
             ErrorClassification::QuotaExceeded => {
-                let until = Instant::now() + self.cooldown_duration;
+                let header_cooldown =
+                    self.rate_limits
+                        .read()
+                        .await
+                        .get(account_id)
+                        .and_then(|snapshot| {
+                            let now = SystemTime::now();
+                            snapshot
+                                .reset_at(now)
+                                .and_then(|reset| reset.duration_since(now).ok())
+                        });
+                let (cooldown, source) = match header_cooldown {
+                    Some(d) => (d, "headers"),
+                    None => (self.cooldown_duration, "default"),
+                };
                 info!(
                     account_id,
-                    cooldown_secs = self.cooldown_duration.as_secs(),
+                    cooldown_secs = cooldown.as_secs(),
+                    source,
                     "account entering cooldown (quota exhausted)"
                 );
-                statuses.insert(account_id.to_string(), AccountStatus::CoolingDown { until });
+                self.set_status(
+                    account_id,
+                    AccountStatus::CoolingDown {
+                        until: SystemTime::now() + cooldown,
+                    },
+                )
+                .await;
             }
             ErrorClassification::Permanent => {
                 warn!(account_id, "account disabled (permanent error)");
-                statuses.insert(account_id.to_string(), AccountStatus::Disabled);
+                self.set_status(
+                    account_id,
+                    AccountStatus::Disabled {
+                        reason: "upstream rejected credentials".into(),
+                    },
+                )
+                .await;
             }
             ErrorClassification::Transient => {
                 debug!(account_id, "transient error, no pool action");
@@ -238,16 +445,81 @@ impl Pool {
         }
     }
 
+    /// Record the rate-limit state reported on an upstream response.
+    ///
+    /// Called for every response on an account, so the snapshot reflects the
+    /// most recent limiter state when a quota error arrives.
+    pub async fn record_rate_limits(&self, account_id: &str, snapshot: RateLimitSnapshot) {
+        self.rate_limits
+            .write()
+            .await
+            .insert(account_id.to_string(), snapshot);
+    }
+
+    /// Release an account selected by `select` once its request has finished.
+    pub fn release(&self, account_id: &str) {
+        if let Some(entry) = self.usage.lock().unwrap().get_mut(account_id) {
+            entry.in_flight = entry.in_flight.saturating_sub(1);
+        }
+    }
+
+    /// Record a selection for the usage-based strategies and affinity.
+    fn mark_selected(&self, account_id: &str, affinity_key: Option<&str>) {
+        let now = Instant::now();
+        {
+            let mut usage = self.usage.lock().unwrap();
+            let entry = usage.entry(account_id.to_string()).or_default();
+            entry.in_flight += 1;
+            entry.last_selected = Some(now);
+        }
+
+        let Some(key) = affinity_key else {
+            return;
+        };
+        let mut affinity = self.affinity.lock().unwrap();
+        if !affinity.entries.contains_key(key) {
+            affinity.prune(now, self.affinity_ttl);
+        }
+        match affinity.entries.get_mut(key) {
+            Some(entry) if entry.account_id == account_id => entry.last_used = now,
+            previous => {
+                if let Some(entry) = previous {
+                    debug!(
+                        from = %entry.account_id,
+                        to = account_id,
+                        "affinity key moved to another account"
+                    );
+                }
+                affinity.entries.insert(
+                    key.to_string(),
+                    AffinityEntry {
+                        account_id: account_id.to_string(),
+                        last_used: now,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Account currently bound to an affinity key, if the binding hasn't expired.
+    fn affinity_account(&self, key: &str) -> Option<String> {
+        let affinity = self.affinity.lock().unwrap();
+        affinity
+            .entries
+            .get(key)
+            .filter(|entry| entry.last_used.elapsed() < self.affinity_ttl)
+            .map(|entry| entry.account_id.clone())
+    }
+
     /// Add a new account to the pool. Starts as Available.
     pub async fn add_account(&self, account_id: String) {
         let mut ids = self.account_ids.write().await;
         if !ids.contains(&account_id) {
             ids.push(account_id.clone());
         }
-        self.statuses
-            .write()
-            .await
-            .insert(account_id.clone(), AccountStatus::Available);
+        self.account_count.store(ids.len(), Ordering::Relaxed);
+        drop(ids);
+        self.set_status(&account_id, AccountStatus::Available).await;
         info!(account_id, "account added to pool");
     }
 
@@ -255,7 +527,17 @@ impl Pool {
     pub async fn remove_account(&self, account_id: &str) {
         let mut ids = self.account_ids.write().await;
         ids.retain(|id| id != account_id);
+        self.account_count.store(ids.len(), Ordering::Relaxed);
         self.statuses.write().await.remove(account_id);
+        self.rate_limits.write().await.remove(account_id);
+        self.usage.lock().unwrap().remove(account_id);
+        self.affinity
+            .lock()
+            .unwrap()
+            .entries
+            .retain(|_, entry| entry.account_id != account_id);
+        drop(ids);
+        self.persist_statuses().await;
         info!(account_id, "account removed from pool");
     }
 
@@ -267,7 +549,9 @@ impl Pool {
     pub async fn health(&self) -> serde_json::Value {
         let ids = self.account_ids.read().await;
         let statuses = self.statuses.read().await;
-        let now = Instant::now();
+        let rate_limits = self.rate_limits.read().await;
+        let usage = self.usage.lock().unwrap().clone();
+        let now = SystemTime::now();
 
         let mut accounts = Vec::new();
         let mut available_count = 0usize;
@@ -279,17 +563,18 @@ impl Pool {
             match status {
                 Some(AccountStatus::Available) => {
                     available_count += 1;
+                    let quota_low = rate_limits
+                        .get(id)
+                        .is_some_and(|snapshot| snapshot.is_low(now, self.quota_reserve));
                     accounts.push(serde_json::json!({
                         "id": id,
-                        "status": "available"
+                        "status": "available",
+                        "quota_low": quota_low,
+                        "in_flight": usage.get(id).map_or(0, |u| u.in_flight)
                     }));
                 }
                 Some(AccountStatus::CoolingDown { until }) => {
-                    let remaining = if *until > now {
-                        (*until - now).as_secs()
-                    } else {
-                        0
-                    };
+                    let remaining = until.duration_since(now).unwrap_or_default().as_secs();
                     cooling_count += 1;
                     accounts.push(serde_json::json!({
                         "id": id,
@@ -297,11 +582,12 @@ impl Pool {
                         "cooldown_remaining_secs": remaining
                     }));
                 }
-                Some(AccountStatus::Disabled) => {
+                Some(AccountStatus::Disabled { reason }) => {
                     disabled_count += 1;
                     accounts.push(serde_json::json!({
                         "id": id,
-                        "status": "disabled"
+                        "status": "disabled",
+                        "reason": reason
                     }));
                 }
                 None => {
@@ -325,6 +611,7 @@ impl Pool {
 
         serde_json::json!({
             "status": pool_status,
+            "strategy": self.strategy.label(),
             "accounts_total": total,
             "accounts_available": available_count,
             "accounts_cooling_down": cooling_count,
@@ -343,24 +630,52 @@ impl Pool {
         &self.http_client
     }
 
+    /// Number of accounts in the pool.
+    pub fn len(&self) -> usize {
+        self.account_count.load(Ordering::Relaxed)
+    }
+
+    /// Whether the pool has no accounts.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     /// Get a snapshot of all account IDs.
     pub async fn account_ids(&self) -> Vec<String> {
         self.account_ids.read().await.clone()
     }
 
+    /// Current status of an account, or `None` if the pool doesn't know it.
+    pub async fn status(&self, account_id: &str) -> Option<AccountStatus> {
+        self.statuses.read().await.get(account_id).cloned()
+    }
+
     /// Set an account's status directly (used by background refresh on failure).
+    ///
+    /// Every status change goes through here so it reaches the status file.
     pub async fn set_status(&self, account_id: &str, status: AccountStatus) {
         self.statuses
             .write()
             .await
             .insert(account_id.to_string(), status);
+        self.persist_statuses().await;
+    }
+
+    /// Write current statuses to the status file, if one is configured.
+    /// Failures are logged; in-memory state stays authoritative.
+    async fn persist_statuses(&self) {
+        if let Some(ref store) = self.status_store
+            && let Err(e) = store.save(&self.statuses).await
+        {
+            warn!(error = %e, "failed to persist pool statuses");
+        }
     }
 
     /// Count accounts by status.
     async fn count_statuses(&self) -> (usize, usize, usize, usize) {
         let ids = self.account_ids.read().await;
         let statuses = self.statuses.read().await;
-        let now = Instant::now();
+        let now = SystemTime::now();
         let total = ids.len();
         let mut available = 0usize;
         let mut cooling = 0usize;
@@ -376,7 +691,7 @@ impl Pool {
                         cooling += 1;
                     }
                 }
-                Some(AccountStatus::Disabled) | None => disabled += 1,
+                Some(AccountStatus::Disabled { .. }) | None => disabled += 1,
             }
         }
         (total, available, cooling, disabled)
@@ -463,6 +778,34 @@ mod tests {
         assert_eq!(s3.id, "a");
     }
 
+    #[tokio::test]
+    async fn inline_refresh_adopts_token_rotated_by_another_replica() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_store(&dir, &[("a", past_expiry())]).await;
+        // Another replica sharing the file refreshed "a" in the meantime
+        let other = CredentialStore::load(dir.path().join("credentials.json"))
+            .await
+            .unwrap();
+        other
+            .update_token("a", "at_new".into(), "rt_new".into(), future_expiry())
+            .await
+            .unwrap();
+
+        // The client has no route to the token endpoint: only a re-read works
+        let pool = Pool::new(
+            vec!["a".into()],
+            Duration::from_secs(7200),
+            store.clone(),
+            reqwest::Client::builder()
+                .resolve("console.anthropic.com", "127.0.0.1:9".parse().unwrap())
+                .build()
+                .unwrap(),
+        );
+        let selected = pool.select().await.unwrap();
+        assert_eq!(selected.access_token, "at_new");
+        assert_eq!(store.get("a").await.unwrap().refresh, "rt_new");
+    }
+
     #[tokio::test]
     async fn skips_cooling_down_accounts() {
         let dir = tempfile::tempdir().unwrap();
@@ -528,7 +871,7 @@ mod tests {
             .await;
 
         // Cooldown is 0 seconds, so it should be expired immediately
-        // (Instant::now() >= until since until = now + 0)
+        // (SystemTime::now() >= until since until = now + 0)
         // Small sleep to ensure time advances past the instant
         tokio::time::sleep(Duration::from_millis(1)).await;
 
@@ -596,6 +939,242 @@ mod tests {
         assert_eq!(health["accounts_cooling_down"], 1);
     }
 
+    #[tokio::test]
+    async fn report_error_quota_uses_header_reset_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_store(&dir, &[("a", future_expiry())]).await;
+        let pool = Pool::new(
+            vec!["a".into()],
+            Duration::from_secs(7200),
+            store,
+            reqwest::Client::new(),
+        );
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("retry-after", "60".parse().unwrap());
+        let snapshot = RateLimitSnapshot::from_headers(&headers, SystemTime::now()).unwrap();
+        pool.record_rate_limits("a", snapshot).await;
+        pool.report_error("a", ErrorClassification::QuotaExceeded)
+            .await;
+
+        let health = pool.health().await;
+        let remaining = health["accounts"][0]["cooldown_remaining_secs"]
+            .as_u64()
+            .unwrap();
+        assert!(
+            remaining <= 60,
+            "cooldown must follow retry-after, got {remaining}s"
+        );
+    }
+
+    #[tokio::test]
+    async fn report_error_quota_falls_back_to_default_without_reset() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_store(&dir, &[("a", future_expiry())]).await;
+        let pool = Pool::new(
+            vec!["a".into()],
+            Duration::from_secs(7200),
+            store,
+            reqwest::Client::new(),
+        );
+
+        // Snapshot with no exhausted window carries no usable reset time
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            "anthropic-ratelimit-requests-remaining",
+            "10".parse().unwrap(),
+        );
+        let snapshot = RateLimitSnapshot::from_headers(&headers, SystemTime::now()).unwrap();
+        pool.record_rate_limits("a", snapshot).await;
+        pool.report_error("a", ErrorClassification::QuotaExceeded)
+            .await;
+
+        let health = pool.health().await;
+        let remaining = health["accounts"][0]["cooldown_remaining_secs"]
+            .as_u64()
+            .unwrap();
+        assert!(
+            remaining > 7000,
+            "expected default cooldown, got {remaining}s"
+        );
+    }
+
+    /// Snapshot reporting `remaining` of 100 requests, resetting in an hour.
+    fn requests_snapshot(remaining: u64) -> RateLimitSnapshot {
+        let reset = humantime::format_rfc3339(SystemTime::now() + Duration::from_secs(3600));
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("anthropic-ratelimit-requests-limit", "100".parse().unwrap());
+        headers.insert(
+            "anthropic-ratelimit-requests-remaining",
+            remaining.to_string().parse().unwrap(),
+        );
+        headers.insert(
+            "anthropic-ratelimit-requests-reset",
+            reset.to_string().parse().unwrap(),
+        );
+        RateLimitSnapshot::from_headers(&headers, SystemTime::now()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn select_skips_low_quota_accounts() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_store(&dir, &[("a", future_expiry()), ("b", future_expiry())]).await;
+        let pool = Pool::new(
+            vec!["a".into(), "b".into()],
+            Duration::from_secs(7200),
+            store,
+            reqwest::Client::new(),
+        );
+
+        pool.record_rate_limits("a", requests_snapshot(2)).await;
+        pool.record_rate_limits("b", requests_snapshot(80)).await;
+
+        for _ in 0..4 {
+            assert_eq!(pool.select().await.unwrap().id, "b");
+        }
+    }
+
+    #[tokio::test]
+    async fn select_falls_back_to_low_quota_account() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_store(&dir, &[("a", future_expiry()), ("b", future_expiry())]).await;
+        let pool = Pool::new(
+            vec!["a".into(), "b".into()],
+            Duration::from_secs(7200),
+            store,
+            reqwest::Client::new(),
+        );
+
+        pool.record_rate_limits("a", requests_snapshot(2)).await;
+        pool.report_error("b", ErrorClassification::Permanent).await;
+
+        assert_eq!(pool.select().await.unwrap().id, "a");
+    }
+
+    #[tokio::test]
+    async fn quota_reserve_is_configurable() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_store(&dir, &[("a", future_expiry()), ("b", future_expiry())]).await;
+        let pool = Pool::new(
+            vec!["a".into(), "b".into()],
+            Duration::from_secs(7200),
+            store,
+            reqwest::Client::new(),
+        )
+        .with_quota_reserve(0.25);
+
+        pool.record_rate_limits("a", requests_snapshot(20)).await;
+
+        for _ in 0..4 {
+            assert_eq!(pool.select().await.unwrap().id, "b");
+        }
+        let health = pool.health().await;
+        assert_eq!(health["accounts"][0]["quota_low"], true);
+        assert_eq!(health["accounts"][1]["quota_low"], false);
+    }
+
+    #[tokio::test]
+    async fn affinity_key_sticks_to_account() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_store(
+            &dir,
+            &[
+                ("a", future_expiry()),
+                ("b", future_expiry()),
+                ("c", future_expiry()),
+            ],
+        )
+        .await;
+        let pool = Pool::new(
+            vec!["a".into(), "b".into(), "c".into()],
+            Duration::from_secs(7200),
+            store,
+            reqwest::Client::new(),
+        );
+
+        let first = pool.select_with_affinity(Some("conv-1")).await.unwrap();
+        for _ in 0..5 {
+            // Unkeyed selections keep advancing round-robin in between
+            pool.select().await.unwrap();
+            let s = pool.select_with_affinity(Some("conv-1")).await.unwrap();
+            assert_eq!(s.id, first.id);
+        }
+    }
+
+    #[tokio::test]
+    async fn affinity_falls_back_and_remaps_on_cooldown() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_store(&dir, &[("a", future_expiry()), ("b", future_expiry())]).await;
+        let pool = Pool::new(
+            vec!["a".into(), "b".into()],
+            Duration::from_secs(7200),
+            store,
+            reqwest::Client::new(),
+        );
+
+        let first = pool.select_with_affinity(Some("conv-1")).await.unwrap();
+        pool.report_error(&first.id, ErrorClassification::QuotaExceeded)
+            .await;
+
+        let second = pool.select_with_affinity(Some("conv-1")).await.unwrap();
+        assert_ne!(second.id, first.id);
+        // The key now follows the new account
+        for _ in 0..3 {
+            let s = pool.select_with_affinity(Some("conv-1")).await.unwrap();
+            assert_eq!(s.id, second.id);
+        }
+    }
+
+    #[test]
+    fn affinity_map_sweeps_expired_and_caps_size() {
+        let ttl = Duration::from_secs(3600);
+        let mut map = AffinityMap::new();
+        let start = map.swept;
+        let entry = |last_used| AffinityEntry {
+            account_id: "a".into(),
+            last_used,
+        };
+        map.entries.insert("old".into(), entry(start));
+
+        // Expired mappings go at the next sweep, not only once the map is full
+        let later = start + ttl + AFFINITY_SWEEP_INTERVAL;
+        map.entries.insert("recent".into(), entry(later));
+        map.prune(later, ttl);
+        assert!(!map.entries.contains_key("old"));
+        assert!(map.entries.contains_key("recent"));
+
+        // A full map of live mappings sheds its least recently used
+        for i in 0..AFFINITY_MAX_ENTRIES {
+            map.entries.insert(
+                format!("k{i}"),
+                entry(later + Duration::from_secs(i as u64)),
+            );
+        }
+        map.prune(later, ttl);
+        assert!(map.entries.len() < AFFINITY_MAX_ENTRIES);
+        assert!(!map.entries.contains_key("recent"));
+        assert!(!map.entries.contains_key("k0"));
+        let newest = format!("k{}", AFFINITY_MAX_ENTRIES - 1);
+        assert!(map.entries.contains_key(&newest));
+    }
+
+    #[tokio::test]
+    async fn affinity_disabled_with_zero_ttl() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_store(&dir, &[("a", future_expiry()), ("b", future_expiry())]).await;
+        let pool = Pool::new(
+            vec!["a".into(), "b".into()],
+            Duration::from_secs(7200),
+            store,
+            reqwest::Client::new(),
+        )
+        .with_affinity_ttl(Duration::ZERO);
+
+        let s1 = pool.select_with_affinity(Some("conv-1")).await.unwrap();
+        let s2 = pool.select_with_affinity(Some("conv-1")).await.unwrap();
+        assert_ne!(s1.id, s2.id);
+    }
+
     #[tokio::test]
     async fn report_error_permanent_sets_disabled() {
         let dir = tempfile::tempdir().unwrap();
@@ -613,6 +1192,61 @@ mod tests {
         assert_eq!(health["accounts_disabled"], 1);
     }
 
+    #[tokio::test]
+    async fn statuses_survive_restart_via_status_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_store(
+            &dir,
+            &[
+                ("a", future_expiry()),
+                ("b", future_expiry()),
+                ("c", future_expiry()),
+            ],
+        )
+        .await;
+        let status_path = StatusStore::path_for(&dir.path().join("credentials.json"));
+        let ids: Vec<String> = vec!["a".into(), "b".into(), "c".into()];
+
+        let pool = Pool::new(
+            ids.clone(),
+            Duration::from_secs(7200),
+            store.clone(),
+            reqwest::Client::new(),
+        )
+        .with_status_store(StatusStore::load(status_path.clone()).await);
+        pool.report_error("a", ErrorClassification::QuotaExceeded)
+            .await;
+        pool.report_error("b", ErrorClassification::Permanent).await;
+        drop(pool);
+
+        // "Restart": a fresh pool over the same files
+        let pool = Pool::new(
+            ids,
+            Duration::from_secs(7200),
+            store,
+            reqwest::Client::new(),
+        )
+        .with_status_store(StatusStore::load(status_path).await);
+
+        let health = pool.health().await;
+        assert_eq!(health["accounts"][0]["status"], "cooling_down");
+        assert!(
+            health["accounts"][0]["cooldown_remaining_secs"]
+                .as_u64()
+                .unwrap()
+                > 7000
+        );
+        assert_eq!(health["accounts"][1]["status"], "disabled");
+        assert_eq!(
+            health["accounts"][1]["reason"],
+            "upstream rejected credentials"
+        );
+        assert_eq!(health["accounts"][2]["status"], "available");
+        for _ in 0..3 {
+            assert_eq!(pool.select().await.unwrap().id, "c");
+        }
+    }
+
     #[tokio::test]
     async fn report_error_transient_no_change() {
         let dir = tempfile::tempdir().unwrap();
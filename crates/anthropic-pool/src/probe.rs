@@ -0,0 +1,391 @@
+//! Recovery probing for disabled accounts
+//!
+//! A disabled account stays out of rotation until someone intervenes, even
+//! when the cause was temporary (a revoked-then-restored subscription, an
+//! upstream auth outage). This background task periodically checks each
+//! disabled account: it refreshes the token if it is expiring (or was just
+//! rejected), sends a cheap authenticated request, and re-enables the account
+//! when that request succeeds. Failed probes back off exponentially per account.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use provider::ErrorClassification;
+use reqwest::header::{HeaderMap, HeaderValue};
+use tracing::{debug, info};
+
+use crate::pool::{AccountStatus, Pool};
+
+/// Settings for the recovery probe task.
+#[derive(Debug, Clone)]
+pub struct ProbeConfig {
+    /// URL of the probe request (a cheap authenticated GET, e.g. `/v1/models`)
+    pub url: String,
+    /// Headers sent with every probe, besides the account's Bearer token
+    pub headers: HeaderMap,
+    /// How often the task wakes up, and the first backoff after a failed probe
+    pub interval: Duration,
+    /// Upper bound for the per-account backoff
+    pub max_backoff: Duration,
+}
+
+/// Outcome of one probe, used as the `result` metric label.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProbeResult {
+    /// Probe succeeded, account re-enabled
+    Recovered,
+    /// Credentials work but the quota is exhausted; account moved to cooldown
+    QuotaExceeded,
+    /// Token refresh failed
+    RefreshFailed,
+    /// Upstream still rejects the credentials (401/403)
+    Rejected,
+    /// Network error or transient upstream error
+    Error,
+}
+
+impl ProbeResult {
+    fn label(self) -> &'static str {
+        match self {
+            ProbeResult::Recovered => "recovered",
+            ProbeResult::QuotaExceeded => "quota_exceeded",
+            ProbeResult::RefreshFailed => "refresh_failed",
+            ProbeResult::Rejected => "rejected",
+            ProbeResult::Error => "error",
+        }
+    }
+}
+
+/// Per-account probe schedule.
+struct Backoff {
+    failures: u32,
+    next_attempt: Instant,
+}
+
+/// Spawn a background task that probes disabled accounts and re-enables
+/// those that work again.
+///
+/// Each disabled account is first probed on the tick after it is disabled.
+/// After a failed probe the next attempt waits `interval × 2^(failures-1)`,
+/// capped at `max_backoff`. Results are counted in
+/// `pool_recovery_probes_total{account_id, result}`.
+///
+/// Returns a `JoinHandle` for the spawned task.
+pub fn spawn_probe_task(pool: Arc<Pool>, config: ProbeConfig) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(config.interval);
+        let mut schedule: HashMap<String, Backoff> = HashMap::new();
+        // Skip the immediate first tick — statuses were just restored
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+            probe_cycle(&pool, &config, &mut schedule).await;
+        }
+    })
+}
+
+/// Probe every disabled account that is due.
+async fn probe_cycle(pool: &Pool, config: &ProbeConfig, schedule: &mut HashMap<String, Backoff>) {
+    let mut disabled = Vec::new();
+    for id in pool.account_ids().await {
+        if let Some(AccountStatus::Disabled { .. }) = pool.status(&id).await {
+            disabled.push(id);
+        }
+    }
+    // Forget schedules of accounts that recovered or were removed
+    schedule.retain(|id, _| disabled.contains(id));
+
+    let now = Instant::now();
+    for id in disabled {
+        if schedule.get(&id).is_some_and(|b| now < b.next_attempt) {
+            continue;
+        }
+
+        let result = probe_account(pool, config, &id).await;
+        metrics::counter!("pool_recovery_probes_total", "account_id" => id.clone(), "result" => result.label())
+            .increment(1);
+
+        match result {
+            ProbeResult::Recovered | ProbeResult::QuotaExceeded => {
+                schedule.remove(&id);
+            }
+            _ => {
+                let backoff = schedule.entry(id.clone()).or_insert(Backoff {
+                    failures: 0,
+                    next_attempt: now,
+                });
+                backoff.failures += 1;
+                let delay = config
+                    .interval
+                    .saturating_mul(2u32.saturating_pow(backoff.failures - 1))
+                    .min(config.max_backoff);
+                backoff.next_attempt = now + delay;
+                debug!(
+                    account_id = id,
+                    result = result.label(),
+                    retry_in_secs = delay.as_secs(),
+                    "recovery probe failed"
+                );
+            }
+        }
+    }
+}
+
+/// Refresh (if needed) and probe one disabled account.
+async fn probe_account(pool: &Pool, config: &ProbeConfig, id: &str) -> ProbeResult {
+    let Some(credential) = pool.credential_store().get(id).await else {
+        return ProbeResult::RefreshFailed;
+    };
+
+    let now_millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+
+    let mut refreshed = false;
+    let mut access = credential.access.clone();
+    if credential.expires <= now_millis + 60_000 {
+        match refresh(pool, id, &credential.refresh).await {
+            Some(token) => {
+                access = token;
+                refreshed = true;
+            }
+            None => return ProbeResult::RefreshFailed,
+        }
+    }
+
+    loop {
+        let (status, body) = match send_probe(pool.http_client(), config, &access).await {
+            Ok(r) => r,
+            Err(e) => {
+                debug!(account_id = id, error = %e, "recovery probe request failed");
+                return ProbeResult::Error;
+            }
+        };
+
+        if (200..300).contains(&status) {
+            info!(
+                account_id = id,
+                "recovery probe succeeded, re-enabling account"
+            );
+            pool.set_status(id, AccountStatus::Available).await;
+            return ProbeResult::Recovered;
+        }
+
+        match crate::classify_status(status, &body) {
+            ErrorClassification::QuotaExceeded => {
+                info!(
+                    account_id = id,
+                    "recovery probe hit quota limit, credentials valid; cooling down"
+                );
+                pool.set_status(id, AccountStatus::Available).await;
+                pool.report_error(id, ErrorClassification::QuotaExceeded)
+                    .await;
+                return ProbeResult::QuotaExceeded;
+            }
+            // A rejected token may just be stale: refresh once and re-probe
+            ErrorClassification::Permanent if !refreshed => {
+                match refresh(pool, id, &credential.refresh).await {
+                    Some(token) => {
+                        access = token;
+                        refreshed = true;
+                    }
+                    None => return ProbeResult::RefreshFailed,
+                }
+            }
+            ErrorClassification::Permanent => return ProbeResult::Rejected,
+            ErrorClassification::Transient => return ProbeResult::Error,
+        }
+    }
+}
+
+/// Refresh an account's token and persist it (or pick up the token another
+/// replica already refreshed). Returns the new access token.
+async fn refresh(pool: &Pool, id: &str, refresh_token: &str) -> Option<String> {
+    let client = pool.http_client();
+    let refreshed = pool
+        .credential_store()
+        .refresh(id, refresh_token, |refresh| async move {
+            anthropic_auth::refresh_token(client, &refresh).await
+        })
+        .await;
+    match refreshed {
+        Ok(outcome) => Some(outcome.credential.access),
+        Err(e) => {
+            debug!(account_id = id, error = %e, "recovery probe token refresh failed");
+            None
+        }
+    }
+}
+
+/// Send the probe request, returning status and body.
+async fn send_probe(
+    client: &reqwest::Client,
+    config: &ProbeConfig,
+    access_token: &str,
+) -> Result<(u16, String), String> {
+    let bearer = HeaderValue::from_str(&format!("Bearer {access_token}"))
+        .map_err(|e| format!("invalid token value: {e}"))?;
+    let response = client
+        .get(&config.url)
+        .headers(config.headers.clone())
+        .header(reqwest::header::AUTHORIZATION, bearer)
+        .timeout(Duration::from_secs(30))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    let status = response.status().as_u16();
+    let body = response.text().await.unwrap_or_default();
+    Ok((status, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anthropic_auth::{Credential, CredentialStore};
+
+    async fn disabled_pool(dir: &tempfile::TempDir) -> Arc<Pool> {
+        let store = CredentialStore::load(dir.path().join("credentials.json"))
+            .await
+            .unwrap();
+        store
+            .add(
+                "a".into(),
+                Credential {
+                    credential_type: "oauth".into(),
+                    refresh: "rt_a".into(),
+                    access: "at_a".into(),
+                    // Far future: no refresh needed before probing
+                    expires: 4_102_444_800_000,
+                },
+            )
+            .await
+            .unwrap();
+        let pool = Arc::new(Pool::new(
+            vec!["a".into()],
+            Duration::from_secs(7200),
+            Arc::new(store),
+            reqwest::Client::new(),
+        ));
+        pool.set_status(
+            "a",
+            AccountStatus::Disabled {
+                reason: "test".into(),
+            },
+        )
+        .await;
+        pool
+    }
+
+    fn config(url: String) -> ProbeConfig {
+        ProbeConfig {
+            url,
+            headers: HeaderMap::new(),
+            interval: Duration::from_secs(60),
+            max_backoff: Duration::from_secs(600),
+        }
+    }
+
+    /// Minimal upstream answering every request with a fixed status and body.
+    async fn start_upstream(status: u16, body: &'static str) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 4096];
+                    let _ = socket.read(&mut buf).await;
+                    let response = format!(
+                        "HTTP/1.1 {status} X\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                        body.len()
+                    );
+                    let _ = socket.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+        format!("http://{addr}/v1/models")
+    }
+
+    #[tokio::test]
+    async fn successful_probe_re_enables_account() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = disabled_pool(&dir).await;
+        let url = start_upstream(200, r#"{"data":[]}"#).await;
+
+        let mut schedule = HashMap::new();
+        probe_cycle(&pool, &config(url), &mut schedule).await;
+
+        assert!(matches!(
+            pool.status("a").await,
+            Some(AccountStatus::Available)
+        ));
+        assert!(schedule.is_empty());
+    }
+
+    #[tokio::test]
+    async fn quota_response_moves_account_to_cooldown() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = disabled_pool(&dir).await;
+        let url =
+            start_upstream(429, r#"{"error":{"message":"5-hour usage limit reached"}}"#).await;
+
+        probe_cycle(&pool, &config(url), &mut HashMap::new()).await;
+
+        assert!(matches!(
+            pool.status("a").await,
+            Some(AccountStatus::CoolingDown { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn failed_probe_backs_off() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = disabled_pool(&dir).await;
+        let url = start_upstream(503, "unavailable").await;
+        let config = config(url);
+
+        let mut schedule = HashMap::new();
+        probe_cycle(&pool, &config, &mut schedule).await;
+        assert!(matches!(
+            pool.status("a").await,
+            Some(AccountStatus::Disabled { .. })
+        ));
+        let first = &schedule["a"];
+        assert_eq!(first.failures, 1);
+        let first_delay = first.next_attempt - Instant::now();
+        assert!(first_delay <= Duration::from_secs(60));
+
+        // Not due yet: the next cycle skips the account
+        probe_cycle(&pool, &config, &mut schedule).await;
+        assert_eq!(schedule["a"].failures, 1);
+
+        // Force it due; the second failure doubles the delay
+        schedule.get_mut("a").unwrap().next_attempt = Instant::now();
+        probe_cycle(&pool, &config, &mut schedule).await;
+        let second = &schedule["a"];
+        assert_eq!(second.failures, 2);
+        assert!(second.next_attempt - Instant::now() > Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn backoff_is_capped() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = disabled_pool(&dir).await;
+        let url = start_upstream(503, "unavailable").await;
+        let config = config(url);
+
+        let mut schedule = HashMap::from([(
+            "a".to_string(),
+            Backoff {
+                failures: 20,
+                next_attempt: Instant::now(),
+            },
+        )]);
+        probe_cycle(&pool, &config, &mut schedule).await;
+        assert!(schedule["a"].next_attempt - Instant::now() <= config.max_backoff);
+    }
+}
@@ -0,0 +1,482 @@
+//! Rate-limit header parsing for Anthropic API responses
+//!
+//! Anthropic reports per-window limiter state on every response through
+//! `anthropic-ratelimit-{window}-{field}` headers (e.g. `requests-remaining`,
+//! `unified-5h-reset`, `unified-7d-utilization`) and sets `retry-after` on
+//! 429s. The pool records the
+//! latest snapshot per account so a quota cooldown can end when the exhausted
+//! window actually reopens instead of after a fixed duration.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use reqwest::header::HeaderMap;
+
+/// Common prefix of all Anthropic rate-limit headers.
+const RATELIMIT_PREFIX: &str = "anthropic-ratelimit-";
+
+/// Upper bound for a header-derived cooldown. The longest Anthropic window is
+/// weekly; anything beyond that is treated as a malformed header and capped.
+const MAX_HEADER_COOLDOWN: Duration = Duration::from_secs(7 * 24 * 3600);
+
+/// State of a single rate-limit window (e.g. "requests", "tokens", "unified-5h").
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RateLimitWindow {
+    pub name: String,
+    pub limit: Option<u64>,
+    pub remaining: Option<u64>,
+    /// Absolute reset time of this window
+    pub reset: Option<SystemTime>,
+    /// Limiter verdict for subscription windows ("allowed", "allowed_warning", "rejected")
+    pub status: Option<String>,
+    /// Used fraction of a subscription window, in `0.0..=1.0`. Subscription
+    /// windows report this instead of a limit and remaining count.
+    pub utilization: Option<f64>,
+}
+
+impl RateLimitWindow {
+    /// Whether the upstream reports this window as used up.
+    pub fn is_exhausted(&self) -> bool {
+        self.remaining == Some(0) || self.status.as_deref() == Some("rejected")
+    }
+
+    /// Remaining fraction of this window, in `0.0..=1.0`, from whichever of
+    /// the status, utilization, or counters the upstream reported.
+    fn remaining_fraction(&self) -> Option<f64> {
+        if self.status.as_deref() == Some("rejected") {
+            return Some(0.0);
+        }
+        if let Some(utilization) = self.utilization {
+            return Some((1.0 - utilization).clamp(0.0, 1.0));
+        }
+        match (self.limit, self.remaining) {
+            (Some(limit), Some(remaining)) if limit > 0 => {
+                Some((remaining as f64 / limit as f64).min(1.0))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Rate-limit state parsed from one upstream response.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RateLimitSnapshot {
+    /// Absolute time derived from the `retry-after` header
+    pub retry_after: Option<SystemTime>,
+    pub windows: Vec<RateLimitWindow>,
+}
+
+impl RateLimitSnapshot {
+    /// Parse rate-limit headers from an upstream response.
+    ///
+    /// Returns `None` when the response carries no rate-limit information, so
+    /// callers keep the previous snapshot rather than overwriting it with an
+    /// empty one. Unparseable values are ignored field by field.
+    pub fn from_headers(headers: &HeaderMap, now: SystemTime) -> Option<Self> {
+        let retry_after = headers
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| parse_retry_after(v, now));
+
+        let mut windows: Vec<RateLimitWindow> = Vec::new();
+        for (name, value) in headers {
+            let Some(rest) = name.as_str().strip_prefix(RATELIMIT_PREFIX) else {
+                continue;
+            };
+            let Some((window_name, field)) = rest.rsplit_once('-') else {
+                continue;
+            };
+            let Ok(value) = value.to_str() else {
+                continue;
+            };
+            let value = value.trim();
+
+            let idx = match windows.iter().position(|w| w.name == window_name) {
+                Some(i) => i,
+                None => {
+                    windows.push(RateLimitWindow {
+                        name: window_name.to_string(),
+                        ..Default::default()
+                    });
+                    windows.len() - 1
+                }
+            };
+            let window = &mut windows[idx];
+            match field {
+                "limit" => window.limit = value.parse().ok(),
+                "remaining" => window.remaining = value.parse().ok(),
+                "reset" => window.reset = parse_reset(value, now),
+                "status" => window.status = Some(value.to_string()),
+                "utilization" => window.utilization = parse_utilization(value),
+                _ => {}
+            }
+        }
+
+        if retry_after.is_none() && windows.is_empty() {
+            return None;
+        }
+        Some(Self {
+            retry_after,
+            windows,
+        })
+    }
+
+    /// When an account that just hit its quota becomes usable again.
+    ///
+    /// Prefers `retry-after`; otherwise uses the latest reset among windows
+    /// reported as exhausted. Returns `None` when the headers don't identify
+    /// a future reset, in which case the caller falls back to its default
+    /// cooldown. The result is capped at one week from `now`.
+    pub fn reset_at(&self, now: SystemTime) -> Option<SystemTime> {
+        let reset = match self.retry_after.filter(|t| *t > now) {
+            Some(t) => t,
+            None => self
+                .windows
+                .iter()
+                .filter(|w| w.is_exhausted())
+                .filter_map(|w| w.reset)
+                .filter(|t| *t > now)
+                .max()?,
+        };
+        Some(reset.min(now + MAX_HEADER_COOLDOWN))
+    }
+
+    /// Remaining fraction of the tightest still-open window, in `0.0..=1.0`.
+    ///
+    /// Returns `None` when no open window reports a utilization, a limit and
+    /// remaining count, or a rejected status.
+    pub fn remaining_fraction(&self, now: SystemTime) -> Option<f64> {
+        self.windows
+            .iter()
+            .filter(|w| w.reset.is_none_or(|reset| reset > now))
+            .filter_map(RateLimitWindow::remaining_fraction)
+            .min_by(f64::total_cmp)
+    }
+
+    /// Whether any still-open window is at or below `reserve_ratio` of its limit.
+    ///
+    /// Windows whose reset time has passed are ignored — their budget has
+    /// refilled since the snapshot was taken. A window the limiter reports as
+    /// rejected or `allowed_warning` counts as low regardless of its counters.
+    pub fn is_low(&self, now: SystemTime, reserve_ratio: f64) -> bool {
+        self.windows.iter().any(|w| {
+            if w.reset.is_some_and(|reset| reset <= now) {
+                return false;
+            }
+            if matches!(w.status.as_deref(), Some("rejected" | "allowed_warning")) {
+                return true;
+            }
+            match w.remaining_fraction() {
+                Some(fraction) => fraction <= reserve_ratio,
+                None => w.remaining == Some(0),
+            }
+        })
+    }
+}
+
+/// Parse a `retry-after` value: delta-seconds (capped at
+/// `MAX_HEADER_COOLDOWN`) or an HTTP-date (RFC 9110).
+fn parse_retry_after(value: &str, now: SystemTime) -> Option<SystemTime> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(now + Duration::from_secs(secs).min(MAX_HEADER_COOLDOWN));
+    }
+    httpdate::parse_http_date(value).ok()
+}
+
+/// Parse a window utilization value: a used fraction such as `0.42`.
+///
+/// The `unified-*-utilization` headers only ever carry fractions, and a
+/// window can run slightly over its limit, so values above 1 mean
+/// exhausted rather than a percentage.
+fn parse_utilization(value: &str) -> Option<f64> {
+    value
+        .parse()
+        .ok()
+        .filter(|u: &f64| u.is_finite() && *u >= 0.0)
+        .map(|u| u.min(1.0))
+}
+
+/// Parse a window reset value: unix seconds or an RFC 3339 timestamp.
+/// Unix seconds past what `SystemTime` can hold become `MAX_HEADER_COOLDOWN`
+/// from `now`, the same cap [`RateLimitSnapshot::reset_at`] applies.
+///
+/// The standard API windows use RFC 3339; the subscription (`unified`)
+/// windows use unix seconds.
+fn parse_reset(value: &str, now: SystemTime) -> Option<SystemTime> {
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(
+            UNIX_EPOCH
+                .checked_add(Duration::from_secs(secs))
+                .unwrap_or(now + MAX_HEADER_COOLDOWN),
+        );
+    }
+    humantime::parse_rfc3339_weak(value).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.insert(*name, HeaderValue::from_str(value).unwrap());
+        }
+        map
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn no_ratelimit_headers_returns_none() {
+        let h = headers(&[("content-type", "application/json")]);
+        assert!(RateLimitSnapshot::from_headers(&h, at(1_000)).is_none());
+    }
+
+    #[test]
+    fn parses_standard_windows() {
+        let h = headers(&[
+            ("anthropic-ratelimit-requests-limit", "50"),
+            ("anthropic-ratelimit-requests-remaining", "49"),
+            ("anthropic-ratelimit-requests-reset", "2026-01-01T00:00:30Z"),
+            ("anthropic-ratelimit-tokens-remaining", "1000"),
+        ]);
+        let snap = RateLimitSnapshot::from_headers(&h, at(1_000)).unwrap();
+        let requests = snap.windows.iter().find(|w| w.name == "requests").unwrap();
+        assert_eq!(requests.limit, Some(50));
+        assert_eq!(requests.remaining, Some(49));
+        assert_eq!(
+            requests.reset,
+            Some(humantime::parse_rfc3339("2026-01-01T00:00:30Z").unwrap())
+        );
+        let tokens = snap.windows.iter().find(|w| w.name == "tokens").unwrap();
+        assert_eq!(tokens.remaining, Some(1000));
+    }
+
+    #[test]
+    fn parses_unified_subscription_windows() {
+        let h = headers(&[
+            ("anthropic-ratelimit-unified-status", "rejected"),
+            ("anthropic-ratelimit-unified-5h-status", "rejected"),
+            ("anthropic-ratelimit-unified-5h-reset", "1700003600"),
+        ]);
+        let snap = RateLimitSnapshot::from_headers(&h, at(1_700_000_000)).unwrap();
+        let five_hour = snap
+            .windows
+            .iter()
+            .find(|w| w.name == "unified-5h")
+            .unwrap();
+        assert!(five_hour.is_exhausted());
+        assert_eq!(five_hour.reset, Some(at(1_700_003_600)));
+    }
+
+    #[test]
+    fn retry_after_seconds_is_relative_to_now() {
+        let h = headers(&[("retry-after", "120")]);
+        let snap = RateLimitSnapshot::from_headers(&h, at(1_000)).unwrap();
+        assert_eq!(snap.retry_after, Some(at(1_120)));
+        assert_eq!(snap.reset_at(at(1_000)), Some(at(1_120)));
+    }
+
+    #[test]
+    fn retry_after_http_date() {
+        let h = headers(&[("retry-after", "Sun, 06 Nov 1994 08:49:37 GMT")]);
+        let snap = RateLimitSnapshot::from_headers(&h, at(0)).unwrap();
+        assert_eq!(snap.retry_after, Some(at(784_111_777)));
+    }
+
+    #[test]
+    fn absurd_values_are_capped_instead_of_overflowing() {
+        let h = headers(&[
+            ("retry-after", "18446744073709551615"),
+            ("anthropic-ratelimit-unified-status", "rejected"),
+            ("anthropic-ratelimit-unified-reset", "18446744073709551615"),
+        ]);
+        let snap = RateLimitSnapshot::from_headers(&h, at(1_000)).unwrap();
+        let cap = at(1_000) + MAX_HEADER_COOLDOWN;
+        assert_eq!(snap.retry_after, Some(cap));
+        assert_eq!(snap.windows[0].reset, Some(cap));
+        assert_eq!(snap.reset_at(at(1_000)), Some(cap));
+    }
+
+    #[test]
+    fn reset_at_prefers_retry_after() {
+        let h = headers(&[
+            ("retry-after", "60"),
+            ("anthropic-ratelimit-unified-5h-status", "rejected"),
+            ("anthropic-ratelimit-unified-5h-reset", "5000"),
+        ]);
+        let snap = RateLimitSnapshot::from_headers(&h, at(1_000)).unwrap();
+        assert_eq!(snap.reset_at(at(1_000)), Some(at(1_060)));
+    }
+
+    #[test]
+    fn reset_at_uses_latest_exhausted_window() {
+        let h = headers(&[
+            ("anthropic-ratelimit-requests-remaining", "0"),
+            ("anthropic-ratelimit-requests-reset", "1200"),
+            ("anthropic-ratelimit-tokens-remaining", "0"),
+            ("anthropic-ratelimit-tokens-reset", "1500"),
+            // Not exhausted — must not extend the cooldown
+            ("anthropic-ratelimit-output-tokens-remaining", "10"),
+            ("anthropic-ratelimit-output-tokens-reset", "9000"),
+        ]);
+        let snap = RateLimitSnapshot::from_headers(&h, at(1_000)).unwrap();
+        assert_eq!(snap.reset_at(at(1_000)), Some(at(1_500)));
+    }
+
+    #[test]
+    fn reset_at_none_without_exhausted_window() {
+        let h = headers(&[
+            ("anthropic-ratelimit-requests-remaining", "5"),
+            ("anthropic-ratelimit-requests-reset", "1200"),
+        ]);
+        let snap = RateLimitSnapshot::from_headers(&h, at(1_000)).unwrap();
+        assert_eq!(snap.reset_at(at(1_000)), None);
+    }
+
+    #[test]
+    fn reset_at_ignores_past_resets() {
+        let h = headers(&[
+            ("anthropic-ratelimit-unified-status", "rejected"),
+            ("anthropic-ratelimit-unified-reset", "500"),
+        ]);
+        let snap = RateLimitSnapshot::from_headers(&h, at(1_000)).unwrap();
+        assert_eq!(snap.reset_at(at(1_000)), None);
+    }
+
+    #[test]
+    fn reset_at_capped_at_one_week() {
+        let h = headers(&[
+            ("anthropic-ratelimit-unified-status", "rejected"),
+            ("anthropic-ratelimit-unified-reset", "4102444800"),
+        ]);
+        let snap = RateLimitSnapshot::from_headers(&h, at(1_000)).unwrap();
+        assert_eq!(
+            snap.reset_at(at(1_000)),
+            Some(at(1_000) + MAX_HEADER_COOLDOWN)
+        );
+    }
+
+    #[test]
+    fn is_low_below_reserve_ratio() {
+        let h = headers(&[
+            ("anthropic-ratelimit-requests-limit", "100"),
+            ("anthropic-ratelimit-requests-remaining", "4"),
+            ("anthropic-ratelimit-requests-reset", "2000"),
+        ]);
+        let snap = RateLimitSnapshot::from_headers(&h, at(1_000)).unwrap();
+        assert!(snap.is_low(at(1_000), 0.05));
+        assert!(!snap.is_low(at(1_000), 0.01));
+    }
+
+    #[test]
+    fn is_low_ignores_windows_past_reset() {
+        let h = headers(&[
+            ("anthropic-ratelimit-requests-limit", "100"),
+            ("anthropic-ratelimit-requests-remaining", "0"),
+            ("anthropic-ratelimit-requests-reset", "2000"),
+        ]);
+        let snap = RateLimitSnapshot::from_headers(&h, at(1_000)).unwrap();
+        assert!(snap.is_low(at(1_500), 0.05));
+        assert!(!snap.is_low(at(2_000), 0.05));
+    }
+
+    #[test]
+    fn is_low_on_rejected_status() {
+        let h = headers(&[("anthropic-ratelimit-unified-5h-status", "rejected")]);
+        let snap = RateLimitSnapshot::from_headers(&h, at(1_000)).unwrap();
+        assert!(snap.is_low(at(1_000), 0.0));
+    }
+
+    #[test]
+    fn is_low_from_unified_utilization_and_warning() {
+        let h = headers(&[
+            ("anthropic-ratelimit-unified-status", "allowed"),
+            ("anthropic-ratelimit-unified-5h-status", "allowed"),
+            ("anthropic-ratelimit-unified-5h-utilization", "0.97"),
+            ("anthropic-ratelimit-unified-5h-reset", "2000"),
+            ("anthropic-ratelimit-unified-7d-status", "allowed"),
+            ("anthropic-ratelimit-unified-7d-utilization", "0.4"),
+            ("anthropic-ratelimit-unified-7d-reset", "600000"),
+        ]);
+        let snap = RateLimitSnapshot::from_headers(&h, at(1_000)).unwrap();
+        let five_hour = snap
+            .windows
+            .iter()
+            .find(|w| w.name == "unified-5h")
+            .unwrap();
+        assert_eq!(five_hour.utilization, Some(0.97));
+        assert!(snap.is_low(at(1_000), 0.05));
+        assert!(!snap.is_low(at(1_000), 0.01));
+        let fraction = snap.remaining_fraction(at(1_000)).unwrap();
+        assert!((fraction - 0.03).abs() < 1e-9, "{fraction}");
+        // Once the 5h window resets, only the 7d window counts
+        assert!(!snap.is_low(at(2_000), 0.05));
+        assert_eq!(snap.remaining_fraction(at(2_000)), Some(0.6));
+
+        let h = headers(&[
+            ("anthropic-ratelimit-unified-7d-status", "allowed_warning"),
+            ("anthropic-ratelimit-unified-7d-utilization", "0.8"),
+            ("anthropic-ratelimit-unified-7d-reset", "600000"),
+        ]);
+        let snap = RateLimitSnapshot::from_headers(&h, at(1_000)).unwrap();
+        assert!(snap.is_low(at(1_000), 0.05));
+    }
+
+    #[test]
+    fn utilization_over_one_is_exhausted_not_a_percentage() {
+        let h = headers(&[
+            ("anthropic-ratelimit-unified-5h-status", "allowed"),
+            ("anthropic-ratelimit-unified-5h-utilization", "1.02"),
+            ("anthropic-ratelimit-unified-5h-reset", "2000"),
+        ]);
+        let snap = RateLimitSnapshot::from_headers(&h, at(1_000)).unwrap();
+        assert_eq!(snap.windows[0].utilization, Some(1.0));
+        assert_eq!(snap.remaining_fraction(at(1_000)), Some(0.0));
+        assert!(snap.is_low(at(1_000), 0.05));
+    }
+
+    #[test]
+    fn is_low_false_with_plenty_remaining() {
+        let h = headers(&[
+            ("anthropic-ratelimit-tokens-limit", "80000"),
+            ("anthropic-ratelimit-tokens-remaining", "60000"),
+            ("anthropic-ratelimit-unified-status", "allowed"),
+        ]);
+        let snap = RateLimitSnapshot::from_headers(&h, at(1_000)).unwrap();
+        assert!(!snap.is_low(at(1_000), 0.05));
+    }
+
+    #[test]
+    fn remaining_fraction_uses_tightest_window() {
+        let h = headers(&[
+            ("anthropic-ratelimit-requests-limit", "100"),
+            ("anthropic-ratelimit-requests-remaining", "50"),
+            ("anthropic-ratelimit-tokens-limit", "1000"),
+            ("anthropic-ratelimit-tokens-remaining", "100"),
+        ]);
+        let snap = RateLimitSnapshot::from_headers(&h, at(1_000)).unwrap();
+        assert_eq!(snap.remaining_fraction(at(1_000)), Some(0.1));
+
+        let h = headers(&[("anthropic-ratelimit-unified-status", "allowed")]);
+        let snap = RateLimitSnapshot::from_headers(&h, at(1_000)).unwrap();
+        assert_eq!(snap.remaining_fraction(at(1_000)), None);
+    }
+
+    #[test]
+    fn unparseable_values_are_ignored() {
+        let h = headers(&[
+            ("anthropic-ratelimit-requests-remaining", "lots"),
+            ("anthropic-ratelimit-requests-reset", "soon"),
+            ("retry-after", "later"),
+        ]);
+        let snap = RateLimitSnapshot::from_headers(&h, at(1_000)).unwrap();
+        assert!(snap.retry_after.is_none());
+        assert_eq!(snap.windows[0].remaining, None);
+        assert_eq!(snap.windows[0].reset, None);
+    }
+}
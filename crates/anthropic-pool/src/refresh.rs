@@ -63,20 +63,19 @@ async fn refresh_cycle(pool: &Pool, threshold: Duration) {
             "token expiring within threshold, refreshing"
         );
 
-        match anthropic_auth::refresh_token(client, &credential.refresh).await {
-            Ok(token_response) => {
-                let new_expires = now_millis + (token_response.expires_in * 1000);
-                if let Err(e) = store
-                    .update_token(
-                        id,
-                        token_response.access_token,
-                        token_response.refresh_token,
-                        new_expires,
-                    )
-                    .await
-                {
-                    warn!(account_id = id, error = %e, "failed to persist refreshed token");
-                }
+        let refreshed = store
+            .refresh(id, &credential.refresh, |refresh| async move {
+                anthropic_auth::refresh_token(client, &refresh).await
+            })
+            .await;
+        match refreshed {
+            Ok(outcome) if !outcome.refreshed => {
+                debug!(
+                    account_id = id,
+                    "token already refreshed by another replica"
+                );
+            }
+            Ok(_) => {
                 metrics::counter!("pool_token_refreshes_total", "account_id" => id.to_string(), "result" => "success")
                     .increment(1);
                 info!(account_id = id, "background token refresh succeeded");
@@ -85,7 +84,13 @@ async fn refresh_cycle(pool: &Pool, threshold: Duration) {
                 metrics::counter!("pool_token_refreshes_total", "account_id" => id.to_string(), "result" => "failure")
                     .increment(1);
                 warn!(account_id = id, error = %msg, "refresh token rejected, disabling account");
-                pool.set_status(id, AccountStatus::Disabled).await;
+                pool.set_status(
+                    id,
+                    AccountStatus::Disabled {
+                        reason: format!("refresh token rejected: {msg}"),
+                    },
+                )
+                .await;
             }
             Err(e) => {
                 metrics::counter!("pool_token_refreshes_total", "account_id" => id.to_string(), "result" => "failure")
@@ -0,0 +1,145 @@
+//! Reloading credentials changed outside the proxy
+//!
+//! The credential store only sees its own writes unless it re-reads the
+//! backend. This background task polls the store for changes made by anyone
+//! else (an operator editing `credentials.json`, a sidecar, another replica's
+//! admin API) and adds or removes pool accounts to match. Polling the
+//! backend version rather than watching for filesystem events works for
+//! every storage backend, and for files replaced through a symlink swap as
+//! mounted Secrets are.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tracing::{debug, info, warn};
+
+use crate::pool::Pool;
+
+/// Spawn a background task that reloads the credential store every
+/// `interval` and applies added and removed accounts to the pool.
+///
+/// `accounts` restricts additions to the listed IDs, for pools configured
+/// with an explicit account list; `None` adds every new account.
+///
+/// A reload that fails (unreadable backend, unparseable document) is logged
+/// and retried on the next tick; the pool keeps its current accounts.
+/// Reloads are counted in `pool_credential_reloads_total{result}`.
+///
+/// Returns a `JoinHandle` for the spawned task.
+pub fn spawn_reload_task(
+    pool: Arc<Pool>,
+    interval: Duration,
+    accounts: Option<Vec<String>>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        // Skip the immediate first tick — credentials were just loaded
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+            reload_cycle(&pool, accounts.as_deref()).await;
+        }
+    })
+}
+
+/// Reload the store once and bring the pool's account list in line.
+async fn reload_cycle(pool: &Pool, accounts: Option<&[String]>) {
+    let store = pool.credential_store();
+    let changes = match store.reload().await {
+        Ok(changes) => changes,
+        Err(e) => {
+            metrics::counter!("pool_credential_reloads_total", "result" => "failure").increment(1);
+            warn!(location = store.location(), error = %e, "failed to reload credentials, keeping current accounts");
+            return;
+        }
+    };
+    if changes.is_empty() {
+        return;
+    }
+    metrics::counter!("pool_credential_reloads_total", "result" => "changed").increment(1);
+
+    for id in changes.added {
+        if accounts.is_some_and(|accounts| !accounts.contains(&id)) {
+            debug!(account_id = %id, "account added to credential store but not listed in providers, ignoring");
+            continue;
+        }
+        info!(account_id = %id, "account added to credential store externally");
+        pool.add_account(id).await;
+    }
+    for id in &changes.removed {
+        info!(account_id = %id, "account removed from credential store externally");
+        pool.remove_account(id).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anthropic_auth::{Credential, CredentialStore};
+
+    fn credential(id: &str) -> Credential {
+        Credential {
+            credential_type: "oauth".into(),
+            refresh: format!("rt_{id}"),
+            access: format!("at_{id}"),
+            expires: 4_102_444_800_000,
+        }
+    }
+
+    #[tokio::test]
+    async fn reload_cycle_applies_external_changes_to_pool() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("credentials.json");
+        let store = Arc::new(CredentialStore::load(path.clone()).await.unwrap());
+        store.add("a".into(), credential("a")).await.unwrap();
+        let pool = Pool::new(
+            vec!["a".into()],
+            Duration::from_secs(7200),
+            store,
+            reqwest::Client::new(),
+        );
+
+        // Another process (or replica) replaces account a with b
+        let other = CredentialStore::load(path.clone()).await.unwrap();
+        other.add("b".into(), credential("b")).await.unwrap();
+        other.remove("a").await.unwrap();
+
+        reload_cycle(&pool, None).await;
+        assert_eq!(pool.account_ids().await, vec!["b"]);
+        assert_eq!(pool.len(), 1);
+        assert!(pool.status("a").await.is_none());
+        let selected = pool.select().await.unwrap();
+        assert_eq!(selected.id, "b");
+        assert_eq!(selected.access_token, "at_b");
+        pool.release(&selected.id);
+
+        // A broken edit leaves the pool as it was
+        std::fs::write(&path, b"not json").unwrap();
+        reload_cycle(&pool, None).await;
+        assert_eq!(pool.account_ids().await, vec!["b"]);
+    }
+
+    #[tokio::test]
+    async fn reload_cycle_only_adds_listed_accounts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("credentials.json");
+        let store = Arc::new(CredentialStore::load(path.clone()).await.unwrap());
+        store.add("a".into(), credential("a")).await.unwrap();
+        let pool = Pool::new(
+            vec!["a".into()],
+            Duration::from_secs(7200),
+            store,
+            reqwest::Client::new(),
+        );
+        let listed = ["a".to_string(), "b".to_string()];
+
+        let other = CredentialStore::load(path).await.unwrap();
+        other.add("b".into(), credential("b")).await.unwrap();
+        other.add("c".into(), credential("c")).await.unwrap();
+
+        reload_cycle(&pool, Some(&listed)).await;
+        assert_eq!(pool.account_ids().await, vec!["a", "b"]);
+        assert_eq!(pool.len(), 2);
+    }
+}
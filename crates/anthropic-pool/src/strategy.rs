@@ -0,0 +1,275 @@
+//! Account selection strategies
+//!
+//! A strategy only decides the order in which the pool tries accounts; the
+//! pool still skips unavailable accounts, refreshes tokens inline, and moves
+//! accounts near their quota reserve to the back of the order.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Instant, SystemTime};
+
+use rand::RngExt;
+use serde::Deserialize;
+
+use crate::ratelimit::RateLimitSnapshot;
+
+/// Order in which `Pool::select` tries accounts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SelectionStrategy {
+    /// Rotate through accounts, one request each.
+    #[default]
+    RoundRobin,
+    /// Prefer the account that was selected longest ago (never-used first).
+    LeastRecentlyUsed,
+    /// Prefer the account with the fewest requests currently in flight.
+    LeastInFlight,
+    /// Random choice weighted by the remaining fraction of each account's
+    /// tightest rate-limit window. Accounts without rate-limit data get full weight.
+    WeightedQuota,
+    /// Always use the first available account in configured order, moving on
+    /// only when it cools down or reaches its quota reserve. Keeps the other
+    /// accounts' rolling windows untouched for bursts.
+    FillFirst,
+}
+
+impl fmt::Display for SelectionStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// Per-account usage the pool tracks for selection.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AccountUsage {
+    pub in_flight: usize,
+    pub last_selected: Option<Instant>,
+}
+
+/// Inputs a strategy may consult when ordering accounts.
+pub(crate) struct SelectionInput<'a> {
+    pub ids: &'a [String],
+    /// Round-robin rotation offset for this selection
+    pub start: usize,
+    pub usage: &'a HashMap<String, AccountUsage>,
+    pub rate_limits: &'a HashMap<String, RateLimitSnapshot>,
+    pub now: SystemTime,
+}
+
+/// Minimum weight so accounts reporting an empty window can still be drawn
+/// when nothing else is available.
+const MIN_QUOTA_WEIGHT: f64 = 0.001;
+
+impl SelectionStrategy {
+    /// Config/health label (matches the TOML spelling).
+    pub fn label(&self) -> &'static str {
+        match self {
+            SelectionStrategy::RoundRobin => "round-robin",
+            SelectionStrategy::LeastRecentlyUsed => "least-recently-used",
+            SelectionStrategy::LeastInFlight => "least-in-flight",
+            SelectionStrategy::WeightedQuota => "weighted-quota",
+            SelectionStrategy::FillFirst => "fill-first",
+        }
+    }
+
+    /// Return indices into `input.ids` in the order they should be tried.
+    pub(crate) fn order(&self, input: &SelectionInput<'_>) -> Vec<usize> {
+        let ids = input.ids;
+        let n = ids.len();
+        let usage = |idx: usize| input.usage.get(&ids[idx]);
+        let mut rotated: Vec<usize> = (0..n).map(|offset| (input.start + offset) % n).collect();
+
+        match self {
+            SelectionStrategy::RoundRobin => rotated,
+            SelectionStrategy::FillFirst => (0..n).collect(),
+            SelectionStrategy::LeastRecentlyUsed => {
+                // None (never selected) sorts before any Some
+                rotated.sort_by_key(|&idx| usage(idx).and_then(|u| u.last_selected));
+                rotated
+            }
+            SelectionStrategy::LeastInFlight => {
+                // Stable sort keeps the rotation as the tie-breaker
+                rotated.sort_by_key(|&idx| usage(idx).map_or(0, |u| u.in_flight));
+                rotated
+            }
+            SelectionStrategy::WeightedQuota => {
+                // Weighted shuffle (Efraimidis–Spirakis): key = u^(1/w), highest first
+                let mut rng = rand::rng();
+                let mut keyed: Vec<(f64, usize)> = ids
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, id)| {
+                        let weight = input
+                            .rate_limits
+                            .get(id)
+                            .and_then(|s| s.remaining_fraction(input.now))
+                            .unwrap_or(1.0)
+                            .max(MIN_QUOTA_WEIGHT);
+                        let u: f64 = rng.random();
+                        (u.powf(1.0 / weight), idx)
+                    })
+                    .collect();
+                keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+                keyed.into_iter().map(|(_, idx)| idx).collect()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderMap;
+    use std::time::Duration;
+
+    fn ids(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn order_of(
+        strategy: SelectionStrategy,
+        ids: &[String],
+        start: usize,
+        usage: &HashMap<String, AccountUsage>,
+        rate_limits: &HashMap<String, RateLimitSnapshot>,
+    ) -> Vec<String> {
+        strategy
+            .order(&SelectionInput {
+                ids,
+                start,
+                usage,
+                rate_limits,
+                now: SystemTime::now(),
+            })
+            .into_iter()
+            .map(|idx| ids[idx].clone())
+            .collect()
+    }
+
+    #[test]
+    fn round_robin_rotates_from_start() {
+        let ids = ids(&["a", "b", "c"]);
+        let order = order_of(
+            SelectionStrategy::RoundRobin,
+            &ids,
+            1,
+            &HashMap::new(),
+            &HashMap::new(),
+        );
+        assert_eq!(order, vec!["b", "c", "a"]);
+    }
+
+    #[test]
+    fn fill_first_ignores_rotation() {
+        let ids = ids(&["a", "b", "c"]);
+        let order = order_of(
+            SelectionStrategy::FillFirst,
+            &ids,
+            2,
+            &HashMap::new(),
+            &HashMap::new(),
+        );
+        assert_eq!(order, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn least_recently_used_prefers_never_used_then_oldest() {
+        let ids = ids(&["a", "b", "c"]);
+        let now = Instant::now();
+        let usage = HashMap::from([
+            (
+                "a".to_string(),
+                AccountUsage {
+                    in_flight: 0,
+                    last_selected: Some(now),
+                },
+            ),
+            (
+                "b".to_string(),
+                AccountUsage {
+                    in_flight: 0,
+                    last_selected: Some(now - Duration::from_secs(10)),
+                },
+            ),
+        ]);
+        let order = order_of(
+            SelectionStrategy::LeastRecentlyUsed,
+            &ids,
+            0,
+            &usage,
+            &HashMap::new(),
+        );
+        assert_eq!(order, vec!["c", "b", "a"]);
+    }
+
+    #[test]
+    fn least_in_flight_prefers_idle_accounts() {
+        let ids = ids(&["a", "b", "c"]);
+        let usage = HashMap::from([
+            (
+                "a".to_string(),
+                AccountUsage {
+                    in_flight: 3,
+                    last_selected: None,
+                },
+            ),
+            (
+                "c".to_string(),
+                AccountUsage {
+                    in_flight: 1,
+                    last_selected: None,
+                },
+            ),
+        ]);
+        let order = order_of(
+            SelectionStrategy::LeastInFlight,
+            &ids,
+            0,
+            &usage,
+            &HashMap::new(),
+        );
+        assert_eq!(order, vec!["b", "c", "a"]);
+    }
+
+    #[test]
+    fn weighted_quota_favors_accounts_with_more_remaining() {
+        let ids = ids(&["low", "high"]);
+        let snapshot = |remaining: &str| {
+            let mut h = HeaderMap::new();
+            h.insert("anthropic-ratelimit-requests-limit", "100".parse().unwrap());
+            h.insert(
+                "anthropic-ratelimit-requests-remaining",
+                remaining.parse().unwrap(),
+            );
+            RateLimitSnapshot::from_headers(&h, SystemTime::now()).unwrap()
+        };
+        let rate_limits = HashMap::from([
+            ("low".to_string(), snapshot("5")),
+            ("high".to_string(), snapshot("95")),
+        ]);
+
+        let high_first = (0..200)
+            .filter(|_| {
+                order_of(
+                    SelectionStrategy::WeightedQuota,
+                    &ids,
+                    0,
+                    &HashMap::new(),
+                    &rate_limits,
+                )[0] == "high"
+            })
+            .count();
+        assert!(high_first > 150, "high chosen first {high_first}/200 times");
+    }
+
+    #[test]
+    fn deserializes_kebab_case() {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            strategy: SelectionStrategy,
+        }
+        let w: Wrapper = serde_json::from_str(r#"{"strategy":"fill-first"}"#).unwrap();
+        assert_eq!(w.strategy, SelectionStrategy::FillFirst);
+        assert_eq!(w.strategy.to_string(), "fill-first");
+    }
+}
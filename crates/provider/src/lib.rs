@@ -9,6 +9,8 @@ pub mod passthrough;
 
 pub use passthrough::PassthroughProvider;
 
+use bytes::Bytes;
+use futures_core::Stream;
 use serde::Serialize;
 use std::future::Future;
 use std::pin::Pin;
@@ -56,11 +58,31 @@ pub enum ProviderError {
 /// Result alias for provider operations.
 pub type Result<T> = std::result::Result<T, ProviderError>;
 
+/// Upstream response body as it streams to the client.
+pub type BodyStream = Pin<
+    Box<
+        dyn Stream<Item = std::result::Result<Bytes, Box<dyn std::error::Error + Send + Sync>>>
+            + Send,
+    >,
+>;
+
+/// What the proxy knows about a request before handing it to the provider.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RequestContext<'a> {
+    /// Authenticated client name (`anonymous` when client auth is off)
+    pub client: &'a str,
+}
+
 /// Abstraction over upstream API authentication strategies.
 ///
 /// The proxy delegates all auth concerns to the provider:
 /// - `prepare_request` injects/modifies headers and optionally the body
+/// - `observe_response` sees the headers of every upstream response
+/// - `wrap_response` adapts the headers and body of a streamed response
+/// - `release_account` marks the end of a request that used an account
 /// - `classify_error` determines retry vs failover vs disable
+/// - `tier` names the tier an account belongs to, for chained providers
+/// - `max_failover_attempts` reports a failover budget that changes at runtime
 /// - `health` reports provider-specific status for the health endpoint
 ///
 /// Uses `Pin<Box<dyn Future>>` return types for dyn-compatibility (`Arc<dyn Provider>`).
@@ -85,12 +107,75 @@ pub trait Provider: Send + Sync {
     /// If `needs_body()` is false, `body` will be `Value::Null` and should be ignored.
     fn prepare_request<'a>(
         &'a self,
+        context: RequestContext<'a>,
         headers: &'a mut reqwest::header::HeaderMap,
         body: &'a mut serde_json::Value,
     ) -> Pin<Box<dyn Future<Output = Result<Option<String>>> + Send + 'a>>;
 
+    /// Observe the status and headers of an upstream response for an account.
+    ///
+    /// Called for every upstream response (success or error) before error
+    /// classification. OAuth mode records rate-limit headers so quota cooldowns
+    /// can follow the upstream's reported reset times. Default: no-op.
+    fn observe_response<'a>(
+        &'a self,
+        _account_id: &'a str,
+        _status: u16,
+        _headers: &'a reqwest::header::HeaderMap,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
+        Box::pin(async {})
+    }
+
+    /// Adapt a response before it streams to the client.
+    ///
+    /// Called once per response the proxy streams back (not for buffered
+    /// error responses), after the proxy's own stream adapters. `account_id`
+    /// is the account `prepare_request` returned. `request` is the body as
+    /// the client sent it, before `prepare_request`, or `Value::Null` when
+    /// `needs_body()` is false. The provider may edit `headers` and return a
+    /// wrapped `body`; a body that changes length must drop `Content-Length`.
+    /// Default: pass through unchanged.
+    fn wrap_response(
+        &self,
+        _account_id: Option<&str>,
+        _request: &serde_json::Value,
+        _status: u16,
+        _headers: &mut reqwest::header::HeaderMap,
+        body: BodyStream,
+    ) -> BodyStream {
+        body
+    }
+
+    /// Signal that the request which selected `account_id` has finished.
+    ///
+    /// Called exactly once per account returned by `prepare_request`, after the
+    /// response body has been fully streamed, the attempt failed over, or the
+    /// client disconnected. Synchronous so the proxy can call it from `Drop`.
+    /// Default: no-op.
+    fn release_account(&self, _account_id: &str) {}
+
     /// Classify an upstream error response to determine the retry strategy.
-    fn classify_error(&self, status: u16, body: &str) -> ErrorClassification;
+    ///
+    /// `account_id` is the account the failed request used, if any.
+    fn classify_error(
+        &self,
+        account_id: Option<&str>,
+        status: u16,
+        body: &str,
+    ) -> ErrorClassification;
+
+    /// Name of the tier that owns `account_id`, for providers that combine
+    /// several (reported in a response header and metrics). Default: None.
+    fn tier(&self, _account_id: &str) -> Option<&str> {
+        None
+    }
+
+    /// Current failover budget (one attempt per account), for providers whose
+    /// accounts can change after startup. Default: None, in which case the
+    /// proxy keeps the budget it computed at startup.
+    fn max_failover_attempts(&self) -> Option<usize> {
+        None
+    }
 
     /// Report an error classification back to the provider for state management.
     /// OAuth mode uses this to transition accounts (cooldown, disable).
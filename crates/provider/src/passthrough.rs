@@ -4,7 +4,7 @@
 //! continues to work identically. The proxy delegates to this provider when no
 //! `[oauth]` section is present.
 
-use crate::{ErrorClassification, Provider, ProviderHealth};
+use crate::{ErrorClassification, Provider, ProviderHealth, RequestContext};
 use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use std::future::Future;
 use std::pin::Pin;
@@ -43,6 +43,7 @@ impl Provider for PassthroughProvider {
 
     fn prepare_request<'a>(
         &'a self,
+        _context: RequestContext<'a>,
         headers: &'a mut HeaderMap,
         _body: &'a mut serde_json::Value,
     ) -> Pin<Box<dyn Future<Output = crate::Result<Option<String>>> + Send + 'a>> {
@@ -70,7 +71,12 @@ impl Provider for PassthroughProvider {
         Box::pin(async { Ok(None) })
     }
 
-    fn classify_error(&self, _status: u16, _body: &str) -> ErrorClassification {
+    fn classify_error(
+        &self,
+        _account_id: Option<&str>,
+        _status: u16,
+        _body: &str,
+    ) -> ErrorClassification {
         // Passthrough has no pool — all errors are transient from its perspective.
         // The existing retry logic in proxy.rs handles timeouts.
         ErrorClassification::Transient
@@ -115,7 +121,7 @@ mod tests {
         let mut headers = HeaderMap::new();
         let mut body = serde_json::Value::Null;
         provider
-            .prepare_request(&mut headers, &mut body)
+            .prepare_request(RequestContext::default(), &mut headers, &mut body)
             .await
             .unwrap();
 
@@ -140,7 +146,7 @@ mod tests {
         headers.insert("authorization", HeaderValue::from_static("Bearer sk-real"));
         let mut body = serde_json::Value::Null;
         provider
-            .prepare_request(&mut headers, &mut body)
+            .prepare_request(RequestContext::default(), &mut headers, &mut body)
             .await
             .unwrap();
 
@@ -160,7 +166,7 @@ mod tests {
         let mut headers = HeaderMap::new();
         let mut body = serde_json::Value::Null;
         provider
-            .prepare_request(&mut headers, &mut body)
+            .prepare_request(RequestContext::default(), &mut headers, &mut body)
             .await
             .unwrap();
 
@@ -172,15 +178,15 @@ mod tests {
     fn classify_error_always_returns_transient() {
         let provider = PassthroughProvider::new(vec![]);
         assert_eq!(
-            provider.classify_error(429, "rate limit"),
+            provider.classify_error(None, 429, "rate limit"),
             ErrorClassification::Transient
         );
         assert_eq!(
-            provider.classify_error(401, "unauthorized"),
+            provider.classify_error(None, 401, "unauthorized"),
             ErrorClassification::Transient
         );
         assert_eq!(
-            provider.classify_error(500, "server error"),
+            provider.classify_error(None, 500, "server error"),
             ErrorClassification::Transient
         );
     }
@@ -216,7 +222,7 @@ mod tests {
         headers.insert("anthropic-beta", HeaderValue::from_static("old-value"));
         let mut body = serde_json::Value::Null;
         provider
-            .prepare_request(&mut headers, &mut body)
+            .prepare_request(RequestContext::default(), &mut headers, &mut body)
             .await
             .unwrap();
 
@@ -239,7 +245,7 @@ mod tests {
         let mut headers = HeaderMap::new();
         let mut body = serde_json::Value::Null;
         provider
-            .prepare_request(&mut headers, &mut body)
+            .prepare_request(RequestContext::default(), &mut headers, &mut body)
             .await
             .unwrap();
 
@@ -8,6 +8,7 @@
 //! - GET  /admin/accounts         — list accounts with status
 //! - POST /admin/accounts/init-oauth    — start PKCE flow, return auth URL
 //! - POST /admin/accounts/complete-oauth — exchange code, store credential, add to pool
+//! - POST /admin/accounts/import  — store Claude CLI credentials, add to pool
 //! - DELETE /admin/accounts/:id   — remove account from pool + credential store
 //! - GET  /admin/pool             — pool status summary
 
@@ -16,7 +17,8 @@ use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use axum::Router;
-use axum::extract::{Path, State};
+use axum::body::Bytes;
+use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::routing::{delete, get, post};
@@ -62,6 +64,7 @@ pub fn build_admin_router(state: AdminState) -> Router {
         .route("/admin/accounts", get(list_accounts))
         .route("/admin/accounts/init-oauth", post(init_oauth))
         .route("/admin/accounts/complete-oauth", post(complete_oauth))
+        .route("/admin/accounts/import", post(import_account))
         .route("/admin/accounts/{id}", delete(delete_account))
         .route("/admin/pool", get(pool_status))
         .with_state(state)
@@ -91,11 +94,7 @@ async fn list_accounts(State(state): State<AdminState>) -> impl IntoResponse {
 /// verifier + challenge, builds the authorization URL, and stores the verifier
 /// in memory for complete-oauth to consume.
 async fn init_oauth(State(state): State<AdminState>) -> impl IntoResponse {
-    let timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs();
-    let account_id = format!("claude-max-{timestamp}");
+    let account_id = new_account_id();
 
     let verifier = anthropic_auth::generate_verifier();
     let challenge = anthropic_auth::compute_challenge(&verifier);
@@ -248,6 +247,76 @@ async fn complete_oauth(
     )
 }
 
+/// Query parameters for the import endpoint.
+#[derive(Deserialize)]
+struct ImportQuery {
+    account_id: Option<String>,
+}
+
+/// POST /admin/accounts/import — add an account from Claude CLI credentials.
+///
+/// The body is the CLI's credential JSON as-is (`~/.claude/.credentials.json`
+/// or its keychain entry). The account ID comes from `?account_id=`, or is
+/// generated like init-oauth's. Importing over an existing ID replaces its
+/// tokens and makes it available again; generated IDs are always new.
+async fn import_account(
+    State(state): State<AdminState>,
+    Query(query): Query<ImportQuery>,
+    body: Bytes,
+) -> impl IntoResponse {
+    let credential = match anthropic_auth::parse_claude_cli_credentials(&body) {
+        Ok(c) => c,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                [(axum::http::header::CONTENT_TYPE, "application/json")],
+                serde_json::json!({ "error": e.to_string() }).to_string(),
+            );
+        }
+    };
+    let account_id = match query.account_id {
+        Some(id) => {
+            if let Err(e) = validate_account_id(&id) {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    [(axum::http::header::CONTENT_TYPE, "application/json")],
+                    serde_json::json!({ "error": e }).to_string(),
+                );
+            }
+            id
+        }
+        None => new_account_id(),
+    };
+
+    let credential_store = state.pool.credential_store();
+    let replaced = credential_store.get(&account_id).await.is_some();
+    if let Err(e) = credential_store.add(account_id.clone(), credential).await {
+        warn!(account_id, error = %e, "failed to store imported credential");
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            [(axum::http::header::CONTENT_TYPE, "application/json")],
+            serde_json::json!({
+                "error": format!("failed to store credential: {e}")
+            })
+            .to_string(),
+        );
+    }
+
+    state.pool.add_account(account_id.clone()).await;
+
+    info!(account_id, replaced, "Claude CLI credentials imported");
+
+    (
+        StatusCode::OK,
+        [(axum::http::header::CONTENT_TYPE, "application/json")],
+        serde_json::json!({
+            "account_id": account_id,
+            "status": if replaced { "replaced" } else { "added" }
+        })
+        .to_string(),
+    )
+}
+
 /// DELETE /admin/accounts/:id — remove account from pool and credential store.
 async fn delete_account(
     State(state): State<AdminState>,
@@ -284,6 +353,31 @@ async fn pool_status(State(state): State<AdminState>) -> impl IntoResponse {
     )
 }
 
+/// A fresh account ID: the current unix timestamp plus a random suffix, so
+/// two accounts created in the same second don't share (and overwrite) one.
+pub(crate) fn new_account_id() -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let suffix = uuid::Uuid::new_v4().simple().to_string();
+    format!("claude-max-{timestamp}-{}", &suffix[..8])
+}
+
+/// Check a caller-chosen account ID: non-empty, and usable as the `{id}`
+/// path segment of `DELETE /admin/accounts/{id}`.
+pub(crate) fn validate_account_id(id: &str) -> Result<(), String> {
+    if id.trim().is_empty() {
+        return Err("account_id must not be empty".into());
+    }
+    if id.contains('/') || id.chars().any(char::is_whitespace) {
+        return Err(format!(
+            "account_id '{id}' must not contain '/' or whitespace"
+        ));
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -554,6 +648,146 @@ mod tests {
         assert!(pool.credential_store().get("delete-me").await.is_none());
     }
 
+    #[tokio::test]
+    async fn import_adds_claude_cli_credentials() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = test_pool(dir.path()).await;
+        let state = test_admin_state(pool.clone());
+        let app = build_admin_router(state);
+        let cli_file = serde_json::json!({
+            "claudeAiOauth": {
+                "accessToken": "at_cli",
+                "refreshToken": "rt_cli",
+                "expiresAt": 4_102_444_800_000u64,
+                "subscriptionType": "max"
+            }
+        });
+
+        let import = |uri: &str| {
+            Request::builder()
+                .method("POST")
+                .uri(uri)
+                .body(Body::from(cli_file.to_string()))
+                .unwrap()
+        };
+        let response = app
+            .clone()
+            .oneshot(import("/admin/accounts/import?account_id=laptop"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), 1024 * 1024)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["account_id"], "laptop");
+        assert_eq!(json["status"], "added");
+
+        assert_eq!(pool.account_ids().await, vec!["laptop"]);
+        let credential = pool.credential_store().get("laptop").await.unwrap();
+        assert_eq!(credential.credential_type, "oauth");
+        assert_eq!(credential.access, "at_cli");
+        assert_eq!(credential.refresh, "rt_cli");
+        assert_eq!(credential.expires, 4_102_444_800_000);
+
+        // Importing again replaces the tokens; no ID generates one
+        let response = app
+            .clone()
+            .oneshot(import("/admin/accounts/import?account_id=laptop"))
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), 1024 * 1024)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["status"], "replaced");
+        let response = app.oneshot(import("/admin/accounts/import")).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), 1024 * 1024)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(
+            json["account_id"]
+                .as_str()
+                .unwrap()
+                .starts_with("claude-max-")
+        );
+        assert_eq!(pool.account_ids().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn import_without_account_id_never_replaces() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = test_pool(dir.path()).await;
+        let app = build_admin_router(test_admin_state(pool.clone()));
+        let cli_file =
+            r#"{"claudeAiOauth": {"accessToken": "at", "refreshToken": "rt", "expiresAt": 1}}"#;
+
+        // Back to back, well inside one second
+        for _ in 0..2 {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/admin/accounts/import")
+                        .body(Body::from(cli_file))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            let body = axum::body::to_bytes(response.into_body(), 1024 * 1024)
+                .await
+                .unwrap();
+            let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(json["status"], "added");
+        }
+        assert_eq!(pool.account_ids().await.len(), 2);
+
+        for bad in ["", "%20", "a%2Fb"] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri(format!("/admin/accounts/import?account_id={bad}"))
+                        .body(Body::from(cli_file))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{bad:?}");
+        }
+        assert_eq!(pool.account_ids().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn import_rejects_non_oauth_credentials() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = test_pool(dir.path()).await;
+        let state = test_admin_state(pool.clone());
+        let app = build_admin_router(state);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/admin/accounts/import")
+                    .body(Body::from(r#"{"primaryApiKey": "sk-ant-api"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), 1024 * 1024)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(json["error"].as_str().unwrap().contains("claudeAiOauth"));
+        assert!(pool.account_ids().await.is_empty());
+    }
+
     #[tokio::test]
     async fn pool_status_returns_pool_health() {
         let dir = tempfile::tempdir().unwrap();
@@ -0,0 +1,324 @@
+//! Provider fallback chain
+//!
+//! `ChainProvider` tries an ordered list of provider tiers, typically the
+//! OAuth pool first and paid API keys second. Each request goes to the first
+//! tier whose model and client filters admit it and that has an account
+//! free: a tier answering `PoolExhausted` hands the request to the next one.
+//! Later callbacks for the selected account (response observation, error
+//! classification, release) go to the tier that selected it.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use provider::{
+    BodyStream, ErrorClassification, Provider, ProviderError, ProviderHealth, RequestContext,
+};
+use reqwest::header::HeaderMap;
+use tracing::{debug, info};
+
+use crate::clients::model_matches;
+use crate::provider_impl::extract_model;
+
+/// One provider in the chain and the requests it may serve.
+pub struct Tier {
+    /// Name reported in the `x-proxy-tier` header, metrics, and health
+    pub name: String,
+    pub provider: Arc<dyn Provider>,
+    /// Model names or `prefix*` patterns (empty: any model)
+    pub models: Vec<String>,
+    /// Client names (empty: any client)
+    pub clients: Vec<String>,
+}
+
+impl Tier {
+    fn serves(&self, client: &str, model: Option<&str>) -> bool {
+        let model_ok =
+            self.models.is_empty() || model.is_some_and(|m| model_matches(&self.models, m));
+        let client_ok = self.clients.is_empty() || self.clients.iter().any(|c| c == client);
+        model_ok && client_ok
+    }
+}
+
+/// Provider that falls back through `tiers` in order.
+pub struct ChainProvider {
+    tiers: Vec<Tier>,
+    /// Account ID → index of the tier that selected it
+    owners: std::sync::Mutex<HashMap<String, usize>>,
+}
+
+impl ChainProvider {
+    pub fn new(tiers: Vec<Tier>) -> Self {
+        Self {
+            tiers,
+            owners: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn owner(&self, account_id: &str) -> Option<&Tier> {
+        let index = *self.owners.lock().unwrap().get(account_id)?;
+        self.tiers.get(index)
+    }
+}
+
+impl Provider for ChainProvider {
+    fn id(&self) -> &str {
+        "chain"
+    }
+
+    fn needs_body(&self) -> bool {
+        // Model filters read the body too
+        self.tiers
+            .iter()
+            .any(|t| t.provider.needs_body() || !t.models.is_empty())
+    }
+
+    fn prepare_request<'a>(
+        &'a self,
+        context: RequestContext<'a>,
+        headers: &'a mut HeaderMap,
+        body: &'a mut serde_json::Value,
+    ) -> Pin<Box<dyn Future<Output = provider::Result<Option<String>>> + Send + 'a>> {
+        Box::pin(async move {
+            let model = extract_model(body).map(str::to_string);
+            let mut exhausted = None;
+            for (index, tier) in self.tiers.iter().enumerate() {
+                if !tier.serves(context.client, model.as_deref()) {
+                    continue;
+                }
+                // A tier that fails must not leave its edits behind for the next
+                let mut tier_headers = headers.clone();
+                let mut tier_body = body.clone();
+                match tier
+                    .provider
+                    .prepare_request(context, &mut tier_headers, &mut tier_body)
+                    .await
+                {
+                    Ok(account_id) => {
+                        if index > 0 {
+                            info!(tier = %tier.name, "served by fallback tier");
+                        }
+                        if let Some(ref id) = account_id {
+                            self.owners.lock().unwrap().insert(id.clone(), index);
+                        }
+                        *headers = tier_headers;
+                        *body = tier_body;
+                        return Ok(account_id);
+                    }
+                    Err(ProviderError::PoolExhausted(msg)) => {
+                        debug!(tier = %tier.name, "tier exhausted, trying next");
+                        exhausted = Some(msg);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            Err(ProviderError::PoolExhausted(exhausted.unwrap_or_else(
+                || {
+                    format!(
+                        "no provider tier serves client '{}' with model '{}'",
+                        context.client,
+                        model.as_deref().unwrap_or("-")
+                    )
+                },
+            )))
+        })
+    }
+
+    fn observe_response<'a>(
+        &'a self,
+        account_id: &'a str,
+        status: u16,
+        headers: &'a HeaderMap,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
+        Box::pin(async move {
+            if let Some(tier) = self.owner(account_id) {
+                tier.provider
+                    .observe_response(account_id, status, headers)
+                    .await;
+            }
+        })
+    }
+
+    fn wrap_response(
+        &self,
+        account_id: Option<&str>,
+        request: &serde_json::Value,
+        status: u16,
+        headers: &mut HeaderMap,
+        body: BodyStream,
+    ) -> BodyStream {
+        match account_id.and_then(|id| self.owner(id)) {
+            Some(tier) => tier
+                .provider
+                .wrap_response(account_id, request, status, headers, body),
+            None => body,
+        }
+    }
+
+    fn release_account(&self, account_id: &str) {
+        if let Some(tier) = self.owner(account_id) {
+            tier.provider.release_account(account_id);
+        }
+    }
+
+    fn classify_error(
+        &self,
+        account_id: Option<&str>,
+        status: u16,
+        body: &str,
+    ) -> ErrorClassification {
+        match account_id.and_then(|id| self.owner(id)) {
+            Some(tier) => tier.provider.classify_error(account_id, status, body),
+            None => anthropic_pool::classify_status(status, body),
+        }
+    }
+
+    fn tier(&self, account_id: &str) -> Option<&str> {
+        self.owner(account_id).map(|t| t.name.as_str())
+    }
+
+    /// Every tier's budget; a tier without one (passthrough) gets a single
+    /// attempt.
+    fn max_failover_attempts(&self) -> Option<usize> {
+        Some(
+            self.tiers
+                .iter()
+                .map(|t| t.provider.max_failover_attempts().unwrap_or(1))
+                .sum(),
+        )
+    }
+
+    fn report_error(
+        &self,
+        account_id: &str,
+        classification: ErrorClassification,
+    ) -> Pin<Box<dyn Future<Output = provider::Result<()>> + Send + '_>> {
+        let account_id = account_id.to_string();
+        Box::pin(async move {
+            match self.owner(&account_id) {
+                Some(tier) => {
+                    tier.provider
+                        .report_error(&account_id, classification)
+                        .await
+                }
+                None => Ok(()),
+            }
+        })
+    }
+
+    /// Healthy while the first tier is; degraded while any tier can still
+    /// serve; unhealthy otherwise. Each tier's own report is under `tiers`.
+    fn health(&self) -> Pin<Box<dyn Future<Output = ProviderHealth> + Send + '_>> {
+        Box::pin(async move {
+            let mut tiers = Vec::with_capacity(self.tiers.len());
+            for tier in &self.tiers {
+                let health = tier.provider.health().await;
+                tiers.push((tier.name.as_str(), health));
+            }
+            let status = match tiers.first() {
+                Some((_, primary)) if primary.status == "healthy" => "healthy",
+                _ if tiers.iter().any(|(_, h)| h.status != "unhealthy") => "degraded",
+                _ => "unhealthy",
+            };
+            let tiers: Vec<serde_json::Value> = tiers
+                .into_iter()
+                .map(|(name, health)| {
+                    serde_json::json!({
+                        "name": name,
+                        "status": health.status,
+                        "pool": health.pool,
+                    })
+                })
+                .collect();
+            ProviderHealth {
+                status: status.to_string(),
+                pool: Some(serde_json::json!({ "tiers": tiers })),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn api_key_tier(name: &str, ids: &[&str]) -> (Tier, Arc<anthropic_pool::ApiKeyPool>) {
+        let pool = Arc::new(anthropic_pool::ApiKeyPool::new(
+            ids.iter()
+                .map(|id| anthropic_pool::ApiKey {
+                    id: id.to_string(),
+                    key: format!("sk-ant-{id}"),
+                })
+                .collect(),
+            Duration::from_secs(60),
+        ));
+        let tier = Tier {
+            name: name.to_string(),
+            provider: Arc::new(crate::provider_impl::AnthropicApiKeyProvider::new(
+                pool.clone(),
+            )),
+            models: Vec::new(),
+            clients: Vec::new(),
+        };
+        (tier, pool)
+    }
+
+    async fn prepare(
+        chain: &ChainProvider,
+        client: &str,
+        model: &str,
+    ) -> provider::Result<Option<String>> {
+        let mut headers = HeaderMap::new();
+        let mut body = serde_json::json!({"model": model, "messages": []});
+        chain
+            .prepare_request(RequestContext { client }, &mut headers, &mut body)
+            .await
+    }
+
+    #[tokio::test]
+    async fn falls_back_when_first_tier_is_exhausted() {
+        let (primary, primary_pool) = api_key_tier("primary", &["p1"]);
+        let (fallback, _) = api_key_tier("fallback", &["f1"]);
+        let chain = ChainProvider::new(vec![primary, fallback]);
+
+        let first = prepare(&chain, "ci", "claude-sonnet-4-5").await.unwrap();
+        assert_eq!(first.as_deref(), Some("p1"));
+        assert_eq!(chain.tier("p1"), Some("primary"));
+
+        // Any 429 cools an API key down; classification comes from the owner
+        let class = chain.classify_error(Some("p1"), 429, "{}");
+        assert_eq!(class, ErrorClassification::QuotaExceeded);
+        chain.report_error("p1", class).await.unwrap();
+        assert!(matches!(
+            primary_pool.status("p1").await,
+            Some(anthropic_pool::AccountStatus::CoolingDown { .. })
+        ));
+
+        let second = prepare(&chain, "ci", "claude-sonnet-4-5").await.unwrap();
+        assert_eq!(second.as_deref(), Some("f1"));
+        assert_eq!(chain.tier("f1"), Some("fallback"));
+        assert_eq!(chain.health().await.status, "degraded");
+    }
+
+    #[tokio::test]
+    async fn fallback_tier_filters_models_and_clients() {
+        let (primary, primary_pool) = api_key_tier("primary", &["p1"]);
+        let (mut fallback, _) = api_key_tier("fallback", &["f1"]);
+        fallback.models = vec!["claude-haiku-*".to_string()];
+        fallback.clients = vec!["ci".to_string()];
+        let chain = ChainProvider::new(vec![primary, fallback]);
+        primary_pool
+            .report_error("p1", ErrorClassification::QuotaExceeded)
+            .await;
+
+        assert!(prepare(&chain, "ci", "claude-haiku-4-5").await.is_ok());
+        let err = prepare(&chain, "ci", "claude-opus-4-1").await.unwrap_err();
+        assert!(matches!(err, ProviderError::PoolExhausted(_)), "{err}");
+        let err = prepare(&chain, "laptop", "claude-haiku-4-5")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ProviderError::PoolExhausted(_)), "{err}");
+    }
+}
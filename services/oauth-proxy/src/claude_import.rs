@@ -0,0 +1,177 @@
+//! Claude CLI credential import command
+//!
+//! `import-claude-credentials --config <path> [--account-id ID] [--file PATH]`
+//! reads a Claude CLI credential file (default `~/.claude/.credentials.json`;
+//! `--file -` reads stdin, e.g. piped from the macOS keychain) and adds it
+//! as a pool account in whichever `[oauth.storage]` backend the config uses.
+//! A running proxy picks the account up on its next credential reload.
+
+use std::io::Read;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result, bail};
+
+use crate::config::Config;
+
+/// The subcommand name.
+pub const COMMAND: &str = "import-claude-credentials";
+
+/// Run the import with the process arguments.
+pub async fn run(args: &[String]) -> Result<()> {
+    let import = Import::from_args(args)?;
+    import.run().await
+}
+
+/// Where the CLI credentials come from.
+enum Source {
+    File(PathBuf),
+    Stdin,
+}
+
+/// A credential source and the store to import it into.
+struct Import {
+    source: Source,
+    account_id: Option<String>,
+    config: crate::config::OAuthConfig,
+}
+
+impl Import {
+    /// Resolve the source from the flags and the store from the config.
+    fn from_args(args: &[String]) -> Result<Self> {
+        let flag = |name: &str| {
+            args.iter()
+                .position(|a| a == name)
+                .and_then(|i| args.get(i + 1))
+                .cloned()
+        };
+
+        let source = match flag("--file").as_deref() {
+            Some("-") => Source::Stdin,
+            Some(path) => Source::File(path.into()),
+            None => {
+                let home = std::env::var("HOME").context("HOME is not set; pass --file")?;
+                Source::File(
+                    PathBuf::from(home)
+                        .join(anthropic_auth::claude_cli::CLAUDE_CLI_CREDENTIALS_PATH),
+                )
+            }
+        };
+
+        let config_path = Config::resolve_path(flag("--config").as_deref());
+        let config = Config::load(&config_path)
+            .with_context(|| format!("failed to load config from {}", config_path.display()))?;
+        let Some(oauth) = config.oauth else {
+            bail!("{} has no [oauth] section", config_path.display());
+        };
+        let account_id = flag("--account-id");
+        if let Some(ref id) = account_id {
+            crate::admin::validate_account_id(id).map_err(anyhow::Error::msg)?;
+        }
+        Ok(Self {
+            source,
+            account_id,
+            config: oauth,
+        })
+    }
+
+    async fn run(self) -> Result<()> {
+        let json = match &self.source {
+            Source::File(path) => {
+                std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?
+            }
+            Source::Stdin => {
+                let mut json = Vec::new();
+                std::io::stdin()
+                    .read_to_end(&mut json)
+                    .context("failed to read stdin")?;
+                json
+            }
+        };
+        let credential = anthropic_auth::parse_claude_cli_credentials(&json)?;
+
+        let encryption = self
+            .config
+            .encryption
+            .as_ref()
+            .map(|e| e.encryption())
+            .transpose()?;
+        let backend = self.config.credential_backend()?;
+        let location = backend.location();
+        let store = anthropic_auth::CredentialStore::open(backend, encryption)
+            .await
+            .with_context(|| format!("failed to load credential store from {location}"))?;
+
+        let account_id = self.account_id.unwrap_or_else(crate::admin::new_account_id);
+        let replaced = store.get(&account_id).await.is_some();
+        store
+            .add(account_id.clone(), credential)
+            .await
+            .with_context(|| format!("failed to store {account_id} in {location}"))?;
+        println!(
+            "{} {account_id} in {location}; a running proxy picks it up within reload_interval_secs",
+            if replaced { "replaced" } else { "imported" }
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn import_adds_cli_credentials_to_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let credentials = dir.path().join("credentials.json");
+        let cli_file = dir.path().join(".credentials.json");
+        std::fs::write(
+            &cli_file,
+            r#"{"claudeAiOauth": {"accessToken": "at_cli", "refreshToken": "rt_cli", "expiresAt": 1735500000000}}"#,
+        )
+        .unwrap();
+        let config_path = dir.path().join("config.toml");
+        std::fs::write(
+            &config_path,
+            format!(
+                r#"
+[proxy]
+listen_addr = "127.0.0.1:8080"
+upstream_url = "https://api.anthropic.com"
+
+[oauth]
+credential_file = "{}"
+"#,
+                credentials.display()
+            ),
+        )
+        .unwrap();
+
+        let args: Vec<String> = [
+            "oauth-proxy",
+            COMMAND,
+            "--config",
+            config_path.to_str().unwrap(),
+            "--account-id",
+            "laptop",
+            "--file",
+            cli_file.to_str().unwrap(),
+        ]
+        .iter()
+        .map(|a| a.to_string())
+        .collect();
+        let import = {
+            // Config::load reads CREDENTIAL_FILE, which config tests set
+            let _lock = crate::config::tests::ENV_MUTEX.lock().unwrap();
+            Import::from_args(&args).unwrap()
+        };
+        import.run().await.unwrap();
+
+        let store = anthropic_auth::CredentialStore::load(credentials)
+            .await
+            .unwrap();
+        let credential = store.get("laptop").await.unwrap();
+        assert_eq!(credential.access, "at_cli");
+        assert_eq!(credential.refresh, "rt_cli");
+        assert_eq!(credential.expires, 1735500000000);
+    }
+}
@@ -0,0 +1,390 @@
+//! Proxy-side client authentication
+//!
+//! When `[[clients]]` entries are configured, every proxied request must carry
+//! one of their keys, either as `Authorization: Bearer <key>` or `x-api-key: <key>`.
+//! Only SHA-256 hashes of the keys live in config. The header that carried the
+//! key is removed before the request reaches the provider, so proxy keys never
+//! leave the proxy.
+//!
+//! With no `[[clients]]` configured, the proxy stays open (previous behavior)
+//! and requests are attributed to `anonymous`.
+//!
+//! Each client also carries its usage limits (see `limits`).
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use reqwest::header::{AUTHORIZATION, HeaderMap, HeaderName};
+use sha2::{Digest, Sha256};
+
+use crate::config::ClientConfig;
+use crate::limits::{ClientLimits, ClientUsage};
+
+/// Client name used for metrics and logs when authentication is disabled.
+pub const ANONYMOUS_CLIENT: &str = "anonymous";
+
+/// Client name used for requests rejected before a client was identified.
+pub const UNAUTHENTICATED_CLIENT: &str = "unauthenticated";
+
+const X_API_KEY: HeaderName = HeaderName::from_static("x-api-key");
+
+/// An authenticated caller.
+#[derive(Debug, Clone)]
+pub struct Client {
+    pub name: String,
+    pub labels: BTreeMap<String, String>,
+    key_hash: [u8; 32],
+    /// Exact model names, or prefixes ending in `*`. Empty allows every model.
+    allowed_models: Vec<String>,
+    expires_at: Option<SystemTime>,
+    /// Limits and live usage, shared by the client's in-flight requests
+    pub usage: Arc<ClientUsage>,
+}
+
+impl Client {
+    /// Whether this client may call `model`.
+    pub fn allows_model(&self, model: &str) -> bool {
+        self.allowed_models.is_empty() || model_matches(&self.allowed_models, model)
+    }
+
+    /// Whether `model` is listed by exact name, not only by a prefix.
+    pub fn lists_model(&self, model: &str) -> bool {
+        self.allowed_models.iter().any(|m| m == model)
+    }
+
+    /// Whether this client's model list restricts anything.
+    pub fn restricts_models(&self) -> bool {
+        !self.allowed_models.is_empty()
+    }
+
+    /// Labels rendered as `k=v,k=v` for log fields.
+    pub fn labels_field(&self) -> String {
+        self.labels
+            .iter()
+            .map(|(k, v)| format!("{k}={v}"))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+/// Whether `model` matches any of `patterns` (exact names or `prefix*`).
+pub fn model_matches(patterns: &[String], model: &str) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => model.starts_with(prefix),
+            None => model == pattern,
+        })
+}
+
+/// Why a request was not authenticated.
+#[derive(Debug, PartialEq, Eq)]
+pub enum AuthError {
+    /// No key in `Authorization` or `x-api-key`
+    MissingKey,
+    /// The key matches no configured client
+    UnknownKey,
+    /// The key belongs to a client whose `expires_at` has passed
+    Expired { client: String },
+}
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthError::MissingKey => f.write_str("missing API key"),
+            AuthError::UnknownKey => f.write_str("invalid API key"),
+            AuthError::Expired { .. } => f.write_str("API key expired"),
+        }
+    }
+}
+
+/// Configured clients, checked on every proxied request.
+#[derive(Debug, Default)]
+pub struct ClientRegistry {
+    clients: Vec<Client>,
+}
+
+impl ClientRegistry {
+    /// Build the registry from config, validating every entry.
+    pub fn from_config(entries: &[ClientConfig]) -> Result<Self, String> {
+        let mut clients: Vec<Client> = Vec::with_capacity(entries.len());
+        for entry in entries {
+            if entry.name.trim().is_empty() {
+                return Err("clients.name must not be empty".into());
+            }
+            if clients.iter().any(|c| c.name == entry.name) {
+                return Err(format!("duplicate client name '{}'", entry.name));
+            }
+            let key_hash = parse_sha256_hex(&entry.key_sha256).ok_or_else(|| {
+                format!(
+                    "client '{}': key_sha256 must be 64 hex characters",
+                    entry.name
+                )
+            })?;
+            if clients.iter().any(|c| c.key_hash == key_hash) {
+                return Err(format!(
+                    "client '{}' reuses another client's key",
+                    entry.name
+                ));
+            }
+            if entry.allowed_models.iter().any(|m| m.trim().is_empty()) {
+                return Err(format!(
+                    "client '{}': allowed_models entries must not be empty",
+                    entry.name
+                ));
+            }
+            let expires_at = entry
+                .expires_at
+                .as_deref()
+                .map(|s| {
+                    humantime::parse_rfc3339_weak(s).map_err(|e| {
+                        format!(
+                            "client '{}': invalid expires_at '{s}' (expected e.g. 2026-12-31T23:59:59Z): {e}",
+                            entry.name
+                        )
+                    })
+                })
+                .transpose()?;
+            let limits = ClientLimits {
+                requests_per_minute: entry.requests_per_minute,
+                max_concurrent_requests: entry.max_concurrent_requests,
+                input_tokens_per_day: entry.input_tokens_per_day,
+                output_tokens_per_day: entry.output_tokens_per_day,
+            };
+            if limits.requests_per_minute == Some(0)
+                || limits.max_concurrent_requests == Some(0)
+                || limits.input_tokens_per_day == Some(0)
+                || limits.output_tokens_per_day == Some(0)
+            {
+                return Err(format!(
+                    "client '{}': limits must be greater than 0 (omit a limit to disable it)",
+                    entry.name
+                ));
+            }
+
+            clients.push(Client {
+                name: entry.name.clone(),
+                labels: entry.labels.clone(),
+                key_hash,
+                allowed_models: entry.allowed_models.clone(),
+                expires_at,
+                usage: Arc::new(ClientUsage::new(limits)),
+            });
+        }
+        Ok(Self { clients })
+    }
+
+    /// Whether authentication is enforced (at least one client configured).
+    pub fn is_enabled(&self) -> bool {
+        !self.clients.is_empty()
+    }
+
+    /// Identify the caller from its request headers and remove the header
+    /// that carried the key.
+    ///
+    /// Both headers are tried, so a passthrough client can send its upstream
+    /// credential in one and its proxy key in the other.
+    pub fn authenticate(&self, headers: &mut HeaderMap) -> Result<&Client, AuthError> {
+        let candidates = presented_keys(headers);
+        if candidates.is_empty() {
+            return Err(AuthError::MissingKey);
+        }
+        let (header, client) = candidates
+            .into_iter()
+            .find_map(|(header, key)| self.lookup(&key).map(|client| (header, client)))
+            .ok_or(AuthError::UnknownKey)?;
+
+        if client.expires_at.is_some_and(|t| t <= SystemTime::now()) {
+            return Err(AuthError::Expired {
+                client: client.name.clone(),
+            });
+        }
+
+        headers.remove(header);
+        Ok(client)
+    }
+
+    /// Client whose key hashes to the same digest as `key`.
+    fn lookup(&self, key: &str) -> Option<&Client> {
+        let digest: [u8; 32] = Sha256::digest(key.as_bytes()).into();
+        // Compare against every entry without early exit so timing doesn't
+        // reveal which (or whether a) hash matched
+        let mut matched = None;
+        for client in &self.clients {
+            if constant_time_eq(&client.key_hash, &digest) {
+                matched = Some(client);
+            }
+        }
+        matched
+    }
+}
+
+/// Keys a request presents: `Authorization: Bearer` first, then `x-api-key`.
+fn presented_keys(headers: &HeaderMap) -> Vec<(HeaderName, String)> {
+    let bearer = headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|k| (AUTHORIZATION, k.trim().to_string()));
+    let api_key = headers
+        .get(X_API_KEY)
+        .and_then(|v| v.to_str().ok())
+        .map(|k| (X_API_KEY, k.trim().to_string()));
+    bearer
+        .into_iter()
+        .chain(api_key)
+        .filter(|(_, k)| !k.is_empty())
+        .collect()
+}
+
+fn parse_sha256_hex(hex: &str) -> Option<[u8; 32]> {
+    let hex = hex.trim();
+    if hex.len() != 64 {
+        return None;
+    }
+    let mut out = [0u8; 32];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(out)
+}
+
+fn constant_time_eq(a: &[u8; 32], b: &[u8; 32]) -> bool {
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sha256_hex(key: &str) -> String {
+        Sha256::digest(key.as_bytes())
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+
+    fn entry(name: &str, key: &str) -> ClientConfig {
+        ClientConfig {
+            name: name.into(),
+            key_sha256: sha256_hex(key),
+            labels: BTreeMap::new(),
+            allowed_models: vec![],
+            expires_at: None,
+            requests_per_minute: None,
+            max_concurrent_requests: None,
+            input_tokens_per_day: None,
+            output_tokens_per_day: None,
+        }
+    }
+
+    fn headers(name: &str, value: &str) -> HeaderMap {
+        let mut h = HeaderMap::new();
+        h.insert(
+            HeaderName::from_bytes(name.as_bytes()).unwrap(),
+            value.parse().unwrap(),
+        );
+        h
+    }
+
+    #[test]
+    fn authenticates_bearer_and_strips_it() {
+        let registry =
+            ClientRegistry::from_config(&[entry("ci", "key-ci"), entry("dev", "key-dev")]).unwrap();
+        let mut h = headers("authorization", "Bearer key-dev");
+        assert_eq!(registry.authenticate(&mut h).unwrap().name, "dev");
+        assert!(h.get(AUTHORIZATION).is_none());
+    }
+
+    #[test]
+    fn authenticates_x_api_key() {
+        let registry = ClientRegistry::from_config(&[entry("ci", "key-ci")]).unwrap();
+        let mut h = headers("x-api-key", "key-ci");
+        assert_eq!(registry.authenticate(&mut h).unwrap().name, "ci");
+        assert!(h.get("x-api-key").is_none());
+    }
+
+    #[test]
+    fn rejects_missing_and_unknown_keys() {
+        let registry = ClientRegistry::from_config(&[entry("ci", "key-ci")]).unwrap();
+        assert_eq!(
+            registry.authenticate(&mut HeaderMap::new()).unwrap_err(),
+            AuthError::MissingKey
+        );
+        let mut h = headers("authorization", "Bearer nope");
+        assert_eq!(
+            registry.authenticate(&mut h).unwrap_err(),
+            AuthError::UnknownKey
+        );
+        // A rejected key is left in place (the request goes no further)
+        assert!(h.get(AUTHORIZATION).is_some());
+    }
+
+    #[test]
+    fn proxy_key_in_x_api_key_leaves_upstream_bearer() {
+        let registry = ClientRegistry::from_config(&[entry("ci", "key-ci")]).unwrap();
+        let mut h = headers("authorization", "Bearer upstream-token");
+        h.insert("x-api-key", "key-ci".parse().unwrap());
+        assert_eq!(registry.authenticate(&mut h).unwrap().name, "ci");
+        assert!(h.get("x-api-key").is_none());
+        assert_eq!(h[AUTHORIZATION], "Bearer upstream-token");
+    }
+
+    #[test]
+    fn rejects_expired_keys() {
+        let mut expired = entry("old", "key-old");
+        expired.expires_at = Some("2020-01-01T00:00:00Z".into());
+        let mut current = entry("new", "key-new");
+        current.expires_at = Some("2999-01-01T00:00:00Z".into());
+        let registry = ClientRegistry::from_config(&[expired, current]).unwrap();
+
+        let mut h = headers("x-api-key", "key-old");
+        assert_eq!(
+            registry.authenticate(&mut h).unwrap_err(),
+            AuthError::Expired {
+                client: "old".into()
+            }
+        );
+        let mut h = headers("x-api-key", "key-new");
+        assert!(registry.authenticate(&mut h).is_ok());
+    }
+
+    #[test]
+    fn allowed_models_match_exact_and_prefix() {
+        let mut restricted = entry("ci", "key-ci");
+        restricted.allowed_models = vec!["claude-haiku-4-5".into(), "claude-sonnet-*".into()];
+        let registry = ClientRegistry::from_config(&[restricted]).unwrap();
+        let mut h = headers("x-api-key", "key-ci");
+        let client = registry.authenticate(&mut h).unwrap();
+
+        assert!(client.allows_model("claude-haiku-4-5"));
+        assert!(client.allows_model("claude-sonnet-4-20250514"));
+        assert!(!client.allows_model("claude-opus-4-1"));
+        assert!(!client.allows_model("claude-haiku-4-5-20251001"));
+        assert!(client.lists_model("claude-haiku-4-5"));
+        assert!(!client.lists_model("claude-sonnet-4-20250514"));
+    }
+
+    #[test]
+    fn config_validation() {
+        let mut bad_hash = entry("ci", "key-ci");
+        bad_hash.key_sha256 = "abc".into();
+        assert!(ClientRegistry::from_config(&[bad_hash]).is_err());
+
+        assert!(
+            ClientRegistry::from_config(&[entry("ci", "key-a"), entry("ci", "key-b")]).is_err()
+        );
+        assert!(ClientRegistry::from_config(&[entry("a", "same"), entry("b", "same")]).is_err());
+
+        let mut bad_expiry = entry("ci", "key-ci");
+        bad_expiry.expires_at = Some("next tuesday".into());
+        assert!(ClientRegistry::from_config(&[bad_expiry]).is_err());
+
+        let mut zero_limit = entry("ci", "key-ci");
+        zero_limit.requests_per_minute = Some(0);
+        assert!(ClientRegistry::from_config(&[zero_limit]).is_err());
+
+        assert!(!ClientRegistry::from_config(&[]).unwrap().is_enabled());
+    }
+}
@@ -3,12 +3,17 @@
 //! Config precedence: CLI args > env vars > config file > defaults.
 //!
 //! Auth mode detection: if `[oauth]` is present, the proxy runs in OAuth pool
-//! mode. If only `[[headers]]` is present, it runs in passthrough mode. When
-//! both are present, `[oauth]` takes precedence (`[[headers]]` is ignored with
-//! a warning).
+//! mode. Otherwise `[api_keys]` selects API key pool mode, and with neither it
+//! runs in passthrough mode using `[[headers]]`. `[oauth]` takes precedence
+//! over both others, and `[api_keys]` over `[[headers]]` (the ignored section
+//! is logged with a warning). A `[[provider_chain]]` list instead combines
+//! `[oauth]` and `[api_keys]` as ordered fallback tiers. Sections named by
+//! the chain, `[[routes]]`, or `[[model_routes]]` are kept whatever the
+//! precedence.
 
 use axum::http::{HeaderName, HeaderValue};
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
@@ -20,6 +25,10 @@ pub enum AuthMode {
     Passthrough,
     /// OAuth 2.0 pool with PKCE, token refresh, subscription pooling
     OAuthPool,
+    /// Rotation across plain `x-api-key` credentials
+    ApiKeyPool,
+    /// Ordered fallback across the providers listed in `[[provider_chain]]`
+    Chain,
 }
 
 /// Root configuration
@@ -29,7 +38,25 @@ pub struct Config {
     #[serde(default)]
     pub headers: Vec<HeaderInjection>,
     pub oauth: Option<OAuthConfig>,
+    pub api_keys: Option<ApiKeysConfig>,
+    /// Provider tiers tried in order (empty: single provider by config shape)
+    #[serde(default)]
+    pub provider_chain: Vec<ProviderTierConfig>,
+    /// Path prefixes with their own upstream, provider, and limits
+    #[serde(default)]
+    pub routes: Vec<RouteConfig>,
+    /// Short model names rewritten before forwarding (`fast` → a full name)
+    #[serde(default)]
+    pub model_aliases: HashMap<String, String>,
+    /// Providers and upstreams for specific models, first match wins
+    #[serde(default)]
+    pub model_routes: Vec<ModelRouteConfig>,
     pub admin: Option<AdminConfig>,
+    /// Callers allowed to use the proxy. Empty leaves the proxy open.
+    #[serde(default)]
+    pub clients: Vec<ClientConfig>,
+    /// Authorize callers by tailnet identity when present
+    pub tailnet: Option<TailnetConfig>,
 }
 
 /// HTTP proxy settings
@@ -41,6 +68,54 @@ pub struct ProxyConfig {
     pub timeout_secs: u64,
     #[serde(default = "default_max_connections")]
     pub max_connections: usize,
+    /// Hold SSE responses until the first `message_start` event, so upstream
+    /// failures before it are retried instead of truncating the stream
+    #[serde(default)]
+    pub buffer_first_event: bool,
+    /// Read token usage from responses into `proxy_tokens_total` and the
+    /// access log. Responses are then requested uncompressed.
+    #[serde(default = "default_usage_accounting")]
+    pub usage_accounting: bool,
+    #[serde(default)]
+    pub retry: RetryConfig,
+}
+
+/// Upstream retry settings (`[proxy.retry]`)
+#[derive(Debug, Deserialize)]
+pub struct RetryConfig {
+    /// Total attempts per account, including the first
+    #[serde(default = "default_retry_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_retry_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    #[serde(default = "default_retry_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+    #[serde(default = "default_retry_backoff_multiplier")]
+    pub backoff_multiplier: f64,
+    #[serde(default = "default_retry_jitter")]
+    pub jitter: bool,
+    /// Failure classes to retry: "connect", "timeout", "server_error"
+    /// (502/503/504), "rate_limited" (non-quota 429), "overloaded" (529)
+    #[serde(default = "default_retry_on")]
+    pub retry_on: Vec<crate::retry::RetryClass>,
+    /// Time since the request arrived after which no more retries start
+    /// (0 disables the budget)
+    #[serde(default)]
+    pub budget_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_retry_max_attempts(),
+            initial_backoff_ms: default_retry_initial_backoff_ms(),
+            max_backoff_ms: default_retry_max_backoff_ms(),
+            backoff_multiplier: default_retry_backoff_multiplier(),
+            jitter: default_retry_jitter(),
+            retry_on: default_retry_on(),
+            budget_ms: 0,
+        }
+    }
 }
 
 /// Header to inject into proxied requests
@@ -50,18 +125,342 @@ pub struct HeaderInjection {
     pub value: String,
 }
 
+/// Proxy client entry (`[[clients]]`)
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClientConfig {
+    /// Identity used in metrics and logs
+    pub name: String,
+    /// Hex SHA-256 of the client's key (`printf %s "$KEY" | sha256sum`)
+    pub key_sha256: String,
+    /// Free-form labels added to the client's request logs
+    #[serde(default)]
+    pub labels: std::collections::BTreeMap<String, String>,
+    /// Exact model names or `prefix*` patterns (empty allows all)
+    #[serde(default)]
+    pub allowed_models: Vec<String>,
+    /// RFC 3339 UTC timestamp after which the key is rejected
+    #[serde(default)]
+    pub expires_at: Option<String>,
+    /// Requests admitted per sliding minute
+    #[serde(default)]
+    pub requests_per_minute: Option<u32>,
+    /// Requests in flight at once, streams included
+    #[serde(default)]
+    pub max_concurrent_requests: Option<u32>,
+    /// Input tokens (cached included) per UTC day
+    #[serde(default)]
+    pub input_tokens_per_day: Option<u64>,
+    /// Output tokens per UTC day
+    #[serde(default)]
+    pub output_tokens_per_day: Option<u64>,
+}
+
+/// Tailnet identity authorization (`[tailnet]`)
+#[derive(Debug, Clone, Deserialize)]
+pub struct TailnetConfig {
+    /// Base URL of the Tailscale LocalAPI (or a stand-in answering `whois`)
+    #[serde(default = "default_tailnet_localapi_url")]
+    pub localapi_url: String,
+    /// Look up the `X-Forwarded-For` address appended by a proxy in
+    /// `trusted_proxies` instead of the TCP peer (e.g. operator ingress)
+    #[serde(default)]
+    pub trust_forwarded_for: bool,
+    /// Addresses or CIDR ranges of the proxies whose `X-Forwarded-For` is
+    /// believed. Required with `trust_forwarded_for`.
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+    /// How long a `whois` answer is reused for the same address
+    #[serde(default = "default_tailnet_cache_ttl_secs")]
+    pub cache_ttl_secs: u64,
+    /// ACL tags admitted (e.g. `tag:ci`)
+    #[serde(default)]
+    pub allow_tags: Vec<String>,
+    /// Tailnet login names admitted (e.g. `alice@example.com`)
+    #[serde(default)]
+    pub allow_users: Vec<String>,
+    /// Machine names admitted. With all three lists empty, any tailnet node is admitted.
+    #[serde(default)]
+    pub allow_nodes: Vec<String>,
+}
+
 /// OAuth pool configuration — activates pool mode when present in TOML.
 #[derive(Debug, Deserialize)]
 pub struct OAuthConfig {
-    pub credential_file: String,
+    /// Credential file for the default `file` storage (required there,
+    /// unused by the other backends)
+    #[serde(default)]
+    pub credential_file: Option<String>,
+    /// Where account statuses persist across restarts. Default: with `file`
+    /// storage, `pool-state.json` next to `credential_file`; otherwise not
+    /// persisted. Replicas each need their own.
+    #[serde(default)]
+    pub status_file: Option<String>,
     #[serde(default = "default_cooldown_secs")]
     pub cooldown_secs: u64,
     #[serde(default = "default_refresh_interval_secs")]
     pub refresh_interval_secs: u64,
     #[serde(default = "default_refresh_threshold_secs")]
     pub refresh_threshold_secs: u64,
+    /// Fraction of a rate-limit window held back before an account is deprioritized
+    #[serde(default = "default_quota_reserve_ratio")]
+    pub quota_reserve_ratio: f64,
+    /// Account selection order: "round-robin", "least-recently-used",
+    /// "least-in-flight", "weighted-quota", or "fill-first"
+    #[serde(default)]
+    pub selection_strategy: anthropic_pool::SelectionStrategy,
+    /// How long a conversation stays pinned to its account after its last
+    /// request (0 disables affinity)
+    #[serde(default = "default_affinity_ttl_secs")]
+    pub affinity_ttl_secs: u64,
+    /// Client header whose value identifies a conversation. When absent from
+    /// a request, affinity falls back to hashing the opening messages.
+    #[serde(default)]
+    pub affinity_header: Option<String>,
+    /// How often disabled accounts are probed for recovery (0 disables probing)
+    #[serde(default = "default_probe_interval_secs")]
+    pub probe_interval_secs: u64,
+    /// Upper bound for the per-account probe backoff after failed probes
+    #[serde(default = "default_probe_max_backoff_secs")]
+    pub probe_max_backoff_secs: u64,
+    /// How often the credential storage is checked for changes made outside
+    /// this process (0 disables reloading)
+    #[serde(default = "default_reload_interval_secs")]
+    pub reload_interval_secs: u64,
     #[serde(default)]
     pub providers: Vec<String>,
+    /// Client tool name → Claude Code tool name (e.g. `bash = "Bash"`).
+    /// Requests are rewritten to the canonical names and responses back.
+    #[serde(default)]
+    pub tool_names: HashMap<String, String>,
+    /// Encrypt the credential file at rest (absent: plaintext)
+    pub encryption: Option<EncryptionConfig>,
+    /// Where credentials are stored (absent: `credential_file`)
+    #[serde(default)]
+    pub storage: StorageConfig,
+}
+
+impl OAuthConfig {
+    /// Open the configured credential storage backend.
+    pub fn credential_backend(&self) -> common::Result<Box<dyn anthropic_auth::CredentialBackend>> {
+        let invalid =
+            |e: anthropic_auth::Error| common::Error::Config(format!("oauth.storage: {e}"));
+        Ok(match self.storage {
+            StorageConfig::File => {
+                let path = self.credential_file.as_deref().ok_or_else(|| {
+                    common::Error::Config(
+                        "oauth.credential_file is required with file storage".into(),
+                    )
+                })?;
+                Box::new(anthropic_auth::FileBackend::new(path))
+            }
+            StorageConfig::Sqlite { ref path } => {
+                Box::new(anthropic_auth::SqliteBackend::open(path).map_err(invalid)?)
+            }
+            StorageConfig::Kubernetes {
+                ref secret,
+                ref namespace,
+                ref key,
+                ref api_url,
+            } => {
+                let backend = match api_url {
+                    Some(url) => anthropic_auth::KubernetesSecretBackend::new(
+                        url,
+                        namespace.as_deref().unwrap_or("default"),
+                        secret,
+                    )
+                    .map_err(invalid)?,
+                    None => anthropic_auth::KubernetesSecretBackend::in_cluster(
+                        namespace.clone(),
+                        secret,
+                    )
+                    .map_err(invalid)?,
+                };
+                Box::new(backend.with_key(key))
+            }
+        })
+    }
+
+    /// Where account statuses are persisted, if anywhere.
+    pub fn status_file(&self) -> Option<PathBuf> {
+        match (&self.status_file, &self.storage, &self.credential_file) {
+            (Some(path), _, _) => Some(PathBuf::from(path)),
+            (None, StorageConfig::File, Some(credential_file)) => Some(
+                anthropic_pool::StatusStore::path_for(Path::new(credential_file)),
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// Credential storage backend (`[oauth.storage]`), chosen by `backend`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(tag = "backend", rename_all = "snake_case")]
+pub enum StorageConfig {
+    /// The JSON file at `oauth.credential_file`
+    #[default]
+    File,
+    /// A SQLite database
+    Sqlite { path: String },
+    /// A key in a Kubernetes Secret, written through the API server
+    Kubernetes {
+        /// Secret name (created on first write if missing)
+        secret: String,
+        /// Secret namespace (default: the pod's own, or `default` with `api_url`)
+        #[serde(default)]
+        namespace: Option<String>,
+        /// Key within the Secret's data
+        #[serde(default = "default_secret_key")]
+        key: String,
+        /// API server base URL, used without authentication (e.g. `kubectl
+        /// proxy`). Absent: the in-cluster API server and service account.
+        #[serde(default)]
+        api_url: Option<String>,
+    },
+}
+
+/// Credential file encryption key (`[oauth.encryption]`). Exactly one
+/// source must be set.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EncryptionConfig {
+    /// Environment variable holding a base64-encoded 32-byte key
+    pub key_env: Option<String>,
+    /// File holding a base64-encoded 32-byte key (e.g. a mounted Secret)
+    pub key_file: Option<String>,
+    /// Environment variable holding a passphrase the key is derived from
+    pub passphrase_env: Option<String>,
+}
+
+impl EncryptionConfig {
+    /// Resolve the configured source into a key.
+    pub fn encryption(&self) -> common::Result<anthropic_auth::Encryption> {
+        let invalid =
+            |e: anthropic_auth::Error| common::Error::Config(format!("oauth.encryption: {e}"));
+        let env = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .filter(|v| !v.is_empty())
+                .ok_or_else(|| {
+                    common::Error::Config(format!(
+                        "oauth.encryption: environment variable {name} is not set"
+                    ))
+                })
+        };
+        match (&self.key_env, &self.key_file, &self.passphrase_env) {
+            (Some(name), None, None) => {
+                anthropic_auth::Encryption::from_base64_key(&env(name)?).map_err(invalid)
+            }
+            (None, Some(path), None) => {
+                let key = std::fs::read_to_string(path).map_err(|e| {
+                    common::Error::Config(format!("oauth.encryption: reading {path}: {e}"))
+                })?;
+                anthropic_auth::Encryption::from_base64_key(&key).map_err(invalid)
+            }
+            (None, None, Some(name)) => {
+                anthropic_auth::Encryption::from_passphrase(env(name)?).map_err(invalid)
+            }
+            _ => Err(common::Error::Config(
+                "oauth.encryption must set exactly one of key_env, key_file, passphrase_env".into(),
+            )),
+        }
+    }
+}
+
+/// API key pool configuration (`[api_keys]`)
+#[derive(Debug, Deserialize)]
+pub struct ApiKeysConfig {
+    /// Cooldown for a rate-limited key whose response reports no reset time
+    #[serde(default = "default_api_key_cooldown_secs")]
+    pub cooldown_secs: u64,
+    pub keys: Vec<ApiKeyConfig>,
+}
+
+/// One API key in the pool (`[[api_keys.keys]]`)
+#[derive(Debug, Deserialize)]
+pub struct ApiKeyConfig {
+    /// Identity used in metrics, logs, and health
+    pub id: String,
+    /// Environment variable holding the key, so it stays out of the config file
+    pub key_env: String,
+}
+
+impl ApiKeyConfig {
+    /// Read the key from its environment variable.
+    pub fn key(&self) -> common::Result<String> {
+        std::env::var(&self.key_env)
+            .ok()
+            .filter(|key| !key.is_empty())
+            .ok_or_else(|| {
+                common::Error::Config(format!(
+                    "api_keys key '{}': environment variable {} is not set",
+                    self.id, self.key_env
+                ))
+            })
+    }
+}
+
+/// Provider sections that chain tiers and routes can name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderKind {
+    /// The `[oauth]` subscription pool
+    Oauth,
+    /// The `[api_keys]` pool
+    ApiKeys,
+    /// `[[headers]]` injection, client credentials passed through
+    Passthrough,
+}
+
+impl ProviderKind {
+    /// Tier name in headers, metrics, and health (matches the TOML spelling).
+    pub fn label(&self) -> &'static str {
+        match self {
+            ProviderKind::Oauth => "oauth",
+            ProviderKind::ApiKeys => "api_keys",
+            ProviderKind::Passthrough => "passthrough",
+        }
+    }
+}
+
+/// One tier of the fallback chain (`[[provider_chain]]`)
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProviderTierConfig {
+    pub provider: ProviderKind,
+    /// Exact model names or `prefix*` patterns this tier serves (empty: all)
+    #[serde(default)]
+    pub models: Vec<String>,
+    /// Client names this tier serves (empty: all)
+    #[serde(default)]
+    pub clients: Vec<String>,
+}
+
+/// One path route (`[[routes]]`)
+#[derive(Debug, Clone, Deserialize)]
+pub struct RouteConfig {
+    /// Value of the `route` metrics label
+    pub name: String,
+    /// Requests whose path is this prefix or below it use the route
+    pub path_prefix: String,
+    /// Remove `path_prefix` from the path sent upstream
+    #[serde(default)]
+    pub strip_prefix: bool,
+    /// Upstream for this route (default: `proxy.upstream_url`)
+    pub upstream_url: Option<String>,
+    /// Provider for this route (default: the proxy's default provider)
+    pub provider: Option<ProviderKind>,
+    /// Upstream timeout (default: `proxy.timeout_secs`)
+    pub timeout_secs: Option<u64>,
+    /// Request body limit in bytes (default: 10 MiB)
+    pub max_body_bytes: Option<usize>,
+}
+
+/// One model route (`[[model_routes]]`)
+#[derive(Debug, Clone, Deserialize)]
+pub struct ModelRouteConfig {
+    /// Exact model names or `prefix*` patterns, matched after alias rewriting
+    pub models: Vec<String>,
+    pub provider: ProviderKind,
+    /// Upstream for these models (default: `proxy.upstream_url`)
+    pub upstream_url: Option<String>,
 }
 
 /// Admin API configuration — separate listener for account management.
@@ -81,6 +480,34 @@ fn default_max_connections() -> usize {
     1000
 }
 
+fn default_usage_accounting() -> bool {
+    true
+}
+
+fn default_retry_max_attempts() -> u32 {
+    3
+}
+
+fn default_retry_initial_backoff_ms() -> u64 {
+    100
+}
+
+fn default_retry_max_backoff_ms() -> u64 {
+    2000
+}
+
+fn default_retry_backoff_multiplier() -> f64 {
+    2.0
+}
+
+fn default_retry_jitter() -> bool {
+    true
+}
+
+fn default_retry_on() -> Vec<crate::retry::RetryClass> {
+    vec![crate::retry::RetryClass::Timeout]
+}
+
 fn default_cooldown_secs() -> u64 {
     7200
 }
@@ -93,6 +520,42 @@ fn default_refresh_threshold_secs() -> u64 {
     900
 }
 
+fn default_quota_reserve_ratio() -> f64 {
+    anthropic_pool::DEFAULT_QUOTA_RESERVE
+}
+
+fn default_affinity_ttl_secs() -> u64 {
+    anthropic_pool::DEFAULT_AFFINITY_TTL.as_secs()
+}
+
+fn default_api_key_cooldown_secs() -> u64 {
+    60
+}
+
+fn default_probe_interval_secs() -> u64 {
+    300
+}
+
+fn default_probe_max_backoff_secs() -> u64 {
+    3600
+}
+
+fn default_reload_interval_secs() -> u64 {
+    10
+}
+
+fn default_secret_key() -> String {
+    anthropic_auth::kubernetes::DEFAULT_SECRET_KEY.into()
+}
+
+fn default_tailnet_localapi_url() -> String {
+    "http://127.0.0.1:41112".into()
+}
+
+fn default_tailnet_cache_ttl_secs() -> u64 {
+    60
+}
+
 fn default_admin_listen_addr() -> SocketAddr {
     "0.0.0.0:9090".parse().unwrap()
 }
@@ -100,8 +563,12 @@ fn default_admin_listen_addr() -> SocketAddr {
 impl Config {
     /// Determine auth mode from config shape.
     pub fn mode(&self) -> AuthMode {
-        if self.oauth.is_some() {
+        if !self.provider_chain.is_empty() {
+            AuthMode::Chain
+        } else if self.oauth.is_some() {
             AuthMode::OAuthPool
+        } else if self.api_keys.is_some() {
+            AuthMode::ApiKeyPool
         } else {
             AuthMode::Passthrough
         }
@@ -116,29 +583,44 @@ impl Config {
         if let Ok(cred_path) = std::env::var("CREDENTIAL_FILE")
             && let Some(ref mut oauth) = config.oauth
         {
-            oauth.credential_file = cred_path;
+            oauth.credential_file = Some(cred_path);
+        }
+
+        // STATUS_FILE env var override, so replicas sharing one config can
+        // each keep their own statuses
+        if let Ok(status_path) = std::env::var("STATUS_FILE")
+            && let Some(ref mut oauth) = config.oauth
+        {
+            oauth.status_file = Some(status_path);
         }
 
         // When both [oauth] and [[headers]] present, [oauth] takes precedence
-        if config.oauth.is_some() && !config.headers.is_empty() {
+        let keep_headers = config.references(ProviderKind::Passthrough);
+        if config.oauth.is_some() && !config.headers.is_empty() && !keep_headers {
             tracing::warn!(
                 "[oauth] and [[headers]] both present — [oauth] takes precedence, [[headers]] ignored"
             );
             config.headers.clear();
         }
+        if config.oauth.is_some()
+            && config.api_keys.is_some()
+            && !config.references(ProviderKind::ApiKeys)
+        {
+            tracing::warn!(
+                "[oauth] and [api_keys] both present — [oauth] takes precedence, [api_keys] ignored"
+            );
+            config.api_keys = None;
+        }
+        if config.api_keys.is_some() && !config.headers.is_empty() && !keep_headers {
+            tracing::warn!(
+                "[api_keys] and [[headers]] both present — [api_keys] takes precedence, [[headers]] ignored"
+            );
+            config.headers.clear();
+        }
 
         // Validate upstream_url is a parseable URL with http(s) scheme.
         // Catches malformed URLs at startup rather than on first request.
-        let url = reqwest::Url::parse(&config.proxy.upstream_url)
-            .map_err(|e| common::Error::Config(format!("upstream_url is not a valid URL: {e}")))?;
-        match url.scheme() {
-            "http" | "https" => {}
-            scheme => {
-                return Err(common::Error::Config(format!(
-                    "upstream_url must use http or https scheme, got: {scheme}"
-                )));
-            }
-        }
+        validate_upstream_url("upstream_url", &config.proxy.upstream_url)?;
 
         // Validate timeout_secs is non-zero
         if config.proxy.timeout_secs == 0 {
@@ -154,6 +636,52 @@ impl Config {
             ));
         }
 
+        crate::clients::ClientRegistry::from_config(&config.clients)
+            .map_err(|e| common::Error::Config(format!("invalid [[clients]]: {e}")))?;
+
+        if let Some(ref tailnet) = config.tailnet {
+            if !tailnet.localapi_url.starts_with("http://")
+                && !tailnet.localapi_url.starts_with("https://")
+            {
+                return Err(common::Error::Config(format!(
+                    "tailnet.localapi_url must be an http(s) URL, got '{}'",
+                    tailnet.localapi_url
+                )));
+            }
+            if let Some(tag) = tailnet.allow_tags.iter().find(|t| !t.starts_with("tag:")) {
+                return Err(common::Error::Config(format!(
+                    "tailnet.allow_tags entry '{tag}' must start with 'tag:'"
+                )));
+            }
+            if tailnet.trust_forwarded_for && tailnet.trusted_proxies.is_empty() {
+                return Err(common::Error::Config(
+                    "tailnet.trust_forwarded_for needs tailnet.trusted_proxies".into(),
+                ));
+            }
+            for range in &tailnet.trusted_proxies {
+                range
+                    .parse::<crate::tailnet::IpRange>()
+                    .map_err(|e| common::Error::Config(format!("tailnet.trusted_proxies: {e}")))?;
+            }
+        }
+
+        let retry = &config.proxy.retry;
+        if retry.max_attempts == 0 {
+            return Err(common::Error::Config(
+                "proxy.retry.max_attempts must be greater than 0".into(),
+            ));
+        }
+        if retry.backoff_multiplier < 1.0 {
+            return Err(common::Error::Config(
+                "proxy.retry.backoff_multiplier must be at least 1".into(),
+            ));
+        }
+        if retry.max_backoff_ms < retry.initial_backoff_ms {
+            return Err(common::Error::Config(
+                "proxy.retry.max_backoff_ms must be at least proxy.retry.initial_backoff_ms".into(),
+            ));
+        }
+
         // Validate header injection entries at load time so misconfigured
         // headers fail fast at startup instead of being silently skipped
         // per-request at runtime.
@@ -168,9 +696,22 @@ impl Config {
 
         // Validate [oauth] fields if present
         if let Some(ref oauth) = config.oauth {
-            if oauth.credential_file.is_empty() {
+            match oauth.credential_file.as_deref() {
+                Some("") => {
+                    return Err(common::Error::Config(
+                        "oauth.credential_file must not be empty".into(),
+                    ));
+                }
+                None if matches!(oauth.storage, StorageConfig::File) => {
+                    return Err(common::Error::Config(
+                        "oauth.credential_file is required with file storage".into(),
+                    ));
+                }
+                _ => {}
+            }
+            if oauth.status_file.as_deref() == Some("") {
                 return Err(common::Error::Config(
-                    "oauth.credential_file must not be empty".into(),
+                    "oauth.status_file must not be empty".into(),
                 ));
             }
             if oauth.cooldown_secs == 0 {
@@ -188,11 +729,232 @@ impl Config {
                     "oauth.refresh_threshold_secs must be greater than 0".into(),
                 ));
             }
+            if !(0.0..1.0).contains(&oauth.quota_reserve_ratio) {
+                return Err(common::Error::Config(
+                    "oauth.quota_reserve_ratio must be at least 0 and less than 1".into(),
+                ));
+            }
+            if oauth.probe_interval_secs > 0
+                && oauth.probe_max_backoff_secs < oauth.probe_interval_secs
+            {
+                return Err(common::Error::Config(
+                    "oauth.probe_max_backoff_secs must be at least oauth.probe_interval_secs"
+                        .into(),
+                ));
+            }
+            if let Some(ref name) = oauth.affinity_header {
+                HeaderName::from_str(name).map_err(|e| {
+                    common::Error::Config(format!("invalid oauth.affinity_header '{name}': {e}"))
+                })?;
+            }
+            if let Some(ref encryption) = oauth.encryption {
+                encryption.encryption()?;
+            }
+            match oauth.storage {
+                StorageConfig::File => {}
+                StorageConfig::Sqlite { ref path } => {
+                    if path.is_empty() {
+                        return Err(common::Error::Config(
+                            "oauth.storage.path must not be empty".into(),
+                        ));
+                    }
+                }
+                StorageConfig::Kubernetes {
+                    ref secret,
+                    ref key,
+                    ref api_url,
+                    ..
+                } => {
+                    if secret.is_empty() || key.is_empty() {
+                        return Err(common::Error::Config(
+                            "oauth.storage.secret and oauth.storage.key must not be empty".into(),
+                        ));
+                    }
+                    if let Some(url) = api_url {
+                        validate_upstream_url("oauth.storage.api_url", url)?;
+                    }
+                }
+            }
+            let mut canonical_names = HashMap::new();
+            for (name, canonical) in &oauth.tool_names {
+                if canonical.is_empty() {
+                    return Err(common::Error::Config(format!(
+                        "oauth.tool_names.{name} must not be empty"
+                    )));
+                }
+                // Two client names for one canonical name could not be told
+                // apart in the response
+                if let Some(other) = canonical_names.insert(canonical, name) {
+                    let (a, b) = if other < name {
+                        (other, name)
+                    } else {
+                        (name, other)
+                    };
+                    return Err(common::Error::Config(format!(
+                        "oauth.tool_names maps both '{a}' and '{b}' to '{canonical}'"
+                    )));
+                }
+            }
+        }
+
+        let mut chained = Vec::new();
+        for tier in &config.provider_chain {
+            if !config.is_configured(tier.provider) {
+                return Err(common::Error::Config(format!(
+                    "provider_chain lists '{0}' but [{0}] is not configured",
+                    tier.provider.label()
+                )));
+            }
+            if chained.contains(&tier.provider) {
+                return Err(common::Error::Config(format!(
+                    "provider_chain lists '{}' twice",
+                    tier.provider.label()
+                )));
+            }
+            chained.push(tier.provider);
+        }
+        if let Some(ref oauth) = config.oauth
+            && let Some(id) = config
+                .chained_api_key_ids()
+                .into_iter()
+                .find(|id| oauth.providers.iter().any(|p| p == id))
+        {
+            return Err(common::Error::Config(format!(
+                "provider_chain: account id '{id}' is used by both [oauth] and [api_keys]; \
+                 IDs must be unique across tiers"
+            )));
+        }
+
+        let mut names = std::collections::HashSet::new();
+        let mut prefixes = std::collections::HashSet::new();
+        for route in &config.routes {
+            if route.name.is_empty() || route.name == crate::routing::DEFAULT_ROUTE {
+                return Err(common::Error::Config(format!(
+                    "routes name '{}' is reserved or empty",
+                    route.name
+                )));
+            }
+            if !names.insert(&route.name) {
+                return Err(common::Error::Config(format!(
+                    "routes name '{}' is listed twice",
+                    route.name
+                )));
+            }
+            if !route.path_prefix.starts_with('/') {
+                return Err(common::Error::Config(format!(
+                    "routes '{}': path_prefix must start with '/'",
+                    route.name
+                )));
+            }
+            if !prefixes.insert(&route.path_prefix) {
+                return Err(common::Error::Config(format!(
+                    "routes path_prefix '{}' is listed twice",
+                    route.path_prefix
+                )));
+            }
+            if let Some(ref url) = route.upstream_url {
+                validate_upstream_url(&format!("routes '{}' upstream_url", route.name), url)?;
+            }
+            if let Some(provider) = route.provider
+                && !config.is_configured(provider)
+            {
+                return Err(common::Error::Config(format!(
+                    "routes '{}' uses '{1}' but [{1}] is not configured",
+                    route.name,
+                    provider.label()
+                )));
+            }
+            if route.timeout_secs == Some(0) || route.max_body_bytes == Some(0) {
+                return Err(common::Error::Config(format!(
+                    "routes '{}': timeout_secs and max_body_bytes must be greater than 0",
+                    route.name
+                )));
+            }
+        }
+
+        for (alias, model) in &config.model_aliases {
+            if model.is_empty() {
+                return Err(common::Error::Config(format!(
+                    "model_aliases.{alias} must not be empty"
+                )));
+            }
+        }
+        for route in &config.model_routes {
+            if route.models.is_empty() {
+                return Err(common::Error::Config(
+                    "model_routes entries must list at least one model".into(),
+                ));
+            }
+            if !config.is_configured(route.provider) {
+                return Err(common::Error::Config(format!(
+                    "model_routes entry for {:?} uses '{1}' but [{1}] is not configured",
+                    route.models,
+                    route.provider.label()
+                )));
+            }
+            if let Some(ref url) = route.upstream_url {
+                validate_upstream_url("model_routes upstream_url", url)?;
+            }
+        }
+
+        if let Some(ref api_keys) = config.api_keys {
+            if api_keys.keys.is_empty() {
+                return Err(common::Error::Config(
+                    "api_keys.keys must list at least one key".into(),
+                ));
+            }
+            if api_keys.cooldown_secs == 0 {
+                return Err(common::Error::Config(
+                    "api_keys.cooldown_secs must be greater than 0".into(),
+                ));
+            }
+            let mut ids = std::collections::HashSet::new();
+            for key in &api_keys.keys {
+                if !ids.insert(&key.id) {
+                    return Err(common::Error::Config(format!(
+                        "api_keys key id '{}' is listed twice",
+                        key.id
+                    )));
+                }
+                key.key()?;
+            }
         }
 
         Ok(config)
     }
 
+    /// Whether the chain, a path route, or a model route names `kind`.
+    fn references(&self, kind: ProviderKind) -> bool {
+        self.provider_chain.iter().any(|t| t.provider == kind)
+            || self.routes.iter().any(|r| r.provider == Some(kind))
+            || self.model_routes.iter().any(|r| r.provider == kind)
+    }
+
+    /// Key IDs of `[api_keys]` when the chain also has an `[oauth]` tier.
+    /// The chain tells its tiers' accounts apart by ID, so OAuth accounts
+    /// must not use these.
+    pub fn chained_api_key_ids(&self) -> Vec<&str> {
+        let chained = |kind| self.provider_chain.iter().any(|t| t.provider == kind);
+        match self.api_keys {
+            Some(ref api_keys)
+                if chained(ProviderKind::Oauth) && chained(ProviderKind::ApiKeys) =>
+            {
+                api_keys.keys.iter().map(|k| k.id.as_str()).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Whether the section `kind` builds from is present (passthrough needs
+    /// none).
+    fn is_configured(&self, kind: ProviderKind) -> bool {
+        match kind {
+            ProviderKind::Oauth => self.oauth.is_some(),
+            ProviderKind::ApiKeys => self.api_keys.is_some(),
+            ProviderKind::Passthrough => true,
+        }
+    }
+
     /// Resolve config file path from CLI arg or CONFIG_PATH env var.
     pub fn resolve_path(cli_path: Option<&str>) -> PathBuf {
         if let Some(p) = cli_path {
@@ -205,14 +967,26 @@ impl Config {
     }
 }
 
+/// Reject upstream URLs that don't parse or aren't http(s).
+fn validate_upstream_url(field: &str, url: &str) -> common::Result<()> {
+    let url = reqwest::Url::parse(url)
+        .map_err(|e| common::Error::Config(format!("{field} is not a valid URL: {e}")))?;
+    match url.scheme() {
+        "http" | "https" => Ok(()),
+        scheme => Err(common::Error::Config(format!(
+            "{field} must use http or https scheme, got: {scheme}"
+        ))),
+    }
+}
+
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use std::sync::Mutex;
 
-    /// Mutex to serialize tests that mutate environment variables, preventing
-    /// data races when tests run in parallel.
-    static ENV_MUTEX: Mutex<()> = Mutex::new(());
+    /// Mutex to serialize tests that mutate or read (through `Config::load`)
+    /// environment variables, preventing data races when tests run in parallel.
+    pub(crate) static ENV_MUTEX: Mutex<()> = Mutex::new(());
 
     /// SAFETY: Callers must hold ENV_MUTEX to prevent concurrent env mutation.
     unsafe fn set_env(key: &str, val: &str) {
@@ -246,6 +1020,13 @@ value = "oauth-2025-04-20"
         let config = Config::load(&path).unwrap();
         assert_eq!(config.proxy.upstream_url, "https://api.anthropic.com");
         assert_eq!(config.proxy.timeout_secs, 60);
+        assert!(!config.proxy.buffer_first_event);
+        assert!(config.proxy.usage_accounting);
+        assert_eq!(config.proxy.retry.max_attempts, 3);
+        assert_eq!(
+            config.proxy.retry.retry_on,
+            vec![crate::retry::RetryClass::Timeout]
+        );
         assert_eq!(config.proxy.max_connections, 1000);
         assert_eq!(config.headers.len(), 1);
         assert_eq!(config.headers[0].name, "anthropic-beta");
@@ -571,10 +1352,27 @@ providers = ["claude-max-1"]
         assert_eq!(config.mode(), AuthMode::OAuthPool);
         assert!(config.oauth.is_some());
         let oauth = config.oauth.unwrap();
-        assert_eq!(oauth.credential_file, "/data/credentials.json");
+        assert_eq!(
+            oauth.credential_file.as_deref(),
+            Some("/data/credentials.json")
+        );
+        assert_eq!(
+            oauth.status_file(),
+            Some(PathBuf::from("/data/pool-state.json"))
+        );
         assert_eq!(oauth.cooldown_secs, 7200);
         assert_eq!(oauth.refresh_interval_secs, 300);
         assert_eq!(oauth.refresh_threshold_secs, 900);
+        assert_eq!(oauth.quota_reserve_ratio, 0.05);
+        assert_eq!(
+            oauth.selection_strategy,
+            anthropic_pool::SelectionStrategy::RoundRobin
+        );
+        assert_eq!(oauth.affinity_ttl_secs, 3600);
+        assert!(oauth.affinity_header.is_none());
+        assert_eq!(oauth.probe_interval_secs, 300);
+        assert_eq!(oauth.probe_max_backoff_secs, 3600);
+        assert_eq!(oauth.reload_interval_secs, 10);
 
         std::fs::remove_dir_all(&dir).unwrap();
     }
@@ -660,11 +1458,9 @@ cooldown_secs = 0
     }
 
     #[test]
-    fn test_credential_file_env_override() {
+    fn test_oauth_validation_quota_reserve_out_of_range() {
         let _lock = ENV_MUTEX.lock().unwrap();
-        unsafe { set_env("CREDENTIAL_FILE", "/override/path.json") };
-
-        let dir = std::env::temp_dir().join("oauth-proxy-test-cred-env");
+        let dir = std::env::temp_dir().join("oauth-proxy-test-quota-reserve");
         std::fs::create_dir_all(&dir).unwrap();
 
         let toml_content = r#"
@@ -674,12 +1470,671 @@ upstream_url = "https://api.anthropic.com"
 
 [oauth]
 credential_file = "/data/credentials.json"
+quota_reserve_ratio = 1.5
 "#;
         let path = dir.join("config.toml");
         std::fs::write(&path, toml_content).unwrap();
 
-        let config = Config::load(&path).unwrap();
-        assert_eq!(config.oauth.unwrap().credential_file, "/override/path.json");
+        let result = Config::load(&path);
+        assert!(result.is_err());
+        let err = format!("{}", result.unwrap_err());
+        assert!(err.contains("quota_reserve_ratio"), "got: {err}");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_oauth_validation_probe_backoff_below_interval() {
+        let _lock = ENV_MUTEX.lock().unwrap();
+        let dir = std::env::temp_dir().join("oauth-proxy-test-probe-backoff");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let toml_content = r#"
+[proxy]
+listen_addr = "127.0.0.1:8080"
+upstream_url = "https://api.anthropic.com"
+
+[oauth]
+credential_file = "/data/credentials.json"
+probe_interval_secs = 600
+probe_max_backoff_secs = 60
+"#;
+        let path = dir.join("config.toml");
+        std::fs::write(&path, toml_content).unwrap();
+
+        let result = Config::load(&path);
+        assert!(result.is_err());
+        let err = format!("{}", result.unwrap_err());
+        assert!(err.contains("probe_max_backoff_secs"), "got: {err}");
+
+        // Probing disabled: the backoff bound is irrelevant
+        std::fs::write(
+            &path,
+            toml_content.replace("probe_interval_secs = 600", "probe_interval_secs = 0"),
+        )
+        .unwrap();
+        assert!(Config::load(&path).is_ok());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_proxy_retry_section() {
+        let _lock = ENV_MUTEX.lock().unwrap();
+        let dir = std::env::temp_dir().join("oauth-proxy-test-retry");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let toml_content = r#"
+[proxy]
+listen_addr = "127.0.0.1:8080"
+upstream_url = "https://api.anthropic.com"
+
+[proxy.retry]
+max_attempts = 5
+initial_backoff_ms = 200
+max_backoff_ms = 5000
+jitter = false
+retry_on = ["connect", "timeout", "server_error", "rate_limited", "overloaded"]
+budget_ms = 30000
+"#;
+        let path = dir.join("config.toml");
+        std::fs::write(&path, toml_content).unwrap();
+
+        let retry = Config::load(&path).unwrap().proxy.retry;
+        assert_eq!(retry.max_attempts, 5);
+        assert_eq!(retry.initial_backoff_ms, 200);
+        assert_eq!(retry.max_backoff_ms, 5000);
+        assert_eq!(retry.backoff_multiplier, 2.0);
+        assert!(!retry.jitter);
+        assert_eq!(retry.retry_on.len(), 5);
+        assert_eq!(retry.budget_ms, 30000);
+
+        // Backoff cap below the initial backoff is rejected
+        std::fs::write(
+            &path,
+            toml_content.replace("max_backoff_ms = 5000", "max_backoff_ms = 50"),
+        )
+        .unwrap();
+        let err = format!("{}", Config::load(&path).unwrap_err());
+        assert!(err.contains("max_backoff_ms"), "got: {err}");
+
+        // Unknown failure classes are rejected at parse time
+        std::fs::write(&path, toml_content.replace("\"overloaded\"", "\"teapot\"")).unwrap();
+        assert!(Config::load(&path).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_clients_section() {
+        let _lock = ENV_MUTEX.lock().unwrap();
+        let dir = std::env::temp_dir().join("oauth-proxy-test-clients");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let toml_content = r#"
+[proxy]
+listen_addr = "127.0.0.1:8080"
+upstream_url = "https://api.anthropic.com"
+
+[[clients]]
+name = "ci"
+key_sha256 = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+labels = { team = "infra" }
+allowed_models = ["claude-haiku-*"]
+expires_at = "2030-01-01T00:00:00Z"
+
+[[clients]]
+name = "laptop"
+key_sha256 = "60303ae22b998861bce3b28f33eec1be758a213c86c93c076dbe9f558c11c752"
+"#;
+        let path = dir.join("config.toml");
+        std::fs::write(&path, toml_content).unwrap();
+
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config.clients.len(), 2);
+        assert_eq!(config.clients[0].labels["team"], "infra");
+        assert_eq!(config.clients[0].allowed_models, vec!["claude-haiku-*"]);
+        assert!(config.clients[1].expires_at.is_none());
+
+        // A malformed hash is rejected at startup
+        std::fs::write(&path, toml_content.replace("9f86d081", "not-hex")).unwrap();
+        let err = format!("{}", Config::load(&path).unwrap_err());
+        assert!(err.contains("key_sha256"), "got: {err}");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_tailnet_section() {
+        let _lock = ENV_MUTEX.lock().unwrap();
+        let dir = std::env::temp_dir().join("oauth-proxy-test-tailnet");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let toml_content = r#"
+[proxy]
+listen_addr = "127.0.0.1:8080"
+upstream_url = "https://api.anthropic.com"
+
+[tailnet]
+trust_forwarded_for = true
+trusted_proxies = ["10.0.0.0/8"]
+allow_tags = ["tag:ci"]
+allow_users = ["alice@example.com"]
+"#;
+        let path = dir.join("config.toml");
+        std::fs::write(&path, toml_content).unwrap();
+
+        let tailnet = Config::load(&path).unwrap().tailnet.unwrap();
+        assert_eq!(tailnet.localapi_url, "http://127.0.0.1:41112");
+        assert_eq!(tailnet.cache_ttl_secs, 60);
+        assert!(tailnet.trust_forwarded_for);
+        assert_eq!(tailnet.trusted_proxies, vec!["10.0.0.0/8"]);
+        assert_eq!(tailnet.allow_tags, vec!["tag:ci"]);
+        assert!(tailnet.allow_nodes.is_empty());
+
+        // Tags must carry the tag: prefix, as they do in tailnet ACLs
+        std::fs::write(&path, toml_content.replace("tag:ci", "ci")).unwrap();
+        let err = format!("{}", Config::load(&path).unwrap_err());
+        assert!(err.contains("allow_tags"), "got: {err}");
+
+        // Forwarded addresses are only believed from known proxies
+        std::fs::write(
+            &path,
+            toml_content.replace("trusted_proxies = [\"10.0.0.0/8\"]\n", ""),
+        )
+        .unwrap();
+        let err = format!("{}", Config::load(&path).unwrap_err());
+        assert!(err.contains("trusted_proxies"), "got: {err}");
+        std::fs::write(&path, toml_content.replace("10.0.0.0/8", "ingress")).unwrap();
+        let err = format!("{}", Config::load(&path).unwrap_err());
+        assert!(err.contains("trusted_proxies"), "got: {err}");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_oauth_selection_strategy() {
+        let _lock = ENV_MUTEX.lock().unwrap();
+        let dir = std::env::temp_dir().join("oauth-proxy-test-strategy");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let toml_content = r#"
+[proxy]
+listen_addr = "127.0.0.1:8080"
+upstream_url = "https://api.anthropic.com"
+
+[oauth]
+credential_file = "/data/credentials.json"
+selection_strategy = "fill-first"
+"#;
+        let path = dir.join("config.toml");
+        std::fs::write(&path, toml_content).unwrap();
+
+        let config = Config::load(&path).unwrap();
+        assert_eq!(
+            config.oauth.unwrap().selection_strategy,
+            anthropic_pool::SelectionStrategy::FillFirst
+        );
+
+        // Unknown strategies are rejected at parse time
+        std::fs::write(
+            &path,
+            toml_content.replace("fill-first", "most-expensive-first"),
+        )
+        .unwrap();
+        assert!(Config::load(&path).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_oauth_validation_invalid_affinity_header() {
+        let _lock = ENV_MUTEX.lock().unwrap();
+        let dir = std::env::temp_dir().join("oauth-proxy-test-affinity-header");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let toml_content = r#"
+[proxy]
+listen_addr = "127.0.0.1:8080"
+upstream_url = "https://api.anthropic.com"
+
+[oauth]
+credential_file = "/data/credentials.json"
+affinity_header = "bad header"
+"#;
+        let path = dir.join("config.toml");
+        std::fs::write(&path, toml_content).unwrap();
+
+        let result = Config::load(&path);
+        assert!(result.is_err());
+        let err = format!("{}", result.unwrap_err());
+        assert!(err.contains("affinity_header"), "got: {err}");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_oauth_tool_names() {
+        let _lock = ENV_MUTEX.lock().unwrap();
+        let dir = std::env::temp_dir().join("oauth-proxy-test-tool-names");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let toml_content = r#"
+[proxy]
+listen_addr = "127.0.0.1:8080"
+upstream_url = "https://api.anthropic.com"
+
+[oauth]
+credential_file = "/data/credentials.json"
+
+[oauth.tool_names]
+bash = "Bash"
+read_file = "Read"
+"#;
+        let path = dir.join("config.toml");
+        std::fs::write(&path, toml_content).unwrap();
+        let config = Config::load(&path).unwrap();
+        let oauth = config.oauth.unwrap();
+        assert_eq!(oauth.tool_names.len(), 2);
+        assert_eq!(oauth.tool_names["bash"], "Bash");
+
+        // Two client names for one canonical name are rejected
+        std::fs::write(
+            &path,
+            toml_content.replace("read_file = \"Read\"", "shell = \"Bash\""),
+        )
+        .unwrap();
+        let err = format!("{}", Config::load(&path).unwrap_err());
+        assert!(
+            err.contains("maps both 'bash' and 'shell' to 'Bash'"),
+            "got: {err}"
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_api_keys_section() {
+        let _lock = ENV_MUTEX.lock().unwrap();
+        let dir = std::env::temp_dir().join("oauth-proxy-test-api-keys");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let toml_content = r#"
+[proxy]
+listen_addr = "127.0.0.1:8080"
+upstream_url = "https://api.anthropic.com"
+
+[api_keys]
+keys = [
+    { id = "paid-1", key_env = "TEST_API_KEY_1" },
+    { id = "paid-2", key_env = "TEST_API_KEY_2" },
+]
+"#;
+        let path = dir.join("config.toml");
+        std::fs::write(&path, toml_content).unwrap();
+
+        unsafe { set_env("TEST_API_KEY_1", "sk-ant-one") };
+        unsafe { remove_env("TEST_API_KEY_2") };
+        let err = format!("{}", Config::load(&path).unwrap_err());
+        assert!(err.contains("TEST_API_KEY_2 is not set"), "got: {err}");
+
+        unsafe { set_env("TEST_API_KEY_2", "sk-ant-two") };
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config.mode(), AuthMode::ApiKeyPool);
+        let api_keys = config.api_keys.unwrap();
+        assert_eq!(api_keys.cooldown_secs, 60);
+        assert_eq!(api_keys.keys[1].id, "paid-2");
+        assert_eq!(api_keys.keys[1].key().unwrap(), "sk-ant-two");
+
+        unsafe { remove_env("TEST_API_KEY_1") };
+        unsafe { remove_env("TEST_API_KEY_2") };
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_provider_chain_section() {
+        let _lock = ENV_MUTEX.lock().unwrap();
+        let dir = std::env::temp_dir().join("oauth-proxy-test-provider-chain");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let toml_content = r#"
+[proxy]
+listen_addr = "127.0.0.1:8080"
+upstream_url = "https://api.anthropic.com"
+
+[oauth]
+credential_file = "/data/credentials.json"
+
+[api_keys]
+keys = [{ id = "paid-1", key_env = "TEST_CHAIN_API_KEY" }]
+
+[[provider_chain]]
+provider = "oauth"
+
+[[provider_chain]]
+provider = "api_keys"
+models = ["claude-haiku-*"]
+clients = ["ci"]
+"#;
+        let path = dir.join("config.toml");
+        std::fs::write(&path, toml_content).unwrap();
+        unsafe { set_env("TEST_CHAIN_API_KEY", "sk-ant-chain") };
+
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config.mode(), AuthMode::Chain);
+        assert!(config.oauth.is_some());
+        assert!(config.api_keys.is_some());
+        assert_eq!(config.provider_chain.len(), 2);
+        assert_eq!(config.provider_chain[1].provider, ProviderKind::ApiKeys);
+        assert_eq!(config.provider_chain[1].clients, vec!["ci"]);
+
+        // A tier must name a configured section
+        let missing = toml_content.replace(
+            "[api_keys]\nkeys = [{ id = \"paid-1\", key_env = \"TEST_CHAIN_API_KEY\" }]\n",
+            "",
+        );
+        std::fs::write(&path, missing).unwrap();
+        let err = format!("{}", Config::load(&path).unwrap_err());
+        assert!(err.contains("[api_keys] is not configured"), "got: {err}");
+
+        // Tiers may not share account IDs
+        let shared = toml_content.replace(
+            "credential_file = \"/data/credentials.json\"\n",
+            "credential_file = \"/data/credentials.json\"\nproviders = [\"max-1\", \"paid-1\"]\n",
+        );
+        std::fs::write(&path, shared).unwrap();
+        let err = format!("{}", Config::load(&path).unwrap_err());
+        assert!(err.contains("account id 'paid-1'"), "got: {err}");
+
+        unsafe { remove_env("TEST_CHAIN_API_KEY") };
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_model_routes_keep_referenced_sections() {
+        let _lock = ENV_MUTEX.lock().unwrap();
+        let dir = std::env::temp_dir().join("oauth-proxy-test-model-routes");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let toml_content = r#"
+[proxy]
+listen_addr = "127.0.0.1:8080"
+upstream_url = "https://api.anthropic.com"
+
+[oauth]
+credential_file = "/data/credentials.json"
+
+[api_keys]
+keys = [{ id = "paid-1", key_env = "TEST_ROUTES_API_KEY" }]
+
+[model_aliases]
+fast = "claude-haiku-4-5"
+
+[[model_routes]]
+models = ["claude-haiku-*"]
+provider = "api_keys"
+
+[[model_routes]]
+models = ["qwen3"]
+provider = "passthrough"
+upstream_url = "http://localhost:11434"
+"#;
+        let path = dir.join("config.toml");
+        std::fs::write(&path, toml_content).unwrap();
+        unsafe { set_env("TEST_ROUTES_API_KEY", "sk-ant-routes") };
+
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config.mode(), AuthMode::OAuthPool);
+        assert!(
+            config.api_keys.is_some(),
+            "[api_keys] is kept for the route that uses it"
+        );
+        assert_eq!(config.model_aliases["fast"], "claude-haiku-4-5");
+        assert_eq!(config.model_routes.len(), 2);
+        assert_eq!(config.model_routes[1].provider, ProviderKind::Passthrough);
+
+        let bad_url = toml_content.replace("http://localhost:11434", "localhost:11434");
+        std::fs::write(&path, bad_url).unwrap();
+        let err = format!("{}", Config::load(&path).unwrap_err());
+        assert!(err.contains("model_routes upstream_url"), "got: {err}");
+
+        unsafe { remove_env("TEST_ROUTES_API_KEY") };
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_routes_section() {
+        let dir = std::env::temp_dir().join("oauth-proxy-test-routes");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let toml_content = r#"
+[proxy]
+listen_addr = "127.0.0.1:8080"
+upstream_url = "https://api.anthropic.com"
+
+[[routes]]
+name = "messages"
+path_prefix = "/v1/messages"
+
+[[routes]]
+name = "local"
+path_prefix = "/local/"
+strip_prefix = true
+upstream_url = "http://127.0.0.1:11434"
+provider = "passthrough"
+timeout_secs = 300
+max_body_bytes = 1048576
+"#;
+        let path = dir.join("config.toml");
+        std::fs::write(&path, toml_content).unwrap();
+
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config.routes.len(), 2);
+        assert_eq!(config.routes[0].provider, None);
+        assert!(config.routes[1].strip_prefix);
+        assert_eq!(config.routes[1].timeout_secs, Some(300));
+        assert_eq!(config.routes[1].max_body_bytes, Some(1048576));
+
+        let api_keys_route = toml_content.replace("\"passthrough\"", "\"api_keys\"");
+        std::fs::write(&path, api_keys_route).unwrap();
+        let err = format!("{}", Config::load(&path).unwrap_err());
+        assert!(err.contains("[api_keys] is not configured"), "got: {err}");
+
+        let duplicate = toml_content.replace("name = \"local\"", "name = \"messages\"");
+        std::fs::write(&path, duplicate).unwrap();
+        let err = format!("{}", Config::load(&path).unwrap_err());
+        assert!(err.contains("listed twice"), "got: {err}");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_oauth_encryption_section() {
+        let _lock = ENV_MUTEX.lock().unwrap();
+        let dir = std::env::temp_dir().join("oauth-proxy-test-encryption");
+        std::fs::create_dir_all(&dir).unwrap();
+        let key_path = dir.join("credential.key");
+        std::fs::write(&key_path, anthropic_auth::Encryption::generate_key() + "\n").unwrap();
+
+        let toml_content = format!(
+            r#"
+[proxy]
+listen_addr = "127.0.0.1:8080"
+upstream_url = "https://api.anthropic.com"
+
+[oauth]
+credential_file = "/data/credentials.json"
+
+[oauth.encryption]
+key_file = "{}"
+"#,
+            key_path.display()
+        );
+        let path = dir.join("config.toml");
+        std::fs::write(&path, &toml_content).unwrap();
+        let config = Config::load(&path).unwrap();
+        let encryption = config.oauth.unwrap().encryption.unwrap();
+        assert!(encryption.key_file.is_some());
+
+        // Two sources are ambiguous
+        let both = toml_content.replace(
+            "[oauth.encryption]\n",
+            "[oauth.encryption]\npassphrase_env = \"TEST_CREDENTIAL_PASSPHRASE\"\n",
+        );
+        std::fs::write(&path, both).unwrap();
+        let err = format!("{}", Config::load(&path).unwrap_err());
+        assert!(err.contains("exactly one"), "got: {err}");
+
+        // A missing passphrase fails at startup, not at first write
+        let passphrase = toml_content.replace(
+            &format!("key_file = \"{}\"", key_path.display()),
+            "passphrase_env = \"TEST_CREDENTIAL_PASSPHRASE\"",
+        );
+        std::fs::write(&path, passphrase).unwrap();
+        unsafe { remove_env("TEST_CREDENTIAL_PASSPHRASE") };
+        let err = format!("{}", Config::load(&path).unwrap_err());
+        assert!(err.contains("TEST_CREDENTIAL_PASSPHRASE"), "got: {err}");
+        unsafe { set_env("TEST_CREDENTIAL_PASSPHRASE", "correct horse") };
+        assert!(Config::load(&path).is_ok());
+        unsafe { remove_env("TEST_CREDENTIAL_PASSPHRASE") };
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_oauth_storage_section() {
+        let _lock = ENV_MUTEX.lock().unwrap();
+        let dir = std::env::temp_dir().join("oauth-proxy-test-storage");
+        std::fs::create_dir_all(&dir).unwrap();
+        let base = r#"
+[proxy]
+listen_addr = "127.0.0.1:8080"
+upstream_url = "https://api.anthropic.com"
+
+[oauth]
+credential_file = "/data/credentials.json"
+"#;
+        let path = dir.join("config.toml");
+        std::fs::write(&path, base).unwrap();
+        let config = Config::load(&path).unwrap();
+        assert!(matches!(config.oauth.unwrap().storage, StorageConfig::File));
+
+        let kubernetes = format!(
+            "{base}\n[oauth.storage]\nbackend = \"kubernetes\"\nsecret = \"oauth-credentials\"\n"
+        );
+        std::fs::write(&path, &kubernetes).unwrap();
+        match Config::load(&path).unwrap().oauth.unwrap().storage {
+            StorageConfig::Kubernetes {
+                secret,
+                namespace,
+                key,
+                api_url,
+            } => {
+                assert_eq!(secret, "oauth-credentials");
+                assert_eq!(key, "credentials.json");
+                assert!(namespace.is_none() && api_url.is_none());
+            }
+            other => panic!("unexpected storage {other:?}"),
+        }
+
+        let bad_url = format!("{kubernetes}api_url = \"ftp://localhost\"\n");
+        std::fs::write(&path, bad_url).unwrap();
+        let err = format!("{}", Config::load(&path).unwrap_err());
+        assert!(err.contains("oauth.storage.api_url"), "got: {err}");
+
+        let sqlite = format!("{base}\n[oauth.storage]\nbackend = \"sqlite\"\npath = \"\"\n");
+        std::fs::write(&path, sqlite).unwrap();
+        let err = format!("{}", Config::load(&path).unwrap_err());
+        assert!(err.contains("oauth.storage.path"), "got: {err}");
+
+        let unknown = format!("{base}\n[oauth.storage]\nbackend = \"etcd\"\n");
+        std::fs::write(&path, unknown).unwrap();
+        assert!(Config::load(&path).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_credential_file_only_required_for_file_storage() {
+        let _lock = ENV_MUTEX.lock().unwrap();
+        let dir = std::env::temp_dir().join("oauth-proxy-test-no-cred-file");
+        std::fs::create_dir_all(&dir).unwrap();
+        let base = r#"
+[proxy]
+listen_addr = "127.0.0.1:8080"
+upstream_url = "https://api.anthropic.com"
+
+[oauth]
+cooldown_secs = 60
+"#;
+        let path = dir.join("config.toml");
+        std::fs::write(&path, base).unwrap();
+        let err = format!("{}", Config::load(&path).unwrap_err());
+        assert!(err.contains("oauth.credential_file"), "got: {err}");
+
+        // Other backends don't need it, and persist no statuses by default
+        let sqlite = format!(
+            "{base}\n[oauth.storage]\nbackend = \"sqlite\"\npath = \"/data/credentials.db\"\n"
+        );
+        std::fs::write(&path, &sqlite).unwrap();
+        let oauth = Config::load(&path).unwrap().oauth.unwrap();
+        assert!(oauth.credential_file.is_none());
+        assert!(oauth.status_file().is_none());
+
+        let with_status = sqlite.replace(
+            "cooldown_secs = 60",
+            "cooldown_secs = 60\nstatus_file = \"/state/pool-state.json\"",
+        );
+        std::fs::write(&path, &with_status).unwrap();
+        let oauth = Config::load(&path).unwrap().oauth.unwrap();
+        assert_eq!(
+            oauth.status_file(),
+            Some(PathBuf::from("/state/pool-state.json"))
+        );
+
+        unsafe { set_env("STATUS_FILE", "/state/replica-1.json") };
+        let result = Config::load(&path);
+        unsafe { remove_env("STATUS_FILE") };
+        assert_eq!(
+            result.unwrap().oauth.unwrap().status_file(),
+            Some(PathBuf::from("/state/replica-1.json"))
+        );
+
+        let empty = sqlite.replace(
+            "cooldown_secs = 60",
+            "cooldown_secs = 60\nstatus_file = \"\"",
+        );
+        std::fs::write(&path, empty).unwrap();
+        let err = format!("{}", Config::load(&path).unwrap_err());
+        assert!(err.contains("oauth.status_file"), "got: {err}");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_credential_file_env_override() {
+        let _lock = ENV_MUTEX.lock().unwrap();
+        unsafe { set_env("CREDENTIAL_FILE", "/override/path.json") };
+
+        let dir = std::env::temp_dir().join("oauth-proxy-test-cred-env");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let toml_content = r#"
+[proxy]
+listen_addr = "127.0.0.1:8080"
+upstream_url = "https://api.anthropic.com"
+
+[oauth]
+credential_file = "/data/credentials.json"
+"#;
+        let path = dir.join("config.toml");
+        std::fs::write(&path, toml_content).unwrap();
+
+        let config = Config::load(&path).unwrap();
+        assert_eq!(
+            config.oauth.unwrap().credential_file.as_deref(),
+            Some("/override/path.json")
+        );
 
         unsafe { remove_env("CREDENTIAL_FILE") };
         std::fs::remove_dir_all(&dir).unwrap();
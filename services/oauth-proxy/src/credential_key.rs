@@ -0,0 +1,226 @@
+//! Credential key maintenance commands
+//!
+//! - `generate-credential-key` prints a random base64 key for
+//!   `[oauth.encryption]` (`key_env` or `key_file`).
+//! - `rotate-credential-key --config <path> <new key>` rewrites the stored
+//!   credentials (in whichever `[oauth.storage]` backend) from the key
+//!   configured in `[oauth.encryption]` (or plaintext, if none) to the new
+//!   key, given as `--new-key-env VAR`, `--new-key-file PATH`,
+//!   `--new-passphrase-env VAR`, or `--plaintext`.
+//!
+//! Rotation must run while no proxy is writing the credentials; afterwards point
+//! `[oauth.encryption]` at the new key.
+
+use anyhow::{Context, Result, bail};
+
+use crate::config::{Config, EncryptionConfig};
+
+/// A maintenance subcommand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    GenerateKey,
+    RotateKey,
+}
+
+impl Command {
+    /// Recognize a subcommand name (anything else starts the proxy).
+    pub fn parse(arg: &str) -> Option<Self> {
+        match arg {
+            "generate-credential-key" => Some(Command::GenerateKey),
+            "rotate-credential-key" => Some(Command::RotateKey),
+            _ => None,
+        }
+    }
+}
+
+/// Run `command` with the process arguments.
+pub async fn run(command: Command, args: &[String]) -> Result<()> {
+    match command {
+        Command::GenerateKey => {
+            println!("{}", anthropic_auth::Encryption::generate_key());
+            Ok(())
+        }
+        Command::RotateKey => {
+            let rotation = Rotation::from_args(args)?;
+            rotation.run().await
+        }
+    }
+}
+
+/// A credential backend and the keys to rotate it between.
+struct Rotation {
+    backend: Box<dyn anthropic_auth::CredentialBackend>,
+    from: Option<anthropic_auth::Encryption>,
+    to: Option<anthropic_auth::Encryption>,
+}
+
+impl Rotation {
+    /// Resolve the backend and current key from the config, and the new key
+    /// from the flags.
+    fn from_args(args: &[String]) -> Result<Self> {
+        let flag = |name: &str| {
+            args.iter()
+                .position(|a| a == name)
+                .and_then(|i| args.get(i + 1))
+                .cloned()
+        };
+
+        let target = new_key_config(
+            flag("--new-key-env"),
+            flag("--new-key-file"),
+            flag("--new-passphrase-env"),
+            args.iter().any(|a| a == "--plaintext"),
+        )?;
+        let to = target.map(|c| c.encryption()).transpose()?;
+
+        let config_path = Config::resolve_path(flag("--config").as_deref());
+        let config = Config::load(&config_path)
+            .with_context(|| format!("failed to load config from {}", config_path.display()))?;
+        let Some(oauth) = config.oauth else {
+            bail!("{} has no [oauth] section", config_path.display());
+        };
+        let from = oauth
+            .encryption
+            .as_ref()
+            .map(|e| e.encryption())
+            .transpose()?;
+        Ok(Self {
+            backend: oauth.credential_backend()?,
+            from,
+            to,
+        })
+    }
+
+    async fn run(self) -> Result<()> {
+        let location = self.backend.location();
+        let accounts = anthropic_auth::CredentialStore::rotate_key(
+            self.backend.as_ref(),
+            self.from.as_ref(),
+            self.to.as_ref(),
+        )
+        .await
+        .with_context(|| format!("failed to rotate {location}"))?;
+        println!(
+            "rewrote {accounts} account(s) in {location} ({}); update [oauth.encryption] to match",
+            if self.to.is_some() {
+                "encrypted"
+            } else {
+                "plaintext"
+            }
+        );
+        Ok(())
+    }
+}
+
+/// The `[oauth.encryption]` equivalent of the new-key flags (`None`:
+/// plaintext).
+fn new_key_config(
+    key_env: Option<String>,
+    key_file: Option<String>,
+    passphrase_env: Option<String>,
+    plaintext: bool,
+) -> Result<Option<EncryptionConfig>> {
+    let sources = [
+        key_env.is_some(),
+        key_file.is_some(),
+        passphrase_env.is_some(),
+        plaintext,
+    ];
+    if sources.iter().filter(|s| **s).count() != 1 {
+        bail!(
+            "rotate-credential-key needs exactly one of --new-key-env, --new-key-file, \
+             --new-passphrase-env, --plaintext"
+        );
+    }
+    if plaintext {
+        return Ok(None);
+    }
+    Ok(Some(EncryptionConfig {
+        key_env,
+        key_file,
+        passphrase_env,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_key_needs_exactly_one_source() {
+        assert!(new_key_config(None, None, None, false).is_err());
+        assert!(new_key_config(Some("A".into()), None, None, true).is_err());
+        assert!(new_key_config(None, None, None, true).unwrap().is_none());
+        let config = new_key_config(None, Some("/key".into()), None, false)
+            .unwrap()
+            .unwrap();
+        assert_eq!(config.key_file.as_deref(), Some("/key"));
+    }
+
+    #[tokio::test]
+    async fn rotate_rewrites_file_under_new_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let credentials = dir.path().join("credentials.json");
+        let key_file = dir.path().join("new.key");
+        std::fs::write(&key_file, anthropic_auth::Encryption::generate_key()).unwrap();
+        let config_path = dir.path().join("config.toml");
+        std::fs::write(
+            &config_path,
+            format!(
+                r#"
+[proxy]
+listen_addr = "127.0.0.1:8080"
+upstream_url = "https://api.anthropic.com"
+
+[oauth]
+credential_file = "{}"
+"#,
+                credentials.display()
+            ),
+        )
+        .unwrap();
+        anthropic_auth::CredentialStore::load(credentials.clone())
+            .await
+            .unwrap()
+            .add(
+                "acct-1".into(),
+                anthropic_auth::Credential {
+                    credential_type: "oauth".into(),
+                    refresh: "rt_secret".into(),
+                    access: "at_secret".into(),
+                    expires: 0,
+                },
+            )
+            .await
+            .unwrap();
+
+        let args: Vec<String> = [
+            "oauth-proxy",
+            "rotate-credential-key",
+            "--config",
+            config_path.to_str().unwrap(),
+            "--new-key-file",
+            key_file.to_str().unwrap(),
+        ]
+        .iter()
+        .map(|a| a.to_string())
+        .collect();
+        let rotation = {
+            // Config::load reads CREDENTIAL_FILE, which config tests set
+            let _lock = crate::config::tests::ENV_MUTEX.lock().unwrap();
+            Rotation::from_args(&args).unwrap()
+        };
+        rotation.run().await.unwrap();
+
+        let contents = std::fs::read_to_string(&credentials).unwrap();
+        assert!(!contents.contains("rt_secret"));
+        let key = std::fs::read_to_string(&key_file).unwrap();
+        let store = anthropic_auth::CredentialStore::load_with_encryption(
+            credentials,
+            Some(anthropic_auth::Encryption::from_base64_key(&key).unwrap()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(store.get("acct-1").await.unwrap().refresh, "rt_secret");
+    }
+}
@@ -0,0 +1,321 @@
+//! Per-client request and token limits
+//!
+//! Each `[[clients]]` entry may cap its requests per minute, concurrent
+//! requests (streams count until their last chunk is sent), and input/output
+//! tokens per UTC day. A client over any limit gets a 429 before its request
+//! reaches the pool, so one runaway caller cannot drain every account.
+//!
+//! Token budgets are checked on admission and charged when a response
+//! completes, so the request that crosses a budget still finishes; the next
+//! one is refused until the day rolls over.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::usage::TokenUsage;
+
+const MINUTE: Duration = Duration::from_secs(60);
+const SECS_PER_DAY: u64 = 86_400;
+
+/// Limits configured for one client. `None` leaves that dimension unlimited.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientLimits {
+    pub requests_per_minute: Option<u32>,
+    pub max_concurrent_requests: Option<u32>,
+    pub input_tokens_per_day: Option<u64>,
+    pub output_tokens_per_day: Option<u64>,
+}
+
+impl ClientLimits {
+    /// Whether any limit is set.
+    pub fn is_limited(&self) -> bool {
+        *self != Self::default()
+    }
+
+    /// Whether token usage has to be counted for this client.
+    pub fn tracks_tokens(&self) -> bool {
+        self.input_tokens_per_day.is_some() || self.output_tokens_per_day.is_some()
+    }
+}
+
+/// Which limit refused a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LimitExceeded {
+    RequestsPerMinute { limit: u32, retry_after: Duration },
+    ConcurrentRequests { limit: u32 },
+    InputTokensPerDay { limit: u64, retry_after: Duration },
+    OutputTokensPerDay { limit: u64, retry_after: Duration },
+}
+
+impl LimitExceeded {
+    /// Metric label.
+    pub fn label(&self) -> &'static str {
+        match self {
+            LimitExceeded::RequestsPerMinute { .. } => "requests_per_minute",
+            LimitExceeded::ConcurrentRequests { .. } => "concurrent_requests",
+            LimitExceeded::InputTokensPerDay { .. } => "input_tokens_per_day",
+            LimitExceeded::OutputTokensPerDay { .. } => "output_tokens_per_day",
+        }
+    }
+
+    /// How long the client should wait before trying again.
+    pub fn retry_after(&self) -> Duration {
+        match self {
+            LimitExceeded::RequestsPerMinute { retry_after, .. }
+            | LimitExceeded::InputTokensPerDay { retry_after, .. }
+            | LimitExceeded::OutputTokensPerDay { retry_after, .. } => *retry_after,
+            // A slot frees as soon as any in-flight request finishes
+            LimitExceeded::ConcurrentRequests { .. } => Duration::from_secs(1),
+        }
+    }
+}
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitExceeded::RequestsPerMinute { limit, .. } => {
+                write!(f, "rate limit of {limit} requests per minute exceeded")
+            }
+            LimitExceeded::ConcurrentRequests { limit } => {
+                write!(f, "limit of {limit} concurrent requests exceeded")
+            }
+            LimitExceeded::InputTokensPerDay { limit, .. } => {
+                write!(f, "daily budget of {limit} input tokens exhausted")
+            }
+            LimitExceeded::OutputTokensPerDay { limit, .. } => {
+                write!(f, "daily budget of {limit} output tokens exhausted")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct UsageState {
+    /// Admission times within the last minute (sliding window)
+    recent: VecDeque<Instant>,
+    concurrent: u32,
+    /// UTC day number the token counters belong to
+    day: u64,
+    input_tokens: u64,
+    output_tokens: u64,
+}
+
+/// Live usage of one client, shared by all of its in-flight requests.
+#[derive(Debug, Default)]
+pub struct ClientUsage {
+    limits: ClientLimits,
+    state: Mutex<UsageState>,
+}
+
+impl ClientUsage {
+    pub fn new(limits: ClientLimits) -> Self {
+        Self {
+            limits,
+            state: Mutex::new(UsageState::default()),
+        }
+    }
+
+    pub fn limits(&self) -> &ClientLimits {
+        &self.limits
+    }
+
+    /// Admit a request, or say which limit refuses it. The returned permit
+    /// holds a concurrency slot until dropped.
+    pub fn admit(self: &Arc<Self>) -> Result<UsagePermit, LimitExceeded> {
+        self.admit_at(Instant::now(), unix_now())
+    }
+
+    fn admit_at(
+        self: &Arc<Self>,
+        now: Instant,
+        unix_secs: u64,
+    ) -> Result<UsagePermit, LimitExceeded> {
+        let mut state = self.state.lock().unwrap();
+        roll_day(&mut state, unix_secs);
+        let until_tomorrow = Duration::from_secs(SECS_PER_DAY - unix_secs % SECS_PER_DAY);
+
+        if let Some(limit) = self.limits.input_tokens_per_day
+            && state.input_tokens >= limit
+        {
+            return Err(LimitExceeded::InputTokensPerDay {
+                limit,
+                retry_after: until_tomorrow,
+            });
+        }
+        if let Some(limit) = self.limits.output_tokens_per_day
+            && state.output_tokens >= limit
+        {
+            return Err(LimitExceeded::OutputTokensPerDay {
+                limit,
+                retry_after: until_tomorrow,
+            });
+        }
+        if let Some(limit) = self.limits.max_concurrent_requests
+            && state.concurrent >= limit
+        {
+            return Err(LimitExceeded::ConcurrentRequests { limit });
+        }
+        if let Some(limit) = self.limits.requests_per_minute {
+            while state
+                .recent
+                .front()
+                .is_some_and(|t| now.duration_since(*t) >= MINUTE)
+            {
+                state.recent.pop_front();
+            }
+            if state.recent.len() >= limit as usize {
+                let oldest = state.recent[0];
+                return Err(LimitExceeded::RequestsPerMinute {
+                    limit,
+                    retry_after: MINUTE - now.duration_since(oldest),
+                });
+            }
+            state.recent.push_back(now);
+        }
+
+        state.concurrent += 1;
+        Ok(UsagePermit {
+            usage: self.clone(),
+        })
+    }
+
+    /// Charge a completed response's tokens to today's budget.
+    pub fn record_tokens(&self, usage: &TokenUsage) {
+        self.record_tokens_at(usage, unix_now());
+    }
+
+    fn record_tokens_at(&self, usage: &TokenUsage, unix_secs: u64) {
+        let mut state = self.state.lock().unwrap();
+        roll_day(&mut state, unix_secs);
+        state.input_tokens += usage.total_input();
+        state.output_tokens += usage.output_tokens;
+    }
+}
+
+/// Holds one of a client's concurrency slots while a request (including its
+/// response stream) is in flight.
+#[derive(Debug)]
+pub struct UsagePermit {
+    usage: Arc<ClientUsage>,
+}
+
+impl UsagePermit {
+    pub fn usage(&self) -> &Arc<ClientUsage> {
+        &self.usage
+    }
+}
+
+impl Drop for UsagePermit {
+    fn drop(&mut self) {
+        let mut state = self.usage.state.lock().unwrap();
+        state.concurrent = state.concurrent.saturating_sub(1);
+    }
+}
+
+fn roll_day(state: &mut UsageState, unix_secs: u64) {
+    let day = unix_secs / SECS_PER_DAY;
+    if state.day != day {
+        state.day = day;
+        state.input_tokens = 0;
+        state.output_tokens = 0;
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(limits: ClientLimits) -> Arc<ClientUsage> {
+        Arc::new(ClientUsage::new(limits))
+    }
+
+    #[test]
+    fn requests_per_minute_is_a_sliding_window() {
+        let u = usage(ClientLimits {
+            requests_per_minute: Some(2),
+            ..Default::default()
+        });
+        let t0 = Instant::now();
+        drop(u.admit_at(t0, 0).unwrap());
+        drop(u.admit_at(t0 + Duration::from_secs(20), 0).unwrap());
+
+        let err = u.admit_at(t0 + Duration::from_secs(30), 0).unwrap_err();
+        assert_eq!(
+            err,
+            LimitExceeded::RequestsPerMinute {
+                limit: 2,
+                retry_after: Duration::from_secs(30)
+            }
+        );
+        // The first request has left the window
+        assert!(u.admit_at(t0 + Duration::from_secs(60), 0).is_ok());
+    }
+
+    #[test]
+    fn concurrency_slot_is_held_until_permit_drops() {
+        let u = usage(ClientLimits {
+            max_concurrent_requests: Some(1),
+            ..Default::default()
+        });
+        let permit = u.admit().unwrap();
+        assert_eq!(
+            u.admit().unwrap_err(),
+            LimitExceeded::ConcurrentRequests { limit: 1 }
+        );
+        drop(permit);
+        assert!(u.admit().is_ok());
+    }
+
+    #[test]
+    fn token_budgets_reset_at_utc_midnight() {
+        let u = usage(ClientLimits {
+            input_tokens_per_day: Some(1000),
+            output_tokens_per_day: Some(100),
+            ..Default::default()
+        });
+        let now = Instant::now();
+        let noon = 20_000 * SECS_PER_DAY + SECS_PER_DAY / 2;
+
+        u.record_tokens_at(
+            &TokenUsage {
+                input_tokens: 10,
+                output_tokens: 100,
+                ..Default::default()
+            },
+            noon,
+        );
+        let err = u.admit_at(now, noon).unwrap_err();
+        assert_eq!(err.label(), "output_tokens_per_day");
+        assert_eq!(err.retry_after(), Duration::from_secs(SECS_PER_DAY / 2));
+
+        assert!(u.admit_at(now, noon + SECS_PER_DAY / 2).is_ok());
+    }
+
+    #[test]
+    fn cache_tokens_count_toward_input_budget() {
+        let u = usage(ClientLimits {
+            input_tokens_per_day: Some(1000),
+            ..Default::default()
+        });
+        u.record_tokens_at(
+            &TokenUsage {
+                input_tokens: 10,
+                cache_read_input_tokens: 990,
+                ..Default::default()
+            },
+            0,
+        );
+        assert_eq!(
+            u.admit_at(Instant::now(), 0).unwrap_err().label(),
+            "input_tokens_per_day"
+        );
+    }
+}
@@ -8,17 +8,29 @@
 //! Tailnet exposure is handled externally by the Tailscale Operator.
 
 mod admin;
+mod chain;
+mod claude_import;
+mod clients;
 mod config;
+mod credential_key;
+mod limits;
 mod metrics;
+mod openai;
 mod provider_impl;
 mod proxy;
+mod retry;
+mod routing;
 mod service;
+mod tailnet;
+mod tool_names;
+mod usage;
 
 use anyhow::{Context, Result};
 use axum::Router;
 use axum::extract::State;
 use axum::response::{IntoResponse, Response};
 use axum::routing::get;
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::sync::atomic::Ordering;
 use std::time::Duration;
@@ -30,7 +42,7 @@ use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use metrics_exporter_prometheus::PrometheusHandle;
 use provider::PassthroughProvider;
 
-use crate::config::{AuthMode, Config};
+use crate::config::{AuthMode, Config, ProviderKind};
 use crate::proxy::ProxyState;
 use crate::service::{
     DRAIN_TIMEOUT, ServiceAction, ServiceEvent, ServiceMetrics, ServiceState, handle_event,
@@ -57,6 +69,10 @@ struct AppState {
 /// requests occupying all `max_connections` slots.
 fn build_router(state: AppState, max_connections: usize) -> Router {
     let proxy_routes = Router::new()
+        .route(
+            openai::CHAT_COMPLETIONS_PATH,
+            axum::routing::post(chat_completions_handler),
+        )
         .fallback(proxy_handler)
         .layer(tower::limit::ConcurrencyLimitLayer::new(max_connections));
 
@@ -79,6 +95,15 @@ async fn main() -> Result<()> {
         .with(tracing_subscriber::fmt::layer().json())
         .init();
 
+    // One-shot maintenance commands run instead of the proxy
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(command) = args.get(1).and_then(|a| credential_key::Command::parse(a)) {
+        return credential_key::run(command, &args).await;
+    }
+    if args.get(1).map(String::as_str) == Some(claude_import::COMMAND) {
+        return claude_import::run(&args).await;
+    }
+
     info!("starting anthropic-oauth-proxy");
 
     // Install Prometheus metrics recorder before any metrics are emitted
@@ -88,7 +113,6 @@ async fn main() -> Result<()> {
     let mut state = ServiceState::Initializing;
 
     // CLI: simple --config flag parsing
-    let args: Vec<String> = std::env::args().collect();
     let cli_config_path = args
         .iter()
         .position(|a| a == "--config")
@@ -135,92 +159,133 @@ async fn main() -> Result<()> {
         .context("failed to build HTTP client")?;
 
     // Construct provider based on config mode
-    let (provider, max_failover_attempts): (Arc<dyn provider::Provider>, usize) = match mode {
+    // Each section is built once, however many of the default provider,
+    // chain tiers, and model routes use it
+    let mut providers = Providers::default();
+    let (provider, max_failover_attempts) = match mode {
         AuthMode::Passthrough => {
-            let headers = config
-                .headers
-                .iter()
-                .map(|h| provider::passthrough::HeaderInjection {
-                    name: h.name.clone(),
-                    value: h.value.clone(),
-                })
-                .collect();
-            (Arc::new(PassthroughProvider::new(headers)), 1)
+            providers
+                .get(ProviderKind::Passthrough, &config, &client)
+                .await?
         }
-        AuthMode::OAuthPool => {
-            let oauth_config = config.oauth.as_ref().unwrap();
-
-            let credential_store = anthropic_auth::CredentialStore::load(std::path::PathBuf::from(
-                &oauth_config.credential_file,
-            ))
-            .await
-            .with_context(|| {
-                format!(
-                    "failed to load credential store from {}",
-                    oauth_config.credential_file
-                )
-            })?;
-            let credential_store = Arc::new(credential_store);
-
-            // Populate pool from providers list in config, falling back to
-            // all accounts found in the credential store if no explicit list.
-            let account_ids = if oauth_config.providers.is_empty() {
-                credential_store.account_ids().await
-            } else {
-                oauth_config.providers.clone()
-            };
-            let pool_size = account_ids.len().max(1);
-
+        AuthMode::OAuthPool => providers.get(ProviderKind::Oauth, &config, &client).await?,
+        AuthMode::ApiKeyPool => {
+            providers
+                .get(ProviderKind::ApiKeys, &config, &client)
+                .await?
+        }
+        AuthMode::Chain => {
+            let mut tiers = Vec::with_capacity(config.provider_chain.len());
+            let mut attempts = 0;
+            for tier in &config.provider_chain {
+                let (provider, size) = providers.get(tier.provider, &config, &client).await?;
+                attempts += size;
+                tiers.push(chain::Tier {
+                    name: tier.provider.label().to_string(),
+                    provider,
+                    models: tier.models.clone(),
+                    clients: tier.clients.clone(),
+                });
+            }
             info!(
-                accounts = account_ids.len(),
-                credential_file = %oauth_config.credential_file,
-                "initializing OAuth pool"
+                tiers = ?config.provider_chain.iter().map(|t| t.provider.label()).collect::<Vec<_>>(),
+                "provider fallback chain enabled"
             );
+            let provider: Arc<dyn provider::Provider> = Arc::new(chain::ChainProvider::new(tiers));
+            (provider, attempts)
+        }
+    };
 
-            let pool = Arc::new(anthropic_pool::Pool::new(
-                account_ids,
-                Duration::from_secs(oauth_config.cooldown_secs),
-                credential_store,
-                client.clone(),
-            ));
-
-            // Spawn background proactive refresh task
-            let _refresh_handle = anthropic_pool::spawn_refresh_task(
-                pool.clone(),
-                Duration::from_secs(oauth_config.refresh_interval_secs),
-                Duration::from_secs(oauth_config.refresh_threshold_secs),
+    let routes = if config.routes.is_empty() {
+        None
+    } else {
+        let mut routes = Vec::with_capacity(config.routes.len());
+        for route in &config.routes {
+            let (route_provider, max_failover_attempts) = match route.provider {
+                Some(kind) => providers.get(kind, &config, &client).await?,
+                None => (provider.clone(), max_failover_attempts),
+            };
+            info!(
+                route = %route.name,
+                path_prefix = %route.path_prefix,
+                provider = route_provider.id(),
+                "path route configured"
             );
+            routes.push(routing::PathRoute {
+                name: route.name.clone(),
+                path_prefix: route.path_prefix.clone(),
+                strip_prefix: route.strip_prefix,
+                upstream_url: route
+                    .upstream_url
+                    .clone()
+                    .unwrap_or_else(|| config.proxy.upstream_url.clone()),
+                provider: route_provider,
+                max_failover_attempts,
+                timeout: Duration::from_secs(
+                    route.timeout_secs.unwrap_or(config.proxy.timeout_secs),
+                ),
+                max_body_size: route.max_body_bytes.unwrap_or(proxy::MAX_BODY_SIZE),
+            });
+        }
+        Some(Arc::new(routing::PathRouter::new(routes)))
+    };
 
-            // Start admin API if enabled
-            if let Some(ref admin_config) = config.admin
-                && admin_config.enabled
-            {
-                let admin_state = admin::AdminState::new(pool.clone(), client.clone());
-                let admin_router = admin::build_admin_router(admin_state);
-                let admin_addr = admin_config.listen_addr;
-
-                tokio::spawn(async move {
-                    let listener = match TcpListener::bind(admin_addr).await {
-                        Ok(l) => l,
-                        Err(e) => {
-                            error!(addr = %admin_addr, error = %e, "failed to bind admin listener");
-                            return;
-                        }
-                    };
-                    info!(addr = %admin_addr, "admin API listening");
-                    if let Err(e) = axum::serve(listener, admin_router).await {
-                        error!(error = %e, "admin API server error");
-                    }
-                });
-            }
-
-            let provider = Arc::new(provider_impl::AnthropicOAuthProvider::new(pool));
-            (provider as Arc<dyn provider::Provider>, pool_size)
+    let models = if config.model_aliases.is_empty() && config.model_routes.is_empty() {
+        None
+    } else {
+        let mut routes = Vec::with_capacity(config.model_routes.len());
+        for route in &config.model_routes {
+            let (provider, max_failover_attempts) =
+                providers.get(route.provider, &config, &client).await?;
+            routes.push(routing::ModelRoute {
+                models: route.models.clone(),
+                upstream_url: route
+                    .upstream_url
+                    .clone()
+                    .unwrap_or_else(|| config.proxy.upstream_url.clone()),
+                provider,
+                max_failover_attempts,
+            });
         }
+        info!(
+            aliases = config.model_aliases.len(),
+            routes = routes.len(),
+            "model routing enabled"
+        );
+        Some(Arc::new(routing::ModelRouter::new(
+            config.model_aliases.clone(),
+            routes,
+        )))
     };
 
     info!(provider = provider.id(), "provider initialized");
 
+    // Validated during config load
+    let clients = Arc::new(
+        clients::ClientRegistry::from_config(&config.clients).map_err(anyhow::Error::msg)?,
+    );
+    if clients.is_enabled() {
+        info!(
+            clients = config.clients.len(),
+            "client authentication enabled"
+        );
+    } else {
+        warn!("no [[clients]] configured, proxy accepts unauthenticated requests");
+    }
+
+    let tailnet = config.tailnet.as_ref().map(|tailnet_config| {
+        info!(
+            localapi_url = %tailnet_config.localapi_url,
+            trust_forwarded_for = tailnet_config.trust_forwarded_for,
+            trusted_proxies = ?tailnet_config.trusted_proxies,
+            "tailnet identity authorization enabled"
+        );
+        Arc::new(tailnet::TailnetAuthorizer::new(
+            tailnet_config,
+            client.clone(),
+        ))
+    });
+
     let proxy_state = ProxyState {
         client,
         upstream_url: config.proxy.upstream_url.clone(),
@@ -230,6 +295,13 @@ async fn main() -> Result<()> {
         errors_total: metrics.errors_total.clone(),
         in_flight: metrics.in_flight.clone(),
         max_failover_attempts,
+        buffer_first_event: config.proxy.buffer_first_event,
+        usage_accounting: config.proxy.usage_accounting,
+        retry: retry::RetryPolicy::from(&config.proxy.retry),
+        clients,
+        tailnet,
+        routes,
+        models,
     };
 
     let app_state = AppState {
@@ -262,11 +334,15 @@ async fn main() -> Result<()> {
     let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
 
     let server_handle = tokio::spawn(async move {
-        axum::serve(listener, app)
-            .with_graceful_shutdown(async {
-                let _ = shutdown_rx.await;
-            })
-            .await
+        // Peer addresses feed tailnet identity lookups
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+        )
+        .with_graceful_shutdown(async {
+            let _ = shutdown_rx.await;
+        })
+        .await
     });
 
     // Wait for the OS signal
@@ -302,6 +378,214 @@ async fn main() -> Result<()> {
 
 /// Health endpoint: returns 200 with status, mode, uptime, requests served.
 /// In OAuth mode, includes pool health details from the provider.
+/// Providers built so far, by the config section they come from.
+#[derive(Default)]
+struct Providers(HashMap<ProviderKind, (Arc<dyn provider::Provider>, usize)>);
+
+impl Providers {
+    /// The provider for `kind` and its failover budget, built on first use.
+    async fn get(
+        &mut self,
+        kind: ProviderKind,
+        config: &Config,
+        client: &reqwest::Client,
+    ) -> Result<(Arc<dyn provider::Provider>, usize)> {
+        if let Some(built) = self.0.get(&kind) {
+            return Ok(built.clone());
+        }
+        let built = match kind {
+            ProviderKind::Passthrough => build_passthrough_provider(config),
+            ProviderKind::Oauth => build_oauth_provider(config, client).await?,
+            ProviderKind::ApiKeys => build_api_key_provider(config)?,
+        };
+        self.0.insert(kind, built.clone());
+        Ok(built)
+    }
+}
+
+/// Build the `[[headers]]` passthrough provider (no failover).
+fn build_passthrough_provider(config: &Config) -> (Arc<dyn provider::Provider>, usize) {
+    let headers = config
+        .headers
+        .iter()
+        .map(|h| provider::passthrough::HeaderInjection {
+            name: h.name.clone(),
+            value: h.value.clone(),
+        })
+        .collect();
+    (Arc::new(PassthroughProvider::new(headers)), 1)
+}
+
+/// Build the `[oauth]` pool provider, its background tasks, and the admin
+/// API. Returns the provider and its failover budget (the pool size).
+async fn build_oauth_provider(
+    config: &Config,
+    client: &reqwest::Client,
+) -> Result<(Arc<dyn provider::Provider>, usize)> {
+    let oauth_config = config.oauth.as_ref().unwrap();
+
+    // Validated during config load
+    let encryption = oauth_config
+        .encryption
+        .as_ref()
+        .map(|e| e.encryption())
+        .transpose()?;
+    let backend = oauth_config.credential_backend()?;
+    let location = backend.location();
+    let credential_store = anthropic_auth::CredentialStore::open(backend, encryption)
+        .await
+        .with_context(|| format!("failed to load credential store from {location}"))?;
+    let credential_store = Arc::new(credential_store);
+
+    // Populate pool from providers list in config, falling back to
+    // all accounts found in the credential store if no explicit list.
+    // Store IDs are sorted so fill-first has a stable order.
+    let account_ids = if oauth_config.providers.is_empty() {
+        let mut ids = credential_store.account_ids().await;
+        ids.sort();
+        ids
+    } else {
+        oauth_config.providers.clone()
+    };
+    // Listed providers were checked at config load; stored IDs only now
+    if let Some(id) = config
+        .chained_api_key_ids()
+        .into_iter()
+        .find(|id| account_ids.iter().any(|a| a == id))
+    {
+        anyhow::bail!(
+            "provider_chain: account id '{id}' in {location} is also an [api_keys] key id; \
+             IDs must be unique across tiers"
+        );
+    }
+    let pool_size = account_ids.len().max(1);
+
+    // Cooldowns and disables from the previous run
+    let status_store = match oauth_config.status_file() {
+        Some(path) => Some(anthropic_pool::StatusStore::load(path).await),
+        None => {
+            info!("no oauth.status_file configured, account statuses are not persisted");
+            None
+        }
+    };
+
+    info!(
+        accounts = account_ids.len(),
+        credentials = %credential_store.location(),
+        strategy = %oauth_config.selection_strategy,
+        "initializing OAuth pool"
+    );
+
+    let mut pool = anthropic_pool::Pool::new(
+        account_ids,
+        Duration::from_secs(oauth_config.cooldown_secs),
+        credential_store,
+        client.clone(),
+    )
+    .with_quota_reserve(oauth_config.quota_reserve_ratio)
+    .with_strategy(oauth_config.selection_strategy)
+    .with_affinity_ttl(Duration::from_secs(oauth_config.affinity_ttl_secs));
+    if let Some(status_store) = status_store {
+        pool = pool.with_status_store(status_store);
+    }
+    let pool = Arc::new(pool);
+
+    // Spawn background proactive refresh task
+    let _refresh_handle = anthropic_pool::spawn_refresh_task(
+        pool.clone(),
+        Duration::from_secs(oauth_config.refresh_interval_secs),
+        Duration::from_secs(oauth_config.refresh_threshold_secs),
+    );
+
+    // Spawn background recovery probing for disabled accounts
+    if oauth_config.probe_interval_secs > 0 {
+        let _probe_handle = anthropic_pool::spawn_probe_task(
+            pool.clone(),
+            anthropic_pool::ProbeConfig {
+                url: format!(
+                    "{}/v1/models",
+                    config.proxy.upstream_url.trim_end_matches('/')
+                ),
+                headers: provider_impl::probe_headers(),
+                interval: Duration::from_secs(oauth_config.probe_interval_secs),
+                max_backoff: Duration::from_secs(oauth_config.probe_max_backoff_secs),
+            },
+        );
+    }
+
+    // Spawn background reloading of credentials changed outside this process
+    if oauth_config.reload_interval_secs > 0 {
+        let _reload_handle = anthropic_pool::spawn_reload_task(
+            pool.clone(),
+            Duration::from_secs(oauth_config.reload_interval_secs),
+            (!oauth_config.providers.is_empty()).then(|| oauth_config.providers.clone()),
+        );
+    }
+
+    // Start admin API if enabled
+    if let Some(ref admin_config) = config.admin
+        && admin_config.enabled
+    {
+        let admin_state = admin::AdminState::new(pool.clone(), client.clone());
+        let admin_router = admin::build_admin_router(admin_state);
+        let admin_addr = admin_config.listen_addr;
+
+        tokio::spawn(async move {
+            let listener = match TcpListener::bind(admin_addr).await {
+                Ok(l) => l,
+                Err(e) => {
+                    error!(addr = %admin_addr, error = %e, "failed to bind admin listener");
+                    return;
+                }
+            };
+            info!(addr = %admin_addr, "admin API listening");
+            if let Err(e) = axum::serve(listener, admin_router).await {
+                error!(error = %e, "admin API server error");
+            }
+        });
+    }
+
+    let mut provider = provider_impl::AnthropicOAuthProvider::new(pool);
+    if let Some(ref name) = oauth_config.affinity_header {
+        provider = provider.with_affinity_header(name.parse::<reqwest::header::HeaderName>()?);
+    }
+    if !oauth_config.tool_names.is_empty() {
+        info!(
+            tools = oauth_config.tool_names.len(),
+            "tool name normalization enabled"
+        );
+        provider = provider.with_tool_names(tool_names::ToolNameMap::new(
+            oauth_config.tool_names.clone(),
+        ));
+    }
+    Ok((Arc::new(provider), pool_size))
+}
+
+/// Build the `[api_keys]` pool provider and its failover budget.
+fn build_api_key_provider(config: &Config) -> Result<(Arc<dyn provider::Provider>, usize)> {
+    let api_keys_config = config.api_keys.as_ref().unwrap();
+    let keys = api_keys_config
+        .keys
+        .iter()
+        .map(|k| {
+            Ok(anthropic_pool::ApiKey {
+                id: k.id.clone(),
+                key: k.key()?,
+            })
+        })
+        .collect::<common::Result<Vec<_>>>()?;
+    info!(keys = keys.len(), "initializing API key pool");
+    let pool = Arc::new(anthropic_pool::ApiKeyPool::new(
+        keys,
+        Duration::from_secs(api_keys_config.cooldown_secs),
+    ));
+    let pool_size = pool.len();
+    Ok((
+        Arc::new(provider_impl::AnthropicApiKeyProvider::new(pool)),
+        pool_size,
+    ))
+}
+
 async fn health_handler(State(state): State<AppState>) -> impl IntoResponse {
     let uptime = state.metrics.started_at.elapsed().as_secs();
     let requests = state.metrics.requests_total.load(Ordering::Relaxed);
@@ -348,6 +632,15 @@ async fn proxy_handler(
     proxy::proxy_request(&state.proxy, request, request_id).await
 }
 
+/// OpenAI Chat Completions requests, translated to and from Messages.
+async fn chat_completions_handler(
+    State(state): State<AppState>,
+    request: axum::http::Request<axum::body::Body>,
+) -> Response {
+    let request_id = format!("req_{}", uuid::Uuid::new_v4().as_simple());
+    openai::chat_completions(&state.proxy, request, request_id).await
+}
+
 /// Wait for SIGTERM or SIGINT for graceful shutdown.
 async fn shutdown_signal() {
     let ctrl_c = async {
@@ -393,7 +686,7 @@ mod tests {
 
     fn global_prometheus_handle() -> PrometheusHandle {
         GLOBAL_PROMETHEUS
-            .get_or_init(|| crate::metrics::install_recorder())
+            .get_or_init(crate::metrics::install_recorder)
             .clone()
     }
 
@@ -428,6 +721,13 @@ mod tests {
                 errors_total: metrics.errors_total.clone(),
                 in_flight: metrics.in_flight.clone(),
                 max_failover_attempts: 1,
+                buffer_first_event: false,
+                retry: crate::retry::RetryPolicy::default(),
+                clients: Default::default(),
+                tailnet: None,
+                routes: None,
+                models: None,
+                usage_accounting: true,
             },
             metrics,
             prometheus: test_prometheus_handle(),
@@ -495,6 +795,13 @@ mod tests {
                 errors_total: metrics.errors_total.clone(),
                 in_flight: Arc::new(AtomicU64::new(0)),
                 max_failover_attempts: 1,
+                buffer_first_event: false,
+                retry: crate::retry::RetryPolicy::default(),
+                clients: Default::default(),
+                tailnet: None,
+                routes: None,
+                models: None,
+                usage_accounting: true,
             },
             metrics,
             prometheus: test_prometheus_handle(),
@@ -838,6 +1145,13 @@ mod tests {
                 errors_total: metrics.errors_total.clone(),
                 in_flight: metrics.in_flight.clone(),
                 max_failover_attempts: 1,
+                buffer_first_event: false,
+                retry: crate::retry::RetryPolicy::default(),
+                clients: Default::default(),
+                tailnet: None,
+                routes: None,
+                models: None,
+                usage_accounting: true,
             },
             metrics,
 
@@ -1147,6 +1461,13 @@ mod tests {
                 errors_total: metrics.errors_total.clone(),
                 in_flight: metrics.in_flight.clone(),
                 max_failover_attempts: 1,
+                buffer_first_event: false,
+                retry: crate::retry::RetryPolicy::default(),
+                clients: Default::default(),
+                tailnet: None,
+                routes: None,
+                models: None,
+                usage_accounting: true,
             },
             metrics,
 
@@ -1180,6 +1501,13 @@ mod tests {
                 errors_total: metrics_err.errors_total.clone(),
                 in_flight: metrics_err.in_flight.clone(),
                 max_failover_attempts: 1,
+                buffer_first_event: false,
+                retry: crate::retry::RetryPolicy::default(),
+                clients: Default::default(),
+                tailnet: None,
+                routes: None,
+                models: None,
+                usage_accounting: true,
             },
             metrics: metrics_err,
 
@@ -1205,6 +1533,13 @@ mod tests {
                 errors_total: metrics2.errors_total.clone(),
                 in_flight: metrics2.in_flight.clone(),
                 max_failover_attempts: 1,
+                buffer_first_event: false,
+                retry: crate::retry::RetryPolicy::default(),
+                clients: Default::default(),
+                tailnet: None,
+                routes: None,
+                models: None,
+                usage_accounting: true,
             },
             metrics: metrics2,
 
@@ -1406,6 +1741,13 @@ mod tests {
                 errors_total: metrics.errors_total.clone(),
                 in_flight: metrics.in_flight.clone(),
                 max_failover_attempts: 1,
+                buffer_first_event: false,
+                retry: crate::retry::RetryPolicy::default(),
+                clients: Default::default(),
+                tailnet: None,
+                routes: None,
+                models: None,
+                usage_accounting: true,
             },
             metrics,
 
@@ -1451,15 +1793,10 @@ mod tests {
         drop(listener);
         let listener = TcpListener::bind(addr).await.unwrap();
         let _server = tokio::spawn(async move {
-            loop {
-                match listener.accept().await {
-                    Ok((socket, _)) => {
-                        counter_clone.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-                        // Close immediately — simulates connection reset, not timeout
-                        drop(socket);
-                    }
-                    Err(_) => break,
-                }
+            while let Ok((socket, _)) = listener.accept().await {
+                counter_clone.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                // Close immediately — simulates connection reset, not timeout
+                drop(socket);
             }
         });
         tokio::time::sleep(Duration::from_millis(10)).await;
@@ -1877,6 +2214,13 @@ mod tests {
                 errors_total: metrics.errors_total.clone(),
                 in_flight: metrics.in_flight.clone(),
                 max_failover_attempts: 1,
+                buffer_first_event: false,
+                retry: crate::retry::RetryPolicy::default(),
+                clients: Default::default(),
+                tailnet: None,
+                routes: None,
+                models: None,
+                usage_accounting: true,
             },
             metrics,
 
@@ -1916,6 +2260,13 @@ mod tests {
                 errors_total: metrics.errors_total.clone(),
                 in_flight: metrics.in_flight.clone(),
                 max_failover_attempts: 1,
+                buffer_first_event: false,
+                retry: crate::retry::RetryPolicy::default(),
+                clients: Default::default(),
+                tailnet: None,
+                routes: None,
+                models: None,
+                usage_accounting: true,
             },
             metrics,
 
@@ -1962,6 +2313,13 @@ mod tests {
                 errors_total: metrics.errors_total.clone(),
                 in_flight: metrics.in_flight.clone(),
                 max_failover_attempts: 1,
+                buffer_first_event: false,
+                retry: crate::retry::RetryPolicy::default(),
+                clients: Default::default(),
+                tailnet: None,
+                routes: None,
+                models: None,
+                usage_accounting: true,
             },
             metrics,
 
@@ -2284,6 +2642,13 @@ mod tests {
                 errors_total: metrics.errors_total.clone(),
                 in_flight: metrics.in_flight.clone(),
                 max_failover_attempts: 1,
+                buffer_first_event: false,
+                retry: crate::retry::RetryPolicy::default(),
+                clients: Default::default(),
+                tailnet: None,
+                routes: None,
+                models: None,
+                usage_accounting: true,
             },
             metrics,
 
@@ -2386,6 +2751,13 @@ mod tests {
                 errors_total: metrics.errors_total.clone(),
                 in_flight: metrics.in_flight.clone(),
                 max_failover_attempts: 1,
+                buffer_first_event: false,
+                retry: crate::retry::RetryPolicy::default(),
+                clients: Default::default(),
+                tailnet: None,
+                routes: None,
+                models: None,
+                usage_accounting: true,
             },
             metrics: metrics.clone(),
             prometheus: test_prometheus_handle(),
@@ -2502,11 +2874,11 @@ mod tests {
             // /proc/self/statm fields: size resident shared text lib data dt (in pages)
             if let Ok(statm) = std::fs::read_to_string("/proc/self/statm") {
                 let fields: Vec<&str> = statm.split_whitespace().collect();
-                if fields.len() >= 2 {
-                    if let Ok(resident_pages) = fields[1].parse::<usize>() {
-                        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize };
-                        return Some(resident_pages * page_size);
-                    }
+                if fields.len() >= 2
+                    && let Ok(resident_pages) = fields[1].parse::<usize>()
+                {
+                    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize };
+                    return Some(resident_pages * page_size);
                 }
             }
             None
@@ -2583,6 +2955,13 @@ mod tests {
                 errors_total: metrics.errors_total.clone(),
                 in_flight: metrics.in_flight.clone(),
                 max_failover_attempts: 1,
+                buffer_first_event: false,
+                retry: crate::retry::RetryPolicy::default(),
+                clients: Default::default(),
+                tailnet: None,
+                routes: None,
+                models: None,
+                usage_accounting: true,
             },
             metrics,
             prometheus: test_prometheus_handle(),
@@ -2752,6 +3131,13 @@ mod tests {
                 errors_total: metrics.errors_total.clone(),
                 in_flight: metrics.in_flight.clone(),
                 max_failover_attempts: pool_size,
+                buffer_first_event: false,
+                retry: crate::retry::RetryPolicy::default(),
+                clients: Default::default(),
+                tailnet: None,
+                routes: None,
+                models: None,
+                usage_accounting: true,
             },
             metrics,
             prometheus: test_prometheus_handle(),
@@ -3145,6 +3531,142 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn oauth_failover_reaches_accounts_added_after_startup() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_oauth_credential_store(&dir, &["acct-a", "acct-b"]).await;
+        let pool = Arc::new(anthropic_pool::Pool::new(
+            vec!["acct-a".into()],
+            Duration::from_secs(7200),
+            store,
+            reqwest::Client::new(),
+        ));
+
+        // acct-a is out of quota; acct-b works
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let app = axum::Router::new().fallback(|request: axum::http::Request<Body>| async move {
+                if request.headers()["authorization"] == "Bearer access_acct-a" {
+                    return (
+                        StatusCode::TOO_MANY_REQUESTS,
+                        serde_json::json!({"error":{"message":"You've exceeded your 5-hour usage limit"}}).to_string(),
+                    );
+                }
+                (StatusCode::OK, r#"{"ok": true}"#.to_string())
+            });
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        // Startup saw one account; a credential reload adds the second
+        let state = test_oauth_app_state(&upstream_url, pool.clone(), 1);
+        pool.add_account("acct-b".into()).await;
+        let app = build_router(state, 1000);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/v1/messages")
+                    .header("content-type", "application/json")
+                    .body(Body::from(
+                        serde_json::json!({"model": "claude-sonnet-4-20250514", "messages": []})
+                            .to_string(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn tailnet_request_counted_once_across_failover() {
+        let whois = Router::new().route(
+            "/localapi/v0/whois",
+            axum::routing::get(|| async {
+                serde_json::json!({
+                    "Node": {"ComputedName": "failover-node", "Tags": ["tag:ci"]},
+                    "UserProfile": {"LoginName": "tagged-devices"},
+                })
+                .to_string()
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let localapi_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            axum::serve(listener, whois).await.unwrap();
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_oauth_credential_store(&dir, &["acct-a", "acct-b"]).await;
+        let pool = Arc::new(anthropic_pool::Pool::new(
+            vec!["acct-a".into(), "acct-b".into()],
+            Duration::from_secs(7200),
+            store,
+            reqwest::Client::new(),
+        ));
+
+        // acct-a is out of quota; acct-b works
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let app = axum::Router::new().fallback(|request: axum::http::Request<Body>| async move {
+                if request.headers()["authorization"] == "Bearer access_acct-a" {
+                    return (
+                        StatusCode::TOO_MANY_REQUESTS,
+                        serde_json::json!({"error":{"message":"You've exceeded your 5-hour usage limit"}}).to_string(),
+                    );
+                }
+                (StatusCode::OK, r#"{"ok": true}"#.to_string())
+            });
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        let handle = global_prometheus_handle();
+        let mut state = test_oauth_app_state(&upstream_url, pool, 2);
+        state.proxy.tailnet = Some(Arc::new(tailnet::TailnetAuthorizer::new(
+            &config::TailnetConfig {
+                localapi_url,
+                trust_forwarded_for: false,
+                trusted_proxies: vec![],
+                cache_ttl_secs: 60,
+                allow_tags: vec!["tag:ci".into()],
+                allow_users: vec![],
+                allow_nodes: vec![],
+            },
+            reqwest::Client::new(),
+        )));
+        let app = build_router(state, 1000);
+
+        let mut request = Request::builder()
+            .method("POST")
+            .uri("/v1/messages")
+            .header("content-type", "application/json")
+            .body(Body::from(
+                serde_json::json!({"model": "claude-sonnet-4-20250514", "messages": []})
+                    .to_string(),
+            ))
+            .unwrap();
+        let peer: std::net::SocketAddr = "100.64.0.9:51000".parse().unwrap();
+        request
+            .extensions_mut()
+            .insert(axum::extract::ConnectInfo(peer));
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let rendered = handle.render();
+        let series: Vec<&str> = rendered
+            .lines()
+            .filter(|l| {
+                l.starts_with("proxy_tailnet_requests_total{") && l.contains("failover-node")
+            })
+            .collect();
+        assert_eq!(series.len(), 1, "{rendered}");
+        assert!(series[0].contains(r#"account_id="acct-b""#), "{rendered}");
+        assert!(series[0].ends_with(" 1"), "{rendered}");
+    }
+
     #[tokio::test]
     async fn oauth_provider_permanent_error_returns_immediately() {
         let dir = tempfile::tempdir().unwrap();
@@ -3258,157 +3780,1677 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn oauth_health_endpoint_includes_pool() {
+    async fn oauth_quota_cooldown_follows_retry_after_header() {
         let dir = tempfile::tempdir().unwrap();
-        let store = test_oauth_credential_store(&dir, &["acct-1", "acct-2"]).await;
+        let store = test_oauth_credential_store(&dir, &["acct-1"]).await;
         let pool = Arc::new(anthropic_pool::Pool::new(
-            vec!["acct-1".into(), "acct-2".into()],
+            vec!["acct-1".into()],
             Duration::from_secs(7200),
             store,
             reqwest::Client::new(),
         ));
 
-        let state = test_oauth_app_state("http://unused", pool, 2);
-        let app = build_router(state, 1000);
+        // Quota 429 that reports the window reopening in 20 minutes
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let upstream_url = format!("http://{addr}");
+        let _server = tokio::spawn(async move {
+            let app = axum::Router::new().fallback(|_: axum::http::Request<Body>| async {
+                (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [("retry-after", "1200")],
+                    r#"{"error":{"message":"5-hour usage limit exceeded"}}"#,
+                )
+            });
+            axum::serve(listener, app).await.unwrap();
+        });
 
+        let state = test_oauth_app_state(&upstream_url, pool.clone(), 1);
+        let app = build_router(state, 1000);
         let response = app
             .oneshot(
                 Request::builder()
-                    .uri("/health")
-                    .body(Body::empty())
+                    .method("POST")
+                    .uri("/v1/messages")
+                    .header("content-type", "application/json")
+                    .body(Body::from(
+                        serde_json::json!({"model": "claude-sonnet-4-20250514", "messages": []})
+                            .to_string(),
+                    ))
                     .unwrap(),
             )
             .await
             .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
 
-        assert_eq!(response.status(), StatusCode::OK);
-        let body = axum::body::to_bytes(response.into_body(), 1024 * 1024)
-            .await
+        let health = pool.health().await;
+        let remaining = health["accounts"][0]["cooldown_remaining_secs"]
+            .as_u64()
             .unwrap();
-        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
-
-        assert_eq!(json["mode"], "anthropic");
-        assert_eq!(json["status"], "healthy");
-        assert!(json["pool"].is_object(), "health must include pool details");
-        assert_eq!(json["pool"]["accounts_total"], 2);
-        assert_eq!(json["pool"]["accounts_available"], 2);
-        assert_eq!(json["pool"]["status"], "healthy");
+        assert!(
+            remaining > 1100 && remaining <= 1200,
+            "cooldown must follow retry-after (1200s), not the 7200s default: {remaining}"
+        );
     }
 
     #[tokio::test]
-    async fn proxy_stream_idle_timeout_terminates_stalled_stream() {
-        // Verify the IdleTimeoutStream terminates when the upstream stops sending
-        // mid-stream. The mock sends HTTP 200 + 2 SSE chunks then holds the
-        // connection open without further data.
-        use std::io::Write;
+    async fn oauth_fill_first_releases_account_when_body_consumed() {
+        let (upstream_url, _server) = start_echo_server().await;
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_oauth_credential_store(&dir, &["acct-1", "acct-2"]).await;
+        let pool = Arc::new(
+            anthropic_pool::Pool::new(
+                vec!["acct-1".into(), "acct-2".into()],
+                Duration::from_secs(7200),
+                store,
+                reqwest::Client::new(),
+            )
+            .with_strategy(anthropic_pool::SelectionStrategy::FillFirst),
+        );
 
-        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
-        let addr = listener.local_addr().unwrap();
-        let upstream_url = format!("http://{addr}");
+        let state = test_oauth_app_state(&upstream_url, pool.clone(), 2);
+        let app = build_router(state, 1000);
+        let request = || {
+            Request::builder()
+                .method("POST")
+                .uri("/v1/messages")
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    serde_json::json!({"model": "claude-sonnet-4-20250514", "messages": []})
+                        .to_string(),
+                ))
+                .unwrap()
+        };
 
-        let _server = tokio::spawn(async move {
-            loop {
-                let (mut socket, _) = listener.accept().await.unwrap();
-                tokio::spawn(async move {
-                    // Read the full HTTP request (consume until we see \r\n\r\n)
-                    let mut buf = vec![0u8; 4096];
-                    let mut total = 0;
-                    loop {
-                        let n = tokio::io::AsyncReadExt::read(&mut socket, &mut buf[total..])
-                            .await
-                            .unwrap_or(0);
-                        if n == 0 {
-                            return;
-                        }
-                        total += n;
-                        if total >= 4 && buf[..total].windows(4).any(|w| w == b"\r\n\r\n") {
+        for _ in 0..3 {
+            let response = app.clone().oneshot(request()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+
+            // Account stays in flight while the body is still being streamed
+            let health = pool.health().await;
+            assert_eq!(health["accounts"][0]["in_flight"], 1);
+
+            let body = axum::body::to_bytes(response.into_body(), 1024 * 1024)
+                .await
+                .unwrap();
+            let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            // Fill-first keeps using the first account
+            assert_eq!(
+                json["echoed_headers"]["authorization"],
+                "Bearer access_acct-1"
+            );
+            let health = pool.health().await;
+            assert_eq!(health["accounts"][0]["in_flight"], 0);
+        }
+        assert_eq!(pool.health().await["strategy"], "fill-first");
+    }
+
+    #[tokio::test]
+    async fn oauth_conversation_turns_stay_on_one_account() {
+        let (upstream_url, _server) = start_echo_server().await;
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_oauth_credential_store(&dir, &["acct-1", "acct-2", "acct-3"]).await;
+        let pool = Arc::new(anthropic_pool::Pool::new(
+            vec!["acct-1".into(), "acct-2".into(), "acct-3".into()],
+            Duration::from_secs(7200),
+            store,
+            reqwest::Client::new(),
+        ));
+
+        let state = test_oauth_app_state(&upstream_url, pool, 3);
+        let app = build_router(state, 1000);
+        let send = |messages: serde_json::Value| {
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(
+                        Request::builder()
+                            .method("POST")
+                            .uri("/v1/messages")
+                            .header("content-type", "application/json")
+                            .body(Body::from(
+                                serde_json::json!({
+                                    "model": "claude-sonnet-4-20250514",
+                                    "messages": messages,
+                                })
+                                .to_string(),
+                            ))
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                let body = axum::body::to_bytes(response.into_body(), 1024 * 1024)
+                    .await
+                    .unwrap();
+                let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                json["echoed_headers"]["authorization"]
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            }
+        };
+
+        let opening = serde_json::json!({"role": "user", "content": "refactor the parser"});
+        let first = send(serde_json::json!([opening])).await;
+        let mut turns = vec![opening];
+        for i in 0..3 {
+            turns.push(serde_json::json!({"role": "assistant", "content": format!("step {i}")}));
+            turns.push(serde_json::json!({"role": "user", "content": "continue"}));
+            assert_eq!(send(serde_json::json!(turns)).await, first);
+        }
+    }
+
+    #[tokio::test]
+    async fn oauth_tool_names_are_restored_through_response_hook() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_oauth_credential_store(&dir, &["acct-1"]).await;
+        let pool = Arc::new(anthropic_pool::Pool::new(
+            vec!["acct-1".into()],
+            Duration::from_secs(7200),
+            store,
+            reqwest::Client::new(),
+        ));
+
+        // Answers with a tool_use block for whatever tool name it was sent
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let upstream_url = format!("http://{addr}");
+        let _server = tokio::spawn(async move {
+            let app = axum::Router::new().fallback(|req: axum::http::Request<Body>| async move {
+                let body = axum::body::to_bytes(req.into_body(), 1024 * 1024)
+                    .await
+                    .unwrap();
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                axum::Json(serde_json::json!({
+                    "type": "message",
+                    "content": [{
+                        "type": "tool_use",
+                        "id": "t1",
+                        "name": body["tools"][0]["name"],
+                        "input": {},
+                    }],
+                }))
+            });
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        let mut state = test_oauth_app_state(&upstream_url, pool.clone(), 1);
+        state.proxy.provider = Arc::new(
+            crate::provider_impl::AnthropicOAuthProvider::new(pool).with_tool_names(
+                tool_names::ToolNameMap::new(std::collections::HashMap::from([(
+                    "bash".to_string(),
+                    "Bash".to_string(),
+                )])),
+            ),
+        );
+        let app = build_router(state, 1000);
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/v1/messages")
+                    .header("content-type", "application/json")
+                    .body(Body::from(
+                        serde_json::json!({
+                            "model": "claude-sonnet-4-20250514",
+                            "tools": [{"name": "bash"}],
+                            "messages": [],
+                        })
+                        .to_string(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), 1024 * 1024)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["content"][0]["name"], "bash");
+    }
+
+    #[tokio::test]
+    async fn api_key_provider_fails_over_rate_limited_keys() {
+        // Key one is rate limited; key two answers with the headers it saw
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let upstream_url = format!("http://{addr}");
+        let _server = tokio::spawn(async move {
+            let app = axum::Router::new().fallback(|req: axum::http::Request<Body>| async move {
+                let headers = req.headers();
+                if headers["x-api-key"] == "sk-ant-one" {
+                    return (
+                        StatusCode::TOO_MANY_REQUESTS,
+                        r#"{"type":"error","error":{"type":"rate_limit_error"}}"#.to_string(),
+                    );
+                }
+                let body = serde_json::json!({
+                    "x-api-key": headers["x-api-key"].to_str().unwrap(),
+                    "authorization": headers.contains_key("authorization"),
+                    "anthropic-version": headers["anthropic-version"].to_str().unwrap(),
+                });
+                (StatusCode::OK, body.to_string())
+            });
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        let pool = Arc::new(anthropic_pool::ApiKeyPool::new(
+            vec![
+                anthropic_pool::ApiKey {
+                    id: "paid-1".into(),
+                    key: "sk-ant-one".into(),
+                },
+                anthropic_pool::ApiKey {
+                    id: "paid-2".into(),
+                    key: "sk-ant-two".into(),
+                },
+            ],
+            Duration::from_secs(60),
+        ));
+        let mut state = test_app_state(&upstream_url, vec![]);
+        state.proxy.provider = Arc::new(crate::provider_impl::AnthropicApiKeyProvider::new(
+            pool.clone(),
+        ));
+        state.proxy.max_failover_attempts = 2;
+        let app = build_router(state, 1000);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/v1/messages")
+                    .header("authorization", "Bearer client-token")
+                    .body(Body::from("{}"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), 1024 * 1024)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["x-api-key"], "sk-ant-two");
+        assert_eq!(json["authorization"], false);
+        assert_eq!(json["anthropic-version"], "2023-06-01");
+
+        assert!(matches!(
+            pool.status("paid-1").await,
+            Some(anthropic_pool::AccountStatus::CoolingDown { .. })
+        ));
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/health")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), 1024 * 1024)
+            .await
+            .unwrap();
+        let health: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(health["pool"]["accounts_cooling_down"], 1);
+    }
+
+    #[tokio::test]
+    async fn chain_falls_back_to_api_keys_when_oauth_pool_is_exhausted() {
+        let (upstream_url, _server) = start_echo_server().await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_oauth_credential_store(&dir, &["acct-1"]).await;
+        let oauth_pool = Arc::new(anthropic_pool::Pool::new(
+            vec!["acct-1".into()],
+            Duration::from_secs(7200),
+            store,
+            reqwest::Client::new(),
+        ));
+        oauth_pool
+            .report_error("acct-1", provider::ErrorClassification::Permanent)
+            .await;
+        let key_pool = Arc::new(anthropic_pool::ApiKeyPool::new(
+            vec![anthropic_pool::ApiKey {
+                id: "paid-1".into(),
+                key: "sk-ant-paid".into(),
+            }],
+            Duration::from_secs(60),
+        ));
+
+        let mut state = test_app_state(&upstream_url, vec![]);
+        state.proxy.provider = Arc::new(chain::ChainProvider::new(vec![
+            chain::Tier {
+                name: "oauth".into(),
+                provider: Arc::new(provider_impl::AnthropicOAuthProvider::new(oauth_pool)),
+                models: vec![],
+                clients: vec![],
+            },
+            chain::Tier {
+                name: "api_keys".into(),
+                provider: Arc::new(provider_impl::AnthropicApiKeyProvider::new(key_pool)),
+                models: vec![],
+                clients: vec![],
+            },
+        ]));
+        state.proxy.max_failover_attempts = 2;
+        let app = build_router(state, 1000);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/v1/messages")
+                    .body(Body::from(r#"{"model":"claude-sonnet-4-5","messages":[]}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[proxy::TIER_HEADER], "api_keys");
+        let body = axum::body::to_bytes(response.into_body(), 1024 * 1024)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["echoed_headers"]["x-api-key"], "sk-ant-paid");
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/health")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), 1024 * 1024)
+            .await
+            .unwrap();
+        let health: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(health["mode"], "chain");
+        assert_eq!(health["status"], "degraded");
+        assert_eq!(health["pool"]["tiers"][0]["name"], "oauth");
+        assert_eq!(health["pool"]["tiers"][0]["status"], "unhealthy");
+        assert_eq!(health["pool"]["tiers"][1]["status"], "healthy");
+    }
+
+    #[tokio::test]
+    async fn path_route_sets_upstream_and_body_limit() {
+        let (default_url, _default) = start_echo_server().await;
+        let (local_url, _local) = start_echo_server().await;
+
+        let mut state = test_app_state(&default_url, vec![]);
+        state.proxy.routes = Some(Arc::new(routing::PathRouter::new(vec![
+            routing::PathRoute {
+                name: "local".into(),
+                path_prefix: "/local/".into(),
+                strip_prefix: true,
+                upstream_url: local_url.clone(),
+                provider: Arc::new(PassthroughProvider::new(vec![])),
+                max_failover_attempts: 1,
+                timeout: Duration::from_secs(5),
+                max_body_size: 64,
+            },
+        ])));
+        let app = build_router(state, 1000);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/local/v1/chat/completions?stream=false")
+                    .body(Body::from("{}"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), 1024 * 1024)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            json["echoed_headers"]["host"],
+            local_url.trim_start_matches("http://")
+        );
+        assert_eq!(json["path"], "/v1/chat/completions");
+        assert_eq!(json["query"], "stream=false");
+
+        // The route's body limit applies, not the proxy-wide one
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/local/v1/chat/completions")
+                    .body(Body::from(vec![b'x'; 65]))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // Other paths keep the default upstream
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/v1/messages")
+                    .body(Body::from(vec![b'x'; 65]))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), 1024 * 1024)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            json["echoed_headers"]["host"],
+            default_url.trim_start_matches("http://")
+        );
+    }
+
+    #[tokio::test]
+    async fn model_alias_is_rewritten_and_routed() {
+        let (default_url, _default) = start_echo_server().await;
+        let (routed_url, _routed) = start_echo_server().await;
+
+        let mut state = test_app_state(&default_url, vec![]);
+        state.proxy.models = Some(Arc::new(routing::ModelRouter::new(
+            HashMap::from([("fast".to_string(), "claude-haiku-4-5".to_string())]),
+            vec![routing::ModelRoute {
+                models: vec!["claude-haiku-*".to_string()],
+                upstream_url: routed_url.clone(),
+                provider: Arc::new(PassthroughProvider::new(vec![])),
+                max_failover_attempts: 1,
+            }],
+        )));
+        let app = build_router(state, 1000);
+
+        let send = |model: &str| {
+            let app = app.clone();
+            let body = format!(r#"{{"model":"{model}","messages":[]}}"#);
+            async move {
+                let response = app
+                    .oneshot(
+                        Request::builder()
+                            .method("POST")
+                            .uri("/v1/messages")
+                            .body(Body::from(body))
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = axum::body::to_bytes(response.into_body(), 1024 * 1024)
+                    .await
+                    .unwrap();
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+            }
+        };
+
+        let routed = send("fast").await;
+        assert_eq!(
+            routed["echoed_headers"]["host"],
+            routed_url.trim_start_matches("http://")
+        );
+        let forwarded: serde_json::Value =
+            serde_json::from_str(routed["body"].as_str().unwrap()).unwrap();
+        assert_eq!(forwarded["model"], "claude-haiku-4-5");
+
+        let default = send("claude-opus-4-1").await;
+        assert_eq!(
+            default["echoed_headers"]["host"],
+            default_url.trim_start_matches("http://")
+        );
+    }
+
+    #[tokio::test]
+    async fn oauth_health_endpoint_includes_pool() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_oauth_credential_store(&dir, &["acct-1", "acct-2"]).await;
+        let pool = Arc::new(anthropic_pool::Pool::new(
+            vec!["acct-1".into(), "acct-2".into()],
+            Duration::from_secs(7200),
+            store,
+            reqwest::Client::new(),
+        ));
+
+        let state = test_oauth_app_state("http://unused", pool, 2);
+        let app = build_router(state, 1000);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/health")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), 1024 * 1024)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(json["mode"], "anthropic");
+        assert_eq!(json["status"], "healthy");
+        assert!(json["pool"].is_object(), "health must include pool details");
+        assert_eq!(json["pool"]["accounts_total"], 2);
+        assert_eq!(json["pool"]["accounts_available"], 2);
+        assert_eq!(json["pool"]["status"], "healthy");
+    }
+
+    #[tokio::test]
+    async fn proxy_stream_idle_timeout_terminates_stalled_stream() {
+        // Verify the IdleTimeoutStream terminates when the upstream stops sending
+        // mid-stream. The mock sends HTTP 200 + 2 SSE chunks then holds the
+        // connection open without further data.
+        use std::io::Write;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let upstream_url = format!("http://{addr}");
+
+        let _server = tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    // Read the full HTTP request (consume until we see \r\n\r\n)
+                    let mut buf = vec![0u8; 4096];
+                    let mut total = 0;
+                    loop {
+                        let n = tokio::io::AsyncReadExt::read(&mut socket, &mut buf[total..])
+                            .await
+                            .unwrap_or(0);
+                        if n == 0 {
+                            return;
+                        }
+                        total += n;
+                        if total >= 4 && buf[..total].windows(4).any(|w| w == b"\r\n\r\n") {
+                            break;
+                        }
+                    }
+
+                    // Send HTTP response with 2 SSE chunks then stall
+                    let response = "HTTP/1.1 200 OK\r\n\
+                        content-type: text/event-stream\r\n\
+                        transfer-encoding: chunked\r\n\
+                        \r\n";
+                    let _ =
+                        tokio::io::AsyncWriteExt::write_all(&mut socket, response.as_bytes()).await;
+
+                    // Send chunk 1
+                    let chunk1 = b"data: chunk1\n\n";
+                    let mut encoded = Vec::new();
+                    write!(encoded, "{:x}\r\n", chunk1.len()).unwrap();
+                    encoded.extend_from_slice(chunk1);
+                    encoded.extend_from_slice(b"\r\n");
+                    let _ = tokio::io::AsyncWriteExt::write_all(&mut socket, &encoded).await;
+                    let _ = tokio::io::AsyncWriteExt::flush(&mut socket).await;
+
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+
+                    // Send chunk 2
+                    let chunk2 = b"data: chunk2\n\n";
+                    let mut encoded2 = Vec::new();
+                    write!(encoded2, "{:x}\r\n", chunk2.len()).unwrap();
+                    encoded2.extend_from_slice(chunk2);
+                    encoded2.extend_from_slice(b"\r\n");
+                    let _ = tokio::io::AsyncWriteExt::write_all(&mut socket, &encoded2).await;
+                    let _ = tokio::io::AsyncWriteExt::flush(&mut socket).await;
+
+                    // Stall: hold the connection open but send nothing
+                    tokio::time::sleep(Duration::from_secs(30)).await;
+                    drop(socket);
+                });
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let metrics = ServiceMetrics::new();
+        let state = AppState {
+            proxy: ProxyState {
+                client: reqwest::Client::new(),
+                upstream_url,
+                provider: Arc::new(provider::PassthroughProvider::new(vec![])),
+                timeout: Duration::from_millis(200), // Short idle timeout
+                requests_total: metrics.requests_total.clone(),
+                errors_total: metrics.errors_total.clone(),
+                in_flight: metrics.in_flight.clone(),
+                max_failover_attempts: 1,
+                buffer_first_event: false,
+                retry: crate::retry::RetryPolicy::default(),
+                clients: Default::default(),
+                tailnet: None,
+                routes: None,
+                models: None,
+                usage_accounting: true,
+            },
+            metrics,
+            prometheus: test_prometheus_handle(),
+        };
+
+        let app = build_router(state, 1000);
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/v1/messages")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Collect the full body — the idle timeout should terminate the stream
+        // after ~200ms of silence following the second chunk
+        let body = tokio::time::timeout(
+            Duration::from_secs(5),
+            axum::body::to_bytes(response.into_body(), 1024 * 1024),
+        )
+        .await
+        .expect("body collection must not hang (idle timeout should fire)")
+        .expect("body bytes must be readable");
+
+        let body_str = String::from_utf8(body.to_vec()).unwrap();
+        assert!(
+            body_str.contains("data: chunk1"),
+            "must receive first chunk before idle timeout"
+        );
+        assert!(
+            body_str.contains("data: chunk2"),
+            "must receive second chunk before idle timeout"
+        );
+    }
+
+    /// Start an SSE upstream whose first `drop_first` connections send a `ping`
+    /// event and then drop before `message_start`. Later connections stream a
+    /// complete message. Returns the URL and a connection counter.
+    async fn start_flaky_sse_server(drop_first: usize) -> (String, Arc<AtomicUsize>) {
+        use std::io::Write;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let n = counter.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let mut buf = vec![0u8; 8192];
+                    let mut total = 0;
+                    loop {
+                        let read = tokio::io::AsyncReadExt::read(&mut socket, &mut buf[total..])
+                            .await
+                            .unwrap_or(0);
+                        if read == 0 {
+                            return;
+                        }
+                        total += read;
+                        if buf[..total].windows(4).any(|w| w == b"\r\n\r\n") {
                             break;
                         }
                     }
 
-                    // Send HTTP response with 2 SSE chunks then stall
-                    let response = "HTTP/1.1 200 OK\r\n\
+                    let head = "HTTP/1.1 200 OK\r\n\
                         content-type: text/event-stream\r\n\
                         transfer-encoding: chunked\r\n\
                         \r\n";
-                    let _ =
-                        tokio::io::AsyncWriteExt::write_all(&mut socket, response.as_bytes()).await;
+                    let _ = tokio::io::AsyncWriteExt::write_all(&mut socket, head.as_bytes()).await;
+
+                    let events: &[u8] = if n < drop_first {
+                        b"event: ping\ndata: {}\n\n"
+                    } else {
+                        b"event: message_start\ndata: {}\n\nevent: message_stop\ndata: {}\n\n"
+                    };
+                    let mut encoded = Vec::new();
+                    write!(encoded, "{:x}\r\n", events.len()).unwrap();
+                    encoded.extend_from_slice(events);
+                    encoded.extend_from_slice(b"\r\n");
+                    if n >= drop_first {
+                        encoded.extend_from_slice(b"0\r\n\r\n");
+                    }
+                    let _ = tokio::io::AsyncWriteExt::write_all(&mut socket, &encoded).await;
+                    let _ = tokio::io::AsyncWriteExt::flush(&mut socket).await;
+                    // Dropping the socket mid-body truncates the chunked stream
+                });
+            }
+        });
+
+        (format!("http://{addr}"), connections)
+    }
+
+    fn buffering_app_state(upstream_url: String) -> AppState {
+        let metrics = ServiceMetrics::new();
+        AppState {
+            proxy: ProxyState {
+                client: reqwest::Client::new(),
+                upstream_url,
+                provider: Arc::new(provider::PassthroughProvider::new(vec![])),
+                timeout: Duration::from_secs(5),
+                requests_total: metrics.requests_total.clone(),
+                errors_total: metrics.errors_total.clone(),
+                in_flight: metrics.in_flight.clone(),
+                max_failover_attempts: 1,
+                buffer_first_event: true,
+                retry: crate::retry::RetryPolicy::default(),
+                clients: Default::default(),
+                tailnet: None,
+                routes: None,
+                models: None,
+                usage_accounting: true,
+            },
+            metrics,
+            prometheus: test_prometheus_handle(),
+        }
+    }
+
+    #[tokio::test]
+    async fn proxy_retries_stream_dropped_before_first_event() {
+        let (upstream_url, connections) = start_flaky_sse_server(1).await;
+        let app = build_router(buffering_app_state(upstream_url), 1000);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/v1/messages")
+                    .body(Body::from("{}"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), 1024 * 1024)
+            .await
+            .unwrap();
+        let body_str = String::from_utf8(body.to_vec()).unwrap();
+        assert!(
+            body_str.starts_with("event: message_start"),
+            "got: {body_str}"
+        );
+        assert!(!body_str.contains("ping"), "dropped attempt must not leak");
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn proxy_returns_502_when_every_stream_drops_before_first_event() {
+        let (upstream_url, connections) = start_flaky_sse_server(usize::MAX).await;
+        let app = build_router(buffering_app_state(upstream_url), 1000);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/v1/messages")
+                    .body(Body::from("{}"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        let body = axum::body::to_bytes(response.into_body(), 1024 * 1024)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(
+            json["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("before first event")
+        );
+        assert_eq!(connections.load(Ordering::SeqCst), 3);
+    }
+
+    /// Start an upstream that answers with `statuses` in order (repeating the
+    /// last one), counting requests.
+    async fn start_status_sequence_server(statuses: Vec<u16>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+
+        tokio::spawn(async move {
+            let app = axum::Router::new().fallback(move || {
+                let n = counter.fetch_add(1, Ordering::SeqCst);
+                let status = statuses[n.min(statuses.len() - 1)];
+                async move {
+                    (
+                        StatusCode::from_u16(status).unwrap(),
+                        format!(r#"{{"attempt":{n}}}"#),
+                    )
+                }
+            });
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        (format!("http://{addr}"), hits)
+    }
+
+    fn fast_retry_policy(retry_on: Vec<crate::retry::RetryClass>) -> crate::retry::RetryPolicy {
+        crate::retry::RetryPolicy {
+            max_attempts: 4,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(5),
+            multiplier: 2.0,
+            jitter: false,
+            retry_on,
+            budget: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn proxy_retries_configured_status_classes() {
+        use crate::retry::RetryClass;
+
+        let (upstream_url, hits) = start_status_sequence_server(vec![429, 529, 503, 200]).await;
+        let mut state = test_app_state(&upstream_url, vec![]);
+        state.proxy.retry = fast_retry_policy(vec![
+            RetryClass::RateLimited,
+            RetryClass::Overloaded,
+            RetryClass::ServerError,
+        ]);
+        let app = build_router(state, 1000);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/v1/messages")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(hits.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn proxy_returns_last_error_when_retries_exhausted() {
+        use crate::retry::RetryClass;
+
+        let (upstream_url, hits) = start_status_sequence_server(vec![503]).await;
+        let mut state = test_app_state(&upstream_url, vec![]);
+        state.proxy.retry = fast_retry_policy(vec![RetryClass::ServerError]);
+        let app = build_router(state, 1000);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/v1/messages")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = axum::body::to_bytes(response.into_body(), 1024)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], br#"{"attempt":3}"#);
+        assert_eq!(hits.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn proxy_does_not_retry_unlisted_status_classes() {
+        use crate::retry::RetryClass;
+
+        let (upstream_url, hits) = start_status_sequence_server(vec![529, 200]).await;
+        let mut state = test_app_state(&upstream_url, vec![]);
+        state.proxy.retry = fast_retry_policy(vec![RetryClass::ServerError]);
+        let app = build_router(state, 1000);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/v1/messages")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status().as_u16(), 529);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn proxy_retry_budget_limits_attempts() {
+        use crate::retry::RetryClass;
+
+        let (upstream_url, hits) = start_status_sequence_server(vec![503]).await;
+        let mut state = test_app_state(&upstream_url, vec![]);
+        state.proxy.retry = crate::retry::RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(40),
+            max_backoff: Duration::from_millis(40),
+            budget: Some(Duration::from_millis(100)),
+            ..fast_retry_policy(vec![RetryClass::ServerError])
+        };
+        let app = build_router(state, 1000);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/v1/messages")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let attempts = hits.load(Ordering::SeqCst);
+        assert!(
+            (2..=3).contains(&attempts),
+            "100ms budget with 40ms backoff allows 2-3 attempts, got {attempts}"
+        );
+    }
+
+    #[tokio::test]
+    async fn proxy_authenticates_clients_and_strips_their_keys() {
+        use sha2::{Digest, Sha256};
+
+        let (upstream_url, _server) = start_echo_server().await;
+        let key_sha256: String = Sha256::digest(b"sk-proxy-ci")
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        let mut state = test_app_state(&upstream_url, vec![]);
+        state.proxy.clients = Arc::new(
+            crate::clients::ClientRegistry::from_config(&[config::ClientConfig {
+                name: "ci".into(),
+                key_sha256,
+                labels: Default::default(),
+                allowed_models: vec!["claude-haiku-*".into()],
+                expires_at: None,
+                requests_per_minute: None,
+                max_concurrent_requests: None,
+                input_tokens_per_day: None,
+                output_tokens_per_day: None,
+            }])
+            .unwrap(),
+        );
+        let app = build_router(state, 1000);
+
+        let send = |key: Option<&'static str>, model: &'static str| {
+            let app = app.clone();
+            async move {
+                let mut builder = Request::builder().method("POST").uri("/v1/messages");
+                if let Some(key) = key {
+                    builder = builder.header("x-api-key", key);
+                }
+                app.oneshot(
+                    builder
+                        .body(Body::from(format!(r#"{{"model":"{model}"}}"#)))
+                        .unwrap(),
+                )
+                .await
+                .unwrap()
+            }
+        };
+
+        assert_eq!(
+            send(None, "claude-haiku-4-5").await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            send(Some("sk-wrong"), "claude-haiku-4-5").await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            send(Some("sk-proxy-ci"), "claude-opus-4-1").await.status(),
+            StatusCode::FORBIDDEN
+        );
+
+        // Requests that don't name their model at the top level are checked
+        // too: every batch entry, and bodies the model can't be read from
+        let send_body = |uri: &'static str, body: &'static str| {
+            let app = app.clone();
+            async move {
+                app.oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri(uri)
+                        .header("x-api-key", "sk-proxy-ci")
+                        .body(Body::from(body))
+                        .unwrap(),
+                )
+                .await
+                .unwrap()
+                .status()
+            }
+        };
+        let batch = r#"{"requests": [
+            {"custom_id": "a", "params": {"model": "claude-haiku-4-5"}},
+            {"custom_id": "b", "params": {"model": "claude-opus-4-1"}}
+        ]}"#;
+        assert_eq!(
+            send_body("/v1/messages/batches", batch).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            send_body(
+                "/v1/messages/batches",
+                r#"{"requests": [{"custom_id": "a", "params": {"model": "claude-haiku-4-5"}}]}"#
+            )
+            .await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            send_body(
+                "/v1/messages/batches",
+                r#"{"requests": [{"custom_id": "a"}]}"#
+            )
+            .await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            send_body("/v1/messages", "model=claude-opus-4-1").await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            send_body("/v1/messages", r#"{"messages": []}"#).await,
+            StatusCode::FORBIDDEN
+        );
+        // Bodyless requests (batch status, model list) name no model
+        assert_eq!(send_body("/v1/models", "").await, StatusCode::OK);
+
+        let response = send(Some("sk-proxy-ci"), "claude-haiku-4-5").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), 1024 * 1024)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(
+            json["echoed_headers"].get("x-api-key").is_none(),
+            "proxy key must not be forwarded upstream"
+        );
+    }
+
+    /// Registry with one client, key `sk-limited`, customized by `configure`.
+    fn limited_client(
+        configure: impl FnOnce(&mut config::ClientConfig),
+    ) -> Arc<crate::clients::ClientRegistry> {
+        use sha2::{Digest, Sha256};
+
+        let mut entry = config::ClientConfig {
+            name: "agent".into(),
+            key_sha256: Sha256::digest(b"sk-limited")
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect(),
+            labels: Default::default(),
+            allowed_models: vec![],
+            expires_at: None,
+            requests_per_minute: None,
+            max_concurrent_requests: None,
+            input_tokens_per_day: None,
+            output_tokens_per_day: None,
+        };
+        configure(&mut entry);
+        Arc::new(crate::clients::ClientRegistry::from_config(&[entry]).unwrap())
+    }
 
-                    // Send chunk 1
-                    let chunk1 = b"data: chunk1\n\n";
-                    let mut encoded = Vec::new();
-                    write!(encoded, "{:x}\r\n", chunk1.len()).unwrap();
-                    encoded.extend_from_slice(chunk1);
-                    encoded.extend_from_slice(b"\r\n");
-                    let _ = tokio::io::AsyncWriteExt::write_all(&mut socket, &encoded).await;
-                    let _ = tokio::io::AsyncWriteExt::flush(&mut socket).await;
+    fn limited_request() -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/v1/messages")
+            .header("x-api-key", "sk-limited")
+            .body(Body::from(r#"{"model":"claude-haiku-4-5"}"#))
+            .unwrap()
+    }
 
-                    tokio::time::sleep(Duration::from_millis(10)).await;
+    #[tokio::test]
+    async fn proxy_rejects_clients_over_their_request_rate() {
+        let (upstream_url, _server) = start_echo_server().await;
+        let mut state = test_app_state(&upstream_url, vec![]);
+        state.proxy.clients = limited_client(|c| c.requests_per_minute = Some(1));
+        let app = build_router(state, 1000);
 
-                    // Send chunk 2
-                    let chunk2 = b"data: chunk2\n\n";
-                    let mut encoded2 = Vec::new();
-                    write!(encoded2, "{:x}\r\n", chunk2.len()).unwrap();
-                    encoded2.extend_from_slice(chunk2);
-                    encoded2.extend_from_slice(b"\r\n");
-                    let _ = tokio::io::AsyncWriteExt::write_all(&mut socket, &encoded2).await;
-                    let _ = tokio::io::AsyncWriteExt::flush(&mut socket).await;
+        let first = app.clone().oneshot(limited_request()).await.unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
 
-                    // Stall: hold the connection open but send nothing
-                    tokio::time::sleep(Duration::from_secs(30)).await;
-                    drop(socket);
-                });
+        let second = app.oneshot(limited_request()).await.unwrap();
+        assert_eq!(second.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = second.headers()["retry-after"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((1..=60).contains(&retry_after), "retry-after {retry_after}");
+        let body = axum::body::to_bytes(second.into_body(), 1024 * 1024)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["type"], "error");
+        assert_eq!(json["error"]["type"], "rate_limit_error");
+    }
+
+    #[tokio::test]
+    async fn proxy_enforces_daily_output_token_budget() {
+        // Upstream that reports 60 output tokens per response and records
+        // whether the proxy asked for a compressed body
+        let compressed = Arc::new(AtomicUsize::new(0));
+        let seen = compressed.clone();
+        let app = Router::new().fallback(move |request: Request<Body>| {
+            let seen = seen.clone();
+            async move {
+                if request.headers().contains_key("accept-encoding") {
+                    seen.fetch_add(1, Ordering::SeqCst);
+                }
+                axum::Json(serde_json::json!({
+                    "type": "message",
+                    "usage": {"input_tokens": 12, "output_tokens": 60},
+                }))
             }
         });
-        tokio::time::sleep(Duration::from_millis(10)).await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
 
-        let metrics = ServiceMetrics::new();
-        let state = AppState {
-            proxy: ProxyState {
-                client: reqwest::Client::new(),
-                upstream_url,
-                provider: Arc::new(provider::PassthroughProvider::new(vec![])),
-                timeout: Duration::from_millis(200), // Short idle timeout
-                requests_total: metrics.requests_total.clone(),
-                errors_total: metrics.errors_total.clone(),
-                in_flight: metrics.in_flight.clone(),
-                max_failover_attempts: 1,
-            },
-            metrics,
-            prometheus: test_prometheus_handle(),
-        };
+        let mut state = test_app_state(&upstream_url, vec![]);
+        state.proxy.clients = limited_client(|c| c.output_tokens_per_day = Some(100));
+        let app = build_router(state, 1000);
+
+        for _ in 0..2 {
+            let mut request = limited_request();
+            request
+                .headers_mut()
+                .insert("accept-encoding", "gzip".parse().unwrap());
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            // Usage is charged once the body has been read
+            axum::body::to_bytes(response.into_body(), 1024 * 1024)
+                .await
+                .unwrap();
+        }
+        assert_eq!(compressed.load(Ordering::SeqCst), 0);
+
+        let response = app.oneshot(limited_request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let body = axum::body::to_bytes(response.into_body(), 1024 * 1024)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(
+            json["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("output tokens"),
+            "got: {json}"
+        );
+    }
+
+    #[tokio::test]
+    async fn proxy_accounts_streamed_token_usage() {
+        let sse = concat!(
+            "event: message_start\n",
+            "data: {\"type\":\"message_start\",\"message\":{\"model\":\"claude-usage-test\",\"usage\":{\"input_tokens\":21,\"cache_read_input_tokens\":400,\"output_tokens\":1}}}\n\n",
+            "event: message_delta\n",
+            "data: {\"type\":\"message_delta\",\"usage\":{\"output_tokens\":17}}\n\n",
+            "event: message_stop\n",
+            "data: {\"type\":\"message_stop\"}\n\n",
+        );
+        let app = Router::new()
+            .fallback(move || async move { ([("content-type", "text/event-stream")], sse) });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
 
+        let handle = global_prometheus_handle();
+        let state = test_app_state(&upstream_url, vec![]);
         let app = build_router(state, 1000);
         let response = app
             .oneshot(
                 Request::builder()
+                    .method("POST")
                     .uri("/v1/messages")
-                    .body(Body::empty())
+                    .body(Body::from(r#"{"model":"claude-usage-test","stream":true}"#))
                     .unwrap(),
             )
             .await
             .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), 1024 * 1024)
+            .await
+            .unwrap();
+        assert_eq!(body, sse.as_bytes(), "stream must pass through unchanged");
+
+        let rendered = handle.render();
+        let series = |kind: &str| {
+            rendered
+                .lines()
+                .find(|l| {
+                    l.starts_with("proxy_tokens_total{")
+                        && l.contains("model=\"claude-usage-test\"")
+                        && l.contains(&format!("type=\"{kind}\""))
+                })
+                .map(|l| l.rsplit(' ').next().unwrap().to_string())
+        };
+        assert_eq!(series("input").as_deref(), Some("21"), "{rendered}");
+        assert_eq!(series("output").as_deref(), Some("17"));
+        assert_eq!(series("cache_read").as_deref(), Some("400"));
+        assert!(series("cache_creation").is_none());
+    }
 
+    #[tokio::test]
+    async fn proxy_accounts_usage_from_upstreams_that_compress() {
+        // Upstream that compresses whenever the client allows it
+        let app = Router::new().fallback(|request: Request<Body>| async move {
+            if request.headers().contains_key("accept-encoding") {
+                return (
+                    [
+                        ("content-type", "application/json"),
+                        ("content-encoding", "gzip"),
+                    ],
+                    vec![0x1f, 0x8b, 0x08, 0x00],
+                )
+                    .into_response();
+            }
+            axum::Json(serde_json::json!({
+                "type": "message",
+                "model": "claude-gzip-test",
+                "content": [{"type": "text", "text": "Hello"}],
+                "usage": {"input_tokens": 13, "output_tokens": 8},
+            }))
+            .into_response()
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        let handle = global_prometheus_handle();
+        let app = build_router(test_app_state(&upstream_url, vec![]), 1000);
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/v1/messages")
+                    .header("accept-encoding", "gzip, br")
+                    .body(Body::from(r#"{"model":"claude-gzip-test"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
+        axum::body::to_bytes(response.into_body(), 1024 * 1024)
+            .await
+            .unwrap();
 
-        // Collect the full body — the idle timeout should terminate the stream
-        // after ~200ms of silence following the second chunk
-        let body = tokio::time::timeout(
-            Duration::from_secs(5),
-            axum::body::to_bytes(response.into_body(), 1024 * 1024),
-        )
-        .await
-        .expect("body collection must not hang (idle timeout should fire)")
-        .expect("body bytes must be readable");
+        let rendered = handle.render();
+        let output = rendered.lines().find(|l| {
+            l.starts_with("proxy_tokens_total{")
+                && l.contains("model=\"claude-gzip-test\"")
+                && l.contains("type=\"output\"")
+        });
+        assert!(output.is_some_and(|l| l.ends_with(" 8")), "{rendered}");
+    }
 
-        let body_str = String::from_utf8(body.to_vec()).unwrap();
+    #[tokio::test]
+    async fn proxy_labels_unnamed_request_models_as_other() {
+        // Upstream whose responses don't name a model
+        let app = Router::new().fallback(|| async {
+            axum::Json(serde_json::json!({
+                "type": "message",
+                "usage": {"input_tokens": 3, "output_tokens": 5},
+            }))
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        let handle = global_prometheus_handle();
+        let mut state = test_app_state(&upstream_url, vec![]);
+        state.proxy.models = Some(Arc::new(routing::ModelRouter::new(
+            HashMap::from([("named".to_string(), "claude-label-named".to_string())]),
+            vec![],
+        )));
+        let app = build_router(state, 1000);
+        for model in ["named", "claude-label-made-up"] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/v1/messages")
+                        .body(Body::from(format!(r#"{{"model":"{model}"}}"#)))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            axum::body::to_bytes(response.into_body(), 1024 * 1024)
+                .await
+                .unwrap();
+        }
+
+        let rendered = handle.render();
+        let has_series = |model: &str| {
+            rendered.lines().any(|l| {
+                l.starts_with("proxy_tokens_total{") && l.contains(&format!("model=\"{model}\""))
+            })
+        };
+        assert!(has_series("claude-label-named"), "{rendered}");
+        assert!(has_series("other"), "{rendered}");
+        assert!(!has_series("claude-label-made-up"), "{rendered}");
+    }
+
+    /// Messages API stand-in: records each request (path, body) and answers
+    /// with SSE when the body asks to stream, JSON otherwise.
+    async fn start_messages_server() -> (
+        String,
+        Arc<std::sync::Mutex<Vec<(String, serde_json::Value)>>>,
+    ) {
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = seen.clone();
+        let app = Router::new().fallback(move |request: Request<Body>| {
+            let log = log.clone();
+            async move {
+                let path = request.uri().path().to_string();
+                let bytes = axum::body::to_bytes(request.into_body(), 1024 * 1024)
+                    .await
+                    .unwrap();
+                let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+                let stream = body["stream"] == true;
+                log.lock().unwrap().push((path, body));
+                if stream {
+                    let sse = concat!(
+                        "event: message_start\n",
+                        "data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_s\",\"model\":\"claude-haiku-4-5\",\"usage\":{\"input_tokens\":4}}}\n\n",
+                        "event: content_block_delta\n",
+                        "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hello\"}}\n\n",
+                        "event: message_delta\n",
+                        "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\"},\"usage\":{\"output_tokens\":2}}\n\n",
+                        "event: message_stop\n",
+                        "data: {\"type\":\"message_stop\"}\n\n",
+                    );
+                    ([("content-type", "text/event-stream")], sse.to_string()).into_response()
+                } else {
+                    axum::Json(serde_json::json!({
+                        "id": "msg_j",
+                        "type": "message",
+                        "model": "claude-haiku-4-5",
+                        "content": [{"type": "text", "text": "Hello"}],
+                        "stop_reason": "end_turn",
+                        "usage": {"input_tokens": 4, "output_tokens": 2},
+                    }))
+                    .into_response()
+                }
+            }
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        (url, seen)
+    }
+
+    fn chat_request(stream: bool) -> Request<Body> {
+        let body = serde_json::json!({
+            "model": "claude-haiku-4-5",
+            "messages": [
+                {"role": "system", "content": "Be brief."},
+                {"role": "user", "content": "Hi"},
+            ],
+            "stream": stream,
+        });
+        Request::builder()
+            .method("POST")
+            .uri("/v1/chat/completions")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn chat_completions_are_translated_through_messages() {
+        let (upstream_url, seen) = start_messages_server().await;
+        let app = build_router(test_app_state(&upstream_url, vec![]), 1000);
+
+        let response = app.oneshot(chat_request(false)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), 1024 * 1024)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["object"], "chat.completion");
+        assert_eq!(json["choices"][0]["message"]["content"], "Hello");
+        assert_eq!(json["choices"][0]["finish_reason"], "stop");
+        assert_eq!(json["usage"]["total_tokens"], 6);
+
+        let seen = seen.lock().unwrap();
+        let (path, sent) = &seen[0];
+        assert_eq!(path, "/v1/messages");
+        assert_eq!(sent["system"], "Be brief.");
+        assert_eq!(sent["messages"][0]["content"][0]["text"], "Hi");
+    }
+
+    #[tokio::test]
+    async fn chat_completions_ask_upstream_for_uncompressed_bodies() {
+        // Upstream that honours Accept-Encoding the way api.anthropic.com
+        // does: a compressed body the translator couldn't read
+        let app = Router::new().fallback(|request: Request<Body>| async move {
+            if request.headers().contains_key("accept-encoding") {
+                return (
+                    [
+                        ("content-type", "application/json"),
+                        ("content-encoding", "gzip"),
+                    ],
+                    vec![0x1f, 0x8b, 0x08, 0x00],
+                )
+                    .into_response();
+            }
+            axum::Json(serde_json::json!({
+                "id": "msg_g",
+                "type": "message",
+                "model": "claude-haiku-4-5",
+                "content": [{"type": "text", "text": "Hello"}],
+                "stop_reason": "end_turn",
+                "usage": {"input_tokens": 4, "output_tokens": 2},
+            }))
+            .into_response()
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        let app = build_router(test_app_state(&upstream_url, vec![]), 1000);
+
+        // OpenAI SDKs send this on every request
+        let mut request = chat_request(false);
+        request
+            .headers_mut()
+            .insert("accept-encoding", "gzip, deflate".parse().unwrap());
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), 1024 * 1024)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["object"], "chat.completion");
+        assert_eq!(json["choices"][0]["message"]["content"], "Hello");
+    }
+
+    #[tokio::test]
+    async fn chat_completions_stream_as_openai_chunks() {
+        let (upstream_url, _) = start_messages_server().await;
+        let app = build_router(test_app_state(&upstream_url, vec![]), 1000);
+
+        let response = app.oneshot(chat_request(true)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), 1024 * 1024)
+            .await
+            .unwrap();
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        let data: Vec<&str> = text
+            .lines()
+            .filter_map(|l| l.strip_prefix("data: "))
+            .collect();
+        assert_eq!(*data.last().unwrap(), "[DONE]");
+        let chunks: Vec<serde_json::Value> = data[..data.len() - 1]
+            .iter()
+            .map(|d| serde_json::from_str(d).unwrap())
+            .collect();
         assert!(
-            body_str.contains("data: chunk1"),
-            "must receive first chunk before idle timeout"
+            chunks
+                .iter()
+                .all(|c| c["object"] == "chat.completion.chunk")
         );
-        assert!(
-            body_str.contains("data: chunk2"),
-            "must receive second chunk before idle timeout"
+        assert_eq!(chunks[1]["choices"][0]["delta"]["content"], "Hello");
+        assert_eq!(chunks[2]["choices"][0]["finish_reason"], "stop");
+    }
+
+    #[tokio::test]
+    async fn chat_completions_errors_use_openai_shape() {
+        let (upstream_url, _) = start_messages_server().await;
+        let app = build_router(test_app_state(&upstream_url, vec![]), 1000);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/v1/chat/completions")
+                    .body(Body::from(r#"{"messages":[]}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), 1024 * 1024)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["error"]["type"], "invalid_request_error");
+    }
+
+    #[tokio::test]
+    async fn proxy_authorizes_callers_by_tailnet_identity() {
+        // Stand-in LocalAPI: 100.64.0.7 is a CI node, 100.64.0.8 a personal laptop
+        let whois = Router::new().route(
+            "/localapi/v0/whois",
+            axum::routing::get(
+                |axum::extract::Query(q): axum::extract::Query<
+                    std::collections::HashMap<String, String>,
+                >| async move {
+                    let (name, tags, user) = match q.get("addr").map(String::as_str) {
+                        Some("100.64.0.7") => ("ci-runner", vec!["tag:ci"], "tagged-devices"),
+                        Some("100.64.0.8") => ("laptop", vec![], "alice@example.com"),
+                        _ => return (StatusCode::NOT_FOUND, String::new()),
+                    };
+                    let body = serde_json::json!({
+                        "Node": {"ComputedName": name, "Tags": tags},
+                        "UserProfile": {"LoginName": user},
+                    });
+                    (StatusCode::OK, body.to_string())
+                },
+            ),
         );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let localapi_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            axum::serve(listener, whois).await.unwrap();
+        });
+
+        let (upstream_url, _server) = start_echo_server().await;
+        let tailnet_config = config::TailnetConfig {
+            localapi_url,
+            trust_forwarded_for: false,
+            trusted_proxies: vec![],
+            cache_ttl_secs: 60,
+            allow_tags: vec!["tag:ci".into()],
+            allow_users: vec![],
+            allow_nodes: vec![],
+        };
+        let mut state = test_app_state(&upstream_url, vec![]);
+        state.proxy.tailnet = Some(Arc::new(tailnet::TailnetAuthorizer::new(
+            &tailnet_config,
+            reqwest::Client::new(),
+        )));
+        let app = build_router(state, 1000);
+
+        let send = |peer: Option<&'static str>| {
+            let app = app.clone();
+            async move {
+                let mut request = Request::builder()
+                    .method("POST")
+                    .uri("/v1/messages")
+                    .body(Body::from(r#"{"model":"claude-haiku-4-5"}"#))
+                    .unwrap();
+                if let Some(ip) = peer {
+                    let addr: std::net::SocketAddr = format!("{ip}:51000").parse().unwrap();
+                    request
+                        .extensions_mut()
+                        .insert(axum::extract::ConnectInfo(addr));
+                }
+                app.oneshot(request).await.unwrap().status()
+            }
+        };
+
+        assert_eq!(send(Some("100.64.0.7")).await, StatusCode::OK);
+        // Tailnet node outside the allowlist
+        assert_eq!(send(Some("100.64.0.8")).await, StatusCode::FORBIDDEN);
+        // Not a tailnet node at all
+        assert_eq!(send(Some("192.0.2.1")).await, StatusCode::FORBIDDEN);
+        // No peer address to identify
+        assert_eq!(send(None).await, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn proxy_returns_503_when_tailnet_lookup_fails() {
+        let (upstream_url, _server) = start_echo_server().await;
+        let tailnet_config = config::TailnetConfig {
+            localapi_url: "http://127.0.0.1:1".into(),
+            trust_forwarded_for: false,
+            trusted_proxies: vec![],
+            cache_ttl_secs: 60,
+            allow_tags: vec![],
+            allow_users: vec![],
+            allow_nodes: vec![],
+        };
+        let mut state = test_app_state(&upstream_url, vec![]);
+        state.proxy.tailnet = Some(Arc::new(tailnet::TailnetAuthorizer::new(
+            &tailnet_config,
+            reqwest::Client::new(),
+        )));
+        let app = build_router(state, 1000);
+
+        let mut request = Request::builder()
+            .method("POST")
+            .uri("/v1/messages")
+            .body(Body::from("{}"))
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(axum::extract::ConnectInfo(std::net::SocketAddr::from((
+                [100, 64, 0, 7],
+                51000,
+            ))));
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
     }
 }
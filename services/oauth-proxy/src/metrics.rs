@@ -2,9 +2,15 @@
 //!
 //! Registers and exposes the metrics defined in specs/oauth-proxy.md:
 //!
-//! - `proxy_requests_total` (counter): labels `status`, `method`
-//! - `proxy_request_duration_seconds` (histogram): label `status`
+//! - `proxy_requests_total` (counter): labels `status`, `method`, `client`, `route`
+//! - `proxy_request_duration_seconds` (histogram): labels `status`, `route`
 //! - `proxy_upstream_errors_total` (counter): label `error_type`
+//! - `proxy_upstream_retries_total` (counter): label `reason`
+//! - `proxy_upstream_retries_exhausted_total` (counter): label `reason`
+//! - `proxy_tailnet_requests_total` (counter): labels `node`, `user`, `account_id`
+//! - `proxy_client_limited_total` (counter): labels `client`, `limit`
+//! - `proxy_tokens_total` (counter): labels `account_id`, `model`, `client`, `type`
+//! - `proxy_tier_responses_total` (counter): label `tier`
 
 use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
 
@@ -32,12 +38,14 @@ pub fn install_recorder() -> PrometheusHandle {
         .expect("failed to install Prometheus recorder")
 }
 
-/// Record a completed proxy request with status code and HTTP method labels.
-pub fn record_request(status: u16, method: &str, duration_secs: f64) {
+/// Record a completed proxy request with status code, HTTP method, client,
+/// and `[[routes]]` name labels. The duration histogram is labeled by status
+/// and route only to bound cardinality.
+pub fn record_request(status: u16, method: &str, client: &str, route: &str, duration_secs: f64) {
     let status_str = status.to_string();
-    metrics::counter!("proxy_requests_total", "status" => status_str.clone(), "method" => method.to_string())
+    metrics::counter!("proxy_requests_total", "status" => status_str.clone(), "method" => method.to_string(), "client" => client.to_string(), "route" => route.to_string())
         .increment(1);
-    metrics::histogram!("proxy_request_duration_seconds", "status" => status_str)
+    metrics::histogram!("proxy_request_duration_seconds", "status" => status_str, "route" => route.to_string())
         .record(duration_secs);
 }
 
@@ -47,6 +55,58 @@ pub fn record_upstream_error(error_type: &str) {
         .increment(1);
 }
 
+/// Record a retry of a failed upstream attempt.
+pub fn record_upstream_retry(reason: &str) {
+    metrics::counter!("proxy_upstream_retries_total", "reason" => reason.to_string()).increment(1);
+}
+
+/// Record a retryable failure that was not retried because the attempt limit
+/// or the request's retry budget was used up.
+pub fn record_upstream_retries_exhausted(reason: &str) {
+    metrics::counter!("proxy_upstream_retries_exhausted_total", "reason" => reason.to_string())
+        .increment(1);
+}
+
+/// Record a request forwarded on behalf of a tailnet node, once per request
+/// against the account that last handled it (`account_id` is `-` in
+/// passthrough mode).
+pub fn record_tailnet_request(node: &str, user: &str, account_id: &str) {
+    metrics::counter!("proxy_tailnet_requests_total", "node" => node.to_string(), "user" => user.to_string(), "account_id" => account_id.to_string())
+        .increment(1);
+}
+
+/// Record a request refused because its client is over a limit.
+pub fn record_client_limited(client: &str, limit: &str) {
+    metrics::counter!("proxy_client_limited_total", "client" => client.to_string(), "limit" => limit.to_string())
+        .increment(1);
+}
+
+/// Record the tokens a completed response used, one series per token type
+/// (`input`, `output`, `cache_creation`, `cache_read`).
+pub fn record_tokens(
+    account_id: &str,
+    model: &str,
+    client: &str,
+    tokens: &crate::usage::TokenUsage,
+) {
+    for (kind, count) in [
+        ("input", tokens.input_tokens),
+        ("output", tokens.output_tokens),
+        ("cache_creation", tokens.cache_creation_input_tokens),
+        ("cache_read", tokens.cache_read_input_tokens),
+    ] {
+        if count > 0 {
+            metrics::counter!("proxy_tokens_total", "account_id" => account_id.to_string(), "model" => model.to_string(), "client" => client.to_string(), "type" => kind)
+                .increment(count);
+        }
+    }
+}
+
+/// Record a response served by a provider tier of the fallback chain.
+pub fn record_tier_response(tier: &str) {
+    metrics::counter!("proxy_tier_responses_total", "tier" => tier.to_string()).increment(1);
+}
+
 /// Record a pool account status change (gauge, 1 for current status).
 pub fn record_pool_account_status(account_id: &str, status: &str) {
     // Set all status variants to 0 first, then set the current one to 1.
@@ -80,8 +140,12 @@ mod tests {
     fn record_functions_do_not_panic_without_recorder() {
         // When no recorder is installed, metrics calls are no-ops.
         // This verifies the functions don't panic in test environments.
-        record_request(200, "GET", 0.05);
+        record_request(200, "GET", "anonymous", "default", 0.05);
         record_upstream_error("timeout");
+        record_tailnet_request("build-01", "tagged-devices", "-");
+        record_client_limited("ci", "requests_per_minute");
+        record_tier_response("api_keys");
+        record_tokens("-", "claude-haiku-4-5", "anonymous", &Default::default());
     }
 
     /// Create an isolated recorder/handle pair for unit tests.
@@ -113,8 +177,8 @@ mod tests {
         let (recorder, handle) = isolated_recorder();
         let _guard = metrics::set_default_local_recorder(&recorder);
 
-        record_request(200, "GET", 0.042);
-        record_request(500, "POST", 1.5);
+        record_request(200, "GET", "ci", "default", 0.042);
+        record_request(500, "POST", "ci", "batches", 1.5);
 
         let output = handle.render();
         assert!(
@@ -129,6 +193,14 @@ mod tests {
             output.contains("method=\"GET\""),
             "counter must carry method label"
         );
+        assert!(
+            output.contains("client=\"ci\""),
+            "counter must carry client label"
+        );
+        assert!(
+            output.contains("route=\"batches\""),
+            "counter must carry route label"
+        );
         assert!(
             output.contains("status=\"500\""),
             "second request status label must appear"
@@ -169,6 +241,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn record_tokens_emits_one_series_per_nonzero_type() {
+        let (recorder, handle) = isolated_recorder();
+        let _guard = metrics::set_default_local_recorder(&recorder);
+
+        record_tokens(
+            "acct-1",
+            "claude-sonnet-4-5",
+            "ci",
+            &crate::usage::TokenUsage {
+                input_tokens: 12,
+                output_tokens: 34,
+                cache_creation_input_tokens: 0,
+                cache_read_input_tokens: 56,
+            },
+        );
+
+        let output = handle.render();
+        let series: Vec<&str> = output
+            .lines()
+            .filter(|l| l.starts_with("proxy_tokens_total{"))
+            .collect();
+        assert_eq!(series.len(), 3, "got: {output}");
+        assert!(
+            series
+                .iter()
+                .any(|l| l.contains("type=\"output\"") && l.ends_with(" 34"))
+        );
+        assert!(series.iter().all(|l| l.contains("account_id=\"acct-1\"")
+            && l.contains("model=\"claude-sonnet-4-5\"")
+            && l.contains("client=\"ci\"")));
+        assert!(!output.contains("type=\"cache_creation\""));
+    }
+
     #[test]
     fn histogram_buckets_cover_spec_range() {
         // The spec requires histogram buckets from 5ms to 60s so that
@@ -179,7 +285,7 @@ mod tests {
         let (recorder, handle) = isolated_recorder();
         let _guard = metrics::set_default_local_recorder(&recorder);
 
-        record_request(200, "GET", 0.003); // 3ms, below lowest bucket
+        record_request(200, "GET", "anonymous", "default", 0.003); // 3ms, below lowest bucket
 
         let output = handle.render();
         // Verify specific bucket boundaries from the spec
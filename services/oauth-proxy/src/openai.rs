@@ -0,0 +1,821 @@
+//! OpenAI Chat Completions compatibility
+//!
+//! `POST /v1/chat/completions` requests are translated to Anthropic Messages
+//! requests and sent through `proxy_request` as `POST /v1/messages`, so
+//! provider auth, failover, client limits and usage accounting all apply
+//! unchanged. The response is translated back: JSON into a `chat.completion`,
+//! SSE into `chat.completion.chunk` events ending in `data: [DONE]`, and error
+//! bodies into OpenAI's `{"error": {...}}` shape.
+//!
+//! Supported: system/developer messages, text and image content parts,
+//! assistant tool calls, tool results, `tools`/`tool_choice`, `stop`,
+//! `max_tokens`/`max_completion_tokens`, `temperature`, `top_p`, `user`, and
+//! `stream`/`stream_options.include_usage`. Other fields are dropped.
+
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::http::{HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use bytes::Bytes;
+use futures_util::StreamExt;
+use serde_json::{Map, Value, json};
+use tracing::warn;
+
+use crate::proxy::{MAX_BODY_SIZE, ProxyState, proxy_request};
+
+/// Path this module serves.
+pub const CHAT_COMPLETIONS_PATH: &str = "/v1/chat/completions";
+
+/// Messages API requires `max_tokens`; Chat Completions doesn't.
+const DEFAULT_MAX_TOKENS: u64 = 4096;
+
+/// Messages API version sent when the client didn't choose one.
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Handle a Chat Completions request end to end.
+pub async fn chat_completions(
+    state: &ProxyState,
+    request: axum::http::Request<axum::body::Body>,
+    request_id: String,
+) -> Response {
+    let (mut parts, body) = request.into_parts();
+    let body = match axum::body::to_bytes(body, MAX_BODY_SIZE).await {
+        Ok(b) => b,
+        Err(e) => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "invalid_request_error",
+                &format!("invalid request body: {e}"),
+            );
+        }
+    };
+    let chat: Value = match serde_json::from_slice(&body) {
+        Ok(v) => v,
+        Err(e) => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "invalid_request_error",
+                &format!("Invalid JSON body: {e}"),
+            );
+        }
+    };
+    let messages_request = match to_messages_request(&chat) {
+        Ok(v) => v,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, "invalid_request_error", &e),
+    };
+    let include_usage = chat
+        .pointer("/stream_options/include_usage")
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+
+    parts.uri = axum::http::Uri::from_static("/v1/messages");
+    parts.headers.remove(header::CONTENT_LENGTH);
+    // The response is parsed and rewritten here, and the HTTP client does
+    // not decompress: ask upstream for an identity-encoded body
+    parts.headers.remove(header::ACCEPT_ENCODING);
+    parts.headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    if !parts.headers.contains_key("anthropic-version") {
+        parts.headers.insert(
+            "anthropic-version",
+            HeaderValue::from_static(ANTHROPIC_VERSION),
+        );
+    }
+    let request = axum::http::Request::from_parts(
+        parts,
+        axum::body::Body::from(messages_request.to_string()),
+    );
+
+    let response = proxy_request(state, request, request_id).await;
+    translate_response(response, include_usage).await
+}
+
+/// OpenAI-shaped error: `{"error":{"message","type","param","code"}}`.
+fn error_response(status: StatusCode, error_type: &str, message: &str) -> Response {
+    let body = json!({
+        "error": {
+            "message": message,
+            "type": error_type,
+            "param": null,
+            "code": null,
+        }
+    });
+    (
+        status,
+        [(header::CONTENT_TYPE, "application/json")],
+        body.to_string(),
+    )
+        .into_response()
+}
+
+// --- Request translation ---
+
+/// Translate a Chat Completions request body into a Messages request body.
+pub fn to_messages_request(chat: &Value) -> Result<Value, String> {
+    let model = chat
+        .get("model")
+        .and_then(Value::as_str)
+        .ok_or("'model' is required")?;
+    let chat_messages = chat
+        .get("messages")
+        .and_then(Value::as_array)
+        .ok_or("'messages' must be an array")?;
+    if chat.get("n").and_then(Value::as_u64).is_some_and(|n| n > 1) {
+        return Err("'n' greater than 1 is not supported".into());
+    }
+
+    let mut system: Vec<String> = Vec::new();
+    let mut messages: Vec<Value> = Vec::new();
+    for (i, message) in chat_messages.iter().enumerate() {
+        let role = message
+            .get("role")
+            .and_then(Value::as_str)
+            .ok_or_else(|| format!("messages[{i}].role is required"))?;
+        let (role, blocks) = match role {
+            "system" | "developer" => {
+                system.push(text_of(message.get("content")));
+                continue;
+            }
+            "user" => ("user", content_blocks(message.get("content"), i)?),
+            "assistant" => ("assistant", assistant_blocks(message, i)?),
+            "tool" => ("user", vec![tool_result_block(message, i)?]),
+            other => return Err(format!("messages[{i}].role '{other}' is not supported")),
+        };
+        if blocks.is_empty() {
+            continue;
+        }
+        // Messages expects alternating turns: fold consecutive same-role
+        // messages (e.g. several tool results) into one
+        match messages.last_mut() {
+            Some(last) if last["role"] == role => {
+                last["content"]
+                    .as_array_mut()
+                    .expect("content is always an array")
+                    .extend(blocks);
+            }
+            _ => messages.push(json!({"role": role, "content": blocks})),
+        }
+    }
+
+    let mut out = Map::new();
+    out.insert("model".into(), json!(model));
+    out.insert("messages".into(), Value::Array(messages));
+    let system: Vec<String> = system.into_iter().filter(|s| !s.is_empty()).collect();
+    if !system.is_empty() {
+        out.insert("system".into(), json!(system.join("\n\n")));
+    }
+    let max_tokens = chat
+        .get("max_completion_tokens")
+        .or_else(|| chat.get("max_tokens"))
+        .and_then(Value::as_u64)
+        .unwrap_or(DEFAULT_MAX_TOKENS);
+    out.insert("max_tokens".into(), json!(max_tokens));
+    for key in ["temperature", "top_p", "stream"] {
+        if let Some(v) = chat.get(key).filter(|v| !v.is_null()) {
+            out.insert(key.into(), v.clone());
+        }
+    }
+    match chat.get("stop") {
+        Some(Value::String(s)) => {
+            out.insert("stop_sequences".into(), json!([s]));
+        }
+        Some(Value::Array(a)) if !a.is_empty() => {
+            out.insert("stop_sequences".into(), Value::Array(a.clone()));
+        }
+        _ => {}
+    }
+    if let Some(user) = chat.get("user").and_then(Value::as_str) {
+        out.insert("metadata".into(), json!({ "user_id": user }));
+    }
+    if let Some(tools) = chat.get("tools").and_then(Value::as_array) {
+        out.insert("tools".into(), Value::Array(translate_tools(tools)?));
+    }
+    if let Some(choice) = chat.get("tool_choice") {
+        match choice {
+            Value::String(s) if s == "auto" => {
+                out.insert("tool_choice".into(), json!({"type": "auto"}));
+            }
+            Value::String(s) if s == "required" => {
+                out.insert("tool_choice".into(), json!({"type": "any"}));
+            }
+            Value::String(s) if s == "none" => {
+                out.insert("tool_choice".into(), json!({"type": "none"}));
+            }
+            Value::Object(_) => {
+                let name = choice
+                    .pointer("/function/name")
+                    .and_then(Value::as_str)
+                    .ok_or("tool_choice.function.name is required")?;
+                out.insert("tool_choice".into(), json!({"type": "tool", "name": name}));
+            }
+            _ => return Err("unsupported 'tool_choice'".into()),
+        }
+    }
+    Ok(Value::Object(out))
+}
+
+/// Plain text of a string or content-part array (non-text parts skipped).
+fn text_of(content: Option<&Value>) -> String {
+    match content {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Array(parts)) => parts
+            .iter()
+            .filter_map(|p| p.get("text").and_then(Value::as_str))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+/// Content blocks of a user or assistant message.
+fn content_blocks(content: Option<&Value>, i: usize) -> Result<Vec<Value>, String> {
+    match content {
+        None | Some(Value::Null) => Ok(Vec::new()),
+        Some(Value::String(s)) if s.is_empty() => Ok(Vec::new()),
+        Some(Value::String(s)) => Ok(vec![json!({"type": "text", "text": s})]),
+        Some(Value::Array(parts)) => parts
+            .iter()
+            .map(|part| match part.get("type").and_then(Value::as_str) {
+                Some("text") => Ok(json!({"type": "text", "text": part["text"]})),
+                Some("image_url") => image_block(part, i),
+                other => Err(format!(
+                    "messages[{i}]: content part type {} is not supported",
+                    other.unwrap_or("(missing)")
+                )),
+            })
+            .collect(),
+        Some(_) => Err(format!("messages[{i}].content must be a string or array")),
+    }
+}
+
+/// `image_url` part: data URLs become base64 sources, others URL sources.
+fn image_block(part: &Value, i: usize) -> Result<Value, String> {
+    let url = part
+        .pointer("/image_url/url")
+        .and_then(Value::as_str)
+        .ok_or_else(|| format!("messages[{i}]: image_url.url is required"))?;
+    if let Some(data_url) = url.strip_prefix("data:") {
+        let (media_type, data) = data_url
+            .split_once(";base64,")
+            .ok_or_else(|| format!("messages[{i}]: image data URLs must be base64"))?;
+        return Ok(json!({
+            "type": "image",
+            "source": {"type": "base64", "media_type": media_type, "data": data},
+        }));
+    }
+    Ok(json!({"type": "image", "source": {"type": "url", "url": url}}))
+}
+
+/// Assistant text followed by its tool calls as `tool_use` blocks.
+fn assistant_blocks(message: &Value, i: usize) -> Result<Vec<Value>, String> {
+    let mut blocks = content_blocks(message.get("content"), i)?;
+    for call in message
+        .get("tool_calls")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        let id = call.get("id").and_then(Value::as_str).unwrap_or_default();
+        let name = call
+            .pointer("/function/name")
+            .and_then(Value::as_str)
+            .ok_or_else(|| format!("messages[{i}]: tool_calls[].function.name is required"))?;
+        let arguments = call
+            .pointer("/function/arguments")
+            .and_then(Value::as_str)
+            .unwrap_or("{}");
+        let input: Value = serde_json::from_str(if arguments.trim().is_empty() {
+            "{}"
+        } else {
+            arguments
+        })
+        .map_err(|e| format!("messages[{i}]: tool call arguments are not JSON: {e}"))?;
+        blocks.push(json!({"type": "tool_use", "id": id, "name": name, "input": input}));
+    }
+    Ok(blocks)
+}
+
+fn tool_result_block(message: &Value, i: usize) -> Result<Value, String> {
+    let id = message
+        .get("tool_call_id")
+        .and_then(Value::as_str)
+        .ok_or_else(|| format!("messages[{i}].tool_call_id is required"))?;
+    Ok(json!({
+        "type": "tool_result",
+        "tool_use_id": id,
+        "content": text_of(message.get("content")),
+    }))
+}
+
+fn translate_tools(tools: &[Value]) -> Result<Vec<Value>, String> {
+    tools
+        .iter()
+        .map(|tool| {
+            let function = tool
+                .get("function")
+                .ok_or("only 'function' tools are supported")?;
+            let name = function
+                .get("name")
+                .and_then(Value::as_str)
+                .ok_or("tools[].function.name is required")?;
+            let mut out = json!({
+                "name": name,
+                "input_schema": function
+                    .get("parameters")
+                    .cloned()
+                    .unwrap_or_else(|| json!({"type": "object", "properties": {}})),
+            });
+            if let Some(description) = function.get("description") {
+                out["description"] = description.clone();
+            }
+            Ok(out)
+        })
+        .collect()
+}
+
+// --- Response translation ---
+
+async fn translate_response(response: Response, include_usage: bool) -> Response {
+    let (mut parts, body) = response.into_parts();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    let event_stream = parts
+        .headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/event-stream"));
+
+    if parts.status.is_success() && event_stream {
+        let mut translator = ChunkTranslator::new(include_usage);
+        let stream = body.into_data_stream().filter_map(move |chunk| {
+            let out = chunk.map(|c| Bytes::from(translator.push(&c)));
+            async move {
+                match out {
+                    Ok(b) if b.is_empty() => None,
+                    other => Some(other),
+                }
+            }
+        });
+        return Response::from_parts(parts, axum::body::Body::from_stream(stream));
+    }
+
+    let bytes = match axum::body::to_bytes(body, MAX_BODY_SIZE).await {
+        Ok(b) => b,
+        Err(e) => {
+            warn!(error = %e, "failed to read response for translation");
+            return error_response(
+                StatusCode::BAD_GATEWAY,
+                "api_error",
+                &format!("upstream response could not be read: {e}"),
+            );
+        }
+    };
+    let Ok(value) = serde_json::from_slice::<Value>(&bytes) else {
+        // Not JSON (e.g. a plain-text gateway error): forward as is
+        return Response::from_parts(parts, axum::body::Body::from(bytes));
+    };
+    let translated = if parts.status.is_success() {
+        from_messages_response(&value)
+    } else {
+        from_error_body(&value)
+    };
+    parts.headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    Response::from_parts(parts, axum::body::Body::from(translated.to_string()))
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn finish_reason(stop_reason: Option<&str>) -> Value {
+    match stop_reason {
+        Some("end_turn") | Some("stop_sequence") | Some("pause_turn") => json!("stop"),
+        Some("max_tokens") => json!("length"),
+        Some("tool_use") => json!("tool_calls"),
+        Some("refusal") => json!("content_filter"),
+        Some(other) => json!(other),
+        None => Value::Null,
+    }
+}
+
+/// OpenAI `usage` from an Anthropic `usage` object. Cached input counts as
+/// prompt tokens.
+fn openai_usage(usage: Option<&Value>) -> Value {
+    let field = |name: &str| {
+        usage
+            .and_then(|u| u.get(name))
+            .and_then(Value::as_u64)
+            .unwrap_or(0)
+    };
+    let cached = field("cache_read_input_tokens");
+    let prompt = field("input_tokens") + field("cache_creation_input_tokens") + cached;
+    let completion = field("output_tokens");
+    json!({
+        "prompt_tokens": prompt,
+        "completion_tokens": completion,
+        "total_tokens": prompt + completion,
+        "prompt_tokens_details": {"cached_tokens": cached},
+    })
+}
+
+fn completion_id(message_id: Option<&str>) -> String {
+    format!("chatcmpl-{}", message_id.unwrap_or("unknown"))
+}
+
+/// Translate a Messages response into a `chat.completion`.
+pub fn from_messages_response(message: &Value) -> Value {
+    let mut text = String::new();
+    let mut tool_calls = Vec::new();
+    for block in message
+        .get("content")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        match block.get("type").and_then(Value::as_str) {
+            Some("text") => text.push_str(block["text"].as_str().unwrap_or_default()),
+            Some("tool_use") => tool_calls.push(json!({
+                "id": block["id"],
+                "type": "function",
+                "function": {
+                    "name": block["name"],
+                    "arguments": block.get("input").unwrap_or(&json!({})).to_string(),
+                },
+            })),
+            _ => {}
+        }
+    }
+    let mut reply = json!({
+        "role": "assistant",
+        "content": if text.is_empty() && !tool_calls.is_empty() { Value::Null } else { json!(text) },
+    });
+    if !tool_calls.is_empty() {
+        reply["tool_calls"] = Value::Array(tool_calls);
+    }
+    json!({
+        "id": completion_id(message.get("id").and_then(Value::as_str)),
+        "object": "chat.completion",
+        "created": unix_now(),
+        "model": message.get("model").cloned().unwrap_or(Value::Null),
+        "choices": [{
+            "index": 0,
+            "message": reply,
+            "finish_reason": finish_reason(message.get("stop_reason").and_then(Value::as_str)),
+        }],
+        "usage": openai_usage(message.get("usage")),
+    })
+}
+
+/// Translate an Anthropic (or proxy) error body into OpenAI's error shape.
+fn from_error_body(body: &Value) -> Value {
+    let message = body
+        .pointer("/error/message")
+        .and_then(Value::as_str)
+        .unwrap_or("upstream error");
+    let error_type = body
+        .pointer("/error/type")
+        .and_then(Value::as_str)
+        .unwrap_or("api_error");
+    json!({
+        "error": {
+            "message": message,
+            "type": error_type,
+            "param": null,
+            "code": null,
+        }
+    })
+}
+
+/// Turns Messages SSE into Chat Completions SSE, chunk by chunk.
+pub struct ChunkTranslator {
+    include_usage: bool,
+    /// Incomplete trailing line carried to the next chunk
+    buf: Vec<u8>,
+    id: String,
+    model: Value,
+    created: u64,
+    /// Content block index -> position in the OpenAI `tool_calls` array
+    tool_calls: HashMap<u64, usize>,
+    usage: Map<String, Value>,
+}
+
+impl ChunkTranslator {
+    pub fn new(include_usage: bool) -> Self {
+        Self {
+            include_usage,
+            buf: Vec::new(),
+            id: completion_id(None),
+            model: Value::Null,
+            created: unix_now(),
+            tool_calls: HashMap::new(),
+            usage: Map::new(),
+        }
+    }
+
+    /// Feed upstream bytes; returns the translated bytes ready to send.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<u8> {
+        self.buf.extend_from_slice(chunk);
+        let Some(end) = self.buf.iter().rposition(|b| *b == b'\n') else {
+            return Vec::new();
+        };
+        let lines: Vec<u8> = self.buf.drain(..=end).collect();
+        let mut out = Vec::new();
+        for line in lines.split(|b| *b == b'\n') {
+            if let Some(data) = line.strip_prefix(b"data:")
+                && let Ok(event) = serde_json::from_slice::<Value>(data.trim_ascii())
+            {
+                self.translate_event(&event, &mut out);
+            }
+        }
+        out
+    }
+
+    fn emit(&self, out: &mut Vec<u8>, value: Value) {
+        out.extend_from_slice(b"data: ");
+        out.extend_from_slice(value.to_string().as_bytes());
+        out.extend_from_slice(b"\n\n");
+    }
+
+    fn emit_delta(&self, out: &mut Vec<u8>, delta: Value, finish_reason: Value) {
+        self.emit(
+            out,
+            json!({
+                "id": self.id,
+                "object": "chat.completion.chunk",
+                "created": self.created,
+                "model": self.model,
+                "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}],
+            }),
+        );
+    }
+
+    fn translate_event(&mut self, event: &Value, out: &mut Vec<u8>) {
+        match event.get("type").and_then(Value::as_str) {
+            Some("message_start") => {
+                let message = &event["message"];
+                self.id = completion_id(message.get("id").and_then(Value::as_str));
+                self.model = message.get("model").cloned().unwrap_or(Value::Null);
+                if let Some(Value::Object(usage)) = message.get("usage") {
+                    self.usage.extend(usage.clone());
+                }
+                self.emit_delta(
+                    out,
+                    json!({"role": "assistant", "content": ""}),
+                    Value::Null,
+                );
+            }
+            Some("content_block_start") => {
+                let block = &event["content_block"];
+                if block.get("type").and_then(Value::as_str) == Some("tool_use") {
+                    let position = self.tool_calls.len();
+                    let index = event["index"].as_u64().unwrap_or_default();
+                    self.tool_calls.insert(index, position);
+                    self.emit_delta(
+                        out,
+                        json!({"tool_calls": [{
+                            "index": position,
+                            "id": block["id"],
+                            "type": "function",
+                            "function": {"name": block["name"], "arguments": ""},
+                        }]}),
+                        Value::Null,
+                    );
+                }
+            }
+            Some("content_block_delta") => {
+                let delta = &event["delta"];
+                match delta.get("type").and_then(Value::as_str) {
+                    Some("text_delta") => {
+                        self.emit_delta(out, json!({"content": delta["text"]}), Value::Null);
+                    }
+                    Some("input_json_delta") => {
+                        let index = event["index"].as_u64().unwrap_or_default();
+                        if let Some(position) = self.tool_calls.get(&index) {
+                            self.emit_delta(
+                                out,
+                                json!({"tool_calls": [{
+                                    "index": position,
+                                    "function": {"arguments": delta["partial_json"]},
+                                }]}),
+                                Value::Null,
+                            );
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Some("message_delta") => {
+                if let Some(Value::Object(usage)) = event.get("usage") {
+                    self.usage.extend(usage.clone());
+                }
+                let reason =
+                    finish_reason(event.pointer("/delta/stop_reason").and_then(Value::as_str));
+                self.emit_delta(out, json!({}), reason);
+            }
+            Some("message_stop") => {
+                if self.include_usage {
+                    let usage = Value::Object(self.usage.clone());
+                    self.emit(
+                        out,
+                        json!({
+                            "id": self.id,
+                            "object": "chat.completion.chunk",
+                            "created": self.created,
+                            "model": self.model,
+                            "choices": [],
+                            "usage": openai_usage(Some(&usage)),
+                        }),
+                    );
+                }
+                out.extend_from_slice(b"data: [DONE]\n\n");
+            }
+            Some("error") => self.emit(out, from_error_body(event)),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translates_system_images_and_parameters() {
+        let chat = json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [
+                {"role": "system", "content": "Be brief."},
+                {"role": "developer", "content": [{"type": "text", "text": "Use metric units."}]},
+                {"role": "user", "content": [
+                    {"type": "text", "text": "What is this?"},
+                    {"type": "image_url", "image_url": {"url": "data:image/png;base64,iVBORw0"}},
+                    {"type": "image_url", "image_url": {"url": "https://example.com/cat.jpg"}},
+                ]},
+            ],
+            "max_completion_tokens": 256,
+            "temperature": 0.2,
+            "stop": "END",
+            "stream": true,
+            "user": "u-1",
+        });
+        let req = to_messages_request(&chat).unwrap();
+        assert_eq!(req["system"], "Be brief.\n\nUse metric units.");
+        assert_eq!(req["max_tokens"], 256);
+        assert_eq!(req["temperature"], 0.2);
+        assert_eq!(req["stop_sequences"], json!(["END"]));
+        assert_eq!(req["stream"], true);
+        assert_eq!(req["metadata"]["user_id"], "u-1");
+
+        let content = req["messages"][0]["content"].as_array().unwrap();
+        assert_eq!(content[0], json!({"type": "text", "text": "What is this?"}));
+        assert_eq!(
+            content[1]["source"],
+            json!({"type": "base64", "media_type": "image/png", "data": "iVBORw0"})
+        );
+        assert_eq!(
+            content[2]["source"],
+            json!({"type": "url", "url": "https://example.com/cat.jpg"})
+        );
+    }
+
+    #[test]
+    fn translates_tool_calls_and_results() {
+        let chat = json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [
+                {"role": "user", "content": "Weather in Paris and Rome?"},
+                {"role": "assistant", "content": null, "tool_calls": [
+                    {"id": "call_1", "type": "function",
+                     "function": {"name": "weather", "arguments": "{\"city\":\"Paris\"}"}},
+                    {"id": "call_2", "type": "function",
+                     "function": {"name": "weather", "arguments": "{\"city\":\"Rome\"}"}},
+                ]},
+                {"role": "tool", "tool_call_id": "call_1", "content": "18C"},
+                {"role": "tool", "tool_call_id": "call_2", "content": "24C"},
+            ],
+            "tools": [{"type": "function", "function": {
+                "name": "weather",
+                "description": "Current weather",
+                "parameters": {"type": "object", "properties": {"city": {"type": "string"}}},
+            }}],
+            "tool_choice": {"type": "function", "function": {"name": "weather"}},
+        });
+        let req = to_messages_request(&chat).unwrap();
+        assert_eq!(req["max_tokens"], DEFAULT_MAX_TOKENS);
+        assert_eq!(req["tools"][0]["name"], "weather");
+        assert_eq!(req["tools"][0]["input_schema"]["type"], "object");
+        assert_eq!(
+            req["tool_choice"],
+            json!({"type": "tool", "name": "weather"})
+        );
+
+        let messages = req["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 3, "tool results fold into one user turn");
+        assert_eq!(
+            messages[1]["content"][1],
+            json!({"type": "tool_use", "id": "call_2", "name": "weather", "input": {"city": "Rome"}})
+        );
+        assert_eq!(messages[2]["role"], "user");
+        assert_eq!(
+            messages[2]["content"][1],
+            json!({"type": "tool_result", "tool_use_id": "call_2", "content": "24C"})
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_requests() {
+        assert!(to_messages_request(&json!({"messages": []})).is_err());
+        assert!(to_messages_request(&json!({"model": "m", "messages": [], "n": 2})).is_err());
+        assert!(
+            to_messages_request(&json!({"model": "m", "messages": [
+                {"role": "user", "content": [{"type": "image_url", "image_url": {"url": "data:image/png,raw"}}]}
+            ]}))
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn translates_message_response() {
+        let message = json!({
+            "id": "msg_1",
+            "model": "claude-sonnet-4-5",
+            "content": [
+                {"type": "text", "text": "Checking."},
+                {"type": "tool_use", "id": "toolu_1", "name": "weather", "input": {"city": "Paris"}},
+            ],
+            "stop_reason": "tool_use",
+            "usage": {"input_tokens": 10, "cache_read_input_tokens": 5, "output_tokens": 7},
+        });
+        let chat = from_messages_response(&message);
+        assert_eq!(chat["id"], "chatcmpl-msg_1");
+        assert_eq!(chat["object"], "chat.completion");
+        let choice = &chat["choices"][0];
+        assert_eq!(choice["finish_reason"], "tool_calls");
+        assert_eq!(choice["message"]["content"], "Checking.");
+        assert_eq!(
+            choice["message"]["tool_calls"][0]["function"]["arguments"],
+            r#"{"city":"Paris"}"#
+        );
+        assert_eq!(chat["usage"]["prompt_tokens"], 15);
+        assert_eq!(chat["usage"]["total_tokens"], 22);
+    }
+
+    fn data_lines(out: &[u8]) -> Vec<String> {
+        String::from_utf8(out.to_vec())
+            .unwrap()
+            .lines()
+            .filter_map(|l| l.strip_prefix("data: ").map(str::to_string))
+            .collect()
+    }
+
+    #[test]
+    fn translates_stream_events() {
+        let mut t = ChunkTranslator::new(true);
+        let mut out = Vec::new();
+        for event in [
+            r#"{"type":"message_start","message":{"id":"msg_9","model":"claude-haiku-4-5","usage":{"input_tokens":3,"output_tokens":1}}}"#,
+            r#"{"type":"content_block_start","index":0,"content_block":{"type":"text","text":""}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hi"}}"#,
+            r#"{"type":"content_block_start","index":1,"content_block":{"type":"tool_use","id":"toolu_1","name":"lookup","input":{}}}"#,
+            r#"{"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":"{\"q\":1}"}}"#,
+            r#"{"type":"message_delta","delta":{"stop_reason":"tool_use"},"usage":{"output_tokens":9}}"#,
+            r#"{"type":"message_stop"}"#,
+        ] {
+            // Split each event across two pushes to exercise line buffering
+            let sse = format!("event: x\ndata: {event}\n\n");
+            let (a, b) = sse.split_at(sse.len() / 2);
+            out.extend(t.push(a.as_bytes()));
+            out.extend(t.push(b.as_bytes()));
+        }
+        let lines = data_lines(&out);
+        assert_eq!(lines.last().unwrap(), "[DONE]");
+        let chunks: Vec<Value> = lines[..lines.len() - 1]
+            .iter()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+
+        assert_eq!(chunks[0]["id"], "chatcmpl-msg_9");
+        assert_eq!(chunks[0]["choices"][0]["delta"]["role"], "assistant");
+        assert_eq!(chunks[1]["choices"][0]["delta"]["content"], "Hi");
+        assert_eq!(
+            chunks[2]["choices"][0]["delta"]["tool_calls"][0]["id"],
+            "toolu_1"
+        );
+        assert_eq!(
+            chunks[3]["choices"][0]["delta"]["tool_calls"][0]["function"]["arguments"],
+            r#"{"q":1}"#
+        );
+        assert_eq!(chunks[4]["choices"][0]["finish_reason"], "tool_calls");
+        assert_eq!(chunks[5]["choices"], json!([]));
+        assert_eq!(chunks[5]["usage"]["completion_tokens"], 9);
+        assert_eq!(chunks[5]["usage"]["prompt_tokens"], 3);
+    }
+}
@@ -7,8 +7,9 @@
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use bytes::Bytes;
-use futures_util::Stream;
+use futures_util::{Stream, StreamExt};
 use provider::Provider;
+use std::net::SocketAddr;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
@@ -16,11 +17,21 @@ use std::time::{Duration, Instant};
 use tokio::time::Sleep;
 use tracing::{error, info, instrument, warn};
 
-/// Maximum retry attempts for upstream timeouts (spec: 2 retries = 3 total attempts)
-const MAX_UPSTREAM_ATTEMPTS: u32 = 3;
+use crate::clients::{ANONYMOUS_CLIENT, AuthError, ClientRegistry, UNAUTHENTICATED_CLIENT};
+use crate::limits::{LimitExceeded, UsagePermit};
+use crate::retry::{RetryClass, RetryPolicy};
+use crate::routing::{DEFAULT_ROUTE, ModelRouter, PathRouter};
+use crate::tailnet::{TailnetAuthorizer, TailnetError, TailnetIdentity};
+use crate::usage::{OTHER_MODEL, UsageContext, UsageTap};
 
-/// Fixed backoff between upstream timeout retries (spec: 100ms)
-const UPSTREAM_RETRY_DELAY: Duration = Duration::from_millis(100);
+/// Upper bound on bytes held back while waiting for the first SSE event. An
+/// upstream that streams this much without a `message_start` is past the
+/// point where a retry would be transparent anyway.
+const MAX_FIRST_EVENT_BUFFER: usize = 1024 * 1024;
+
+/// Response header naming the provider tier that served a request (only set
+/// by providers with tiers, such as the fallback chain)
+pub(crate) const TIER_HEADER: &str = "x-proxy-tier";
 
 /// Maximum request body size (spec: 10 MiB)
 pub const MAX_BODY_SIZE: usize = 10 * 1024 * 1024;
@@ -51,8 +62,29 @@ pub struct ProxyState {
     /// mode (each attempt uses a different account). Set to 1 in passthrough mode
     /// (no failover, just forward the error).
     pub max_failover_attempts: usize,
+    /// Hold successful SSE responses until the first `message_start` event so
+    /// an upstream failure before it can be retried instead of reaching the
+    /// client as a truncated stream.
+    pub buffer_first_event: bool,
+    /// Parse token usage out of successful responses for
+    /// `proxy_tokens_total` and the access log
+    pub usage_accounting: bool,
+    /// Which failed attempts are retried on the same account, and how
+    pub retry: RetryPolicy,
+    /// Callers allowed to use the proxy (empty: no authentication)
+    pub clients: Arc<ClientRegistry>,
+    /// Tailnet identity authorization (`None`: callers are not identified)
+    pub tailnet: Option<Arc<TailnetAuthorizer>>,
+    /// Path prefix routes (`None`: every path uses the settings above)
+    pub routes: Option<Arc<PathRouter>>,
+    /// Model aliases and per-model routes (`None`: every request uses
+    /// the provider of its path route)
+    pub models: Option<Arc<ModelRouter>>,
 }
 
+/// Response body stream after idle-timeout wrapping.
+pub(crate) type BodyStream = provider::BodyStream;
+
 /// RAII guard that decrements the in-flight counter when dropped, ensuring the
 /// counter stays accurate even if the handler returns early or panics.
 struct InFlightGuard(Arc<std::sync::atomic::AtomicU64>);
@@ -63,6 +95,40 @@ impl Drop for InFlightGuard {
     }
 }
 
+/// Counts a tailnet caller's request once when dropped, against the account
+/// it was last sent with (`-` in passthrough mode). Nothing is recorded if
+/// the request never reached an account.
+struct TailnetRequestGuard<'a> {
+    identity: &'a TailnetIdentity,
+    account_id: Option<String>,
+}
+
+impl Drop for TailnetRequestGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(ref account_id) = self.account_id {
+            crate::metrics::record_tailnet_request(
+                &self.identity.node,
+                &self.identity.user,
+                account_id,
+            );
+        }
+    }
+}
+
+/// Releases a pool account back to the provider when dropped. Moved into the
+/// response body for streamed responses so the account stays in flight until
+/// the last chunk is sent or the client disconnects.
+struct AccountLease {
+    provider: Arc<dyn Provider>,
+    account_id: String,
+}
+
+impl Drop for AccountLease {
+    fn drop(&mut self) {
+        self.provider.release_account(&self.account_id);
+    }
+}
+
 // Wraps a byte stream with an idle timeout that resets on each received chunk.
 // If no data arrives within the timeout window, the stream terminates cleanly
 // (returns None). This protects against dead upstream connections mid-stream
@@ -122,11 +188,23 @@ where
     }
 }
 
-/// JSON error response per spec: {"error":{"type":"proxy_error","message":"...","request_id":"req_..."}}
+/// JSON error response per spec: {"type":"error","error":{"type":"proxy_error","message":"...","request_id":"req_..."}}
 fn error_response(status: StatusCode, message: &str, request_id: &str) -> Response {
+    typed_error_response(status, "proxy_error", message, request_id)
+}
+
+/// Error response with an Anthropic error type, so SDK clients classify it
+/// the same way as the equivalent upstream error.
+fn typed_error_response(
+    status: StatusCode,
+    error_type: &str,
+    message: &str,
+    request_id: &str,
+) -> Response {
     let body = serde_json::json!({
+        "type": "error",
         "error": {
-            "type": "proxy_error",
+            "type": error_type,
             "message": message,
             "request_id": request_id,
         }
@@ -139,15 +217,43 @@ fn error_response(status: StatusCode, message: &str, request_id: &str) -> Respon
         .into_response()
 }
 
+/// 429 `rate_limit_error` for a client over one of its own limits. Carries
+/// `retry-after` in whole seconds, as upstream 429s do.
+fn client_limited_response(client: &str, limit: &LimitExceeded, request_id: &str) -> Response {
+    let mut response = typed_error_response(
+        StatusCode::TOO_MANY_REQUESTS,
+        "rate_limit_error",
+        &format!("client '{client}': {limit}"),
+        request_id,
+    );
+    let retry_after = limit.retry_after().as_secs_f64().ceil() as u64;
+    response.headers_mut().insert(
+        axum::http::header::RETRY_AFTER,
+        axum::http::HeaderValue::from(retry_after.max(1)),
+    );
+    response
+}
+
 /// Proxy an inbound request to upstream with header injection, retries, and failover.
 ///
-/// Retry strategy per spec: UpstreamTimeout gets 2 retries with 100ms fixed backoff.
+/// Retry strategy: `state.retry` decides which failed attempts are retried on the
+/// same account (by default only timeouts, 3 attempts in total).
 /// Failover strategy: QuotaExceeded triggers account switch and re-send; Permanent
 /// errors disable the account and return the error; Transient errors are returned.
-#[instrument(skip_all, fields(request_id = %request_id, method = %request.method(), path = %request.uri().path()))]
+#[instrument(skip_all, fields(
+    request_id = %request_id,
+    method = %request.method(),
+    path = %request.uri().path(),
+    route = tracing::field::Empty,
+    client = tracing::field::Empty,
+    client_labels = tracing::field::Empty,
+    tailnet_node = tracing::field::Empty,
+    tailnet_user = tracing::field::Empty,
+    tailnet_tags = tracing::field::Empty,
+))]
 pub async fn proxy_request(
     state: &ProxyState,
-    request: axum::http::Request<axum::body::Body>,
+    mut request: axum::http::Request<axum::body::Body>,
     request_id: String,
 ) -> Response {
     let start = Instant::now();
@@ -163,12 +269,96 @@ pub async fn proxy_request(
     let method_str = method.to_string();
     let uri = request.uri().clone();
 
-    // Build the upstream URL by appending the request path and query
-    let upstream_url = if let Some(pq) = uri.path_and_query() {
-        format!("{}{}", state.upstream_url.trim_end_matches('/'), pq)
+    // The path route sets the upstream, provider, timeout, and body limit,
+    // and labels every metric this request records
+    let path_route = state.routes.as_ref().and_then(|r| r.route(uri.path()));
+    let route_name = path_route.map_or(DEFAULT_ROUTE, |r| r.name.as_str());
+    if path_route.is_some() {
+        tracing::Span::current().record("route", route_name);
+    }
+    let timeout = path_route.map_or(state.timeout, |r| r.timeout);
+    let max_body_size = path_route.map_or(MAX_BODY_SIZE, |r| r.max_body_size);
+
+    // Authorize the calling machine by tailnet identity first: an unknown
+    // node is turned away whatever key it presents.
+    let tailnet_identity = match state.tailnet {
+        Some(ref tailnet) => {
+            let peer = request
+                .extensions()
+                .get::<axum::extract::ConnectInfo<SocketAddr>>()
+                .map(|info| info.0);
+            match tailnet.authorize(peer, request.headers()).await {
+                Ok(identity) => {
+                    let span = tracing::Span::current();
+                    span.record("tailnet_node", identity.node.as_str());
+                    span.record("tailnet_user", identity.user.as_str());
+                    if !identity.tags.is_empty() {
+                        span.record("tailnet_tags", identity.tags.join(","));
+                    }
+                    Some(identity)
+                }
+                Err(e) => {
+                    let status = match e {
+                        TailnetError::LookupFailed(_) => StatusCode::SERVICE_UNAVAILABLE,
+                        _ => StatusCode::FORBIDDEN,
+                    };
+                    if let TailnetError::NotAllowed(ref identity) = e {
+                        tracing::Span::current().record("tailnet_node", identity.node.as_str());
+                    }
+                    state
+                        .errors_total
+                        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    crate::metrics::record_request(
+                        status.as_u16(),
+                        &method_str,
+                        UNAUTHENTICATED_CLIENT,
+                        route_name,
+                        start.elapsed().as_secs_f64(),
+                    );
+                    warn!(error = %e, "tailnet authorization failed");
+                    return error_response(status, &e.to_string(), &request_id);
+                }
+            }
+        }
+        None => None,
+    };
+
+    // Identify the caller before anything reaches the provider. The key
+    // header is removed here so it is never forwarded upstream.
+    let client = if state.clients.is_enabled() {
+        match state.clients.authenticate(request.headers_mut()) {
+            Ok(client) => Some(client),
+            Err(e) => {
+                let client_name = match e {
+                    AuthError::Expired { ref client } => client.as_str(),
+                    _ => UNAUTHENTICATED_CLIENT,
+                };
+                tracing::Span::current().record("client", client_name);
+                state
+                    .errors_total
+                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                let status = StatusCode::UNAUTHORIZED;
+                crate::metrics::record_request(
+                    status.as_u16(),
+                    &method_str,
+                    client_name,
+                    route_name,
+                    start.elapsed().as_secs_f64(),
+                );
+                warn!(error = %e, "client authentication failed");
+                return error_response(status, &e.to_string(), &request_id);
+            }
+        }
     } else {
-        state.upstream_url.clone()
+        None
     };
+    let client_name = client.map_or(ANONYMOUS_CLIENT, |c| c.name.as_str());
+    tracing::Span::current().record("client", client_name);
+    if let Some(client) = client
+        && !client.labels.is_empty()
+    {
+        tracing::Span::current().record("client_labels", client.labels_field());
+    }
 
     // Collect original request headers, stripping hop-by-hop, host, and
     // content-length. Host carries the proxy's hostname — reqwest sets the
@@ -190,7 +380,7 @@ pub async fn proxy_request(
     }
 
     // Read the request body
-    let body_bytes = match axum::body::to_bytes(request.into_body(), MAX_BODY_SIZE).await {
+    let mut body_bytes = match axum::body::to_bytes(request.into_body(), max_body_size).await {
         Ok(b) => b,
         Err(e) => {
             state
@@ -201,6 +391,8 @@ pub async fn proxy_request(
             crate::metrics::record_request(
                 status.as_u16(),
                 &method_str,
+                client_name,
+                route_name,
                 start.elapsed().as_secs_f64(),
             );
             crate::metrics::record_upstream_error("invalid_request");
@@ -208,9 +400,42 @@ pub async fn proxy_request(
         }
     };
 
+    // Rewrite model aliases, then pick the provider and upstream for the model
+    let route = match state.models {
+        Some(ref router) => {
+            let (rewritten, route) = router.apply(&body_bytes);
+            if let Some(rewritten) = rewritten {
+                body_bytes = rewritten;
+            }
+            route
+        }
+        None => None,
+    };
+    let provider = match (route, path_route) {
+        (Some(r), _) => &r.provider,
+        (None, Some(r)) => &r.provider,
+        (None, None) => &state.provider,
+    };
+    let upstream_base = match (route, path_route) {
+        (Some(r), _) => r.upstream_url.as_str(),
+        (None, Some(r)) => r.upstream_url.as_str(),
+        (None, None) => state.upstream_url.as_str(),
+    };
+
+    // Build the upstream URL by appending the request path and query
+    let upstream_url = match (uri.path_and_query(), path_route) {
+        (Some(pq), Some(r)) => format!(
+            "{}{}",
+            upstream_base.trim_end_matches('/'),
+            r.upstream_path(pq.as_str())
+        ),
+        (Some(pq), None) => format!("{}{}", upstream_base.trim_end_matches('/'), pq),
+        (None, _) => upstream_base.to_string(),
+    };
+
     // Parse body JSON once if the provider needs it (OAuth mode needs body for
     // system prompt injection). The parsed value is re-used across failover attempts.
-    let parsed_body = if state.provider.needs_body() {
+    let parsed_body = if provider.needs_body() {
         match serde_json::from_slice::<serde_json::Value>(&body_bytes) {
             Ok(v) => Some(v),
             Err(e) => {
@@ -221,6 +446,8 @@ pub async fn proxy_request(
                 crate::metrics::record_request(
                     status.as_u16(),
                     &method_str,
+                    client_name,
+                    route_name,
                     start.elapsed().as_secs_f64(),
                 );
                 crate::metrics::record_upstream_error("invalid_request");
@@ -231,9 +458,101 @@ pub async fn proxy_request(
         None
     };
 
+    // Requested model, as the usage label when the response doesn't name one.
+    // The client picks it, so only models the config names are kept as
+    // label values.
+    let request_model = if state.usage_accounting {
+        body_model(parsed_body.as_ref(), &body_bytes).map(|model| {
+            let named = state.models.as_ref().is_some_and(|r| r.names(&model))
+                || client.is_some_and(|c| c.lists_model(&model));
+            if named {
+                model
+            } else {
+                OTHER_MODEL.to_string()
+            }
+        })
+    } else {
+        None
+    };
+
+    // Enforce the client's model allow-list. A body whose models can't be
+    // determined is refused rather than let through unchecked.
+    if let Some(client) = client
+        && client.restricts_models()
+        && let Some(message) = match body_models(parsed_body.as_ref(), &body_bytes) {
+            Some(models) => models
+                .into_iter()
+                .find(|model| !client.allows_model(model))
+                .map(|model| format!("model '{model}' is not allowed for client '{client_name}'")),
+            None => Some(format!(
+                "client '{client_name}' is restricted to specific models, and the request does not name one"
+            )),
+        }
+    {
+        state
+            .errors_total
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let status = StatusCode::FORBIDDEN;
+        crate::metrics::record_request(
+            status.as_u16(),
+            &method_str,
+            client_name,
+            route_name,
+            start.elapsed().as_secs_f64(),
+        );
+        warn!(reason = %message, "model not allowed for client");
+        return error_response(status, &message, &request_id);
+    }
+
+    // Admit the request against the client's own limits. The permit holds a
+    // concurrency slot until the response (or its stream) is finished.
+    let permit: Option<UsagePermit> = match client {
+        Some(client) if client.usage.limits().is_limited() => match client.usage.admit() {
+            Ok(permit) => Some(permit),
+            Err(limit) => {
+                state
+                    .errors_total
+                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                let status = StatusCode::TOO_MANY_REQUESTS;
+                crate::metrics::record_request(
+                    status.as_u16(),
+                    &method_str,
+                    client_name,
+                    route_name,
+                    start.elapsed().as_secs_f64(),
+                );
+                crate::metrics::record_client_limited(client_name, limit.label());
+                warn!(limit = limit.label(), "client over its limit");
+                return client_limited_response(client_name, &limit, &request_id);
+            }
+        },
+        _ => None,
+    };
+    let tracks_tokens = permit
+        .as_ref()
+        .is_some_and(|p| p.usage().limits().tracks_tokens());
+    if state.usage_accounting || tracks_tokens {
+        // Usage is read from the response body, which must arrive uncompressed
+        original_headers.remove(reqwest::header::ACCEPT_ENCODING);
+    }
+
     // Maximum failover attempts equals the pool size (each attempt uses a
     // different account). Passthrough mode uses 1 attempt (no failover).
-    let max_failovers = state.max_failover_attempts;
+    // Pools that grow or shrink at runtime report their current size.
+    let max_failovers = provider
+        .max_failover_attempts()
+        .unwrap_or(match (route, path_route) {
+            (Some(r), _) => r.max_failover_attempts,
+            (None, Some(r)) => r.max_failover_attempts,
+            (None, None) => state.max_failover_attempts,
+        });
+
+    let mut tailnet_request = tailnet_identity
+        .as_ref()
+        .map(|identity| TailnetRequestGuard {
+            identity,
+            account_id: None,
+        });
 
     for failover in 0..max_failovers {
         // Start from original headers each attempt so provider injection is clean.
@@ -242,9 +561,14 @@ pub async fn proxy_request(
         let mut headers = original_headers.clone();
         let mut body_value = parsed_body.clone().unwrap_or(serde_json::Value::Null);
 
-        let account_id = match state
-            .provider
-            .prepare_request(&mut headers, &mut body_value)
+        let account_id = match provider
+            .prepare_request(
+                provider::RequestContext {
+                    client: client_name,
+                },
+                &mut headers,
+                &mut body_value,
+            )
             .await
         {
             Ok(id) => id,
@@ -256,14 +580,26 @@ pub async fn proxy_request(
                 crate::metrics::record_request(
                     status.as_u16(),
                     &method_str,
+                    client_name,
+                    route_name,
                     start.elapsed().as_secs_f64(),
                 );
                 error!(error = %e, "provider prepare_request failed");
                 return error_response(status, &format!("provider error: {e}"), &request_id);
             }
         };
+        if let Some(ref mut tailnet_request) = tailnet_request {
+            tailnet_request.account_id = Some(account_id.as_deref().unwrap_or("-").to_string());
+        }
 
-        let final_body = if state.provider.needs_body() {
+        let tier = account_id.as_deref().and_then(|id| provider.tier(id));
+
+        let lease = account_id.as_ref().map(|id| AccountLease {
+            provider: provider.clone(),
+            account_id: id.clone(),
+        });
+
+        let final_body = if provider.needs_body() {
             serde_json::to_vec(&body_value)
                 .unwrap_or_else(|_| body_bytes.to_vec())
                 .into()
@@ -271,107 +607,53 @@ pub async fn proxy_request(
             body_bytes.clone()
         };
 
-        // Timeout retry loop within this failover attempt
+        // Retry loop within this failover attempt (same account)
         let mut last_error_response = None;
+        let mut early_stream_failure = None;
 
-        for attempt in 0..MAX_UPSTREAM_ATTEMPTS {
-            if attempt > 0 {
-                warn!(attempt, "retrying after upstream timeout");
-                tokio::time::sleep(UPSTREAM_RETRY_DELAY).await;
-            }
-
+        for attempt in 0..state.retry.max_attempts {
             let req = state
                 .client
                 .request(method.clone(), &upstream_url)
                 .headers(headers.clone())
                 .body(final_body.clone());
 
-            let send_result = tokio::time::timeout(state.timeout, req.send()).await;
+            let send_result = tokio::time::timeout(timeout, req.send()).await;
             match send_result {
                 Ok(Ok(upstream_response)) => {
                     let status = upstream_response.status();
 
+                    // Let the provider see every response's headers (rate-limit
+                    // state) before any classification decisions are made.
+                    if let Some(ref acct) = account_id {
+                        provider
+                            .observe_response(acct, status.as_u16(), upstream_response.headers())
+                            .await;
+                    }
+
                     // For error responses that may need classification (quota/auth
                     // errors), buffer the body. For success or non-classifiable
                     // errors, stream directly.
                     if status.is_client_error() || status.is_server_error() {
-                        if let Some(ref acct) = account_id {
-                            // Buffer error body for classification
-                            let resp_headers = upstream_response.headers().clone();
-                            let error_body = upstream_response.bytes().await.unwrap_or_default();
-                            let error_body_str = String::from_utf8_lossy(&error_body).to_string();
-
-                            let classification = state
-                                .provider
-                                .classify_error(status.as_u16(), &error_body_str);
-
-                            match classification {
-                                provider::ErrorClassification::QuotaExceeded => {
-                                    warn!(
-                                        account_id = acct,
-                                        failover, "quota exhausted, failing over to next account"
-                                    );
-                                    let _ = state.provider.report_error(acct, classification).await;
-                                    crate::metrics::record_upstream_error("quota_exhausted");
-                                    crate::metrics::record_pool_quota_exhaustion(acct);
-                                    crate::metrics::record_pool_failover(acct, "quota_exhausted");
-                                    crate::metrics::record_pool_account_status(
-                                        acct,
-                                        "cooling_down",
-                                    );
-                                    // Store response in case this is the last failover
-                                    last_error_response = Some((status, resp_headers, error_body));
-                                    break; // exit timeout retry loop, continue failover loop
-                                }
-                                provider::ErrorClassification::Permanent => {
-                                    warn!(account_id = acct, "permanent error, disabling account");
-                                    let _ = state.provider.report_error(acct, classification).await;
-                                    crate::metrics::record_upstream_error("permanent");
-                                    crate::metrics::record_pool_account_status(acct, "disabled");
-                                    // Return error to client immediately
-                                    let elapsed = start.elapsed();
-                                    crate::metrics::record_request(
-                                        status.as_u16(),
-                                        &method_str,
-                                        elapsed.as_secs_f64(),
-                                    );
-                                    state
-                                        .errors_total
-                                        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-                                    return build_buffered_response(
-                                        status,
-                                        &resp_headers,
-                                        error_body,
-                                    );
-                                }
-                                provider::ErrorClassification::Transient => {
-                                    // Return error to client (existing timeout retry
-                                    // handles transport-level retries)
-                                    let elapsed = start.elapsed();
-                                    crate::metrics::record_request(
-                                        status.as_u16(),
-                                        &method_str,
-                                        elapsed.as_secs_f64(),
-                                    );
-                                    info!(
-                                        status = status.as_u16(),
-                                        latency_ms = elapsed.as_millis() as u64,
-                                        "request completed (transient error)"
-                                    );
-                                    return build_buffered_response(
-                                        status,
-                                        &resp_headers,
-                                        error_body,
-                                    );
-                                }
-                            }
-                        } else {
+                        let retry_class = RetryClass::from_status(status.as_u16())
+                            .filter(|class| state.retry.retries(*class));
+
+                        if account_id.is_none() && retry_class.is_none() {
                             // Passthrough mode: no account, stream error response directly
-                            let resp_headers = upstream_response.headers().clone();
+                            let mut resp_headers = upstream_response.headers().clone();
+                            let body = provider.wrap_response(
+                                account_id.as_deref(),
+                                parsed_body.as_ref().unwrap_or(&serde_json::Value::Null),
+                                status.as_u16(),
+                                &mut resp_headers,
+                                idle_body(upstream_response, timeout),
+                            );
                             let elapsed = start.elapsed();
                             crate::metrics::record_request(
                                 status.as_u16(),
                                 &method_str,
+                                client_name,
+                                route_name,
                                 elapsed.as_secs_f64(),
                             );
                             info!(
@@ -382,21 +664,174 @@ pub async fn proxy_request(
                             return build_streaming_response(
                                 status,
                                 &resp_headers,
-                                upstream_response,
+                                body,
                                 &request_id,
-                                state.timeout,
+                                lease,
+                                permit,
                             );
                         }
+
+                        // Buffer error body for classification
+                        let resp_headers = upstream_response.headers().clone();
+                        let error_body = upstream_response.bytes().await.unwrap_or_default();
+                        let error_body_str = String::from_utf8_lossy(&error_body).to_string();
+
+                        let classification = provider.classify_error(
+                            account_id.as_deref(),
+                            status.as_u16(),
+                            &error_body_str,
+                        );
+
+                        // Transient errors may be retried on the same account; a
+                        // quota 429 fails over instead
+                        if classification == provider::ErrorClassification::Transient
+                            && let Some(class) = retry_class
+                        {
+                            match state.retry.next_delay(attempt, start.elapsed()) {
+                                Some(delay) => {
+                                    back_off(class.label(), attempt, delay).await;
+                                    continue;
+                                }
+                                None => {
+                                    crate::metrics::record_upstream_retries_exhausted(class.label())
+                                }
+                            }
+                        }
+
+                        match (&classification, account_id.as_ref()) {
+                            (provider::ErrorClassification::QuotaExceeded, Some(acct)) => {
+                                warn!(
+                                    account_id = acct,
+                                    failover, "quota exhausted, failing over to next account"
+                                );
+                                let _ = provider.report_error(acct, classification).await;
+                                crate::metrics::record_upstream_error("quota_exhausted");
+                                crate::metrics::record_pool_quota_exhaustion(acct);
+                                crate::metrics::record_pool_failover(acct, "quota_exhausted");
+                                crate::metrics::record_pool_account_status(acct, "cooling_down");
+                                // Store response in case this is the last failover
+                                last_error_response = Some((status, resp_headers, error_body));
+                                break; // exit retry loop, continue failover loop
+                            }
+                            (provider::ErrorClassification::Permanent, Some(acct)) => {
+                                warn!(account_id = acct, "permanent error, disabling account");
+                                let _ = provider.report_error(acct, classification).await;
+                                crate::metrics::record_upstream_error("permanent");
+                                crate::metrics::record_pool_account_status(acct, "disabled");
+                                // Return error to client immediately
+                                let elapsed = start.elapsed();
+                                crate::metrics::record_request(
+                                    status.as_u16(),
+                                    &method_str,
+                                    client_name,
+                                    route_name,
+                                    elapsed.as_secs_f64(),
+                                );
+                                state
+                                    .errors_total
+                                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                                let mut resp_headers = resp_headers;
+                                tag_tier(&mut resp_headers, tier);
+                                return build_buffered_response(status, &resp_headers, error_body);
+                            }
+                            _ => {
+                                // Return error to client (not retryable, or
+                                // retries used up)
+                                let elapsed = start.elapsed();
+                                crate::metrics::record_request(
+                                    status.as_u16(),
+                                    &method_str,
+                                    client_name,
+                                    route_name,
+                                    elapsed.as_secs_f64(),
+                                );
+                                info!(
+                                    status = status.as_u16(),
+                                    latency_ms = elapsed.as_millis() as u64,
+                                    "request completed (transient error)"
+                                );
+                                let mut resp_headers = resp_headers;
+                                tag_tier(&mut resp_headers, tier);
+                                return build_buffered_response(status, &resp_headers, error_body);
+                            }
+                        }
                     }
 
                     // Success: stream the response body. This is critical for SSE
                     // (Server-Sent Events) from the Anthropic API where Claude
                     // responses are streamed in real-time.
-                    let resp_headers = upstream_response.headers().clone();
+                    let mut resp_headers = upstream_response.headers().clone();
+                    let mut body = idle_body(upstream_response, timeout);
+
+                    // Optionally hold the stream until `message_start`: nothing
+                    // has reached the client yet, so a failure here can be retried.
+                    if state.buffer_first_event && is_event_stream(&resp_headers) {
+                        match buffer_first_event(body).await {
+                            Ok(buffered) => body = buffered,
+                            Err(reason) => {
+                                warn!(
+                                    account_id = account_id.as_deref().unwrap_or("-"),
+                                    attempt, reason, "upstream stream failed before first event"
+                                );
+                                crate::metrics::record_upstream_error("early_stream_failure");
+                                early_stream_failure = Some(reason);
+                                // Always retryable: nothing reached the client
+                                match state.retry.next_delay(attempt, start.elapsed()) {
+                                    Some(delay) => {
+                                        back_off("early_stream_failure", attempt, delay).await;
+                                        continue;
+                                    }
+                                    None => break,
+                                }
+                            }
+                        }
+                    }
+
+                    // Read token usage as the body streams past: charged to
+                    // the client's daily budget, and counted and access-logged
+                    // with usage accounting
+                    if state.usage_accounting || tracks_tokens {
+                        let budget = permit
+                            .as_ref()
+                            .filter(|_| tracks_tokens)
+                            .map(|p| p.usage().clone());
+                        let accounting = state.usage_accounting.then(|| UsageContext {
+                            request_id: request_id.clone(),
+                            client: client_name.to_string(),
+                            account_id: account_id.clone(),
+                            request_model: request_model.clone(),
+                            status: status.as_u16(),
+                            start,
+                        });
+                        body = Box::pin(UsageTap::new(
+                            body,
+                            &resp_headers,
+                            Box::new(move |usage| {
+                                if let Some(budget) = budget {
+                                    budget.record_tokens(&usage.tokens);
+                                }
+                                if let Some(accounting) = accounting {
+                                    accounting.record(&usage);
+                                }
+                            }),
+                        ));
+                    }
+
+                    let body = provider.wrap_response(
+                        account_id.as_deref(),
+                        parsed_body.as_ref().unwrap_or(&serde_json::Value::Null),
+                        status.as_u16(),
+                        &mut resp_headers,
+                        body,
+                    );
+                    tag_tier(&mut resp_headers, tier);
+
                     let elapsed = start.elapsed();
                     crate::metrics::record_request(
                         status.as_u16(),
                         &method_str,
+                        client_name,
+                        route_name,
                         elapsed.as_secs_f64(),
                     );
                     info!(
@@ -407,12 +842,25 @@ pub async fn proxy_request(
                     return build_streaming_response(
                         status,
                         &resp_headers,
-                        upstream_response,
+                        body,
                         &request_id,
-                        state.timeout,
+                        lease,
+                        permit,
                     );
                 }
                 Ok(Err(e)) => {
+                    if e.is_connect() && state.retry.retries(RetryClass::Connect) {
+                        match state.retry.next_delay(attempt, start.elapsed()) {
+                            Some(delay) => {
+                                warn!(error = %e, "upstream connect failed");
+                                back_off(RetryClass::Connect.label(), attempt, delay).await;
+                                continue;
+                            }
+                            None => crate::metrics::record_upstream_retries_exhausted(
+                                RetryClass::Connect.label(),
+                            ),
+                        }
+                    }
                     state
                         .errors_total
                         .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
@@ -420,6 +868,8 @@ pub async fn proxy_request(
                     crate::metrics::record_request(
                         err_status.as_u16(),
                         &method_str,
+                        client_name,
+                        route_name,
                         start.elapsed().as_secs_f64(),
                     );
                     crate::metrics::record_upstream_error("connection");
@@ -431,9 +881,18 @@ pub async fn proxy_request(
                     );
                 }
                 Err(_elapsed) => {
-                    if attempt < MAX_UPSTREAM_ATTEMPTS - 1 {
-                        continue;
+                    if state.retry.retries(RetryClass::Timeout) {
+                        match state.retry.next_delay(attempt, start.elapsed()) {
+                            Some(delay) => {
+                                back_off(RetryClass::Timeout.label(), attempt, delay).await;
+                                continue;
+                            }
+                            None => crate::metrics::record_upstream_retries_exhausted(
+                                RetryClass::Timeout.label(),
+                            ),
+                        }
                     }
+                    let attempts = attempt + 1;
                     state
                         .errors_total
                         .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
@@ -441,19 +900,20 @@ pub async fn proxy_request(
                     crate::metrics::record_request(
                         err_status.as_u16(),
                         &method_str,
+                        client_name,
+                        route_name,
                         start.elapsed().as_secs_f64(),
                     );
                     crate::metrics::record_upstream_error("timeout");
                     error!(
-                        timeout_secs = state.timeout.as_secs(),
-                        attempts = MAX_UPSTREAM_ATTEMPTS,
-                        "upstream response timeout after all retries"
+                        timeout_secs = timeout.as_secs(),
+                        attempts, "upstream response timeout after all retries"
                     );
                     return error_response(
                         err_status,
                         &format!(
-                            "upstream response timeout after {}s ({MAX_UPSTREAM_ATTEMPTS} attempts)",
-                            state.timeout.as_secs()
+                            "upstream response timeout after {}s ({attempts} attempts)",
+                            timeout.as_secs()
                         ),
                         &request_id,
                     );
@@ -467,6 +927,39 @@ pub async fn proxy_request(
             continue;
         }
 
+        // Every attempt on this account lost its stream before the first
+        // event: try the next account, or report the failure
+        if let Some(reason) = early_stream_failure
+            && last_error_response.is_none()
+        {
+            if failover < max_failovers - 1 {
+                if let Some(ref acct) = account_id {
+                    crate::metrics::record_pool_failover(acct, "early_stream_failure");
+                }
+                continue;
+            }
+            state
+                .errors_total
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            let err_status = StatusCode::BAD_GATEWAY;
+            crate::metrics::record_request(
+                err_status.as_u16(),
+                &method_str,
+                client_name,
+                route_name,
+                start.elapsed().as_secs_f64(),
+            );
+            error!(
+                reason,
+                "upstream stream failed before first event on every attempt"
+            );
+            return error_response(
+                err_status,
+                &format!("upstream stream failed before first event: {reason}"),
+                &request_id,
+            );
+        }
+
         // Last failover attempt exhausted — return the last error response
         if let Some((status, resp_headers, error_body)) = last_error_response {
             state
@@ -475,6 +968,8 @@ pub async fn proxy_request(
             crate::metrics::record_request(
                 status.as_u16(),
                 &method_str,
+                client_name,
+                route_name,
                 start.elapsed().as_secs_f64(),
             );
             return build_buffered_response(status, &resp_headers, error_body);
@@ -484,6 +979,55 @@ pub async fn proxy_request(
     unreachable!("failover loop must return on every code path")
 }
 
+/// The `model` field of a JSON request body, reusing the parsed body when the
+/// provider already needed it.
+fn body_model(parsed: Option<&serde_json::Value>, body: &[u8]) -> Option<String> {
+    let model = |v: &serde_json::Value| v.get("model")?.as_str().map(str::to_string);
+    match parsed {
+        Some(v) => model(v),
+        None => model(&serde_json::from_slice(body).ok()?),
+    }
+}
+
+/// Every model a request body asks for: the top-level `model`, or each
+/// `requests[].params.model` of a message batch. A request without a body
+/// asks for none. `None` if a body is present but not every model in it can
+/// be determined (not JSON, no `model`, a batch entry without one).
+fn body_models(parsed: Option<&serde_json::Value>, body: &[u8]) -> Option<Vec<String>> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Some(Vec::new());
+    }
+    let owned;
+    let value = match parsed {
+        Some(v) => v,
+        None => {
+            owned = serde_json::from_slice::<serde_json::Value>(body).ok()?;
+            &owned
+        }
+    };
+    if let Some(model) = value.get("model") {
+        return Some(vec![model.as_str()?.to_string()]);
+    }
+    value
+        .get("requests")?
+        .as_array()?
+        .iter()
+        .map(|r| r.pointer("/params/model")?.as_str().map(str::to_string))
+        .collect()
+}
+
+/// Log and count a retry, then wait out its backoff.
+async fn back_off(reason: &str, attempt: u32, delay: Duration) {
+    warn!(
+        reason,
+        attempt = attempt + 1,
+        delay_ms = delay.as_millis() as u64,
+        "retrying upstream request"
+    );
+    crate::metrics::record_upstream_retry(reason);
+    tokio::time::sleep(delay).await;
+}
+
 /// Build a response from a buffered error body (used after error classification).
 fn build_buffered_response(
     status: StatusCode,
@@ -507,15 +1051,137 @@ fn build_buffered_response(
         })
 }
 
+/// Name the tier that served a response in its headers and metrics.
+fn tag_tier(headers: &mut reqwest::header::HeaderMap, tier: Option<&str>) {
+    if let Some(tier) = tier
+        && let Ok(value) = reqwest::header::HeaderValue::from_str(tier)
+    {
+        headers.insert(TIER_HEADER, value);
+        crate::metrics::record_tier_response(tier);
+    }
+}
+
+/// Wrap an upstream body with an idle timeout that terminates the stream if no
+/// data arrives within the given duration.
+fn idle_body(upstream_response: reqwest::Response, idle_timeout: Duration) -> BodyStream {
+    Box::pin(IdleTimeoutStream::new(
+        upstream_response.bytes_stream(),
+        idle_timeout,
+    ))
+}
+
+/// Whether the upstream response is an SSE stream.
+pub(crate) fn is_event_stream(headers: &reqwest::header::HeaderMap) -> bool {
+    headers
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/event-stream"))
+}
+
+/// Outcome of scanning buffered SSE bytes for the first significant event.
+#[derive(Debug, PartialEq)]
+enum FirstEvent {
+    /// `message_start` received: the stream is committed
+    Started,
+    /// Upstream sent an `error` event before `message_start`
+    Error(String),
+    /// No decisive event yet
+    Pending,
+}
+
+/// Incremental scan of buffered SSE bytes for the first significant event.
+///
+/// Each call only searches bytes added since the last one (plus the tail a
+/// blank line split across chunks could start in), so holding a large first
+/// event stays linear in its size.
+#[derive(Debug, Default)]
+struct FirstEventScanner {
+    /// Start of the first event not yet parsed
+    event_start: usize,
+    /// Where the next search for a blank line resumes
+    search_from: usize,
+}
+
+impl FirstEventScanner {
+    /// Parse the complete (blank-line terminated) events in `buf` not seen
+    /// before. Events other than `message_start` and `error` (e.g. `ping`)
+    /// are skipped.
+    fn scan(&mut self, buf: &[u8]) -> FirstEvent {
+        while let Some((end, next)) = find_blank_line(buf, self.search_from) {
+            let block = String::from_utf8_lossy(&buf[self.event_start..end]);
+            self.event_start = next;
+            self.search_from = next;
+            let mut event = None;
+            let mut data = String::new();
+            for line in block.lines() {
+                if let Some(v) = line.strip_prefix("event:") {
+                    event = Some(v.trim());
+                } else if let Some(v) = line.strip_prefix("data:") {
+                    data.push_str(v.trim());
+                }
+            }
+            match event {
+                Some("message_start") => return FirstEvent::Started,
+                Some("error") => return FirstEvent::Error(data),
+                _ => {}
+            }
+        }
+        // A `\n\r\n` delimiter may begin in the last two bytes
+        self.search_from = buf.len().saturating_sub(2).max(self.event_start);
+        FirstEvent::Pending
+    }
+}
+
+/// Find the first blank line (`\n\n` or `\n\r\n`) at or after `from`.
+/// Returns where the event before it ends and where the next one starts.
+fn find_blank_line(buf: &[u8], from: usize) -> Option<(usize, usize)> {
+    (from..buf.len()).find_map(|i| match &buf[i..] {
+        [b'\n', b'\n', ..] => Some((i, i + 2)),
+        [b'\n', b'\r', b'\n', ..] => Some((i, i + 3)),
+        _ => None,
+    })
+}
+
+/// Read from `body` until the first `message_start` event, then return a
+/// stream that replays the held chunks followed by the rest of the body.
+///
+/// Fails if the stream errors, ends (including idle timeout), or carries an
+/// `error` event first. Gives up holding and commits once
+/// `MAX_FIRST_EVENT_BUFFER` bytes are buffered.
+async fn buffer_first_event(mut body: BodyStream) -> Result<BodyStream, String> {
+    let mut held: Vec<Bytes> = Vec::new();
+    let mut buf: Vec<u8> = Vec::new();
+    let mut scanner = FirstEventScanner::default();
+    loop {
+        match body.next().await {
+            Some(Ok(chunk)) => {
+                buf.extend_from_slice(&chunk);
+                held.push(chunk);
+                match scanner.scan(&buf) {
+                    FirstEvent::Started => break,
+                    FirstEvent::Error(data) => return Err(format!("upstream error event: {data}")),
+                    FirstEvent::Pending if buf.len() >= MAX_FIRST_EVENT_BUFFER => break,
+                    FirstEvent::Pending => {}
+                }
+            }
+            Some(Err(e)) => return Err(format!("upstream stream error: {e}")),
+            None => return Err("upstream stream ended before message_start".into()),
+        }
+    }
+    let replay = futures_util::stream::iter(held.into_iter().map(Ok));
+    Ok(Box::pin(replay.chain(body)))
+}
+
 /// Build a streaming response (used for success and passthrough error responses).
-/// Wraps the upstream byte stream with an idle timeout that terminates the stream
-/// if no data arrives within the given duration.
+/// The account lease and client permit, if any, are held by the body and
+/// released when the stream is dropped.
 fn build_streaming_response(
     status: StatusCode,
     resp_headers: &reqwest::header::HeaderMap,
-    upstream_response: reqwest::Response,
+    body: BodyStream,
     request_id: &str,
-    idle_timeout: Duration,
+    lease: Option<AccountLease>,
+    permit: Option<UsagePermit>,
 ) -> Response {
     let mut response = Response::builder().status(status);
     for (name, value) in resp_headers {
@@ -523,9 +1189,13 @@ fn build_streaming_response(
             response = response.header(name, value);
         }
     }
-    let idle_stream = IdleTimeoutStream::new(upstream_response.bytes_stream(), idle_timeout);
+    let body = body.map(move |chunk| {
+        let _lease = &lease;
+        let _permit = &permit;
+        chunk
+    });
     response
-        .body(axum::body::Body::from_stream(idle_stream))
+        .body(axum::body::Body::from_stream(body))
         .unwrap_or_else(|e| {
             error_response(
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -585,10 +1255,11 @@ mod tests {
         assert_eq!(resp.status(), StatusCode::GATEWAY_TIMEOUT);
 
         // Verify the response body matches the spec JSON format:
-        // {"error":{"type":"proxy_error","message":"...","request_id":"..."}}
+        // {"type":"error","error":{"type":"proxy_error","message":"...","request_id":"..."}}
         let body = resp.into_body();
         let bytes = axum::body::to_bytes(body, 1024 * 1024).await.unwrap();
         let json: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(json["type"], "error");
         assert_eq!(json["error"]["type"], "proxy_error");
         assert_eq!(json["error"]["message"], "upstream timeout after 60s");
         assert_eq!(json["error"]["request_id"], "req_abc123");
@@ -609,6 +1280,67 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_scan_first_event() {
+        let scan = |buf: &[u8]| FirstEventScanner::default().scan(buf);
+        assert_eq!(scan(b""), FirstEvent::Pending);
+        // Incomplete event: no terminating blank line yet
+        assert_eq!(scan(b"event: message_start\ndata: {}"), FirstEvent::Pending);
+        assert_eq!(
+            scan(b"event: ping\ndata: {}\n\nevent: message_start\ndata: {}\n\n"),
+            FirstEvent::Started
+        );
+        assert_eq!(
+            scan(b"event: message_start\r\ndata: {}\r\n\r\n"),
+            FirstEvent::Started
+        );
+        assert_eq!(
+            scan(b"event: error\ndata: {\"type\":\"overloaded_error\"}\n\n"),
+            FirstEvent::Error(r#"{"type":"overloaded_error"}"#.into())
+        );
+    }
+
+    #[test]
+    fn test_scan_first_event_resumes_after_seen_bytes() {
+        let mut scanner = FirstEventScanner::default();
+        let mut buf = b"event: ping\ndata: {}\n\nevent: message_start\r\ndata: {}\r".to_vec();
+        assert_eq!(scanner.scan(&buf), FirstEvent::Pending);
+        // The ping was consumed; only the tail is searched again
+        assert_eq!(scanner.event_start, 22);
+        assert_eq!(scanner.search_from, buf.len() - 2);
+
+        // The blank line is split across chunks
+        buf.extend_from_slice(b"\n\r");
+        assert_eq!(scanner.scan(&buf), FirstEvent::Pending);
+        buf.extend_from_slice(b"\n");
+        assert_eq!(scanner.scan(&buf), FirstEvent::Started);
+    }
+
+    #[tokio::test]
+    async fn test_buffer_first_event_replays_held_chunks() {
+        let chunks: Vec<Result<Bytes, Box<dyn std::error::Error + Send + Sync>>> = vec![
+            Ok(Bytes::from_static(b"event: message_start\n")),
+            Ok(Bytes::from_static(b"data: {}\n\n")),
+            Ok(Bytes::from_static(b"event: message_stop\ndata: {}\n\n")),
+        ];
+        let body = buffer_first_event(Box::pin(futures_util::stream::iter(chunks)))
+            .await
+            .unwrap();
+        let out: Vec<Bytes> = body.map(|c| c.unwrap()).collect().await;
+        assert_eq!(
+            out.concat(),
+            b"event: message_start\ndata: {}\n\nevent: message_stop\ndata: {}\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_buffer_first_event_fails_on_early_end() {
+        let chunks: Vec<Result<Bytes, Box<dyn std::error::Error + Send + Sync>>> =
+            vec![Ok(Bytes::from_static(b"event: ping\ndata: {}\n\n"))];
+        let result = buffer_first_event(Box::pin(futures_util::stream::iter(chunks))).await;
+        assert!(result.is_err());
+    }
+
     #[test]
     fn test_in_flight_guard_decrements_on_drop() {
         let counter = Arc::new(std::sync::atomic::AtomicU64::new(0));
@@ -0,0 +1,200 @@
+//! Upstream retry policy
+//!
+//! Decides whether a failed upstream attempt is retried on the same account
+//! and how long to wait first. Configured by `[proxy.retry]`; the defaults
+//! retry only timeouts, three attempts in total.
+//!
+//! Retries are separate from account failover: quota exhaustion and rejected
+//! credentials move to the next account regardless of this policy.
+
+use std::fmt;
+use std::time::Duration;
+
+use rand::RngExt;
+use serde::Deserialize;
+
+use crate::config::RetryConfig;
+
+/// Failure classes that can be retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetryClass {
+    /// TCP connect or TLS handshake to upstream failed
+    Connect,
+    /// No response headers within `timeout_secs`
+    Timeout,
+    /// 502, 503, or 504 from upstream
+    ServerError,
+    /// 429 that is not a subscription quota exhaustion
+    RateLimited,
+    /// 529 `overloaded_error`
+    Overloaded,
+}
+
+impl RetryClass {
+    /// Retry class of an upstream error status, if it has one.
+    pub fn from_status(status: u16) -> Option<Self> {
+        match status {
+            502..=504 => Some(RetryClass::ServerError),
+            429 => Some(RetryClass::RateLimited),
+            529 => Some(RetryClass::Overloaded),
+            _ => None,
+        }
+    }
+
+    /// Metric label (matches the TOML spelling).
+    pub fn label(&self) -> &'static str {
+        match self {
+            RetryClass::Connect => "connect",
+            RetryClass::Timeout => "timeout",
+            RetryClass::ServerError => "server_error",
+            RetryClass::RateLimited => "rate_limited",
+            RetryClass::Overloaded => "overloaded",
+        }
+    }
+}
+
+impl fmt::Display for RetryClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// Retry settings resolved from config.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total attempts per account, including the first
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+    /// Randomize each delay within [delay/2, delay] so retries from
+    /// concurrent requests don't arrive in lockstep
+    pub jitter: bool,
+    pub retry_on: Vec<RetryClass>,
+    /// Stop retrying once this much time has passed since the request
+    /// arrived (`None`: limited by `max_attempts` only)
+    pub budget: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::from(&RetryConfig::default())
+    }
+}
+
+impl From<&RetryConfig> for RetryPolicy {
+    fn from(config: &RetryConfig) -> Self {
+        Self {
+            max_attempts: config.max_attempts,
+            initial_backoff: Duration::from_millis(config.initial_backoff_ms),
+            max_backoff: Duration::from_millis(config.max_backoff_ms),
+            multiplier: config.backoff_multiplier,
+            jitter: config.jitter,
+            retry_on: config.retry_on.clone(),
+            budget: (config.budget_ms > 0).then(|| Duration::from_millis(config.budget_ms)),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether failures of this class are retried at all.
+    pub fn retries(&self, class: RetryClass) -> bool {
+        self.retry_on.contains(&class)
+    }
+
+    /// Delay before the retry following failed attempt `attempt` (0-based),
+    /// or `None` when attempts or the request's budget are used up.
+    pub fn next_delay(&self, attempt: u32, elapsed: Duration) -> Option<Duration> {
+        if attempt + 1 >= self.max_attempts {
+            return None;
+        }
+        let base = self.initial_backoff.as_secs_f64() * self.multiplier.powi(attempt as i32);
+        let mut delay = Duration::from_secs_f64(base.min(self.max_backoff.as_secs_f64()));
+        if self.jitter {
+            let factor: f64 = rand::rng().random_range(0.5..=1.0);
+            delay = delay.mul_f64(factor);
+        }
+        if self.budget.is_some_and(|budget| elapsed + delay >= budget) {
+            return None;
+        }
+        Some(delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 4,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(250),
+            multiplier: 2.0,
+            jitter: false,
+            retry_on: vec![RetryClass::Timeout],
+            budget: None,
+        }
+    }
+
+    #[test]
+    fn backoff_grows_and_caps() {
+        let p = policy();
+        assert_eq!(
+            p.next_delay(0, Duration::ZERO),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(
+            p.next_delay(1, Duration::ZERO),
+            Some(Duration::from_millis(200))
+        );
+        assert_eq!(
+            p.next_delay(2, Duration::ZERO),
+            Some(Duration::from_millis(250))
+        );
+        // Fourth attempt was the last
+        assert_eq!(p.next_delay(3, Duration::ZERO), None);
+    }
+
+    #[test]
+    fn jitter_stays_within_half_to_full_delay() {
+        let p = RetryPolicy {
+            jitter: true,
+            ..policy()
+        };
+        for _ in 0..100 {
+            let d = p.next_delay(1, Duration::ZERO).unwrap();
+            assert!(d >= Duration::from_millis(100) && d <= Duration::from_millis(200));
+        }
+    }
+
+    #[test]
+    fn budget_stops_retries() {
+        let p = RetryPolicy {
+            budget: Some(Duration::from_secs(1)),
+            ..policy()
+        };
+        assert!(p.next_delay(0, Duration::from_millis(500)).is_some());
+        assert!(p.next_delay(0, Duration::from_millis(950)).is_none());
+    }
+
+    #[test]
+    fn status_classes() {
+        assert_eq!(RetryClass::from_status(502), Some(RetryClass::ServerError));
+        assert_eq!(RetryClass::from_status(504), Some(RetryClass::ServerError));
+        assert_eq!(RetryClass::from_status(429), Some(RetryClass::RateLimited));
+        assert_eq!(RetryClass::from_status(529), Some(RetryClass::Overloaded));
+        assert_eq!(RetryClass::from_status(500), None);
+        assert_eq!(RetryClass::from_status(400), None);
+    }
+
+    #[test]
+    fn default_retries_only_timeouts() {
+        let p = RetryPolicy::default();
+        assert_eq!(p.max_attempts, 3);
+        assert!(p.retries(RetryClass::Timeout));
+        assert!(!p.retries(RetryClass::Connect));
+        assert!(!p.retries(RetryClass::ServerError));
+    }
+}
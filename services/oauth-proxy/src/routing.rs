@@ -0,0 +1,252 @@
+//! Path- and model-based routing
+//!
+//! `[[routes]]` maps request path prefixes to their own upstream, provider,
+//! timeout, and body limit; the longest matching prefix wins, and requests
+//! that match none use the `[proxy]` settings and the default provider. The
+//! route's name labels the request's metrics.
+//!
+//! `[model_aliases]` rewrites short model names (`fast`) to full ones in the
+//! request body before anything else reads it, so client allow-lists, usage
+//! labels, and the upstream all see the real name. `[[model_routes]]` then
+//! sends requests for matching models to their own provider and upstream;
+//! the first matching route wins. Requests whose model matches no route, or
+//! that name no model, keep the provider of their path route (or the
+//! default).
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use provider::Provider;
+use tracing::debug;
+
+use crate::clients::model_matches;
+
+/// Route name for metrics when no `[[routes]]` entry matches.
+pub const DEFAULT_ROUTE: &str = "default";
+
+/// Where requests under a path prefix go, and how they are limited.
+pub struct PathRoute {
+    /// Metrics label
+    pub name: String,
+    pub path_prefix: String,
+    /// Remove `path_prefix` from the path sent upstream
+    pub strip_prefix: bool,
+    pub upstream_url: String,
+    pub provider: Arc<dyn Provider>,
+    /// Failover budget for this route's provider (its pool size)
+    pub max_failover_attempts: usize,
+    pub timeout: Duration,
+    pub max_body_size: usize,
+}
+
+impl PathRoute {
+    /// Whether `path` is `path_prefix` or below it (`/v1/messages` covers
+    /// `/v1/messages/batches` but not `/v1/messagesx`).
+    fn covers(&self, path: &str) -> bool {
+        match path.strip_prefix(&self.path_prefix) {
+            Some(rest) => {
+                rest.is_empty() || rest.starts_with('/') || self.path_prefix.ends_with('/')
+            }
+            None => false,
+        }
+    }
+
+    /// The path to send upstream for `path_and_query`.
+    pub fn upstream_path<'a>(&self, path_and_query: &'a str) -> std::borrow::Cow<'a, str> {
+        if !self.strip_prefix {
+            return path_and_query.into();
+        }
+        let rest = path_and_query
+            .strip_prefix(self.path_prefix.trim_end_matches('/'))
+            .unwrap_or(path_and_query);
+        if rest.starts_with('/') {
+            rest.into()
+        } else {
+            format!("/{rest}").into()
+        }
+    }
+}
+
+/// Path routes, matched by longest prefix.
+pub struct PathRouter {
+    routes: Vec<PathRoute>,
+}
+
+impl PathRouter {
+    pub fn new(routes: Vec<PathRoute>) -> Self {
+        Self { routes }
+    }
+
+    /// The route with the longest prefix covering `path`.
+    pub fn route(&self, path: &str) -> Option<&PathRoute> {
+        self.routes
+            .iter()
+            .filter(|r| r.covers(path))
+            .max_by_key(|r| r.path_prefix.len())
+    }
+}
+
+/// Where requests for a set of models go.
+pub struct ModelRoute {
+    /// Exact model names or `prefix*` patterns
+    pub models: Vec<String>,
+    pub upstream_url: String,
+    pub provider: Arc<dyn Provider>,
+    /// Failover budget for this route's provider (its pool size)
+    pub max_failover_attempts: usize,
+}
+
+/// Alias table and model routes, applied to each request body.
+pub struct ModelRouter {
+    aliases: HashMap<String, String>,
+    routes: Vec<ModelRoute>,
+}
+
+impl ModelRouter {
+    pub fn new(aliases: HashMap<String, String>, routes: Vec<ModelRoute>) -> Self {
+        Self { aliases, routes }
+    }
+
+    /// Rewrite an aliased `model` in a JSON request body and find the route
+    /// for the resulting model.
+    ///
+    /// Returns the rewritten body (`None` if unchanged) and the matching
+    /// route (`None` for the default provider). Bodies that aren't JSON
+    /// objects pass through untouched.
+    pub fn apply(&self, body: &[u8]) -> (Option<Bytes>, Option<&ModelRoute>) {
+        let Ok(mut value) = serde_json::from_slice::<serde_json::Value>(body) else {
+            return (None, None);
+        };
+        let Some(model) = value.get("model").and_then(|m| m.as_str()) else {
+            return (None, None);
+        };
+
+        let rewritten = match self.aliases.get(model) {
+            Some(target) => {
+                debug!(alias = model, model = %target, "model alias rewritten");
+                value["model"] = serde_json::Value::String(target.clone());
+                serde_json::to_vec(&value).ok().map(Bytes::from)
+            }
+            None => None,
+        };
+        let model = value["model"].as_str().unwrap_or_default();
+        let route = self.routes.iter().find(|r| model_matches(&r.models, model));
+        if let Some(route) = route {
+            debug!(
+                model,
+                provider = route.provider.id(),
+                upstream = %route.upstream_url,
+                "model routed"
+            );
+        }
+        (rewritten, route)
+    }
+
+    /// Whether `model` is named exactly in the config: an alias, an alias
+    /// target, or a route entry that isn't a `prefix*` pattern.
+    pub fn names(&self, model: &str) -> bool {
+        self.aliases
+            .iter()
+            .any(|(alias, target)| alias == model || target == model)
+            || self
+                .routes
+                .iter()
+                .any(|r| r.models.iter().any(|m| m == model))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use provider::passthrough::PassthroughProvider;
+
+    fn path_route(name: &str, prefix: &str, strip_prefix: bool) -> PathRoute {
+        PathRoute {
+            name: name.to_string(),
+            path_prefix: prefix.to_string(),
+            strip_prefix,
+            upstream_url: format!("http://{name}"),
+            provider: Arc::new(PassthroughProvider::new(vec![])),
+            max_failover_attempts: 1,
+            timeout: Duration::from_secs(60),
+            max_body_size: 1024,
+        }
+    }
+
+    #[test]
+    fn longest_path_prefix_wins() {
+        let router = PathRouter::new(vec![
+            path_route("messages", "/v1/messages", false),
+            path_route("batches", "/v1/messages/batches", false),
+            path_route("local", "/local/", true),
+        ]);
+        let name = |path| router.route(path).map(|r| r.name.as_str());
+        assert_eq!(name("/v1/messages"), Some("messages"));
+        assert_eq!(name("/v1/messages/count_tokens"), Some("messages"));
+        assert_eq!(name("/v1/messages/batches/msgbatch_1"), Some("batches"));
+        assert_eq!(name("/v1/messagesx"), None);
+        assert_eq!(name("/v1/models"), None);
+        assert_eq!(name("/local/v1/chat"), Some("local"));
+    }
+
+    #[test]
+    fn strip_prefix_rewrites_upstream_path() {
+        let local = path_route("local", "/local/", true);
+        assert_eq!(local.upstream_path("/local/v1/chat?x=1"), "/v1/chat?x=1");
+        assert_eq!(local.upstream_path("/local/"), "/");
+        let messages = path_route("messages", "/v1/messages", false);
+        assert_eq!(messages.upstream_path("/v1/messages"), "/v1/messages");
+    }
+
+    fn router() -> ModelRouter {
+        let route = |models: &[&str], upstream: &str| ModelRoute {
+            models: models.iter().map(|m| m.to_string()).collect(),
+            upstream_url: upstream.to_string(),
+            provider: Arc::new(PassthroughProvider::new(vec![])),
+            max_failover_attempts: 1,
+        };
+        ModelRouter::new(
+            HashMap::from([("fast".to_string(), "claude-haiku-4-5".to_string())]),
+            vec![
+                route(&["claude-haiku-*"], "http://haiku"),
+                route(&["qwen3"], "http://local"),
+            ],
+        )
+    }
+
+    #[test]
+    fn alias_is_rewritten_before_routing() {
+        let router = router();
+        let (body, route) = router.apply(br#"{"model":"fast","max_tokens":5}"#);
+        let body: serde_json::Value = serde_json::from_slice(&body.unwrap()).unwrap();
+        assert_eq!(body["model"], "claude-haiku-4-5");
+        assert_eq!(body["max_tokens"], 5);
+        assert_eq!(route.unwrap().upstream_url, "http://haiku");
+    }
+
+    #[test]
+    fn unrouted_and_non_json_bodies_use_the_default() {
+        let router = router();
+        let (body, route) = router.apply(br#"{"model":"qwen3"}"#);
+        assert!(body.is_none());
+        assert_eq!(route.unwrap().upstream_url, "http://local");
+
+        let (body, route) = router.apply(br#"{"model":"claude-opus-4-1"}"#);
+        assert!(body.is_none() && route.is_none());
+        let (body, route) = router.apply(b"not json");
+        assert!(body.is_none() && route.is_none());
+    }
+
+    #[test]
+    fn names_only_exact_config_entries() {
+        let router = router();
+        assert!(router.names("fast"));
+        assert!(router.names("claude-haiku-4-5"));
+        assert!(router.names("qwen3"));
+        // Matched by a pattern, but not named
+        assert!(!router.names("claude-haiku-9"));
+        assert!(!router.names("claude-opus-4-1"));
+    }
+}
@@ -0,0 +1,477 @@
+//! Tailnet identity authorization
+//!
+//! With `[tailnet]` configured, the proxy asks the Tailscale LocalAPI who the
+//! caller is (`/localapi/v0/whois`) and admits only nodes matching the
+//! allowlist. Identity is resolved from the TCP peer address. Behind the
+//! Tailscale Operator's ingress proxy every connection comes from the ingress
+//! pod, so `trust_forwarded_for` switches the lookup to the `X-Forwarded-For`
+//! address that proxy adds. The header is only read when the TCP peer is in
+//! `trusted_proxies`, and only its rightmost entry is used: that is the one
+//! the trusted proxy appended, while entries to its left come from the
+//! client and can name any address.
+//!
+//! `localapi_url` is a plain HTTP base URL, so any stand-in that answers
+//! `whois` in the LocalAPI's JSON shape works in development and tests.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use reqwest::header::HeaderMap;
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::config::TailnetConfig;
+
+/// Caller identity reported by the LocalAPI.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TailnetIdentity {
+    /// Short machine name (e.g. `build-01`)
+    pub node: String,
+    /// Login name of the node's owner; `tagged-devices` for tagged nodes
+    pub user: String,
+    /// ACL tags (e.g. `tag:ci`)
+    pub tags: Vec<String>,
+}
+
+/// Why a caller was not admitted.
+#[derive(Debug, PartialEq, Eq)]
+pub enum TailnetError {
+    /// No peer address to look up
+    NoPeerAddress,
+    /// The address is not a tailnet node
+    NotTailnetPeer(IpAddr),
+    /// The node is not in the allowlist
+    NotAllowed(TailnetIdentity),
+    /// The LocalAPI could not be reached or returned garbage
+    LookupFailed(String),
+}
+
+impl std::fmt::Display for TailnetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TailnetError::NoPeerAddress => f.write_str("caller address unknown"),
+            TailnetError::NotTailnetPeer(ip) => write!(f, "{ip} is not a tailnet node"),
+            TailnetError::NotAllowed(id) => {
+                write!(f, "tailnet node '{}' is not allowed", id.node)
+            }
+            TailnetError::LookupFailed(e) => write!(f, "tailnet identity lookup failed: {e}"),
+        }
+    }
+}
+
+/// `whois` response fields the proxy uses.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct WhoIsResponse {
+    node: WhoIsNode,
+    #[serde(default)]
+    user_profile: Option<WhoIsUser>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct WhoIsNode {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    computed_name: String,
+    #[serde(default)]
+    tags: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct WhoIsUser {
+    #[serde(default)]
+    login_name: String,
+}
+
+impl From<WhoIsResponse> for TailnetIdentity {
+    fn from(r: WhoIsResponse) -> Self {
+        let node = if r.node.computed_name.is_empty() {
+            // "build-01.tail1234.ts.net." -> "build-01"
+            r.node
+                .name
+                .split('.')
+                .next()
+                .unwrap_or_default()
+                .to_string()
+        } else {
+            r.node.computed_name
+        };
+        Self {
+            node,
+            user: r.user_profile.map(|u| u.login_name).unwrap_or_default(),
+            tags: r.node.tags.unwrap_or_default(),
+        }
+    }
+}
+
+/// An address range in CIDR notation (`10.0.0.0/8`); a bare address is a
+/// single-address range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl std::str::FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let network: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("'{s}' is not an IP address or CIDR range"))?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max)
+                .ok_or_else(|| format!("'{s}' has an invalid prefix length"))?,
+            None => max,
+        };
+        Ok(Self { network, prefix })
+    }
+}
+
+impl IpRange {
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Cached lookup result: an identity, or `None` for "not a tailnet peer".
+type CacheEntry = (Instant, Option<Arc<TailnetIdentity>>);
+
+/// Resolves and authorizes callers by tailnet identity.
+pub struct TailnetAuthorizer {
+    http: reqwest::Client,
+    whois_url: String,
+    /// Peers whose `X-Forwarded-For` is believed (empty unless
+    /// `trust_forwarded_for`)
+    trusted_proxies: Vec<IpRange>,
+    cache_ttl: Duration,
+    cache: Mutex<HashMap<IpAddr, CacheEntry>>,
+    allow_tags: Vec<String>,
+    allow_users: Vec<String>,
+    allow_nodes: Vec<String>,
+}
+
+impl TailnetAuthorizer {
+    pub fn new(config: &TailnetConfig, http: reqwest::Client) -> Self {
+        Self {
+            http,
+            whois_url: format!(
+                "{}/localapi/v0/whois",
+                config.localapi_url.trim_end_matches('/')
+            ),
+            trusted_proxies: if config.trust_forwarded_for {
+                // Validated during config load
+                config
+                    .trusted_proxies
+                    .iter()
+                    .filter_map(|r| r.parse().ok())
+                    .collect()
+            } else {
+                Vec::new()
+            },
+            cache_ttl: Duration::from_secs(config.cache_ttl_secs),
+            cache: Mutex::new(HashMap::new()),
+            allow_tags: config.allow_tags.clone(),
+            allow_users: config.allow_users.clone(),
+            allow_nodes: config.allow_nodes.clone(),
+        }
+    }
+
+    /// Identify the caller and check it against the allowlist.
+    pub async fn authorize(
+        &self,
+        peer: Option<SocketAddr>,
+        headers: &HeaderMap,
+    ) -> Result<Arc<TailnetIdentity>, TailnetError> {
+        let ip = self
+            .caller_ip(peer, headers)
+            .ok_or(TailnetError::NoPeerAddress)?;
+        let identity = self
+            .lookup(ip)
+            .await?
+            .ok_or(TailnetError::NotTailnetPeer(ip))?;
+        if !self.allows(&identity) {
+            return Err(TailnetError::NotAllowed((*identity).clone()));
+        }
+        Ok(identity)
+    }
+
+    /// Whether an identity passes the allowlist. With no rules, any tailnet
+    /// node is admitted.
+    fn allows(&self, identity: &TailnetIdentity) -> bool {
+        if self.allow_tags.is_empty() && self.allow_users.is_empty() && self.allow_nodes.is_empty()
+        {
+            return true;
+        }
+        identity.tags.iter().any(|t| self.allow_tags.contains(t))
+            || self.allow_users.contains(&identity.user)
+            || self.allow_nodes.contains(&identity.node)
+    }
+
+    /// The TCP peer, or the address a trusted proxy in front of it appended
+    /// to `X-Forwarded-For`.
+    fn caller_ip(&self, peer: Option<SocketAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        let peer = peer.map(|p| p.ip().to_canonical())?;
+        if !self.trusted_proxies.iter().any(|r| r.contains(peer)) {
+            return Some(peer);
+        }
+        // Proxies may repeat the header; the last value holds the last hop
+        let forwarded = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .next_back()
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.rsplit(',').next())
+            .and_then(|v| v.trim().parse::<IpAddr>().ok());
+        Some(forwarded.map(|ip| ip.to_canonical()).unwrap_or(peer))
+    }
+
+    /// Cached `whois`. `Ok(None)` means the LocalAPI doesn't know the address.
+    async fn lookup(&self, ip: IpAddr) -> Result<Option<Arc<TailnetIdentity>>, TailnetError> {
+        if let Some((at, cached)) = self.cache.lock().unwrap().get(&ip)
+            && at.elapsed() < self.cache_ttl
+        {
+            return Ok(cached.clone());
+        }
+
+        let response = self
+            .http
+            .get(format!("{}?addr={ip}", self.whois_url))
+            .timeout(Duration::from_secs(5))
+            .send()
+            .await
+            .map_err(|e| TailnetError::LookupFailed(e.to_string()))?;
+
+        let identity = match response.status().as_u16() {
+            200 => {
+                let whois: WhoIsResponse = response
+                    .json()
+                    .await
+                    .map_err(|e| TailnetError::LookupFailed(e.to_string()))?;
+                Some(Arc::new(TailnetIdentity::from(whois)))
+            }
+            404 => None,
+            status => {
+                return Err(TailnetError::LookupFailed(format!(
+                    "LocalAPI returned {status}"
+                )));
+            }
+        };
+        debug!(%ip, identity = ?identity, "tailnet whois");
+
+        let mut cache = self.cache.lock().unwrap();
+        // Drop stale entries so the cache tracks active peers only
+        cache.retain(|_, (at, _)| at.elapsed() < self.cache_ttl);
+        cache.insert(ip, (Instant::now(), identity.clone()));
+        Ok(identity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Stand-in LocalAPI: knows 100.64.0.1 (tagged CI node) and 100.64.0.2
+    /// (alice's laptop). Returns the URL and a request counter.
+    async fn start_localapi() -> (String, Arc<AtomicUsize>) {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let app = axum::Router::new().route(
+            "/localapi/v0/whois",
+            axum::routing::get(
+                move |axum::extract::Query(q): axum::extract::Query<HashMap<String, String>>| {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    async move {
+                        let body = match q.get("addr").map(String::as_str) {
+                            Some("100.64.0.1") => serde_json::json!({
+                                "Node": {"Name": "build-01.tail1234.ts.net.", "Tags": ["tag:ci"]},
+                                "UserProfile": {"LoginName": "tagged-devices"}
+                            }),
+                            Some("100.64.0.2") => serde_json::json!({
+                                "Node": {"Name": "laptop.tail1234.ts.net.", "ComputedName": "laptop"},
+                                "UserProfile": {"LoginName": "alice@example.com"}
+                            }),
+                            _ => return (axum::http::StatusCode::NOT_FOUND, String::new()),
+                        };
+                        (axum::http::StatusCode::OK, body.to_string())
+                    }
+                },
+            ),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{addr}"), hits)
+    }
+
+    fn config(localapi_url: String) -> TailnetConfig {
+        TailnetConfig {
+            localapi_url,
+            trust_forwarded_for: false,
+            trusted_proxies: vec![],
+            cache_ttl_secs: 60,
+            allow_tags: vec!["tag:ci".into()],
+            allow_users: vec![],
+            allow_nodes: vec![],
+        }
+    }
+
+    fn peer(ip: &str) -> Option<SocketAddr> {
+        Some(SocketAddr::new(ip.parse().unwrap(), 40000))
+    }
+
+    #[tokio::test]
+    async fn admits_allowed_tag_and_rejects_others() {
+        let (url, _) = start_localapi().await;
+        let authz = TailnetAuthorizer::new(&config(url), reqwest::Client::new());
+
+        let id = authz
+            .authorize(peer("100.64.0.1"), &HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(id.node, "build-01");
+        assert_eq!(id.tags, vec!["tag:ci"]);
+
+        assert!(matches!(
+            authz.authorize(peer("100.64.0.2"), &HeaderMap::new()).await,
+            Err(TailnetError::NotAllowed(id)) if id.user == "alice@example.com"
+        ));
+        assert!(matches!(
+            authz.authorize(peer("10.0.0.9"), &HeaderMap::new()).await,
+            Err(TailnetError::NotTailnetPeer(_))
+        ));
+        assert_eq!(
+            authz.authorize(None, &HeaderMap::new()).await,
+            Err(TailnetError::NoPeerAddress)
+        );
+    }
+
+    #[tokio::test]
+    async fn user_and_node_rules() {
+        let (url, _) = start_localapi().await;
+        let mut cfg = config(url);
+        cfg.allow_tags.clear();
+        cfg.allow_users = vec!["alice@example.com".into()];
+        let authz = TailnetAuthorizer::new(&cfg, reqwest::Client::new());
+        assert!(
+            authz
+                .authorize(peer("100.64.0.2"), &HeaderMap::new())
+                .await
+                .is_ok()
+        );
+
+        cfg.allow_users.clear();
+        cfg.allow_nodes = vec!["build-01".into()];
+        let authz = TailnetAuthorizer::new(&cfg, reqwest::Client::new());
+        assert!(
+            authz
+                .authorize(peer("100.64.0.1"), &HeaderMap::new())
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn forwarded_for_is_used_only_when_trusted() {
+        let (url, _) = start_localapi().await;
+        let forwarded = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-forwarded-for", value.parse().unwrap());
+            headers
+        };
+        // The ingress at 10.0.0.5 appended the real caller
+        let headers = forwarded("100.64.0.1");
+
+        let authz = TailnetAuthorizer::new(&config(url.clone()), reqwest::Client::new());
+        assert!(authz.authorize(peer("10.0.0.5"), &headers).await.is_err());
+
+        let mut cfg = config(url);
+        cfg.trust_forwarded_for = true;
+        cfg.trusted_proxies = vec!["10.0.0.0/24".into()];
+        let authz = TailnetAuthorizer::new(&cfg, reqwest::Client::new());
+        assert!(authz.authorize(peer("10.0.0.5"), &headers).await.is_ok());
+
+        // A caller reaching the proxy directly can't pick its identity
+        assert!(matches!(
+            authz.authorize(peer("100.64.0.2"), &headers).await,
+            Err(TailnetError::NotAllowed(id)) if id.node == "laptop"
+        ));
+
+        // Through the ingress, a spoofed leftmost entry is ignored: the
+        // ingress appended the caller's real address on the right
+        let spoofed = forwarded("100.64.0.1, 100.64.0.2");
+        assert!(matches!(
+            authz.authorize(peer("10.0.0.5"), &spoofed).await,
+            Err(TailnetError::NotAllowed(id)) if id.node == "laptop"
+        ));
+    }
+
+    #[test]
+    fn ip_ranges() {
+        let range: IpRange = "10.0.0.0/8".parse().unwrap();
+        assert!(range.contains("10.1.2.3".parse().unwrap()));
+        assert!(!range.contains("11.0.0.1".parse().unwrap()));
+        assert!(!range.contains("::1".parse().unwrap()));
+        let single: IpRange = "10.0.0.5".parse().unwrap();
+        assert!(single.contains("10.0.0.5".parse().unwrap()));
+        assert!(!single.contains("10.0.0.6".parse().unwrap()));
+        let all: IpRange = "0.0.0.0/0".parse().unwrap();
+        assert!(all.contains("192.168.1.1".parse().unwrap()));
+        let v6: IpRange = "fd7a:115c:a1e0::/48".parse().unwrap();
+        assert!(v6.contains("fd7a:115c:a1e0::1".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+        assert!("ingress".parse::<IpRange>().is_err());
+    }
+
+    #[tokio::test]
+    async fn lookups_are_cached() {
+        let (url, hits) = start_localapi().await;
+        let authz = TailnetAuthorizer::new(&config(url), reqwest::Client::new());
+        for _ in 0..3 {
+            authz
+                .authorize(peer("100.64.0.1"), &HeaderMap::new())
+                .await
+                .unwrap();
+            let _ = authz.authorize(peer("10.0.0.9"), &HeaderMap::new()).await;
+        }
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn unreachable_localapi_is_a_lookup_failure() {
+        let authz =
+            TailnetAuthorizer::new(&config("http://127.0.0.1:1".into()), reqwest::Client::new());
+        assert!(matches!(
+            authz.authorize(peer("100.64.0.1"), &HeaderMap::new()).await,
+            Err(TailnetError::LookupFailed(_))
+        ));
+    }
+}
@@ -0,0 +1,398 @@
+//! Tool name normalization for OAuth credential compliance
+//!
+//! Anthropic rejects Claude Max OAuth requests whose tool names are not
+//! Claude Code's own (`Bash`, `Read`, `Edit`, ...). With `[oauth.tool_names]`
+//! configured, the provider renames the client's tools to those canonical
+//! names before forwarding, and its response hook rewrites the response on
+//! the way back so `tool_use` blocks carry the names the client declared.
+//!
+//! Only tools a request actually declares under a mapped name are restored:
+//! a client that already uses a canonical name sees it unchanged.
+
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use futures_util::Stream;
+use provider::BodyStream;
+use reqwest::header::{CONTENT_ENCODING, HeaderMap};
+use tracing::debug;
+
+use crate::proxy::is_event_stream;
+
+/// JSON bodies larger than this are forwarded without restoring names.
+const MAX_JSON_BODY: usize = 10 * 1024 * 1024;
+
+/// Client tool name → canonical Claude Code tool name.
+#[derive(Debug, Clone, Default)]
+pub struct ToolNameMap {
+    canonical: HashMap<String, String>,
+}
+
+impl ToolNameMap {
+    pub fn new(canonical: HashMap<String, String>) -> Self {
+        Self { canonical }
+    }
+
+    /// Rename mapped tools in `tools`, a `tool` choice, and earlier
+    /// `tool_use` blocks in the conversation history. A tool whose canonical
+    /// name the client also declares keeps its name, as in `restore_map`.
+    pub fn canonicalize(&self, body: &mut serde_json::Value) {
+        let declared: Vec<String> = body
+            .get("tools")
+            .and_then(|t| t.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|tool| Some(tool.get("name")?.as_str()?.to_string()))
+            .collect();
+        let mut renamed = 0;
+        if let Some(tools) = body.get_mut("tools").and_then(|t| t.as_array_mut()) {
+            for tool in tools {
+                renamed += self.rename(tool, &declared) as usize;
+            }
+        }
+        if let Some(choice) = body.get_mut("tool_choice")
+            && choice.get("type").and_then(|t| t.as_str()) == Some("tool")
+        {
+            renamed += self.rename(choice, &declared) as usize;
+        }
+        if let Some(messages) = body.get_mut("messages").and_then(|m| m.as_array_mut()) {
+            for block in messages
+                .iter_mut()
+                .filter_map(|m| m.get_mut("content").and_then(|c| c.as_array_mut()))
+                .flatten()
+            {
+                if block.get("type").and_then(|t| t.as_str()) == Some("tool_use") {
+                    renamed += self.rename(block, &declared) as usize;
+                }
+            }
+        }
+        if renamed > 0 {
+            debug!(renamed, "rewrote tool names to canonical names");
+        }
+    }
+
+    /// Canonical → client name for the mapped tools `body` declares, or None
+    /// when the response needs no rewriting. A canonical name the client
+    /// also declares itself is left alone.
+    pub fn restore_map(&self, body: &serde_json::Value) -> Option<HashMap<String, String>> {
+        let declared: Vec<&str> = body
+            .get("tools")?
+            .as_array()?
+            .iter()
+            .filter_map(|tool| tool.get("name")?.as_str())
+            .collect();
+        let restore: HashMap<String, String> = declared
+            .iter()
+            .filter_map(|name| {
+                let canonical = self.canonical.get(*name)?;
+                (!declared.contains(&canonical.as_str()))
+                    .then(|| (canonical.clone(), name.to_string()))
+            })
+            .collect();
+        (!restore.is_empty()).then_some(restore)
+    }
+
+    /// Rename `object` to its canonical name unless that name is `declared`.
+    fn rename(&self, object: &mut serde_json::Value, declared: &[String]) -> bool {
+        let Some(canonical) = object
+            .get("name")
+            .and_then(|n| n.as_str())
+            .and_then(|n| self.canonical.get(n))
+            .filter(|canonical| !declared.contains(canonical))
+        else {
+            return false;
+        };
+        object["name"] = serde_json::Value::String(canonical.clone());
+        true
+    }
+}
+
+/// Replace a `tool_use` block's canonical name with the client's.
+fn restore_block(block: &mut serde_json::Value, restore: &HashMap<String, String>) -> bool {
+    if block.get("type").and_then(|t| t.as_str()) != Some("tool_use") {
+        return false;
+    }
+    let Some(original) = block
+        .get("name")
+        .and_then(|n| n.as_str())
+        .and_then(|n| restore.get(n))
+    else {
+        return false;
+    };
+    block["name"] = serde_json::Value::String(original.clone());
+    true
+}
+
+/// How the rewriter reads the body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BodyFormat {
+    EventStream,
+    Json,
+    /// Compressed or too large: forwarded unchanged
+    Opaque,
+}
+
+/// Body stream that restores client tool names in `tool_use` blocks.
+///
+/// SSE is rewritten line by line (only `content_block_start` events carry a
+/// tool name), holding back an incomplete trailing line. JSON is held until
+/// the end and rewritten whole, so the response's Content-Length must not be
+/// forwarded.
+pub struct ToolNameRestore {
+    inner: BodyStream,
+    format: BodyFormat,
+    restore: HashMap<String, String>,
+    buf: Vec<u8>,
+    done: bool,
+}
+
+impl ToolNameRestore {
+    pub fn new(inner: BodyStream, headers: &HeaderMap, restore: HashMap<String, String>) -> Self {
+        let compressed = headers
+            .get(CONTENT_ENCODING)
+            .is_some_and(|v| v.as_bytes() != b"identity");
+        let format = if compressed {
+            BodyFormat::Opaque
+        } else if is_event_stream(headers) {
+            BodyFormat::EventStream
+        } else {
+            BodyFormat::Json
+        };
+        Self {
+            inner,
+            format,
+            restore,
+            buf: Vec::new(),
+            done: false,
+        }
+    }
+
+    /// Rewrite the complete lines in the buffer, keeping the partial tail.
+    fn take_lines(&mut self) -> Option<Bytes> {
+        let end = self.buf.iter().rposition(|b| *b == b'\n')?;
+        let lines: Vec<u8> = self.buf.drain(..=end).collect();
+        let mut out = Vec::with_capacity(lines.len());
+        for line in lines.split_inclusive(|b| *b == b'\n') {
+            match self.restore_event(line) {
+                Some(rewritten) => out.extend_from_slice(&rewritten),
+                None => out.extend_from_slice(line),
+            }
+        }
+        Some(Bytes::from(out))
+    }
+
+    fn restore_event(&self, line: &[u8]) -> Option<Vec<u8>> {
+        let data = line.strip_prefix(b"data:")?;
+        let mut event: serde_json::Value = serde_json::from_slice(data.trim_ascii()).ok()?;
+        if event.get("type").and_then(|t| t.as_str()) != Some("content_block_start") {
+            return None;
+        }
+        if !restore_block(event.get_mut("content_block")?, &self.restore) {
+            return None;
+        }
+        let ending: &[u8] = if line.ends_with(b"\r\n") {
+            b"\r\n"
+        } else {
+            b"\n"
+        };
+        let mut out = b"data: ".to_vec();
+        out.extend_from_slice(&serde_json::to_vec(&event).ok()?);
+        out.extend_from_slice(ending);
+        Some(out)
+    }
+
+    fn restore_json(&mut self) -> Bytes {
+        let buf = std::mem::take(&mut self.buf);
+        let Ok(mut body) = serde_json::from_slice::<serde_json::Value>(&buf) else {
+            return Bytes::from(buf);
+        };
+        let mut restored = false;
+        if let Some(content) = body.get_mut("content").and_then(|c| c.as_array_mut()) {
+            for block in content {
+                restored |= restore_block(block, &self.restore);
+            }
+        }
+        if !restored {
+            return Bytes::from(buf);
+        }
+        serde_json::to_vec(&body).map_or_else(|_| Bytes::from(buf), Bytes::from)
+    }
+}
+
+impl Stream for ToolNameRestore {
+    type Item = Result<Bytes, Box<dyn std::error::Error + Send + Sync>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if self.done {
+                return Poll::Ready(None);
+            }
+            let chunk = match self.inner.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(chunk))) => chunk,
+                Poll::Ready(None) => {
+                    // Flush whatever is still held
+                    self.done = true;
+                    let rest = match self.format {
+                        BodyFormat::Json => self.restore_json(),
+                        _ => Bytes::from(std::mem::take(&mut self.buf)),
+                    };
+                    if rest.is_empty() {
+                        return Poll::Ready(None);
+                    }
+                    return Poll::Ready(Some(Ok(rest)));
+                }
+                other => return other,
+            };
+            match self.format {
+                BodyFormat::Opaque => return Poll::Ready(Some(Ok(chunk))),
+                BodyFormat::EventStream => {
+                    self.buf.extend_from_slice(&chunk);
+                    if let Some(lines) = self.take_lines() {
+                        return Poll::Ready(Some(Ok(lines)));
+                    }
+                }
+                BodyFormat::Json => {
+                    if self.buf.len() + chunk.len() > MAX_JSON_BODY {
+                        // Give up on this body: release what is held and
+                        // forward the rest untouched
+                        self.format = BodyFormat::Opaque;
+                        let mut held = std::mem::take(&mut self.buf);
+                        held.extend_from_slice(&chunk);
+                        return Poll::Ready(Some(Ok(Bytes::from(held))));
+                    }
+                    self.buf.extend_from_slice(&chunk);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use serde_json::json;
+
+    fn map() -> ToolNameMap {
+        ToolNameMap::new(HashMap::from([
+            ("bash".to_string(), "Bash".to_string()),
+            ("read_file".to_string(), "Read".to_string()),
+        ]))
+    }
+
+    async fn restore(chunks: Vec<&'static str>, content_type: &str) -> String {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", content_type.parse().unwrap());
+        let body: BodyStream = Box::pin(futures_util::stream::iter(
+            chunks.into_iter().map(|c| Ok(Bytes::from(c))),
+        ));
+        let restore = HashMap::from([("Bash".to_string(), "bash".to_string())]);
+        let mut stream = ToolNameRestore::new(body, &headers, restore);
+        let mut out = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            out.extend_from_slice(&chunk.unwrap());
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn canonicalizes_tools_choice_and_history() {
+        let mut body = json!({
+            "model": "claude-sonnet-4-5",
+            "tools": [{"name": "bash"}, {"name": "read_file"}, {"name": "Grep"}],
+            "tool_choice": {"type": "tool", "name": "bash"},
+            "messages": [
+                {"role": "user", "content": "list files"},
+                {"role": "assistant", "content": [
+                    {"type": "text", "text": "ok"},
+                    {"type": "tool_use", "id": "t1", "name": "bash", "input": {}},
+                ]},
+                {"role": "user", "content": [{"type": "tool_result", "tool_use_id": "t1", "content": "a"}]},
+            ],
+        });
+        map().canonicalize(&mut body);
+        assert_eq!(body["tools"][0]["name"], "Bash");
+        assert_eq!(body["tools"][1]["name"], "Read");
+        assert_eq!(body["tools"][2]["name"], "Grep");
+        assert_eq!(body["tool_choice"]["name"], "Bash");
+        assert_eq!(body["messages"][1]["content"][1]["name"], "Bash");
+        assert_eq!(body["messages"][0]["content"], "list files");
+
+        // The client declares Read itself, so read_file keeps its name
+        let mut body = json!({
+            "tools": [{"name": "bash"}, {"name": "read_file"}, {"name": "Read"}],
+            "tool_choice": {"type": "tool", "name": "read_file"},
+            "messages": [
+                {"role": "assistant", "content": [
+                    {"type": "tool_use", "id": "t1", "name": "read_file", "input": {}},
+                    {"type": "tool_use", "id": "t2", "name": "Read", "input": {}},
+                ]},
+            ],
+        });
+        assert_eq!(
+            map().restore_map(&body),
+            Some(HashMap::from([("Bash".to_string(), "bash".to_string())]))
+        );
+        map().canonicalize(&mut body);
+        assert_eq!(body["tools"][0]["name"], "Bash");
+        assert_eq!(body["tools"][1]["name"], "read_file");
+        assert_eq!(body["tools"][2]["name"], "Read");
+        assert_eq!(body["tool_choice"]["name"], "read_file");
+        assert_eq!(body["messages"][0]["content"][0]["name"], "read_file");
+        assert_eq!(body["messages"][0]["content"][1]["name"], "Read");
+    }
+
+    #[test]
+    fn restore_map_covers_only_declared_mapped_tools() {
+        let body = json!({"tools": [{"name": "bash"}, {"name": "Grep"}]});
+        assert_eq!(
+            map().restore_map(&body),
+            Some(HashMap::from([("Bash".to_string(), "bash".to_string())]))
+        );
+        assert_eq!(
+            map().restore_map(&json!({"tools": [{"name": "Bash"}]})),
+            None
+        );
+        assert_eq!(map().restore_map(&json!({"messages": []})), None);
+    }
+
+    #[tokio::test]
+    async fn restores_names_in_sse_split_across_chunks() {
+        let out = restore(
+            vec![
+                "event: content_block_start\ndata: {\"type\":\"content_block_start\",\"index\":1,",
+                "\"content_block\":{\"type\":\"tool_use\",\"id\":\"t1\",\"name\":\"Bash\",\"input\":{}}}\n\n",
+                "event: content_block_start\ndata: {\"type\":\"content_block_start\",\"index\":2,\"content_block\":{\"type\":\"tool_use\",\"id\":\"t2\",\"name\":\"Grep\",\"input\":{}}}\n\n",
+            ],
+            "text/event-stream",
+        )
+        .await;
+        let events: Vec<serde_json::Value> = out
+            .lines()
+            .filter_map(|l| l.strip_prefix("data: "))
+            .map(|d| serde_json::from_str(d).unwrap())
+            .collect();
+        assert_eq!(events[0]["content_block"]["name"], "bash");
+        assert_eq!(events[0]["index"], 1);
+        assert_eq!(events[1]["content_block"]["name"], "Grep");
+        assert!(out.starts_with("event: content_block_start\n"));
+        assert!(out.ends_with("\n\n"));
+    }
+
+    #[tokio::test]
+    async fn restores_names_in_json() {
+        let out = restore(
+            vec![
+                r#"{"type":"message","content":[{"type":"text","text":"hi"},"#,
+                r#"{"type":"tool_use","id":"t1","name":"Bash","input":{"command":"ls"}}]}"#,
+            ],
+            "application/json",
+        )
+        .await;
+        let body: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(body["content"][1]["name"], "bash");
+        assert_eq!(body["content"][1]["input"]["command"], "ls");
+    }
+}
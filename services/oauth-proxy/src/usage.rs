@@ -0,0 +1,396 @@
+//! Token usage extraction from Messages API responses
+//!
+//! Wraps a response body and reads the `usage` objects out of it as it
+//! streams past, without altering the bytes. SSE streams report input tokens
+//! in `message_start` and the cumulative output count in `message_delta`;
+//! JSON responses carry a single top-level `usage`. The callback fires once,
+//! when the body ends or is dropped (client disconnect), with whatever usage
+//! was seen up to that point.
+//!
+//! SSE is parsed line by line as chunks pass, holding back only an incomplete
+//! trailing line. JSON bodies are parsed once complete, from the forwarded
+//! chunks themselves rather than a copy, reading only `model` and `usage`.
+//! Compressed JSON bodies are not parsed; the proxy strips `Accept-Encoding`
+//! whenever it reads usage, so they only arrive from an upstream that ignores
+//! it.
+
+use std::io::Read;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use bytes::Bytes;
+use futures_util::Stream;
+use reqwest::header::{CONTENT_ENCODING, HeaderMap};
+use tracing::info;
+
+use crate::proxy::{BodyStream, is_event_stream};
+
+/// Usage label for a requested model the config doesn't name.
+pub const OTHER_MODEL: &str = "other";
+
+/// JSON bodies larger than this are not parsed for usage.
+const MAX_JSON_BODY: usize = 10 * 1024 * 1024;
+
+/// The parts of a JSON message body the tap reads; everything else is
+/// skipped without being allocated.
+#[derive(serde::Deserialize)]
+struct JsonMessage {
+    model: Option<String>,
+    usage: Option<serde_json::Value>,
+}
+
+/// Token counts reported by upstream for one response.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TokenUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cache_creation_input_tokens: u64,
+    pub cache_read_input_tokens: u64,
+}
+
+impl TokenUsage {
+    /// All input tokens, cached or not.
+    pub fn total_input(&self) -> u64 {
+        self.input_tokens + self.cache_creation_input_tokens + self.cache_read_input_tokens
+    }
+
+    /// Take every count present in a `usage` object. Later events report
+    /// cumulative values, so they replace rather than add.
+    fn merge(&mut self, usage: &serde_json::Value) {
+        let field = |name: &str| usage.get(name).and_then(serde_json::Value::as_u64);
+        if let Some(n) = field("input_tokens") {
+            self.input_tokens = n;
+        }
+        if let Some(n) = field("output_tokens") {
+            self.output_tokens = n;
+        }
+        if let Some(n) = field("cache_creation_input_tokens") {
+            self.cache_creation_input_tokens = n;
+        }
+        if let Some(n) = field("cache_read_input_tokens") {
+            self.cache_read_input_tokens = n;
+        }
+    }
+}
+
+/// What a response reported about itself once it finished.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResponseUsage {
+    /// Model that served the response, when the body names one
+    pub model: Option<String>,
+    pub tokens: TokenUsage,
+}
+
+/// Request details needed to account a response's usage once it completes,
+/// after the request's tracing span has closed.
+#[derive(Debug, Clone)]
+pub struct UsageContext {
+    pub request_id: String,
+    pub client: String,
+    /// Pool account (`None` in passthrough mode)
+    pub account_id: Option<String>,
+    /// Model named in the request body, or `OTHER_MODEL` if the config
+    /// doesn't name it
+    pub request_model: Option<String>,
+    pub status: u16,
+    pub start: Instant,
+}
+
+impl UsageContext {
+    /// Count the response's tokens and write its access log line.
+    pub fn record(&self, usage: &ResponseUsage) {
+        let account_id = self.account_id.as_deref().unwrap_or("-");
+        let model = usage
+            .model
+            .as_deref()
+            .or(self.request_model.as_deref())
+            .unwrap_or("unknown");
+        crate::metrics::record_tokens(account_id, model, &self.client, &usage.tokens);
+        info!(
+            request_id = %self.request_id,
+            client = %self.client,
+            account_id,
+            model,
+            status = self.status,
+            input_tokens = usage.tokens.input_tokens,
+            output_tokens = usage.tokens.output_tokens,
+            cache_creation_input_tokens = usage.tokens.cache_creation_input_tokens,
+            cache_read_input_tokens = usage.tokens.cache_read_input_tokens,
+            duration_ms = self.start.elapsed().as_millis() as u64,
+            "access"
+        );
+    }
+}
+
+/// Called once with the response's final usage.
+pub type UsageCallback = Box<dyn FnOnce(ResponseUsage) + Send>;
+
+/// How the tap reads the body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BodyFormat {
+    EventStream,
+    Json,
+    /// Compressed: forwarded without parsing
+    Opaque,
+}
+
+/// Body stream that records token usage as it is forwarded.
+pub struct UsageTap {
+    inner: BodyStream,
+    format: BodyFormat,
+    /// SSE: the incomplete trailing line
+    buf: Vec<u8>,
+    /// JSON: the body chunks so far (shared with the forwarded ones)
+    chunks: Vec<Bytes>,
+    /// JSON: total length of `chunks`
+    json_len: usize,
+    usage: ResponseUsage,
+    on_complete: Option<UsageCallback>,
+}
+
+impl UsageTap {
+    /// Tap `inner`, choosing SSE or JSON parsing from the response headers.
+    pub fn new(inner: BodyStream, headers: &HeaderMap, on_complete: UsageCallback) -> Self {
+        let compressed = headers
+            .get(CONTENT_ENCODING)
+            .is_some_and(|v| v.as_bytes() != b"identity");
+        let format = if is_event_stream(headers) {
+            BodyFormat::EventStream
+        } else if compressed {
+            BodyFormat::Opaque
+        } else {
+            BodyFormat::Json
+        };
+        Self {
+            inner,
+            format,
+            buf: Vec::new(),
+            chunks: Vec::new(),
+            json_len: 0,
+            usage: ResponseUsage::default(),
+            on_complete: Some(on_complete),
+        }
+    }
+
+    fn observe(&mut self, chunk: &Bytes) {
+        match self.format {
+            BodyFormat::EventStream => {}
+            BodyFormat::Json => {
+                self.json_len += chunk.len();
+                if self.json_len <= MAX_JSON_BODY {
+                    self.chunks.push(chunk.clone());
+                } else {
+                    self.chunks = Vec::new();
+                }
+                return;
+            }
+            BodyFormat::Opaque => return,
+        }
+        self.buf.extend_from_slice(chunk);
+        let Some(end) = self.buf.iter().rposition(|b| *b == b'\n') else {
+            return;
+        };
+        let lines: Vec<u8> = self.buf.drain(..=end).collect();
+        for line in lines.split(|b| *b == b'\n') {
+            if let Some(data) = line.strip_prefix(b"data:") {
+                self.observe_event(data);
+            }
+        }
+    }
+
+    fn observe_event(&mut self, data: &[u8]) {
+        let Ok(event) = serde_json::from_slice::<serde_json::Value>(data.trim_ascii()) else {
+            return;
+        };
+        match event.get("type").and_then(|t| t.as_str()) {
+            Some("message_start") => {
+                if let Some(message) = event.get("message") {
+                    self.observe_message(message);
+                }
+            }
+            Some("message_delta") => {
+                if let Some(usage) = event.get("usage") {
+                    self.usage.tokens.merge(usage);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Take `model` and `usage` from a message object.
+    fn observe_message(&mut self, message: &serde_json::Value) {
+        if let Some(model) = message.get("model").and_then(|m| m.as_str()) {
+            self.usage.model = Some(model.to_string());
+        }
+        if let Some(usage) = message.get("usage") {
+            self.usage.tokens.merge(usage);
+        }
+    }
+
+    fn complete(&mut self) {
+        let Some(on_complete) = self.on_complete.take() else {
+            return;
+        };
+        let chunks = std::mem::take(&mut self.chunks);
+        if self.format == BodyFormat::Json
+            && self.json_len <= MAX_JSON_BODY
+            && let Ok(body) = serde_json::from_reader::<_, JsonMessage>(ChunkReader::new(&chunks))
+        {
+            if let Some(model) = body.model {
+                self.usage.model = Some(model);
+            }
+            if let Some(usage) = body.usage {
+                self.usage.tokens.merge(&usage);
+            }
+        }
+        self.buf = Vec::new();
+        on_complete(std::mem::take(&mut self.usage));
+    }
+}
+
+/// Reads a sequence of chunks as one contiguous body.
+struct ChunkReader<'a> {
+    chunks: std::slice::Iter<'a, Bytes>,
+    current: &'a [u8],
+}
+
+impl<'a> ChunkReader<'a> {
+    fn new(chunks: &'a [Bytes]) -> Self {
+        Self {
+            chunks: chunks.iter(),
+            current: &[],
+        }
+    }
+}
+
+impl Read for ChunkReader<'_> {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        while self.current.is_empty() {
+            match self.chunks.next() {
+                Some(chunk) => self.current = chunk,
+                None => return Ok(0),
+            }
+        }
+        self.current.read(out)
+    }
+}
+
+impl Stream for UsageTap {
+    type Item = Result<Bytes, Box<dyn std::error::Error + Send + Sync>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let polled = self.inner.as_mut().poll_next(cx);
+        match &polled {
+            Poll::Ready(Some(Ok(chunk))) => self.observe(chunk),
+            Poll::Ready(None) => self.complete(),
+            _ => {}
+        }
+        polled
+    }
+}
+
+impl Drop for UsageTap {
+    fn drop(&mut self) {
+        self.complete();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use std::sync::{Arc, Mutex};
+
+    fn headers(content_type: &str, encoding: Option<&str>) -> HeaderMap {
+        let mut h = HeaderMap::new();
+        h.insert("content-type", content_type.parse().unwrap());
+        if let Some(encoding) = encoding {
+            h.insert(CONTENT_ENCODING, encoding.parse().unwrap());
+        }
+        h
+    }
+
+    async fn tap(chunks: Vec<&'static str>, headers: HeaderMap) -> ResponseUsage {
+        let seen = Arc::new(Mutex::new(None));
+        let sink = seen.clone();
+        let body: BodyStream = Box::pin(futures_util::stream::iter(
+            chunks.into_iter().map(|c| Ok(Bytes::from(c))),
+        ));
+        let mut tapped = UsageTap::new(
+            body,
+            &headers,
+            Box::new(move |u| *sink.lock().unwrap() = Some(u)),
+        );
+        while tapped.next().await.is_some() {}
+        drop(tapped);
+        seen.lock().unwrap().take().expect("callback fired")
+    }
+
+    #[tokio::test]
+    async fn reads_sse_usage_split_across_chunks() {
+        let usage = tap(
+            vec![
+                "event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"model\":\"claude-sonnet-4-5\",\"usage\":{\"input_tokens\":25,\"cache_read_input_tokens\":100,\"output_tokens\":1}}}\n\n",
+                "event: message_delta\ndata: {\"type\":\"message_delta\",\"usage\":{\"output_",
+                "tokens\":42}}\n\nevent: message_stop\ndata: {\"type\":\"message_stop\"}\n\n",
+            ],
+            headers("text/event-stream", None),
+        )
+        .await;
+        assert_eq!(usage.model.as_deref(), Some("claude-sonnet-4-5"));
+        assert_eq!(
+            usage.tokens,
+            TokenUsage {
+                input_tokens: 25,
+                output_tokens: 42,
+                cache_creation_input_tokens: 0,
+                cache_read_input_tokens: 100,
+            }
+        );
+        assert_eq!(usage.tokens.total_input(), 125);
+    }
+
+    #[tokio::test]
+    async fn reads_json_usage() {
+        let usage = tap(
+            vec![
+                r#"{"type":"message","model":"claude-haiku-4-5","usage":{"input_tokens":10,"#,
+                r#""output_tokens":5}}"#,
+            ],
+            headers("application/json", None),
+        )
+        .await;
+        assert_eq!(usage.model.as_deref(), Some("claude-haiku-4-5"));
+        assert_eq!(usage.tokens.input_tokens, 10);
+        assert_eq!(usage.tokens.output_tokens, 5);
+    }
+
+    #[tokio::test]
+    async fn skips_compressed_json() {
+        let usage = tap(
+            vec![r#"{"usage":{"input_tokens":10}}"#],
+            headers("application/json", Some("gzip")),
+        )
+        .await;
+        assert_eq!(usage, ResponseUsage::default());
+    }
+
+    #[tokio::test]
+    async fn reports_partial_usage_when_dropped_early() {
+        let seen = Arc::new(Mutex::new(None));
+        let sink = seen.clone();
+        let body: BodyStream = Box::pin(futures_util::stream::iter(vec![Ok(Bytes::from(
+            "data: {\"type\":\"message_start\",\"message\":{\"usage\":{\"input_tokens\":7}}}\n\n",
+        ))]));
+        let mut tapped = UsageTap::new(
+            body,
+            &headers("text/event-stream", None),
+            Box::new(move |u| *sink.lock().unwrap() = Some(u)),
+        );
+        tapped.next().await;
+        drop(tapped);
+        let usage = seen.lock().unwrap().take().unwrap();
+        assert_eq!(usage.tokens.input_tokens, 7);
+    }
+}